@@ -3,7 +3,7 @@ use std::{
     collections::HashMap,
     env,
     future::IntoFuture,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
     sync::Arc,
     time::{Duration, Instant},
@@ -29,6 +29,7 @@ use rattler_solve::{
     resolvo, SolverImpl, SolverTask,
 };
 use reqwest::Client;
+use reqwest_middleware::ClientWithMiddleware;
 
 use crate::global_multi_progress;
 
@@ -154,39 +155,8 @@ pub async fn create(opt: Opt) -> miette::Result<()> {
     // `repodata.json` that should be available from the corresponding Url. The
     // code below also displays a nice CLI progress-bar to give users some more
     // information about what is going on.
-    let download_client = Client::builder()
-        .no_gzip()
-        .build()
-        .expect("failed to create client");
-
-    let download_client = reqwest_middleware::ClientBuilder::new(download_client)
-        .with_arc(Arc::new(
-            AuthenticationMiddleware::from_env_and_defaults().into_diagnostic()?,
-        ))
-        .with(rattler_networking::OciMiddleware)
-        .with(rattler_networking::S3Middleware::new(
-            HashMap::new(),
-            AuthenticationStorage::from_env_and_defaults().into_diagnostic()?,
-        ))
-        .with(rattler_networking::GCSMiddleware)
-        .build();
-
-    // Get the package names from the matchspecs so we can only load the package
-    // records that we need.
-    let gateway = Gateway::builder()
-        .with_cache_dir(cache_dir.join(rattler_cache::REPODATA_CACHE_DIR))
-        .with_package_cache(PackageCache::new(
-            cache_dir.join(rattler_cache::PACKAGE_CACHE_DIR),
-        ))
-        .with_client(download_client.clone())
-        .with_channel_config(rattler_repodata_gateway::ChannelConfig {
-            default: SourceConfig {
-                sharded_enabled: true,
-                ..SourceConfig::default()
-            },
-            per_channel: HashMap::new(),
-        })
-        .finish();
+    let download_client = download_client()?;
+    let gateway = gateway(&cache_dir, download_client.clone());
 
     let start_load_repo_data = Instant::now();
     let repo_data = wrap_in_async_progress(
@@ -215,33 +185,7 @@ pub async fn create(opt: Opt) -> miette::Result<()> {
     // capabilities of the system. Some packages depend on these virtual
     // packages to indicate compatibility with the hardware of the system.
     let virtual_packages = wrap_in_progress("determining virtual packages", move || {
-        if let Some(virtual_packages) = opt.virtual_package {
-            Ok(virtual_packages
-                .iter()
-                .map(|virt_pkg| {
-                    let elems = virt_pkg.split('=').collect::<Vec<&str>>();
-                    Ok(GenericVirtualPackage {
-                        name: elems[0].try_into().into_diagnostic()?,
-                        version: elems
-                            .get(1)
-                            .map_or(Version::from_str("0"), |s| Version::from_str(s))
-                            .expect("Could not parse virtual package version"),
-                        build_string: (*elems.get(2).unwrap_or(&"")).to_string(),
-                    })
-                })
-                .collect::<miette::Result<Vec<_>>>()?)
-        } else {
-            rattler_virtual_packages::VirtualPackage::detect(
-                &rattler_virtual_packages::VirtualPackageOverrides::default(),
-            )
-            .map(|vpkgs| {
-                vpkgs
-                    .iter()
-                    .map(|vpkg| GenericVirtualPackage::from(vpkg.clone()))
-                    .collect::<Vec<_>>()
-            })
-            .into_diagnostic()
-        }
+        detect_virtual_packages(opt.virtual_package)
     })?;
 
     println!(
@@ -339,8 +283,80 @@ pub async fn create(opt: Opt) -> miette::Result<()> {
     Ok(())
 }
 
+/// Returns the client used to download repodata and packages.
+pub(crate) fn download_client() -> miette::Result<ClientWithMiddleware> {
+    let download_client = Client::builder()
+        .no_gzip()
+        .build()
+        .expect("failed to create client");
+
+    Ok(reqwest_middleware::ClientBuilder::new(download_client)
+        .with_arc(Arc::new(
+            AuthenticationMiddleware::from_env_and_defaults().into_diagnostic()?,
+        ))
+        .with(rattler_networking::OciMiddleware)
+        .with(rattler_networking::S3Middleware::new(
+            HashMap::new(),
+            AuthenticationStorage::from_env_and_defaults().into_diagnostic()?,
+        ))
+        .with(rattler_networking::GCSMiddleware)
+        .build())
+}
+
+/// Returns a gateway that caches repodata and packages in `cache_dir`.
+pub(crate) fn gateway(cache_dir: &Path, download_client: ClientWithMiddleware) -> Gateway {
+    Gateway::builder()
+        .with_cache_dir(cache_dir.join(rattler_cache::REPODATA_CACHE_DIR))
+        .with_package_cache(PackageCache::new(
+            cache_dir.join(rattler_cache::PACKAGE_CACHE_DIR),
+        ))
+        .with_client(download_client)
+        .with_channel_config(rattler_repodata_gateway::ChannelConfig {
+            default: SourceConfig {
+                sharded_enabled: true,
+                ..SourceConfig::default()
+            },
+            per_channel: HashMap::new(),
+        })
+        .finish()
+}
+
+/// Parses the virtual packages passed on the command line in the form
+/// `name=version=build` or detects them from the system if none were passed.
+pub(crate) fn detect_virtual_packages(
+    virtual_packages: Option<Vec<String>>,
+) -> miette::Result<Vec<GenericVirtualPackage>> {
+    if let Some(virtual_packages) = virtual_packages {
+        Ok(virtual_packages
+            .iter()
+            .map(|virt_pkg| {
+                let elems = virt_pkg.split('=').collect::<Vec<&str>>();
+                Ok(GenericVirtualPackage {
+                    name: elems[0].try_into().into_diagnostic()?,
+                    version: elems
+                        .get(1)
+                        .map_or(Version::from_str("0"), |s| Version::from_str(s))
+                        .expect("Could not parse virtual package version"),
+                    build_string: (*elems.get(2).unwrap_or(&"")).to_string(),
+                })
+            })
+            .collect::<miette::Result<Vec<_>>>()?)
+    } else {
+        rattler_virtual_packages::VirtualPackage::detect(
+            &rattler_virtual_packages::VirtualPackageOverrides::default(),
+        )
+        .map(|vpkgs| {
+            vpkgs
+                .iter()
+                .map(|vpkg| GenericVirtualPackage::from(vpkg.clone()))
+                .collect::<Vec<_>>()
+        })
+        .into_diagnostic()
+    }
+}
+
 /// Prints the operations of the transaction to the console.
-fn print_transaction(
+pub(crate) fn print_transaction(
     transaction: &Transaction<PrefixRecord, RepoDataRecord>,
     features: HashMap<PackageName, Vec<String>>,
 ) {
@@ -404,7 +420,10 @@ fn print_transaction(
 
 /// Displays a spinner with the given message while running the specified
 /// function to completion.
-fn wrap_in_progress<T, F: FnOnce() -> T>(msg: impl Into<Cow<'static, str>>, func: F) -> T {
+pub(crate) fn wrap_in_progress<T, F: FnOnce() -> T>(
+    msg: impl Into<Cow<'static, str>>,
+    func: F,
+) -> T {
     let pb = ProgressBar::new_spinner();
     pb.enable_steady_tick(Duration::from_millis(100));
     pb.set_style(long_running_progress_style());
@@ -416,7 +435,7 @@ fn wrap_in_progress<T, F: FnOnce() -> T>(msg: impl Into<Cow<'static, str>>, func
 
 /// Displays a spinner with the given message while running the specified
 /// function to completion.
-async fn wrap_in_async_progress<T, F: IntoFuture<Output = T>>(
+pub(crate) async fn wrap_in_async_progress<T, F: IntoFuture<Output = T>>(
     msg: impl Into<Cow<'static, str>>,
     fut: F,
 ) -> T {
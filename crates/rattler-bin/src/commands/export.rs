@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use miette::{Context, IntoDiagnostic};
+use rattler::export::{ExplicitHash, PrefixExport};
+use rattler_conda_types::{ChannelConfig, Platform};
+
+/// The format of the exported environment.
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum Format {
+    /// An explicit environment file with the urls of all packages.
+    #[default]
+    Explicit,
+
+    /// An `environment.yml` with the requested specs.
+    EnvironmentYaml,
+
+    /// A lock-file with a single environment.
+    LockFile,
+}
+
+/// The hash added to the urls of an explicit environment file.
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum Hash {
+    /// Do not add hashes.
+    None,
+
+    /// Add MD5 hashes.
+    #[default]
+    Md5,
+
+    /// Add SHA256 hashes.
+    Sha256,
+}
+
+/// Export the packages installed in a prefix.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The prefix to export, defaults to `.prefix` in the current directory.
+    #[clap(long)]
+    target_prefix: Option<PathBuf>,
+
+    /// The output format.
+    #[clap(long, value_enum, default_value_t)]
+    format: Format,
+
+    /// The hash to add to the urls of an explicit environment file.
+    #[clap(long, value_enum, default_value_t)]
+    hash: Hash,
+
+    /// Pin the requested packages of an `environment.yml` to the installed
+    /// version and build instead of writing the requested specs.
+    #[clap(long)]
+    include_builds: bool,
+
+    /// The name of the environment in an `environment.yml` or lock-file.
+    #[clap(long)]
+    name: Option<String>,
+
+    /// The platform of the prefix, defaults to the current platform.
+    #[clap(long)]
+    platform: Option<Platform>,
+
+    /// Write the export to this path instead of stdout.
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+}
+
+pub fn export(opt: Opt) -> miette::Result<()> {
+    let current_dir = std::env::current_dir().into_diagnostic()?;
+    let target_prefix = opt
+        .target_prefix
+        .unwrap_or_else(|| current_dir.join(".prefix"));
+    let export = PrefixExport::from_prefix(
+        &target_prefix,
+        opt.platform.unwrap_or_else(Platform::current),
+    )
+    .into_diagnostic()
+    .with_context(|| {
+        format!(
+            "failed to read the packages installed in {}",
+            target_prefix.display()
+        )
+    })?;
+
+    let contents = match opt.format {
+        Format::Explicit => export
+            .to_explicit_spec(match opt.hash {
+                Hash::None => ExplicitHash::None,
+                Hash::Md5 => ExplicitHash::Md5,
+                Hash::Sha256 => ExplicitHash::Sha256,
+            })
+            .to_spec_string(),
+        Format::EnvironmentYaml => export
+            .to_environment_yaml(
+                opt.name,
+                opt.include_builds,
+                &ChannelConfig::default_with_root_dir(current_dir),
+            )
+            .to_yaml_string(),
+        Format::LockFile => export
+            .to_lock_file(opt.name.as_deref().unwrap_or("default"))
+            .render_to_string()
+            .into_diagnostic()?,
+    };
+
+    if let Some(output) = opt.output {
+        std::fs::write(&output, contents)
+            .into_diagnostic()
+            .with_context(|| format!("failed to write {}", output.display()))
+    } else {
+        print!("{contents}");
+        Ok(())
+    }
+}
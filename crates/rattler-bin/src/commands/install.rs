@@ -0,0 +1,43 @@
+use miette::IntoDiagnostic;
+use rattler_conda_types::{MatchSpec, ParseStrictness};
+
+use super::solve::{PrefixSolver, SolveOpt};
+
+/// Install packages into an existing prefix.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The specs of the packages to install.
+    #[clap(required = true)]
+    specs: Vec<String>,
+
+    #[clap(flatten)]
+    solve: SolveOpt,
+}
+
+pub async fn install(opt: Opt) -> miette::Result<()> {
+    let specs = opt
+        .specs
+        .iter()
+        .map(|spec| MatchSpec::from_str(spec, ParseStrictness::Strict))
+        .collect::<Result<Vec<_>, _>>()
+        .into_diagnostic()?;
+    let names = specs
+        .iter()
+        .filter_map(|spec| spec.name.clone())
+        .collect::<Vec<_>>();
+
+    // The new specs replace any previously requested spec for the same package.
+    // The installed packages are kept as they are unless they are named
+    // explicitly.
+    let prefix = PrefixSolver::new(opt.solve)?;
+    let specs = prefix
+        .requested_specs()
+        .into_iter()
+        .filter(|spec| spec.name.as_ref().is_none_or(|name| !names.contains(name)))
+        .chain(specs)
+        .collect::<Vec<_>>();
+    let records = prefix
+        .solve(&specs, prefix.installed_except(&names))
+        .await?;
+    prefix.install(specs, records).await
+}
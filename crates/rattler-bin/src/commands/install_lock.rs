@@ -0,0 +1,157 @@
+use std::{collections::HashMap, env, path::PathBuf, time::Instant};
+
+use itertools::{Either, Itertools};
+use miette::{Context, IntoDiagnostic};
+use rattler::install::{IndicatifReporter, Installer};
+use rattler_conda_types::Platform;
+use rattler_lock::{LockFile, LockedPackageRef, DEFAULT_ENVIRONMENT_NAME};
+
+use crate::{
+    commands::create::{download_client, print_transaction},
+    global_multi_progress,
+};
+
+/// Install an environment from a lock-file without solving.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The lock-file to install from.
+    lock_file: PathBuf,
+
+    /// The environment in the lock-file to install.
+    #[clap(long, short, default_value = DEFAULT_ENVIRONMENT_NAME)]
+    environment: String,
+
+    /// The platform to install, defaults to the current platform.
+    #[clap(long)]
+    platform: Option<Platform>,
+
+    /// The prefix to install into, defaults to `.prefix` in the current
+    /// directory.
+    #[clap(long)]
+    target_prefix: Option<PathBuf>,
+
+    /// Only print the operations that would be performed.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+pub async fn install_lock(opt: Opt) -> miette::Result<()> {
+    let current_dir = env::current_dir().into_diagnostic()?;
+    let target_prefix = opt
+        .target_prefix
+        .unwrap_or_else(|| current_dir.join(".prefix"));
+    let target_prefix = std::path::absolute(target_prefix).into_diagnostic()?;
+    let platform = opt.platform.unwrap_or_else(Platform::current);
+
+    let lock_file = LockFile::from_path(&opt.lock_file)
+        .into_diagnostic()
+        .with_context(|| format!("failed to read lock-file {}", opt.lock_file.display()))?;
+    let environment = lock_file.environment(&opt.environment).ok_or_else(|| {
+        miette::miette!(
+            "the lock-file does not contain an environment named '{}', available environments: {}",
+            opt.environment,
+            lock_file.environments().map(|(name, _)| name).join(", ")
+        )
+    })?;
+    let Some(packages) = environment.packages(platform) else {
+        miette::bail!(
+            "the environment '{}' is not locked for {platform}, locked platforms: {}",
+            opt.environment,
+            environment.platforms().join(", ")
+        );
+    };
+
+    // We can only install binary conda packages, refuse to install a partial
+    // environment.
+    let (pypi, source): (Vec<_>, Vec<_>) = packages
+        .filter(|package| package.as_binary_conda().is_none())
+        .partition_map(|package| match package {
+            LockedPackageRef::Pypi(..) => Either::Left(package.name()),
+            LockedPackageRef::Conda(_) => Either::Right(package.name()),
+        });
+    if !pypi.is_empty() {
+        miette::bail!(
+            "the environment contains pypi packages which cannot be installed: {}",
+            pypi.join(", ")
+        );
+    }
+    if !source.is_empty() {
+        miette::bail!(
+            "the environment contains source packages which cannot be installed: {}",
+            source.join(", ")
+        );
+    }
+
+    let records = environment
+        .conda_repodata_records(platform)
+        .into_diagnostic()?
+        .unwrap_or_default();
+
+    // The package cache verifies the sha256 of every downloaded package against
+    // the hash in the record, make sure that every package has one.
+    let missing_hashes = records
+        .iter()
+        .filter(|record| record.package_record.sha256.is_none())
+        .map(|record| record.file_name.as_str())
+        .collect_vec();
+    if !missing_hashes.is_empty() {
+        miette::bail!(
+            "the lock-file does not contain a sha256 hash for: {}",
+            missing_hashes.join(", ")
+        );
+    }
+
+    println!("Target prefix: {}", target_prefix.display());
+    println!(
+        "Installing {} packages of environment '{}' for {platform}",
+        records.len(),
+        opt.environment
+    );
+
+    let download_client = download_client()?;
+
+    let installer = Installer::new()
+        .with_download_client(download_client)
+        .with_target_platform(platform)
+        .with_execute_link_scripts(true)
+        .with_reporter(
+            IndicatifReporter::builder()
+                .with_multi_progress(global_multi_progress())
+                .finish(),
+        );
+
+    if opt.dry_run {
+        let result = installer
+            .dry_run(&target_prefix, records)
+            .await
+            .into_diagnostic()?;
+        if result.transaction.operations.is_empty() {
+            println!("No operations necessary");
+        } else {
+            print_transaction(&result.transaction, HashMap::new());
+        }
+        return Ok(());
+    }
+
+    let install_start = Instant::now();
+    let result = installer
+        .install(&target_prefix, records)
+        .await
+        .into_diagnostic()?;
+
+    if result.transaction.operations.is_empty() {
+        println!(
+            "{} Already up to date",
+            console::style(console::Emoji("✔", "")).green(),
+        );
+    } else {
+        println!(
+            "{} Successfully installed the environment in {:?}",
+            console::style(console::Emoji("✔", "")).green(),
+            install_start.elapsed()
+        );
+        print_transaction(&result.transaction, HashMap::new());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+use std::{env, path::PathBuf};
+
+use itertools::Itertools;
+use miette::{Context, IntoDiagnostic};
+use rattler_conda_types::{ChannelConfig, PackageRecord, PrefixRecord};
+use url::Url;
+
+/// List the packages installed in a prefix.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// Only list packages whose name contains this string.
+    filter: Option<String>,
+
+    /// The prefix to list, defaults to `.prefix` in the current directory.
+    #[clap(long)]
+    target_prefix: Option<PathBuf>,
+
+    /// Print the packages as JSON.
+    #[clap(long)]
+    json: bool,
+}
+
+pub fn list(opt: Opt) -> miette::Result<()> {
+    let current_dir = env::current_dir().into_diagnostic()?;
+    let target_prefix = opt
+        .target_prefix
+        .unwrap_or_else(|| current_dir.join(".prefix"));
+    let channel_config = ChannelConfig::default_with_root_dir(current_dir);
+
+    let records = PrefixRecord::collect_from_prefix::<PrefixRecord>(&target_prefix)
+        .into_diagnostic()
+        .with_context(|| {
+            format!(
+                "failed to read the packages installed in {}",
+                target_prefix.display()
+            )
+        })?
+        .into_iter()
+        .filter(|record| {
+            opt.filter.as_deref().is_none_or(|filter| {
+                record
+                    .repodata_record
+                    .package_record
+                    .name
+                    .as_normalized()
+                    .contains(filter)
+            })
+        })
+        .sorted_by(|a, b| {
+            a.repodata_record
+                .package_record
+                .name
+                .cmp(&b.repodata_record.package_record.name)
+        })
+        .collect_vec();
+
+    if opt.json {
+        let packages = records
+            .iter()
+            .map(|record| {
+                let package_record = &record.repodata_record.package_record;
+                serde_json::json!({
+                    "name": package_record.name.as_normalized(),
+                    "version": package_record.version.to_string(),
+                    "build": package_record.build,
+                    "build_number": package_record.build_number,
+                    "subdir": package_record.subdir,
+                    "channel": record.repodata_record.channel,
+                    "url": record.repodata_record.url,
+                    "requested_specs": record.requested_specs,
+                })
+            })
+            .collect_vec();
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&packages).into_diagnostic()?
+        );
+        return Ok(());
+    }
+
+    if records.is_empty() {
+        println!("No packages installed in {}", target_prefix.display());
+        return Ok(());
+    }
+
+    let rows = records
+        .iter()
+        .map(|record| {
+            let channel = record
+                .repodata_record
+                .channel
+                .as_deref()
+                .map(|channel| channel_name(channel, &channel_config))
+                .unwrap_or_default();
+            package_row(&record.repodata_record.package_record, channel)
+        })
+        .collect_vec();
+    print_table(&["Name", "Version", "Build", "Channel"], &rows);
+
+    Ok(())
+}
+
+/// Returns the columns that describe a package in a table.
+pub(crate) fn package_row(record: &PackageRecord, channel: String) -> Vec<String> {
+    vec![
+        record.name.as_normalized().to_string(),
+        record.version.to_string(),
+        record.build.clone(),
+        channel,
+    ]
+}
+
+/// Returns the name of a channel if it is hosted at the channel alias,
+/// otherwise the channel itself.
+pub(crate) fn channel_name(channel: &str, channel_config: &ChannelConfig) -> String {
+    Url::parse(channel)
+        .ok()
+        .and_then(|url| channel_config.strip_channel_alias(&url))
+        .unwrap_or_else(|| channel.to_string())
+}
+
+/// Prints rows of columns aligned below a header.
+pub(crate) fn print_table(header: &[&str], rows: &[Vec<String>]) {
+    let widths = header
+        .iter()
+        .enumerate()
+        .map(|(idx, title)| {
+            rows.iter()
+                .map(|row| row[idx].len())
+                .chain([title.len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect_vec();
+    let print_row = |row: &mut dyn Iterator<Item = &str>| {
+        let line = row
+            .zip(&widths)
+            .map(|(column, width)| format!("{column:width$}"))
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(&mut header.iter().copied());
+    for row in rows {
+        print_row(&mut row.iter().map(String::as_str));
+    }
+}
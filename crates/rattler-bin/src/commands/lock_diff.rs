@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+use miette::{Context, IntoDiagnostic};
+use rattler_lock::{LockFile, LockFileDiff};
+
+/// The format in which the difference is printed.
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum Format {
+    /// A plain text table.
+    #[default]
+    Table,
+
+    /// Markdown, e.g. to post as a comment on a pull request.
+    Markdown,
+
+    /// JSON for further processing.
+    Json,
+}
+
+/// Show the packages that changed between two lock-files.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The original lock-file.
+    old: PathBuf,
+
+    /// The updated lock-file.
+    new: PathBuf,
+
+    /// The output format.
+    #[clap(long, value_enum, default_value_t)]
+    format: Format,
+}
+
+pub fn lock_diff(opt: Opt) -> miette::Result<()> {
+    let read = |path: &PathBuf| {
+        LockFile::from_path(path)
+            .into_diagnostic()
+            .with_context(|| format!("failed to read lock-file {}", path.display()))
+    };
+    let diff = LockFileDiff::from_lock_files(&read(&opt.old)?, &read(&opt.new)?);
+
+    match opt.format {
+        Format::Table if diff.is_empty() => println!("The lock-files are identical."),
+        Format::Table => print!("{}", diff.to_table()),
+        Format::Markdown => print!("{}", diff.to_markdown()),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&diff).into_diagnostic()?),
+    }
+
+    Ok(())
+}
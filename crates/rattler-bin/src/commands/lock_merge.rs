@@ -0,0 +1,65 @@
+use std::path::PathBuf;
+
+use miette::{Context, IntoDiagnostic};
+use rattler_lock::{LockFile, LockFileMerge};
+
+/// Three-way merge of lock-files.
+///
+/// This command can be used as a git merge driver by adding the following to
+/// the git configuration:
+///
+/// ```text
+/// [merge "rattler-lock"]
+///     name = rattler lock-file merge driver
+///     driver = rattler lock-merge %O %A %B
+/// ```
+///
+/// and assigning the driver to the lock-files in `.gitattributes`, e.g.
+/// `pixi.lock merge=rattler-lock`.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The lock-file of the common ancestor.
+    base: PathBuf,
+
+    /// Our version of the lock-file. The merged lock-file is written to this
+    /// file unless `--output` is specified.
+    ours: PathBuf,
+
+    /// Their version of the lock-file.
+    theirs: PathBuf,
+
+    /// Write the merged lock-file to this path instead.
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+}
+
+pub fn lock_merge(opt: Opt) -> miette::Result<()> {
+    let read = |path: &PathBuf| {
+        LockFile::from_path(path)
+            .into_diagnostic()
+            .with_context(|| format!("failed to read lock-file {}", path.display()))
+    };
+    let merge =
+        LockFileMerge::from_lock_files(&read(&opt.base)?, &read(&opt.ours)?, &read(&opt.theirs)?);
+
+    // Always write the merged lock-file, conflicting parts are taken from our
+    // side so the result is a valid lock-file.
+    let output = opt.output.as_ref().unwrap_or(&opt.ours);
+    merge
+        .lock_file
+        .to_path(output)
+        .into_diagnostic()
+        .with_context(|| format!("failed to write lock-file {}", output.display()))?;
+
+    if merge.is_clean() {
+        return Ok(());
+    }
+
+    for conflict in &merge.conflicts {
+        eprintln!("{} {conflict}", console::style("conflict:").red().bold());
+    }
+    Err(miette::miette!(
+        "failed to merge lock-files, {} conflict(s) were resolved using our side",
+        merge.conflicts.len()
+    ))
+}
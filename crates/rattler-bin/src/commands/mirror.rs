@@ -0,0 +1,125 @@
+use std::{collections::HashMap, env, path::PathBuf, str::FromStr, sync::Arc};
+
+use miette::{Context, IntoDiagnostic};
+use rattler::default_cache_dir;
+use rattler_conda_types::{Channel, ChannelConfig, MatchSpec, ParseStrictness, Platform};
+use rattler_networking::{AuthenticationMiddleware, AuthenticationStorage};
+use rattler_repodata_gateway::{mirror::ChannelMirror, Gateway};
+use reqwest::Client;
+
+use super::create::wrap_in_async_progress;
+
+/// Mirror the packages required by a set of specs into a local channel.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The channels to mirror packages from, in order of priority.
+    #[clap(short, required = true)]
+    channels: Vec<String>,
+
+    /// The specs of the packages to mirror, including their dependencies.
+    #[clap(required = true)]
+    specs: Vec<String>,
+
+    /// The platforms to mirror. `noarch` is always included.
+    #[clap(long = "platform")]
+    platforms: Vec<String>,
+
+    /// The directory to write the mirror to.
+    #[clap(long, short)]
+    output: PathBuf,
+
+    /// Remove packages from the mirror that are no longer required.
+    #[clap(long)]
+    prune: bool,
+
+    /// Also write sharded repodata.
+    #[clap(long)]
+    write_shards: bool,
+
+    /// The maximum number of concurrent downloads.
+    #[clap(long, default_value = "50")]
+    max_concurrent_downloads: usize,
+}
+
+pub async fn mirror(opt: Opt) -> miette::Result<()> {
+    let channel_config =
+        ChannelConfig::default_with_root_dir(env::current_dir().into_diagnostic()?);
+
+    let channels = opt
+        .channels
+        .into_iter()
+        .map(|channel_str| Channel::from_str(channel_str, &channel_config))
+        .collect::<Result<Vec<_>, _>>()
+        .into_diagnostic()?;
+
+    let specs = opt
+        .specs
+        .iter()
+        .map(|spec| MatchSpec::from_str(spec, ParseStrictness::Strict))
+        .collect::<Result<Vec<_>, _>>()
+        .into_diagnostic()?;
+
+    let mut platforms = opt
+        .platforms
+        .iter()
+        .map(|platform| Platform::from_str(platform))
+        .collect::<Result<Vec<_>, _>>()
+        .into_diagnostic()?;
+    if platforms.is_empty() {
+        platforms.push(Platform::current());
+    }
+    if !platforms.contains(&Platform::NoArch) {
+        platforms.push(Platform::NoArch);
+    }
+
+    let cache_dir = default_cache_dir()
+        .map_err(|e| miette::miette!("could not determine default cache directory: {}", e))?;
+
+    let download_client = Client::builder()
+        .no_gzip()
+        .build()
+        .expect("failed to create client");
+    let download_client = reqwest_middleware::ClientBuilder::new(download_client)
+        .with_arc(Arc::new(
+            AuthenticationMiddleware::from_env_and_defaults().into_diagnostic()?,
+        ))
+        .with(rattler_networking::S3Middleware::new(
+            HashMap::new(),
+            AuthenticationStorage::from_env_and_defaults().into_diagnostic()?,
+        ))
+        .with(rattler_networking::GCSMiddleware)
+        .build();
+
+    let gateway = Gateway::builder()
+        .with_cache_dir(cache_dir.join(rattler_cache::REPODATA_CACHE_DIR))
+        .with_client(download_client.clone())
+        .finish();
+
+    let summary = wrap_in_async_progress(
+        format!("mirroring into {}", opt.output.display()),
+        ChannelMirror::new(gateway, &opt.output)
+            .with_client(download_client)
+            .with_prune(opt.prune)
+            .with_shards(opt.write_shards)
+            .with_max_concurrent_downloads(opt.max_concurrent_downloads)
+            .sync(channels, platforms, specs),
+    )
+    .await
+    .into_diagnostic()
+    .context("failed to mirror packages")?;
+
+    for path in &summary.downloaded {
+        println!("+ {path}");
+    }
+    for path in &summary.removed {
+        println!("- {path}");
+    }
+    println!(
+        "Downloaded {} packages, {} up to date, removed {}",
+        summary.downloaded.len(),
+        summary.up_to_date.len(),
+        summary.removed.len()
+    );
+
+    Ok(())
+}
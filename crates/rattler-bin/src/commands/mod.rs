@@ -1,4 +1,16 @@
 pub mod auth;
 pub mod create;
+pub mod export;
+pub mod install;
+pub mod install_lock;
+pub mod list;
+pub mod lock_diff;
+pub mod lock_merge;
 pub mod menu;
+pub mod mirror;
+pub mod remove;
+pub mod run;
+pub mod search;
+pub mod solve;
+pub mod update;
 pub mod virtual_packages;
@@ -0,0 +1,54 @@
+use itertools::Itertools;
+use miette::IntoDiagnostic;
+use rattler_conda_types::PackageName;
+
+use super::solve::{PrefixSolver, SolveOpt};
+
+/// Remove packages from an existing prefix.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The names of the packages to remove.
+    #[clap(required = true)]
+    names: Vec<String>,
+
+    #[clap(flatten)]
+    solve: SolveOpt,
+}
+
+pub async fn remove(opt: Opt) -> miette::Result<()> {
+    let names = opt
+        .names
+        .iter()
+        .map(|name| PackageName::try_from(name.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+        .into_diagnostic()?;
+
+    let prefix = PrefixSolver::new(opt.solve)?;
+    prefix.ensure_installed(&names)?;
+
+    // Solve for the remaining requested specs. Dependencies that are no longer
+    // needed are removed as well.
+    let specs = prefix
+        .requested_specs()
+        .into_iter()
+        .filter(|spec| spec.name.as_ref().is_none_or(|name| !names.contains(name)))
+        .collect::<Vec<_>>();
+    let records = prefix
+        .solve(&specs, prefix.installed_except(&names))
+        .await?;
+
+    let required = records
+        .iter()
+        .map(|record| &record.package_record.name)
+        .filter(|name| names.contains(name))
+        .map(PackageName::as_source)
+        .collect_vec();
+    if !required.is_empty() {
+        miette::bail!(
+            "cannot remove {} because other installed packages depend on it",
+            required.join(", ")
+        );
+    }
+
+    prefix.install(specs, records).await
+}
@@ -0,0 +1,67 @@
+use std::{env, path::PathBuf, process::Command};
+
+use miette::{Context, IntoDiagnostic};
+use rattler_conda_types::Platform;
+use rattler_shell::{
+    activation::{ActivationVariables, Activator},
+    shell::ShellEnum,
+};
+
+/// Run a command in an activated prefix.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The prefix to activate, defaults to `.prefix` in the current directory.
+    #[clap(long)]
+    target_prefix: Option<PathBuf>,
+
+    /// The command to run and its arguments.
+    #[clap(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    command: Vec<String>,
+}
+
+pub fn run(opt: Opt) -> miette::Result<()> {
+    let current_dir = env::current_dir().into_diagnostic()?;
+    let target_prefix = opt
+        .target_prefix
+        .unwrap_or_else(|| current_dir.join(".prefix"));
+    let target_prefix = std::path::absolute(target_prefix).into_diagnostic()?;
+    if !target_prefix.join("conda-meta").is_dir() {
+        miette::bail!("{} is not a prefix", target_prefix.display());
+    }
+
+    // Run the activation scripts of the prefix and apply the resulting
+    // environment variables to the command.
+    let activator = Activator::from_path(&target_prefix, ShellEnum::default(), Platform::current())
+        .into_diagnostic()?;
+    let variables = activator
+        .run_activation(ActivationVariables::from_env().unwrap_or_default(), None)
+        .into_diagnostic()
+        .context("failed to activate the prefix")?;
+
+    let (program, args) = opt
+        .command
+        .split_first()
+        .expect("clap requires at least one argument");
+    let mut command = Command::new(program);
+    command.args(args).envs(variables);
+
+    // Replace the current process so that signals reach the command directly
+    // and its exit status is passed on unchanged.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        let err = command.exec();
+        Err(err)
+            .into_diagnostic()
+            .with_context(|| format!("failed to run '{program}'"))
+    }
+
+    #[cfg(not(unix))]
+    {
+        let status = command
+            .status()
+            .into_diagnostic()
+            .with_context(|| format!("failed to run '{program}'"))?;
+        std::process::exit(status.code().unwrap_or(1));
+    }
+}
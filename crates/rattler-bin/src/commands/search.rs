@@ -0,0 +1,91 @@
+use std::env;
+
+use itertools::Itertools;
+use miette::{Context, IntoDiagnostic};
+use rattler::default_cache_dir;
+use rattler_conda_types::{Channel, ChannelConfig, MatchSpec, Matches, ParseStrictness, Platform};
+
+use super::{
+    create::{download_client, gateway, wrap_in_async_progress},
+    list::{channel_name, package_row, print_table},
+};
+
+/// Search for packages in channels.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The spec of the packages to search for, e.g. `python >=3.12`.
+    spec: String,
+
+    /// The channels to search, defaults to conda-forge.
+    #[clap(short)]
+    channels: Option<Vec<String>>,
+
+    /// The platform to search packages for, defaults to the current platform.
+    #[clap(long)]
+    platform: Option<Platform>,
+}
+
+pub async fn search(opt: Opt) -> miette::Result<()> {
+    let channel_config =
+        ChannelConfig::default_with_root_dir(env::current_dir().into_diagnostic()?);
+    let spec = MatchSpec::from_str(&opt.spec, ParseStrictness::Lenient).into_diagnostic()?;
+    if spec.name.is_none() {
+        miette::bail!("the spec '{}' does not contain a package name", opt.spec);
+    }
+    let channels = opt
+        .channels
+        .unwrap_or_else(|| vec![String::from("conda-forge")])
+        .into_iter()
+        .map(|channel| Channel::from_str(channel, &channel_config))
+        .collect::<Result<Vec<_>, _>>()
+        .into_diagnostic()?;
+    let platform = opt.platform.unwrap_or_else(Platform::current);
+
+    let cache_dir = default_cache_dir()
+        .map_err(|e| miette::miette!("could not determine default cache directory: {}", e))?;
+    let gateway = gateway(&cache_dir, download_client()?);
+    let repo_data = wrap_in_async_progress(
+        "loading repodata",
+        gateway.query(channels, [platform, Platform::NoArch], [spec.clone()]),
+    )
+    .await
+    .into_diagnostic()
+    .context("failed to load repodata")?;
+
+    let records = repo_data
+        .iter()
+        .flat_map(|repo_data| repo_data.iter())
+        .filter(|record| spec.matches(*record))
+        .sorted_by(|a, b| {
+            a.package_record
+                .name
+                .cmp(&b.package_record.name)
+                .then_with(|| a.package_record.version.cmp(&b.package_record.version))
+                .then_with(|| {
+                    a.package_record
+                        .build_number
+                        .cmp(&b.package_record.build_number)
+                })
+        })
+        .collect_vec();
+    if records.is_empty() {
+        miette::bail!("no packages found matching '{}'", opt.spec);
+    }
+
+    let rows = records
+        .iter()
+        .map(|record| {
+            let channel = record
+                .channel
+                .as_deref()
+                .map(|channel| channel_name(channel, &channel_config))
+                .unwrap_or_default();
+            let mut row = package_row(&record.package_record, channel);
+            row.push(record.package_record.subdir.clone());
+            row
+        })
+        .collect_vec();
+    print_table(&["Name", "Version", "Build", "Channel", "Subdir"], &rows);
+
+    Ok(())
+}
@@ -0,0 +1,420 @@
+//! Shared functionality of the commands that modify an existing prefix.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    path::PathBuf,
+    time::Duration,
+};
+
+use itertools::Itertools;
+use miette::{Context, IntoDiagnostic};
+use rattler::{
+    default_cache_dir,
+    install::{IndicatifReporter, Installer},
+};
+use rattler_conda_types::{
+    Channel, ChannelConfig, MatchSpec, PackageName, ParseStrictness, Platform, PrefixRecord,
+    RepoDataRecord,
+};
+use rattler_solve::{libsolv_c, resolvo, SolverImpl, SolverTask};
+use reqwest_middleware::ClientWithMiddleware;
+
+use crate::{
+    commands::create::{
+        detect_virtual_packages, download_client, gateway, print_transaction,
+        wrap_in_async_progress, wrap_in_progress, SolveStrategy, Solver,
+    },
+    global_multi_progress,
+};
+
+/// Options shared by the commands that solve for and modify an existing
+/// prefix.
+#[derive(Debug, clap::Args)]
+pub struct SolveOpt {
+    /// The channels to solve against, defaults to the channels of the
+    /// installed packages.
+    #[clap(short)]
+    channels: Option<Vec<String>>,
+
+    /// The prefix to modify, defaults to `.prefix` in the current directory.
+    #[clap(long)]
+    target_prefix: Option<PathBuf>,
+
+    /// The platform of the prefix, defaults to the current platform.
+    #[clap(long)]
+    platform: Option<Platform>,
+
+    #[clap(long)]
+    virtual_package: Option<Vec<String>>,
+
+    #[clap(long)]
+    solver: Option<Solver>,
+
+    #[clap(long)]
+    timeout: Option<u64>,
+
+    #[clap(long)]
+    strategy: Option<SolveStrategy>,
+
+    /// Only print the operations that would be performed.
+    #[clap(long)]
+    dry_run: bool,
+}
+
+/// A prefix and the packages that are installed in it.
+pub struct PrefixSolver {
+    opt: SolveOpt,
+    target_prefix: PathBuf,
+    platform: Platform,
+    installed: Vec<PrefixRecord>,
+    download_client: ClientWithMiddleware,
+}
+
+impl PrefixSolver {
+    /// Reads the packages installed in the target prefix of `opt`.
+    pub fn new(opt: SolveOpt) -> miette::Result<Self> {
+        let current_dir = env::current_dir().into_diagnostic()?;
+        let target_prefix = opt
+            .target_prefix
+            .clone()
+            .unwrap_or_else(|| current_dir.join(".prefix"));
+        let target_prefix = std::path::absolute(target_prefix).into_diagnostic()?;
+        let installed = PrefixRecord::collect_from_prefix::<PrefixRecord>(&target_prefix)
+            .into_diagnostic()
+            .with_context(|| {
+                format!(
+                    "failed to read the packages installed in {}",
+                    target_prefix.display()
+                )
+            })?;
+
+        Ok(Self {
+            platform: opt.platform.unwrap_or_else(Platform::current),
+            download_client: download_client()?,
+            opt,
+            target_prefix,
+            installed,
+        })
+    }
+
+    /// Returns the records of the installed packages, except for the packages
+    /// with the given names.
+    pub fn installed_except(&self, names: &[PackageName]) -> Vec<RepoDataRecord> {
+        self.installed
+            .iter()
+            .filter(|record| !names.contains(&record.repodata_record.package_record.name))
+            .map(|record| record.repodata_record.clone())
+            .collect()
+    }
+
+    /// Returns an error if any of the given packages is not installed.
+    pub fn ensure_installed(&self, names: &[PackageName]) -> miette::Result<()> {
+        let missing = names
+            .iter()
+            .filter(|name| {
+                !self
+                    .installed
+                    .iter()
+                    .any(|record| &record.repodata_record.package_record.name == *name)
+            })
+            .map(PackageName::as_source)
+            .collect_vec();
+        if !missing.is_empty() {
+            miette::bail!(
+                "the following packages are not installed in {}: {}",
+                self.target_prefix.display(),
+                missing.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the specs that were requested by the user when the installed
+    /// packages were installed.
+    ///
+    /// Prefixes that were not created from specs (e.g. from a lock-file) do
+    /// not record any, in that case every installed package that is not a
+    /// dependency of another installed package is considered to be requested
+    /// so that solving does not remove packages.
+    pub fn requested_specs(&self) -> Vec<MatchSpec> {
+        #[allow(deprecated)]
+        let requested = self
+            .installed
+            .iter()
+            .flat_map(|record| {
+                record
+                    .requested_specs
+                    .iter()
+                    .chain(record.requested_spec.iter())
+            })
+            .filter_map(
+                |spec| match MatchSpec::from_str(spec, ParseStrictness::Lenient) {
+                    Ok(spec) => Some(spec),
+                    Err(err) => {
+                        tracing::warn!("ignoring invalid requested spec '{spec}': {err}");
+                        None
+                    }
+                },
+            )
+            .collect_vec();
+        if !requested.is_empty() {
+            return requested;
+        }
+
+        // Start from the packages that nothing depends on and add packages that
+        // are not reachable from them, e.g. because they form a cycle.
+        let dependencies = |name: &PackageName| {
+            self.installed
+                .iter()
+                .find(|record| &record.repodata_record.package_record.name == name)
+                .into_iter()
+                .flat_map(|record| &record.repodata_record.package_record.depends)
+                .filter_map(|dependency| dependency.split_whitespace().next())
+                .filter_map(|dependency| PackageName::try_from(dependency).ok())
+                .collect_vec()
+        };
+        let names = self
+            .installed
+            .iter()
+            .map(|record| record.repodata_record.package_record.name.clone())
+            .collect_vec();
+        let all_dependencies = names.iter().flat_map(dependencies).collect::<HashSet<_>>();
+        let mut requested = Vec::new();
+        let mut reachable = HashSet::new();
+        let leaves = names
+            .iter()
+            .filter(|name| !all_dependencies.contains(*name))
+            .chain(&names);
+        for name in leaves {
+            if reachable.contains(name) {
+                continue;
+            }
+            requested.push(MatchSpec::from(name.clone()));
+            let mut queue = vec![name.clone()];
+            while let Some(name) = queue.pop() {
+                if reachable.insert(name.clone()) {
+                    queue.extend(dependencies(&name));
+                }
+            }
+        }
+        requested
+    }
+
+    /// Returns the channels to solve against.
+    fn channels(&self) -> miette::Result<Vec<Channel>> {
+        let channel_config =
+            ChannelConfig::default_with_root_dir(env::current_dir().into_diagnostic()?);
+        let channels = if let Some(channels) = &self.opt.channels {
+            channels.clone()
+        } else {
+            let channels = self
+                .installed
+                .iter()
+                .filter_map(|record| record.repodata_record.channel.clone())
+                .unique()
+                .collect_vec();
+            if channels.is_empty() {
+                vec![String::from("conda-forge")]
+            } else {
+                channels
+            }
+        };
+        channels
+            .into_iter()
+            .map(|channel| Channel::from_str(channel, &channel_config))
+            .collect::<Result<Vec<_>, _>>()
+            .into_diagnostic()
+    }
+
+    /// Solves for the given specs, preferring the versions of the `locked`
+    /// packages.
+    pub async fn solve(
+        &self,
+        specs: &[MatchSpec],
+        locked: Vec<RepoDataRecord>,
+    ) -> miette::Result<Vec<RepoDataRecord>> {
+        if specs.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let cache_dir = default_cache_dir()
+            .map_err(|e| miette::miette!("could not determine default cache directory: {}", e))?;
+        let gateway = gateway(&cache_dir, self.download_client.clone());
+        let repo_data = wrap_in_async_progress(
+            "loading repodata",
+            gateway
+                .query(
+                    self.channels()?,
+                    [self.platform, Platform::NoArch],
+                    specs.to_vec(),
+                )
+                .recursive(true),
+        )
+        .await
+        .into_diagnostic()
+        .context("failed to load repodata")?;
+
+        let virtual_packages = wrap_in_progress("determining virtual packages", || {
+            detect_virtual_packages(self.opt.virtual_package.clone())
+        })?;
+
+        let solver_task = SolverTask {
+            locked_packages: locked,
+            virtual_packages,
+            specs: specs.to_vec(),
+            timeout: self.opt.timeout.map(Duration::from_millis),
+            strategy: self.opt.strategy.map_or_else(Default::default, Into::into),
+            ..SolverTask::from_iter(&repo_data)
+        };
+        wrap_in_progress("solving", move || {
+            match self.opt.solver.unwrap_or_default() {
+                Solver::Resolvo => resolvo::Solver.solve(solver_task),
+                Solver::LibSolv => libsolv_c::Solver.solve(solver_task),
+            }
+        })
+        .map(|result| result.records)
+        .into_diagnostic()
+    }
+
+    /// Updates the prefix to contain exactly the given records and records
+    /// `specs` as the requested specs.
+    pub async fn install(
+        self,
+        specs: Vec<MatchSpec>,
+        records: Vec<RepoDataRecord>,
+    ) -> miette::Result<()> {
+        // The installer reads the installed packages from the prefix itself so
+        // that it sees the state of the prefix once it holds the prefix lock.
+        let installer = Installer::new()
+            .with_download_client(self.download_client)
+            .with_target_platform(self.platform)
+            .with_execute_link_scripts(true)
+            .with_requested_specs(specs)
+            .with_reporter(
+                IndicatifReporter::builder()
+                    .with_multi_progress(global_multi_progress())
+                    .finish(),
+            );
+
+        if self.opt.dry_run {
+            let result = installer
+                .dry_run(&self.target_prefix, records)
+                .await
+                .into_diagnostic()?;
+            if result.transaction.operations.is_empty() {
+                println!("No operations necessary");
+            } else {
+                print_transaction(&result.transaction, HashMap::new());
+            }
+            return Ok(());
+        }
+
+        let result = installer
+            .install(&self.target_prefix, records)
+            .await
+            .into_diagnostic()?;
+
+        if result.transaction.operations.is_empty() {
+            println!(
+                "{} Already up to date",
+                console::style(console::Emoji("✔", "")).green(),
+            );
+        } else {
+            println!(
+                "{} Successfully updated {}",
+                console::style(console::Emoji("✔", "")).green(),
+                self.target_prefix.display()
+            );
+            print_transaction(&result.transaction, HashMap::new());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rattler_conda_types::{PackageRecord, Version};
+
+    use super::*;
+
+    fn prefix_record(name: &str, depends: &[&str], requested_specs: &[&str]) -> PrefixRecord {
+        let mut package_record = PackageRecord::new(
+            PackageName::new_unchecked(name),
+            "1.0".parse::<Version>().unwrap(),
+            String::from("0"),
+        );
+        package_record.depends = depends.iter().map(ToString::to_string).collect();
+        let mut record = PrefixRecord::from_repodata_record(
+            RepoDataRecord {
+                package_record,
+                file_name: format!("{name}-1.0-0.conda"),
+                url: format!("https://conda.anaconda.org/conda-forge/noarch/{name}-1.0-0.conda")
+                    .parse()
+                    .unwrap(),
+                channel: Some(String::from("https://conda.anaconda.org/conda-forge/")),
+            },
+            Vec::new(),
+        );
+        record.requested_specs = requested_specs.iter().map(ToString::to_string).collect();
+        record
+    }
+
+    fn prefix_solver(installed: Vec<PrefixRecord>) -> PrefixSolver {
+        PrefixSolver {
+            opt: SolveOpt {
+                channels: None,
+                target_prefix: None,
+                platform: None,
+                virtual_package: None,
+                solver: None,
+                timeout: None,
+                strategy: None,
+                dry_run: false,
+            },
+            target_prefix: PathBuf::from(".prefix"),
+            platform: Platform::current(),
+            installed,
+            download_client: reqwest::Client::new().into(),
+        }
+    }
+
+    fn spec_strings(specs: Vec<MatchSpec>) -> Vec<String> {
+        specs.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn test_requested_specs() {
+        let solver = prefix_solver(vec![
+            prefix_record("python", &[], &["python >=3.12"]),
+            prefix_record("numpy", &["python"], &["numpy", "numpy >=>1"]),
+            prefix_record("libzlib", &[], &[]),
+        ]);
+        assert_eq!(
+            spec_strings(solver.requested_specs()),
+            ["python >=3.12", "numpy"]
+        );
+    }
+
+    #[test]
+    fn test_requested_specs_fallback() {
+        // Without recorded specs the packages that nothing depends on are
+        // requested, plus one package of every cycle that is not reachable
+        // from them.
+        let solver = prefix_solver(vec![
+            prefix_record("numpy", &["python >=3.12", "libzlib"], &[]),
+            prefix_record("python", &["libzlib 1.*"], &[]),
+            prefix_record("libzlib", &[], &[]),
+            prefix_record("ca-certificates", &[], &[]),
+            prefix_record("cycle-a", &["cycle-b"], &[]),
+            prefix_record("cycle-b", &["cycle-a"], &[]),
+        ]);
+        assert_eq!(
+            spec_strings(solver.requested_specs()),
+            ["numpy", "ca-certificates", "cycle-a"]
+        );
+
+        assert!(prefix_solver(Vec::new()).requested_specs().is_empty());
+    }
+}
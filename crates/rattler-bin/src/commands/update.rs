@@ -0,0 +1,39 @@
+use miette::IntoDiagnostic;
+use rattler_conda_types::PackageName;
+
+use super::solve::{PrefixSolver, SolveOpt};
+
+/// Update the packages in an existing prefix to the latest versions allowed by
+/// the requested specs.
+#[derive(Debug, clap::Parser)]
+pub struct Opt {
+    /// The names of the packages to update, updates all packages if none are
+    /// given.
+    names: Vec<String>,
+
+    #[clap(flatten)]
+    solve: SolveOpt,
+}
+
+pub async fn update(opt: Opt) -> miette::Result<()> {
+    let names = opt
+        .names
+        .iter()
+        .map(|name| PackageName::try_from(name.as_str()))
+        .collect::<Result<Vec<_>, _>>()
+        .into_diagnostic()?;
+
+    let prefix = PrefixSolver::new(opt.solve)?;
+    prefix.ensure_installed(&names)?;
+
+    // Only the packages that are not updated are preferred at their installed
+    // version.
+    let locked = if names.is_empty() {
+        Vec::new()
+    } else {
+        prefix.installed_except(&names)
+    };
+    let specs = prefix.requested_specs();
+    let records = prefix.solve(&specs, locked).await?;
+    prefix.install(specs, records).await
+}
@@ -42,9 +42,20 @@ struct Opt {
 enum Command {
     Auth(commands::auth::Opt),
     Create(commands::create::Opt),
+    Install(commands::install::Opt),
+    Remove(commands::remove::Opt),
+    Update(commands::update::Opt),
+    List(commands::list::Opt),
+    Search(commands::search::Opt),
+    Run(commands::run::Opt),
+    Export(commands::export::Opt),
+    InstallLock(commands::install_lock::Opt),
     VirtualPackages(commands::virtual_packages::Opt),
     InstallMenu(commands::menu::InstallOpt),
     RemoveMenu(commands::menu::InstallOpt),
+    Mirror(commands::mirror::Opt),
+    LockDiff(commands::lock_diff::Opt),
+    LockMerge(commands::lock_merge::Opt),
     Upload(Box<rattler_upload::upload::opt::UploadOpts>),
 }
 
@@ -81,9 +92,20 @@ async fn main() -> miette::Result<()> {
     match opt.command {
         Command::Auth(opts) => commands::auth::auth(opts).await,
         Command::Create(opts) => commands::create::create(opts).await,
+        Command::Install(opts) => commands::install::install(opts).await,
+        Command::Remove(opts) => commands::remove::remove(opts).await,
+        Command::Update(opts) => commands::update::update(opts).await,
+        Command::List(opts) => commands::list::list(opts),
+        Command::Search(opts) => commands::search::search(opts).await,
+        Command::Run(opts) => commands::run::run(opts),
+        Command::Export(opts) => commands::export::export(opts),
+        Command::InstallLock(opts) => commands::install_lock::install_lock(opts).await,
         Command::VirtualPackages(opts) => commands::virtual_packages::virtual_packages(opts),
         Command::InstallMenu(opts) => commands::menu::install_menu(opts).await,
         Command::RemoveMenu(opts) => commands::menu::remove_menu(opts).await,
+        Command::Mirror(opts) => commands::mirror::mirror(opts).await,
+        Command::LockDiff(opts) => commands::lock_diff::lock_diff(opts),
+        Command::LockMerge(opts) => commands::lock_merge::lock_merge(opts),
         Command::Upload(opts) => rattler_upload::upload_from_args(*opts).await,
     }
 }
@@ -1,7 +1,9 @@
 //! This module contains CLI common entrypoint for authentication.
 use clap::Parser;
 use rattler_networking::{
-    authentication_storage::AuthenticationStorageError, Authentication, AuthenticationStorage,
+    authentication_storage::AuthenticationStorageError,
+    oauth::{DeviceAuthorization, DevicePoll, OAuthError, OidcConfiguration},
+    Authentication, AuthenticationStorage,
 };
 use reqwest::{
     header::{AUTHORIZATION, CONTENT_TYPE},
@@ -44,6 +46,23 @@ struct LoginArgs {
     /// The S3 session token
     #[clap(long, requires_all = ["s3_access_key_id"])]
     s3_session_token: Option<String>,
+
+    /// Log in through the device authorization flow of an OIDC identity
+    /// provider, the access token is refreshed automatically
+    #[clap(long, conflicts_with_all = ["token", "username", "password", "conda_token", "s3_access_key_id"])]
+    oidc: bool,
+
+    /// The issuer of the OIDC identity provider, defaults to the host
+    #[clap(long, requires = "oidc")]
+    oidc_issuer: Option<Url>,
+
+    /// The client ID to request OIDC tokens for
+    #[clap(long, default_value = "rattler")]
+    oidc_client_id: String,
+
+    /// The scopes to request OIDC tokens for
+    #[clap(long, default_values = ["openid", "offline_access"])]
+    oidc_scope: Vec<String>,
 }
 
 #[derive(Parser, Debug)]
@@ -55,7 +74,7 @@ struct LogoutArgs {
 #[derive(Parser, Debug)]
 enum Subcommand {
     /// Store authentication information for a given host
-    Login(LoginArgs),
+    Login(Box<LoginArgs>),
     /// Remove authentication information for a given host
     Logout(LogoutArgs),
 }
@@ -117,14 +136,29 @@ pub enum AuthenticationCLIError {
     /// Token is unauthorized or invalid
     #[error("Unauthorized or invalid token")]
     UnauthorizedToken,
+
+    /// The OIDC device authorization flow failed
+    #[error("Failed to log in with OIDC")]
+    OAuthError(#[from] OAuthError),
 }
 
 fn get_url(url: &str) -> Result<String, AuthenticationCLIError> {
-    // parse as url and extract host without scheme or port
-    let host = if url.contains("://") {
-        url::Url::parse(url)?.host_str().unwrap().to_string()
+    // parse as url and extract host and path without scheme or port
+    let (host, path) = if url.contains("://") {
+        let url = url::Url::parse(url)?;
+        let host = url.host_str().unwrap().to_string();
+        // S3 credentials are looked up by bucket
+        let path = if url.scheme() == "s3" {
+            String::new()
+        } else {
+            url.path().trim_matches('/').to_string()
+        };
+        (host, path)
     } else {
-        url.to_string()
+        match url.split_once('/') {
+            Some((host, path)) => (host.to_string(), path.trim_matches('/').to_string()),
+            None => (url.to_string(), String::new()),
+        }
     };
 
     let host = if host.matches('.').count() == 1 {
@@ -134,7 +168,12 @@ fn get_url(url: &str) -> Result<String, AuthenticationCLIError> {
         host
     };
 
-    Ok(host)
+    // credentials can be scoped to a path prefix, e.g. a single channel
+    if path.is_empty() {
+        Ok(host)
+    } else {
+        Ok(format!("{host}/{path}"))
+    }
 }
 
 /// Result of prefix.dev token validation
@@ -155,6 +194,14 @@ async fn login(
     args: LoginArgs,
     storage: AuthenticationStorage,
 ) -> Result<(), AuthenticationCLIError> {
+    if args.oidc {
+        let host = get_url(&args.host)?;
+        let auth = login_oidc(&args).await?;
+        eprintln!("Authenticating with {host} using {} method", auth.method());
+        storage.store(&host, &auth)?;
+        return Ok(());
+    }
+
     let auth = if let Some(conda_token) = args.conda_token {
         Authentication::CondaToken(conda_token)
     } else if let Some(username) = args.username {
@@ -222,6 +269,43 @@ async fn login(
     Ok(())
 }
 
+/// Obtains tokens through the device authorization flow of the OIDC identity
+/// provider of the host.
+async fn login_oidc(args: &LoginArgs) -> Result<Authentication, AuthenticationCLIError> {
+    let issuer = match &args.oidc_issuer {
+        Some(issuer) => issuer.clone(),
+        None if args.host.contains("://") => Url::parse(&args.host)?,
+        None => Url::parse(&format!("https://{}", args.host.replace("*.", "")))?,
+    };
+
+    let client = Client::new();
+    let configuration = OidcConfiguration::discover(&client, &issuer).await?;
+    let mut authorization = DeviceAuthorization::request(
+        &client,
+        &configuration,
+        &args.oidc_client_id,
+        &args.oidc_scope,
+    )
+    .await?;
+    match &authorization.verification_uri_complete {
+        Some(uri) => eprintln!("To log in, visit {uri}"),
+        None => eprintln!(
+            "To log in, visit {} and enter the code {}",
+            authorization.verification_uri, authorization.user_code
+        ),
+    }
+
+    loop {
+        match authorization
+            .poll(&client, &configuration, &args.oidc_client_id)
+            .await?
+        {
+            DevicePoll::Pending(interval) => tokio::time::sleep(interval).await,
+            DevicePoll::Complete(auth) => return Ok(auth),
+        }
+    }
+}
+
 /// Validates a token with prefix.dev by making a GraphQL API call
 ///
 /// Returns `Ok(true)` if the token is valid, `Ok(false)` if invalid,
@@ -298,7 +382,7 @@ pub async fn execute(args: Args) -> Result<(), AuthenticationCLIError> {
     let storage = AuthenticationStorage::from_env_and_defaults()?;
 
     match args.subcommand {
-        Subcommand::Login(args) => login(args, storage).await,
+        Subcommand::Login(args) => login(*args, storage).await,
         Subcommand::Logout(args) => logout(args, storage),
     }
 }
@@ -334,9 +418,28 @@ mod tests {
             s3_access_key_id: None,
             s3_secret_access_key: None,
             s3_session_token: None,
+            oidc: false,
+            oidc_issuer: None,
+            oidc_client_id: "rattler".to_string(),
+            oidc_scope: vec!["openid".to_string(), "offline_access".to_string()],
         }
     }
 
+    #[test]
+    fn test_get_url() {
+        assert_eq!(get_url("prefix.dev").unwrap(), "*.prefix.dev");
+        assert_eq!(get_url("repo.prefix.dev").unwrap(), "repo.prefix.dev");
+        assert_eq!(
+            get_url("https://repo.prefix.dev/channel-a/").unwrap(),
+            "repo.prefix.dev/channel-a"
+        );
+        assert_eq!(
+            get_url("prefix.dev/channel-a").unwrap(),
+            "*.prefix.dev/channel-a"
+        );
+        assert_eq!(get_url("s3://my-bucket/prefix").unwrap(), "my-bucket");
+    }
+
     #[tokio::test]
     async fn test_login_with_token_success() {
         let (storage, _temp_dir) = create_test_storage();
@@ -522,4 +625,62 @@ mod tests {
         let result = login(args, storage).await;
         assert!(matches!(result, Err(AuthenticationCLIError::S3BadMethod)));
     }
+
+    #[tokio::test]
+    async fn test_login_oidc_device_flow() {
+        let (storage, _temp_dir) = create_test_storage();
+
+        let mut server = Server::new_async().await;
+        let configuration = server
+            .mock("GET", "/.well-known/openid-configuration")
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "token_endpoint": format!("{}/token", server.url()),
+                    "device_authorization_endpoint": format!("{}/device", server.url()),
+                })
+                .to_string(),
+            )
+            .create();
+        let device = server
+            .mock("POST", "/device")
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "device_code": "device",
+                    "user_code": "ABCD-EFGH",
+                    "verification_uri": format!("{}/activate", server.url()),
+                    "expires_in": 600,
+                    "interval": 0,
+                })
+                .to_string(),
+            )
+            .create();
+        let token = server
+            .mock("POST", "/token")
+            .match_body(mockito::Matcher::Regex("device_code=device".to_string()))
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "access_token": "access",
+                    "refresh_token": "refresh",
+                    "expires_in": 3600,
+                })
+                .to_string(),
+            )
+            .create();
+
+        let mut args = create_login_args(&server.url());
+        args.oidc = true;
+        login(args, storage.clone()).await.unwrap();
+
+        configuration.assert();
+        device.assert();
+        token.assert();
+        assert!(matches!(
+            storage.get("127.0.0.1").unwrap(),
+            Some(Authentication::OAuth { access_token, refresh_token: Some(refresh_token), .. })
+                if access_token == "access" && refresh_token == "refresh"
+        ));
+    }
 }
@@ -0,0 +1,237 @@
+//! Exports the packages installed in a prefix to an explicit environment
+//! file, an `environment.yml` or, with the `lock-file` feature, a lock-file.
+
+use std::path::Path;
+
+use indexmap::{IndexMap, IndexSet};
+use rattler_conda_types::{
+    version_spec::EqualityOperator, ChannelConfig, EnvironmentYaml, ExplicitEnvironmentEntry,
+    ExplicitEnvironmentSpec, MatchSpec, MatchSpecOrSubSection, NamedChannelOrUrl, PackageRecord,
+    ParseStrictness, Platform, PrefixRecord, StringMatcher, VersionSpec,
+};
+#[cfg(feature = "lock-file")]
+use rattler_lock::{CondaPackageData, LockFile};
+
+/// The hash that is added to the urls of an explicit environment file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExplicitHash {
+    /// Do not add a hash.
+    None,
+    /// Add the MD5 hash of the package archive, like `conda list --explicit
+    /// --md5`.
+    #[default]
+    Md5,
+    /// Add the SHA256 hash of the package archive.
+    Sha256,
+}
+
+/// The packages installed in a prefix, in installation order.
+#[derive(Debug, Clone)]
+pub struct PrefixExport {
+    records: Vec<PrefixRecord>,
+    platform: Platform,
+}
+
+impl PrefixExport {
+    /// Reads the packages installed in the given prefix.
+    pub fn from_prefix(prefix: &Path, platform: Platform) -> Result<Self, std::io::Error> {
+        Ok(Self::from_records(
+            PrefixRecord::collect_from_prefix(prefix)?,
+            platform,
+        ))
+    }
+
+    /// Constructs an export from the records of installed packages.
+    pub fn from_records(records: Vec<PrefixRecord>, platform: Platform) -> Self {
+        Self {
+            records: PackageRecord::sort_topologically(records),
+            platform,
+        }
+    }
+
+    /// Returns the records of the installed packages in installation order.
+    pub fn records(&self) -> &[PrefixRecord] {
+        &self.records
+    }
+
+    /// Returns an explicit environment file that lists the urls of all
+    /// installed packages.
+    pub fn to_explicit_spec(&self, hash: ExplicitHash) -> ExplicitEnvironmentSpec {
+        let packages = self
+            .records
+            .iter()
+            .map(|record| {
+                let mut url = record.repodata_record.url.clone();
+                let package_record = &record.repodata_record.package_record;
+                let fragment = match hash {
+                    ExplicitHash::None => None,
+                    ExplicitHash::Md5 => package_record.md5.map(|md5| format!("{md5:x}")),
+                    ExplicitHash::Sha256 => package_record
+                        .sha256
+                        .map(|sha256| format!("sha256:{sha256:x}")),
+                };
+                url.set_fragment(fragment.as_deref());
+                ExplicitEnvironmentEntry { url }
+            })
+            .collect();
+
+        ExplicitEnvironmentSpec {
+            platform: Some(self.platform),
+            packages,
+        }
+    }
+
+    /// Returns an `environment.yml` that contains the specs that were
+    /// requested by the user, e.g. `python >=3.12`.
+    ///
+    /// With `include_builds` the requested packages are instead pinned to the
+    /// exact version and build string that is installed. The channels are
+    /// taken from the installed packages, channels that are hosted at the
+    /// channel alias of `channel_config` are written by name.
+    pub fn to_environment_yaml(
+        &self,
+        name: Option<String>,
+        include_builds: bool,
+        channel_config: &ChannelConfig,
+    ) -> EnvironmentYaml {
+        let mut dependencies = Vec::new();
+        for record in &self.records {
+            if record.requested_specs.is_empty() {
+                continue;
+            }
+
+            let package_record = &record.repodata_record.package_record;
+            if include_builds {
+                dependencies.push(MatchSpecOrSubSection::MatchSpec(Box::new(MatchSpec {
+                    name: Some(package_record.name.clone()),
+                    version: Some(VersionSpec::Exact(
+                        EqualityOperator::Equals,
+                        package_record.version.version().clone(),
+                    )),
+                    build: Some(StringMatcher::Exact(package_record.build.clone())),
+                    ..MatchSpec::default()
+                })));
+                continue;
+            }
+
+            for spec in &record.requested_specs {
+                match MatchSpec::from_str(spec, ParseStrictness::Lenient) {
+                    Ok(spec) => dependencies.push(MatchSpecOrSubSection::MatchSpec(Box::new(spec))),
+                    Err(err) => tracing::warn!(
+                        "skipping requested spec '{spec}' of {}: {err}",
+                        package_record.name.as_source()
+                    ),
+                }
+            }
+        }
+
+        let channels = self
+            .channels()
+            .into_iter()
+            .map(|channel| match channel.parse::<NamedChannelOrUrl>() {
+                Ok(NamedChannelOrUrl::Url(url)) => channel_config
+                    .strip_channel_alias(&url)
+                    .map_or(NamedChannelOrUrl::Url(url), NamedChannelOrUrl::Name),
+                Ok(channel) => channel,
+                Err(_) => NamedChannelOrUrl::Name(channel),
+            })
+            .collect();
+
+        EnvironmentYaml {
+            name,
+            prefix: None,
+            channels,
+            dependencies,
+            variables: IndexMap::default(),
+        }
+    }
+
+    /// Returns a lock-file with a single environment that contains the
+    /// installed packages for the platform of the prefix.
+    #[cfg(feature = "lock-file")]
+    pub fn to_lock_file(&self, environment: &str) -> LockFile {
+        let mut builder = LockFile::builder();
+        builder.set_channels(environment, self.channels());
+        for record in &self.records {
+            builder.add_conda_package(
+                environment,
+                self.platform,
+                CondaPackageData::from(record.repodata_record.clone()),
+            );
+        }
+        builder.finish()
+    }
+
+    /// Returns the channels of the installed packages in order of first
+    /// appearance.
+    fn channels(&self) -> Vec<String> {
+        self.records
+            .iter()
+            .filter_map(|record| record.repodata_record.channel.clone())
+            .collect::<IndexSet<_>>()
+            .into_iter()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rattler_conda_types::{
+        ChannelConfig, ExplicitEnvironmentSpec, Platform, PrefixRecord, RepoDataRecord,
+    };
+
+    use super::{ExplicitHash, PrefixExport};
+
+    fn prefix_record(requested_specs: &[&str]) -> PrefixRecord {
+        let mut record: RepoDataRecord = crate::get_repodata_record(
+            crate::get_test_data_dir().join("packages/empty-0.1.0-h4616a5c_0.conda"),
+        );
+        record.channel = Some("https://conda.anaconda.org/conda-forge/".to_string());
+        PrefixRecord {
+            requested_specs: requested_specs.iter().map(ToString::to_string).collect(),
+            ..PrefixRecord::from_repodata_record(record, Vec::new())
+        }
+    }
+
+    #[test]
+    fn test_export() {
+        let export =
+            PrefixExport::from_records(vec![prefix_record(&["empty >=0.1"])], Platform::Linux64);
+
+        let explicit = export.to_explicit_spec(ExplicitHash::Sha256);
+        let explicit: ExplicitEnvironmentSpec = explicit.to_spec_string().parse().unwrap();
+        assert_eq!(explicit.platform, Some(Platform::Linux64));
+        assert!(explicit.packages[0]
+            .package_archive_hash()
+            .unwrap()
+            .is_some());
+
+        let channel_config = ChannelConfig::default_with_root_dir(std::env::temp_dir());
+        let yaml = export
+            .to_environment_yaml(Some("test".to_string()), false, &channel_config)
+            .to_yaml_string();
+        assert!(yaml.contains("- conda-forge"), "{yaml}");
+        assert!(yaml.contains("- empty >=0.1"), "{yaml}");
+        let yaml = export
+            .to_environment_yaml(None, true, &channel_config)
+            .to_yaml_string();
+        assert!(yaml.contains("- empty ==0.1.0 h4616a5c_0"), "{yaml}");
+    }
+
+    #[cfg(feature = "lock-file")]
+    #[test]
+    fn test_export_lock_file() {
+        let export =
+            PrefixExport::from_records(vec![prefix_record(&["empty >=0.1"])], Platform::Linux64);
+
+        let lock_file = export.to_lock_file("default");
+        let packages = lock_file
+            .environment("default")
+            .unwrap()
+            .conda_repodata_records(Platform::Linux64)
+            .unwrap()
+            .unwrap();
+        assert_eq!(packages.len(), 1);
+        assert_eq!(packages[0].package_record.name.as_normalized(), "empty");
+    }
+}
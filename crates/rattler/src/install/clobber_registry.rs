@@ -14,7 +14,8 @@ use rattler_conda_types::{
 
 pub const CLOBBERS_DIR_NAME: &str = "__clobbers__";
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+/// Describes which package provides a path that is part of multiple packages.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub struct ClobberedPath {
     /// The name of the package from which the final file is taken.
     pub package: PackageName,
@@ -202,8 +203,32 @@ impl ClobberRegistry {
         Self::update_conda_meta(target_prefix, &prefix_records, &prefix_records_to_rewrite)?;
 
         // 4
-        let clobbered_paths = self
-            .path_trie
+        Ok(self.clobbered_paths())
+    }
+
+    /// Predicts which paths will be clobbered if the given packages, with the
+    /// given paths, make up an environment. The packages are prioritized in
+    /// the same way as when installing them.
+    pub(crate) fn predict<'a>(
+        packages: impl IntoIterator<Item = (&'a PackageRecord, Vec<PathBuf>)>,
+    ) -> HashMap<PathBuf, ClobberedPath> {
+        let mut paths_by_name = HashMap::new();
+        let mut records = Vec::new();
+        for (record, paths) in packages {
+            paths_by_name.insert(&record.name, paths);
+            records.push(record);
+        }
+
+        let mut registry = ClobberRegistry::default();
+        for record in PackageRecord::sort_topologically(records) {
+            registry.register_paths_by_name(&record.name, &paths_by_name[&record.name]);
+        }
+        registry.clobbered_paths()
+    }
+
+    /// Returns all paths that are provided by more than one package.
+    fn clobbered_paths(&self) -> HashMap<PathBuf, ClobberedPath> {
+        self.path_trie
             .collect_clobbered_paths()
             .into_iter()
             .map(|(k, v)| {
@@ -219,8 +244,7 @@ impl ClobberRegistry {
                     },
                 )
             })
-            .collect();
-        Ok(clobbered_paths)
+            .collect()
     }
 
     /// Update conda metadata on disk with new file tree obtained
@@ -3,6 +3,7 @@ use std::{
     collections::{HashMap, HashSet},
     path::{Path, PathBuf},
     sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
 };
 
 use indexmap::IndexSet;
@@ -16,7 +17,7 @@ use super::{
     clobber_registry::{ClobberError, ClobberRegistry, ClobberedPath},
     link_script::{PrePostLinkError, PrePostLinkResult},
     unlink::{recursively_remove_empty_directories, UnlinkError},
-    Transaction, TransactionOperation,
+    PrefixLock, PrefixLockError, Transaction, TransactionOperation,
 };
 use crate::install::link_script::LinkScriptError;
 
@@ -70,6 +71,10 @@ pub enum PostProcessingError {
     /// Failed to determine the currently installed packages.
     #[error("failed to determine the installed packages")]
     FailedToDetectInstalledPackages(#[source] std::io::Error),
+
+    /// Failed to lock the prefix.
+    #[error("failed to lock the prefix")]
+    FailedToLockPrefix(#[source] PrefixLockError),
 }
 
 impl InstallDriverBuilder {
@@ -211,6 +216,9 @@ impl InstallDriver {
     /// This function will select a winner among multiple packages that might
     /// write to a single package and will also execute any
     /// `post-link.sh/bat` scripts
+    ///
+    /// The caller is responsible for holding the [`PrefixLock`] of the prefix,
+    /// see [`Self::post_process_locked`].
     pub fn post_process<Old: Borrow<PrefixRecord> + AsRef<New>, New: AsRef<PackageRecord>>(
         &self,
         transaction: &Transaction<Old, New>,
@@ -243,6 +251,24 @@ impl InstallDriver {
         })
     }
 
+    /// Same as [`Self::post_process`] but holds the [`PrefixLock`] of the
+    /// prefix while post processing, waiting at most `timeout` for another
+    /// process to release it.
+    pub async fn post_process_locked<
+        Old: Borrow<PrefixRecord> + AsRef<New>,
+        New: AsRef<PackageRecord>,
+    >(
+        &self,
+        transaction: &Transaction<Old, New>,
+        target_prefix: &Prefix,
+        timeout: Option<Duration>,
+    ) -> Result<PostProcessResult, PostProcessingError> {
+        let _lock = PrefixLock::acquire(target_prefix.path(), timeout)
+            .await
+            .map_err(PostProcessingError::FailedToLockPrefix)?;
+        self.post_process(transaction, target_prefix)
+    }
+
     /// Remove all empty directories that are not part of the new prefix
     /// records.
     pub fn remove_empty_directories<Old: Borrow<PrefixRecord>, New>(
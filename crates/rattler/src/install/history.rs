@@ -0,0 +1,171 @@
+//! Records applied transactions in the `conda-meta/history` file of a prefix
+//! and computes the transactions required to restore earlier revisions.
+
+use std::path::Path;
+
+use rattler_conda_types::{
+    history::{HistoryDist, HistoryRevision},
+    History, MatchSpec, Platform, PrefixRecord, RepoDataRecord,
+};
+
+use super::{Transaction, TransactionError};
+
+/// An error that can occur when computing the packages of a revision.
+#[derive(Debug, thiserror::Error)]
+pub enum RevisionError {
+    /// The history does not contain the requested revision.
+    #[error("revision {0} does not exist")]
+    UnknownRevision(usize),
+
+    /// A package of the revision is not installed and was not found among
+    /// the available packages.
+    #[error("the package '{0}' is not available")]
+    MissingPackage(HistoryDist),
+
+    /// Failed to construct a transaction
+    #[error("failed to construct a transaction")]
+    FailedToConstructTransaction(#[from] TransactionError),
+}
+
+/// Appends a revision describing `transaction` to the history of the prefix.
+pub(crate) fn record_transaction(
+    prefix: &Path,
+    transaction: &Transaction<PrefixRecord, RepoDataRecord>,
+    requested_specs: Option<&[MatchSpec]>,
+) -> Result<(), std::io::Error> {
+    let mut revision = HistoryRevision::new(chrono::Local::now().naive_local());
+    revision.removed = transaction
+        .removed_packages()
+        .map(|record| HistoryDist::from(&record.repodata_record))
+        .collect();
+    revision.added = transaction
+        .installed_packages()
+        .map(HistoryDist::from)
+        .collect();
+    revision.update_specs = requested_specs
+        .unwrap_or_default()
+        .iter()
+        .map(MatchSpec::to_string)
+        .collect();
+
+    if revision.is_empty() {
+        return Ok(());
+    }
+
+    revision.append_to_path(&prefix.join("conda-meta").join("history"))
+}
+
+/// Returns the records of the packages that were installed in the prefix at
+/// the given revision of its history.
+///
+/// Packages that are still installed are taken from `installed`, all other
+/// packages must be present in `available`, for instance because they were
+/// looked up in the repodata of the channels they came from.
+pub fn records_for_revision(
+    history: &History,
+    revision: usize,
+    installed: &[PrefixRecord],
+    available: impl IntoIterator<Item = RepoDataRecord>,
+) -> Result<Vec<RepoDataRecord>, RevisionError> {
+    let state = history
+        .state_at(revision)
+        .ok_or(RevisionError::UnknownRevision(revision))?;
+    let available = available.into_iter().collect::<Vec<_>>();
+
+    state
+        .into_iter()
+        .map(|dist| {
+            let dist_name = dist.dist_name();
+            installed
+                .iter()
+                .map(|record| &record.repodata_record)
+                .chain(available.iter())
+                .find(|record| record_dist_name(record) == dist_name)
+                .cloned()
+                .ok_or(RevisionError::MissingPackage(dist))
+        })
+        .collect()
+}
+
+/// Computes the [`Transaction`] that restores the prefix to the given
+/// revision of its history. See [`records_for_revision`] for how the records
+/// of the revision are determined.
+pub fn revision_transaction(
+    history: &History,
+    revision: usize,
+    installed: Vec<PrefixRecord>,
+    available: impl IntoIterator<Item = RepoDataRecord>,
+    platform: Platform,
+) -> Result<Transaction<PrefixRecord, RepoDataRecord>, RevisionError> {
+    let desired = records_for_revision(history, revision, &installed, available)?;
+    Ok(Transaction::from_current_and_desired(
+        installed, desired, None, None, platform,
+    )?)
+}
+
+fn record_dist_name(record: &RepoDataRecord) -> String {
+    format!(
+        "{}-{}-{}",
+        record.package_record.name.as_normalized(),
+        record.package_record.version,
+        record.package_record.build
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use rattler_conda_types::{History, MatchSpec, ParseStrictness, Platform, PrefixRecord};
+
+    use super::{records_for_revision, revision_transaction, RevisionError};
+    use crate::install::{Installer, TransactionOperation};
+
+    #[tokio::test]
+    async fn test_history_and_rollback() {
+        let prefix = tempfile::tempdir().unwrap();
+        let record = crate::get_repodata_record(
+            crate::get_test_data_dir().join("packages/empty-0.1.0-h4616a5c_0.conda"),
+        );
+
+        // Install the package and then remove it again.
+        Installer::new()
+            .with_requested_specs(vec![
+                MatchSpec::from_str("empty", ParseStrictness::Strict).unwrap()
+            ])
+            .install(prefix.path(), vec![record.clone()])
+            .await
+            .unwrap();
+        Installer::new()
+            .install(prefix.path(), Vec::new())
+            .await
+            .unwrap();
+
+        let history = History::from_prefix(prefix.path()).unwrap();
+        assert_eq!(history.revisions.len(), 2);
+        let added = history.revisions[0].added.first().unwrap();
+        assert_eq!(added.to_string(), "test/noarch::empty-0.1.0-h4616a5c_0");
+        assert_eq!(history.revisions[0].update_specs, vec!["empty".to_string()]);
+        assert_eq!(history.revisions[1].removed.first(), Some(added));
+        assert!(history.current_state().is_empty());
+
+        // Restoring the first revision requires the record of the package.
+        let installed: Vec<PrefixRecord> =
+            PrefixRecord::collect_from_prefix(prefix.path()).unwrap();
+        assert!(matches!(
+            records_for_revision(&history, 0, &installed, Vec::new()),
+            Err(RevisionError::MissingPackage(_))
+        ));
+        assert!(matches!(
+            records_for_revision(&history, 2, &installed, Vec::new()),
+            Err(RevisionError::UnknownRevision(2))
+        ));
+
+        let transaction =
+            revision_transaction(&history, 0, installed, vec![record], Platform::current())
+                .unwrap();
+        assert_eq!(transaction.operations.len(), 1);
+        assert!(matches!(
+            &transaction.operations[0],
+            TransactionOperation::Install(record) if record.package_record.name.as_normalized() == "empty"
+        ));
+    }
+}
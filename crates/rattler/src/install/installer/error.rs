@@ -4,7 +4,7 @@ use std::path::PathBuf;
 use crate::{
     install::{
         clobber_registry::ClobberError, driver::PostProcessingError, link_script::PrePostLinkError,
-        unlink::UnlinkError, InstallError, TransactionError,
+        prefix_lock::PrefixLockError, unlink::UnlinkError, InstallError, TransactionError,
     },
     package_cache::PackageCacheError,
 };
@@ -55,6 +55,14 @@ pub enum InstallerError {
     /// Failed to create the prefix
     #[error("failed to create the prefix")]
     FailedToCreatePrefix(PathBuf, #[source] std::io::Error),
+
+    /// Failed to acquire the lock of the prefix
+    #[error("failed to lock the prefix")]
+    FailedToLockPrefix(#[source] PrefixLockError),
+
+    /// The packages installed in the prefix changed since the plan was created
+    #[error("the packages installed in '{0}' changed since the plan was created")]
+    PlanOutdated(PathBuf),
 }
 
 impl From<Cancelled> for InstallerError {
@@ -70,6 +78,7 @@ impl From<PostProcessingError> for InstallerError {
             PostProcessingError::FailedToDetectInstalledPackages(err) => {
                 InstallerError::FailedToDetectInstalledPackages(err)
             }
+            PostProcessingError::FailedToLockPrefix(err) => InstallerError::FailedToLockPrefix(err),
         }
     }
 }
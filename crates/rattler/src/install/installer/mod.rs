@@ -1,12 +1,14 @@
 mod error;
 #[cfg(feature = "indicatif")]
 mod indicatif;
+mod plan;
 mod reporter;
 use std::{
     collections::{HashMap, HashSet},
     future::ready,
     path::{Path, PathBuf},
     sync::Arc,
+    time::Duration,
 };
 
 pub use error::InstallerError;
@@ -17,6 +19,7 @@ pub use indicatif::{
     ProgressFormatter,
 };
 use itertools::Itertools;
+pub use plan::{DryRunResult, InstallPlan, PlannedOperation, PlannedOperationKind};
 use rattler_cache::package_cache::{CacheLock, CacheReporter};
 use rattler_conda_types::{
     prefix_record::{Link, LinkType},
@@ -30,6 +33,9 @@ use simple_spawn_blocking::tokio::run_blocking_task;
 use tokio::{sync::Semaphore, task::JoinError};
 
 use super::{
+    history,
+    journal::{remove_journal, InstallJournal, JournalWriter, PackageState, RecoveryDirection},
+    prefix_lock::PrefixLock,
     unlink_package, AppleCodeSignBehavior, InstallDriver, InstallOptions, Prefix, Transaction,
 };
 use crate::{
@@ -63,6 +69,7 @@ pub struct Installer {
     reinstall_packages: Option<HashSet<PackageName>>,
     ignored_packages: Option<HashSet<PackageName>>,
     requested_specs: Option<Vec<MatchSpec>>,
+    prefix_lock_timeout: Option<Duration>,
     // TODO: Determine upfront if these are possible.
     link_options: LinkOptions,
 }
@@ -348,7 +355,31 @@ impl Installer {
         self
     }
 
+    /// Sets how long to wait for another process to release the lock on the
+    /// prefix before failing with [`InstallerError::FailedToLockPrefix`]. By
+    /// default the installer waits indefinitely.
+    #[must_use]
+    pub fn with_prefix_lock_timeout(self, timeout: Duration) -> Self {
+        Self {
+            prefix_lock_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Sets how long to wait for another process to release the lock on the
+    /// prefix.
+    ///
+    /// This function is similar to [`Self::with_prefix_lock_timeout`], but
+    /// modifies an existing instance.
+    pub fn set_prefix_lock_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.prefix_lock_timeout = Some(timeout);
+        self
+    }
+
     /// Install the packages in the given prefix.
+    ///
+    /// The prefix is locked with a [`PrefixLock`] for the duration of the
+    /// installation.
     pub async fn install(
         self,
         prefix: impl AsRef<Path>,
@@ -357,28 +388,59 @@ impl Installer {
         let prefix = Prefix::create(prefix.as_ref().to_path_buf()).map_err(|err| {
             InstallerError::FailedToCreatePrefix(prefix.as_ref().to_path_buf(), err)
         })?;
+        let _lock = self.lock_prefix(&prefix).await?;
+        self.install_locked(prefix, records).await
+    }
 
+    /// Acquires the lock of the prefix, reporting to the reporter while
+    /// waiting for it.
+    pub(crate) async fn lock_prefix(&self, prefix: &Path) -> Result<PrefixLock, InstallerError> {
+        PrefixLock::acquire_with_wait_callback(prefix, self.prefix_lock_timeout, |waited| {
+            if let Some(reporter) = &self.reporter {
+                reporter.on_prefix_lock_wait(prefix, waited);
+            }
+        })
+        .await
+        .map_err(InstallerError::FailedToLockPrefix)
+    }
+
+    /// Installs the packages in a prefix that is already locked.
+    pub(crate) async fn install_locked(
+        self,
+        prefix: Prefix,
+        records: impl IntoIterator<Item = RepoDataRecord>,
+    ) -> Result<InstallationResult, InstallerError> {
         // Create a future to determine the currently installed packages. We
         // can start this in parallel with the other operations and resolve it
         // when we need it.
         let installed: Vec<PrefixRecord> = if let Some(installed) = self.installed {
             installed
         } else {
-            let prefix = prefix.clone();
             // TODO: Should we add progress reporting here?
-            run_blocking_task(move || {
-                PrefixRecord::collect_from_prefix(&prefix)
-                    .map_err(InstallerError::FailedToDetectInstalledPackages)
-            })
-            .await?
+            detect_installed_packages(&prefix).await?
         };
 
+        // Packages that were left half-linked by an interrupted transaction
+        // have to be reinstalled.
+        let interrupted = InstallJournal::from_prefix(&prefix).map_err(|e| {
+            InstallerError::IoError("failed to read the transaction journal".to_string(), e)
+        })?;
+        let reinstall_packages =
+            merge_incomplete_packages(self.reinstall_packages, interrupted.as_ref());
+
+        // The environment before the transaction, including packages that the
+        // transaction leaves untouched.
+        let previous = installed
+            .iter()
+            .map(|record| record.repodata_record.clone())
+            .collect::<Vec<_>>();
+
         // Construct a transaction from the current and desired situation.
         let target_platform = self.target_platform.unwrap_or_else(Platform::current);
         let transaction = Transaction::from_current_and_desired(
             installed,
-            records.into_iter().collect::<Vec<_>>(),
-            self.reinstall_packages,
+            records,
+            reinstall_packages,
             self.ignored_packages,
             target_platform,
         )?;
@@ -399,6 +461,14 @@ impl Installer {
 
         // If the transaction is empty we can short-circuit the installation
         if transaction.operations.is_empty() {
+            if interrupted.is_some() {
+                remove_journal(&InstallJournal::path(&prefix)).map_err(|e| {
+                    InstallerError::IoError(
+                        "failed to remove the transaction journal".to_string(),
+                        e,
+                    )
+                })?;
+            }
             return Ok(InstallationResult {
                 transaction,
                 pre_link_script_result: None,
@@ -410,13 +480,7 @@ impl Installer {
         let downloader = self
             .downloader
             .unwrap_or_else(|| reqwest_middleware::ClientWithMiddleware::from(Client::default()));
-        let package_cache = self.package_cache.unwrap_or_else(|| {
-            PackageCache::new(
-                default_cache_dir()
-                    .expect("failed to determine default cache directory")
-                    .join(rattler_cache::PACKAGE_CACHE_DIR),
-            )
-        });
+        let package_cache = self.package_cache.unwrap_or_else(default_package_cache);
 
         // Construct a driver.
         let driver = InstallDriver::builder()
@@ -453,12 +517,26 @@ impl Installer {
             reporter.on_transaction_start(&transaction);
         }
 
+        // Record the transaction before the prefix is modified. The target is
+        // the environment the transaction produces, which excludes desired
+        // packages that are ignored.
+        let target = transaction
+            .unchanged_packages()
+            .iter()
+            .map(|record| record.repodata_record.clone())
+            .chain(transaction.installed_packages().cloned())
+            .collect();
+        let journal = JournalWriter::create(&prefix, previous, target).map_err(|e| {
+            InstallerError::IoError("failed to write the transaction journal".to_string(), e)
+        })?;
+
         let mut pending_unlink_futures = FuturesUnordered::new();
         // Execute the operations (remove) in the transaction.
         for (operation_idx, operation) in transaction.operations.iter().enumerate() {
             let reporter = self.reporter.clone();
             let driver = &driver;
             let prefix = &prefix;
+            let journal = &journal;
 
             let op = async move {
                 // Uninstall the package if it was removed.
@@ -470,10 +548,13 @@ impl Installer {
                     let reporter = reporter
                         .as_deref()
                         .map(move |r| (r, r.on_unlink_start(operation_idx, record)));
+                    let name = &record.repodata_record.package_record.name;
+                    record_progress(journal, name, PackageState::Unlinking).await?;
                     driver.clobber_registry().unregister_paths(record);
                     unlink_package(prefix, record).await.map_err(|e| {
                         InstallerError::UnlinkError(record.repodata_record.file_name.clone(), e)
                     })?;
+                    record_progress(journal, name, PackageState::Unlinked).await?;
                     if let Some((reporter, index)) = reporter {
                         reporter.on_unlink_complete(index);
                         if operation.record_to_install().is_none() {
@@ -505,6 +586,7 @@ impl Installer {
             let base_install_options = &base_install_options;
             let driver = &driver;
             let prefix = &prefix;
+            let journal = &journal;
             let spec_mapping_ref = spec_mapping.clone();
             let operation_future = async move {
                 if let Some(reporter) = &reporter {
@@ -556,6 +638,8 @@ impl Installer {
                     let requested_spec = spec_mapping_ref
                         .and_then(|mapping| mapping.get(&record.package_record.name).cloned())
                         .unwrap_or_default();
+                    let name = &record.package_record.name;
+                    record_progress(journal, name, PackageState::Linking).await?;
                     link_package(
                         &record,
                         prefix,
@@ -565,6 +649,7 @@ impl Installer {
                         requested_spec,
                     )
                     .await?;
+                    record_progress(journal, name, PackageState::Linked).await?;
                     if let Some((reporter, index)) = reporter {
                         reporter.on_link_complete(index);
                     }
@@ -604,6 +689,17 @@ impl Installer {
         // Post process the transaction
         let post_process_result = driver.post_process(&transaction, &prefix)?;
 
+        // Record the transaction in the history of the prefix.
+        history::record_transaction(&prefix, &transaction, self.requested_specs.as_deref())
+            .map_err(|e| {
+                InstallerError::IoError("failed to update conda-meta/history".to_string(), e)
+            })?;
+
+        // The prefix is consistent again.
+        journal.finish().map_err(|e| {
+            InstallerError::IoError("failed to remove the transaction journal".to_string(), e)
+        })?;
+
         if let Some(reporter) = &self.reporter {
             reporter.on_transaction_complete();
         }
@@ -615,6 +711,91 @@ impl Installer {
             clobbered_paths: post_process_result.clobbered_paths,
         })
     }
+
+    /// Brings a prefix whose last transaction was interrupted back into a
+    /// consistent state. Depending on `direction` either the environment the
+    /// interrupted transaction was going to create or the environment that
+    /// existed before it is installed, see [`InstallJournal`].
+    ///
+    /// Returns `None` if no transaction was interrupted. Packages of the
+    /// interrupted transaction that were partially linked but are not part of
+    /// the restored environment may leave files behind.
+    pub async fn recover(
+        self,
+        prefix: impl AsRef<Path>,
+        direction: RecoveryDirection,
+    ) -> Result<Option<InstallationResult>, InstallerError> {
+        let read_journal = |prefix: &Path| {
+            InstallJournal::from_prefix(prefix).map_err(|e| {
+                InstallerError::IoError("failed to read the transaction journal".to_string(), e)
+            })
+        };
+        if read_journal(prefix.as_ref())?.is_none() {
+            return Ok(None);
+        }
+
+        // Another process may have recovered the prefix in the meantime, read
+        // the journal again once the prefix is locked.
+        let prefix = Prefix::create(prefix.as_ref().to_path_buf()).map_err(|err| {
+            InstallerError::FailedToCreatePrefix(prefix.as_ref().to_path_buf(), err)
+        })?;
+        let _lock = self.lock_prefix(&prefix).await?;
+        let Some(journal) = read_journal(&prefix)? else {
+            return Ok(None);
+        };
+        self.install_locked(prefix, journal.records(direction).to_vec())
+            .await
+            .map(Some)
+    }
+}
+
+/// Adds the packages that an interrupted transaction left in an inconsistent
+/// state to the packages to reinstall.
+fn merge_incomplete_packages(
+    reinstall: Option<HashSet<PackageName>>,
+    interrupted: Option<&InstallJournal>,
+) -> Option<HashSet<PackageName>> {
+    let Some(journal) = interrupted else {
+        return reinstall;
+    };
+    let mut reinstall = reinstall.unwrap_or_default();
+    reinstall.extend(journal.incomplete_packages());
+    Some(reinstall)
+}
+
+/// Appends the state of a package to the transaction journal.
+async fn record_progress(
+    journal: &JournalWriter,
+    name: &PackageName,
+    state: PackageState,
+) -> Result<(), InstallerError> {
+    let journal = journal.clone();
+    let name = name.clone();
+    run_blocking_task(move || {
+        journal.record(&name, state).map_err(|e| {
+            InstallerError::IoError("failed to update the transaction journal".to_string(), e)
+        })
+    })
+    .await
+}
+
+/// Reads the records of the packages installed in the prefix.
+async fn detect_installed_packages(prefix: &Path) -> Result<Vec<PrefixRecord>, InstallerError> {
+    let prefix = prefix.to_path_buf();
+    run_blocking_task(move || {
+        PrefixRecord::collect_from_prefix(&prefix)
+            .map_err(InstallerError::FailedToDetectInstalledPackages)
+    })
+    .await
+}
+
+/// Returns the package cache in the default cache directory.
+fn default_package_cache() -> PackageCache {
+    PackageCache::new(
+        default_cache_dir()
+            .expect("failed to determine default cache directory")
+            .join(rattler_cache::PACKAGE_CACHE_DIR),
+    )
 }
 
 async fn link_package(
@@ -0,0 +1,455 @@
+//! Computes what an [`Installer`] would do to a prefix without modifying it,
+//! see [`Installer::dry_run`].
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::{
+    package::PathsJson, prefix::Prefix, PackageName, PackageRecord, Platform, PrefixRecord,
+    RepoDataRecord,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    default_package_cache, detect_installed_packages, merge_incomplete_packages,
+    InstallationResult, Installer, InstallerError,
+};
+use crate::install::{
+    clobber_registry::{ClobberRegistry, ClobberedPath},
+    journal::InstallJournal,
+    link_script::LinkScriptType,
+    Transaction, TransactionOperation,
+};
+
+/// The result of [`Installer::dry_run`].
+#[derive(Debug)]
+pub struct DryRunResult {
+    /// The transaction that would be applied to the prefix.
+    pub transaction: Transaction<PrefixRecord, RepoDataRecord>,
+
+    /// A serializable description of the transaction that can later be
+    /// executed with [`Installer::install_plan`].
+    pub plan: InstallPlan,
+}
+
+/// The kind of a [`PlannedOperation`], mirrors [`TransactionOperation`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlannedOperationKind {
+    /// A package is installed.
+    Install,
+    /// A package is replaced by another version of it.
+    Change,
+    /// A package is removed and installed again.
+    Reinstall,
+    /// A package is removed.
+    Remove,
+}
+
+/// A single operation of an [`InstallPlan`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlannedOperation {
+    /// What happens to the package.
+    pub kind: PlannedOperationKind,
+
+    /// The name of the package.
+    pub name: PackageName,
+
+    /// The file name of the package that is removed from the prefix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remove: Option<String>,
+
+    /// The file name of the package that is installed into the prefix.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub install: Option<String>,
+
+    /// Whether the package to install is already present in the package
+    /// cache. Always `true` if nothing is installed.
+    pub cached: bool,
+
+    /// The size of the archive that has to be downloaded, if the package is
+    /// not cached and its size is known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub download_size: Option<u64>,
+}
+
+/// A serializable description of the changes an [`Installer`] would make to
+/// a prefix.
+///
+/// A plan is created with [`Installer::dry_run`] and can be stored (e.g. as
+/// JSON), reviewed and later executed with [`Installer::install_plan`]. Next
+/// to the operations, the plan contains a snapshot of the packages that were
+/// installed when it was created. Executing the plan fails if the prefix was
+/// modified in the meantime.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstallPlan {
+    /// The prefix the plan applies to.
+    pub prefix: PathBuf,
+
+    /// The platform of the prefix.
+    pub platform: Platform,
+
+    /// The file names of the records in `conda-meta` at the time the plan was
+    /// created.
+    pub installed: Vec<String>,
+
+    /// The records that make up the environment after executing the plan.
+    pub records: Vec<RepoDataRecord>,
+
+    /// Packages that are reinstalled even if they did not change.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub reinstall_packages: BTreeSet<PackageName>,
+
+    /// Packages that are left untouched.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub ignored_packages: BTreeSet<PackageName>,
+
+    /// The operations to perform.
+    pub operations: Vec<PlannedOperation>,
+
+    /// The paths that are expected to be provided by more than one package.
+    ///
+    /// This is a prediction: only packages that are already cached can be
+    /// inspected and the paths of `noarch: python` packages are compared
+    /// before they are relocated into `site-packages`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub clobbered_paths: BTreeMap<PathBuf, ClobberedPath>,
+
+    /// The packages whose pre-unlink script runs when they are removed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_unlink_scripts: Vec<PackageName>,
+
+    /// The packages that ship a post-link script. For packages that are not
+    /// cached this cannot be determined.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_link_scripts: Vec<PackageName>,
+
+    /// Whether link scripts were enabled when the plan was created.
+    pub execute_link_scripts: bool,
+}
+
+impl InstallPlan {
+    /// Returns true if executing the plan does not change the prefix.
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// Returns the total number of bytes that have to be downloaded for
+    /// packages with a known size.
+    pub fn download_size(&self) -> u64 {
+        self.operations
+            .iter()
+            .filter_map(|operation| operation.download_size)
+            .sum()
+    }
+}
+
+impl Installer {
+    /// Computes the changes that [`Self::install`] would make to the prefix
+    /// without downloading, linking or removing anything. The prefix does not
+    /// need to exist.
+    pub async fn dry_run(
+        &self,
+        prefix: impl AsRef<Path>,
+        records: impl IntoIterator<Item = RepoDataRecord>,
+    ) -> Result<DryRunResult, InstallerError> {
+        let prefix = prefix.as_ref();
+        let records = records.into_iter().collect::<Vec<_>>();
+        let target_platform = self.target_platform.unwrap_or_else(Platform::current);
+
+        let installed = match &self.installed {
+            Some(installed) => installed.clone(),
+            None if prefix.join("conda-meta").is_dir() => detect_installed_packages(prefix).await?,
+            None => Vec::new(),
+        };
+        let snapshot = installed_snapshot(&installed);
+        let interrupted = InstallJournal::from_prefix(prefix).map_err(|e| {
+            InstallerError::IoError("failed to read the transaction journal".to_string(), e)
+        })?;
+
+        let transaction = Transaction::from_current_and_desired(
+            installed,
+            records.clone(),
+            merge_incomplete_packages(self.reinstall_packages.clone(), interrupted.as_ref()),
+            self.ignored_packages.clone(),
+            target_platform,
+        )?;
+
+        // Determine which of the packages to install are already cached.
+        let package_cache = self
+            .package_cache
+            .clone()
+            .unwrap_or_else(default_package_cache);
+        let cached_paths: HashMap<&PackageName, PathBuf> = transaction
+            .installed_packages()
+            .filter_map(|record| {
+                let path = package_cache.cached_path(&record.package_record, &record.url)?;
+                Some((&record.package_record.name, path))
+            })
+            .collect();
+
+        let operations = transaction
+            .operations
+            .iter()
+            .map(|operation| {
+                let (kind, name) = match operation {
+                    TransactionOperation::Install(new) => {
+                        (PlannedOperationKind::Install, &new.package_record.name)
+                    }
+                    TransactionOperation::Change { new, .. } => {
+                        (PlannedOperationKind::Change, &new.package_record.name)
+                    }
+                    TransactionOperation::Reinstall { new, .. } => {
+                        (PlannedOperationKind::Reinstall, &new.package_record.name)
+                    }
+                    TransactionOperation::Remove(old) => (
+                        PlannedOperationKind::Remove,
+                        &old.repodata_record.package_record.name,
+                    ),
+                };
+                let new = operation.record_to_install();
+                let cached = new.is_none_or(|_| cached_paths.contains_key(name));
+                PlannedOperation {
+                    kind,
+                    name: name.clone(),
+                    remove: operation
+                        .record_to_remove()
+                        .map(|old| old.repodata_record.file_name.clone()),
+                    install: new.map(|new| new.file_name.clone()),
+                    cached,
+                    download_size: new
+                        .filter(|_| !cached)
+                        .and_then(|new| new.package_record.size),
+                }
+            })
+            .collect();
+
+        // Read the paths of the cached packages to predict clobbering and
+        // link scripts.
+        let mut package_paths: Vec<(&PackageRecord, Vec<PathBuf>)> = Vec::new();
+        for record in transaction.installed_packages() {
+            let Some(path) = cached_paths.get(&record.package_record.name) else {
+                continue;
+            };
+            let paths_json = PathsJson::from_package_directory_with_deprecated_fallback(path)
+                .map_err(|err| {
+                    InstallerError::IoError(
+                        format!("failed to read paths.json of {}", record.file_name),
+                        err,
+                    )
+                })?;
+            package_paths.push((
+                &record.package_record,
+                paths_json
+                    .paths
+                    .into_iter()
+                    .map(|entry| entry.relative_path)
+                    .collect(),
+            ));
+        }
+
+        let post_link_scripts = package_paths
+            .iter()
+            .filter(|(record, paths)| {
+                let script = LinkScriptType::PostLink.get_path(record, &target_platform);
+                paths.iter().any(|path| path == Path::new(&script))
+            })
+            .map(|(record, _)| record.name.clone())
+            .collect();
+        let pre_unlink_scripts = transaction
+            .removed_packages()
+            .filter(|record| {
+                let script = LinkScriptType::PreUnlink
+                    .get_path(&record.repodata_record.package_record, &target_platform);
+                prefix.join(script).is_file()
+            })
+            .map(|record| record.repodata_record.package_record.name.clone())
+            .collect();
+
+        let clobbered_paths = ClobberRegistry::predict(
+            transaction
+                .unchanged_packages()
+                .iter()
+                .map(|record| {
+                    (
+                        &record.repodata_record.package_record,
+                        record
+                            .paths_data
+                            .paths
+                            .iter()
+                            .map(|entry| entry.path().clone())
+                            .collect(),
+                    )
+                })
+                .chain(package_paths),
+        )
+        .into_iter()
+        .collect();
+
+        let plan = InstallPlan {
+            prefix: prefix.to_path_buf(),
+            platform: target_platform,
+            installed: snapshot,
+            records,
+            reinstall_packages: self.reinstall_packages.iter().flatten().cloned().collect(),
+            ignored_packages: self.ignored_packages.iter().flatten().cloned().collect(),
+            operations,
+            clobbered_paths,
+            pre_unlink_scripts,
+            post_link_scripts,
+            execute_link_scripts: self.execute_link_scripts,
+        };
+
+        Ok(DryRunResult { transaction, plan })
+    }
+
+    /// Executes a plan previously created with [`Self::dry_run`].
+    ///
+    /// The installed packages are read from the prefix again and compared
+    /// against the snapshot stored in the plan. If they differ,
+    /// [`InstallerError::PlanOutdated`] is returned and the prefix is left
+    /// untouched. The prefix is locked before the snapshot is compared.
+    ///
+    /// The platform, reinstalled and ignored packages of the plan take
+    /// precedence over those configured on the installer, and link scripts are
+    /// only executed if they were enabled when the plan was created.
+    pub async fn install_plan(
+        self,
+        plan: &InstallPlan,
+    ) -> Result<InstallationResult, InstallerError> {
+        let prefix = Prefix::create(&plan.prefix)
+            .map_err(|err| InstallerError::FailedToCreatePrefix(plan.prefix.clone(), err))?;
+        let _lock = self.lock_prefix(&prefix).await?;
+
+        let installed = detect_installed_packages(&prefix).await?;
+        if installed_snapshot(&installed) != plan.installed {
+            return Err(InstallerError::PlanOutdated(plan.prefix.clone()));
+        }
+
+        self.with_installed_packages(installed)
+            .with_target_platform(plan.platform)
+            .with_reinstall_packages(plan.reinstall_packages.iter().cloned().collect())
+            .with_ignored_packages(plan.ignored_packages.iter().cloned().collect())
+            .with_execute_link_scripts(plan.execute_link_scripts)
+            .install_locked(prefix, plan.records.clone())
+            .await
+    }
+}
+
+/// Returns the sorted file names of the records in `conda-meta`.
+fn installed_snapshot(installed: &[PrefixRecord]) -> Vec<String> {
+    let mut snapshot = installed
+        .iter()
+        .map(PrefixRecord::file_name)
+        .collect::<Vec<_>>();
+    snapshot.sort();
+    snapshot
+}
+
+#[cfg(test)]
+mod test {
+    use rattler_conda_types::Platform;
+
+    use super::{InstallPlan, PlannedOperationKind};
+    use crate::{
+        get_repodata_record, get_test_data_dir,
+        install::{Installer, InstallerError},
+        package_cache::PackageCache,
+    };
+
+    #[tokio::test]
+    async fn test_dry_run_and_install_plan() {
+        let cache = tempfile::tempdir().unwrap();
+        let prefix = tempfile::tempdir().unwrap();
+        // The path is canonicalized so that the url survives a round trip
+        // through JSON.
+        let record = get_repodata_record(
+            get_test_data_dir()
+                .join("packages/empty-0.1.0-h4616a5c_0.conda")
+                .canonicalize()
+                .unwrap(),
+        );
+        let installer = || {
+            Installer::new()
+                .with_package_cache(PackageCache::new(cache.path()))
+                .with_target_platform(Platform::current())
+        };
+
+        // A dry run does not touch the prefix.
+        let result = installer()
+            .dry_run(prefix.path(), vec![record.clone()])
+            .await
+            .unwrap();
+        assert_eq!(result.transaction.operations.len(), 1);
+        let operation = &result.plan.operations[0];
+        assert_eq!(operation.kind, PlannedOperationKind::Install);
+        assert!(!operation.cached);
+        assert_eq!(
+            result.plan.download_size(),
+            record.package_record.size.unwrap_or(0)
+        );
+        assert!(!prefix.path().join("conda-meta").exists());
+
+        // The plan survives a round trip through JSON.
+        let json = serde_json::to_string(&result.plan).unwrap();
+        let plan: InstallPlan = serde_json::from_str(&json).unwrap();
+        assert_eq!(plan, result.plan);
+
+        installer().install_plan(&plan).await.unwrap();
+        assert!(prefix
+            .path()
+            .join("conda-meta/empty-0.1.0-h4616a5c_0.json")
+            .is_file());
+
+        // The prefix changed, so the plan can no longer be executed.
+        assert!(matches!(
+            installer().install_plan(&plan).await,
+            Err(InstallerError::PlanOutdated(_))
+        ));
+
+        // The package is cached now and removing it is planned.
+        let result = installer()
+            .dry_run(prefix.path(), vec![record.clone()])
+            .await
+            .unwrap();
+        assert!(result.plan.is_empty());
+        let result = installer()
+            .dry_run(prefix.path(), Vec::new())
+            .await
+            .unwrap();
+        assert_eq!(result.plan.operations[0].kind, PlannedOperationKind::Remove);
+        assert_eq!(result.plan.download_size(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_install_plan_link_scripts() {
+        let cache = tempfile::tempdir().unwrap();
+        let record = get_repodata_record(
+            get_test_data_dir().join("link-scripts/link-scripts-0.1.0-h4616a5c_0.conda"),
+        );
+
+        // The setting of the plan takes precedence over that of the installer.
+        for execute_link_scripts in [false, true] {
+            let prefix = tempfile::tempdir().unwrap();
+            let plan = Installer::new()
+                .with_package_cache(PackageCache::new(cache.path()))
+                .with_execute_link_scripts(execute_link_scripts)
+                .dry_run(prefix.path(), vec![record.clone()])
+                .await
+                .unwrap()
+                .plan;
+            Installer::new()
+                .with_package_cache(PackageCache::new(cache.path()))
+                .with_execute_link_scripts(!execute_link_scripts)
+                .install_plan(&plan)
+                .await
+                .unwrap();
+            assert_eq!(
+                prefix.path().join("i-was-post-linked").exists(),
+                execute_link_scripts
+            );
+        }
+    }
+}
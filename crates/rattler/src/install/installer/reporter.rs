@@ -1,9 +1,17 @@
+use std::{path::Path, time::Duration};
+
 use rattler_conda_types::{PrefixRecord, RepoDataRecord};
 
 use crate::install::Transaction;
 
 /// A trait for reporting progress of the installation process.
 pub trait Reporter: Send + Sync {
+    /// Called while waiting for another process to release the lock on the
+    /// prefix, roughly once a second. `waited` is the time waited so far.
+    fn on_prefix_lock_wait(&self, prefix: &Path, waited: Duration) {
+        let _ = (prefix, waited);
+    }
+
     /// Called when the transaction starts. This is the first method called.
     fn on_transaction_start(&self, transaction: &Transaction<PrefixRecord, RepoDataRecord>);
 
@@ -0,0 +1,307 @@
+//! A write-ahead journal that records the progress of a transaction in a
+//! prefix.
+//!
+//! Before the [`crate::install::Installer`] modifies a prefix it writes the
+//! state of the environment before and after the transaction to
+//! `conda-meta/rattler-journal`. While packages are unlinked and linked, their
+//! progress is appended to the same file and once the transaction completed
+//! the journal is removed again. If the installer is interrupted (e.g. the
+//! process is killed) the journal remains and the next installation into the
+//! prefix reinstalls the packages that were only partially linked or
+//! unlinked. [`crate::install::Installer::recover`] uses the journal to either
+//! finish the interrupted transaction or to restore the previous environment.
+//!
+//! The journal is a JSON-lines file: the first line contains the
+//! [`InstallJournal::previous`] and [`InstallJournal::target`] records, every
+//! following line the new [`PackageState`] of a single package. Because lines
+//! are only ever appended, a crash can at most leave the last line truncated,
+//! which is ignored when reading the journal.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use rattler_conda_types::{PackageName, RepoDataRecord};
+use serde::{Deserialize, Serialize};
+
+/// The name of the journal file in the `conda-meta` directory of a prefix.
+pub const JOURNAL_FILE_NAME: &str = "rattler-journal";
+
+/// The progress of a single package in a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PackageState {
+    /// The files of the package are being removed from the prefix.
+    Unlinking,
+    /// The package was completely removed from the prefix.
+    Unlinked,
+    /// The files of the package are being linked into the prefix.
+    Linking,
+    /// The package was completely linked into the prefix.
+    Linked,
+}
+
+impl PackageState {
+    /// Returns true if the package was left in an inconsistent state.
+    pub fn is_incomplete(self) -> bool {
+        matches!(self, PackageState::Unlinking | PackageState::Linking)
+    }
+}
+
+/// Which state to restore when recovering from an interrupted transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryDirection {
+    /// Finish the transaction by installing [`InstallJournal::target`].
+    RollForward,
+    /// Undo the transaction by installing [`InstallJournal::previous`].
+    RollBack,
+}
+
+/// The contents of the journal of an interrupted transaction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstallJournal {
+    /// The records that were installed before the transaction started.
+    pub previous: Vec<RepoDataRecord>,
+
+    /// The records that are installed once the transaction completed.
+    pub target: Vec<RepoDataRecord>,
+
+    /// The last recorded state of every package touched by the transaction.
+    pub packages: BTreeMap<PackageName, PackageState>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JournalHeader {
+    previous: Vec<RepoDataRecord>,
+    target: Vec<RepoDataRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JournalLine {
+    name: PackageName,
+    state: PackageState,
+}
+
+impl InstallJournal {
+    /// Returns the location of the journal in the given prefix.
+    pub fn path(prefix: &Path) -> PathBuf {
+        prefix.join("conda-meta").join(JOURNAL_FILE_NAME)
+    }
+
+    /// Reads the journal of the given prefix. Returns `None` if no transaction
+    /// was interrupted.
+    pub fn from_prefix(prefix: &Path) -> Result<Option<Self>, std::io::Error> {
+        let file = match fs_err::File::open(Self::path(prefix)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let mut lines = BufReader::new(file).lines();
+
+        // Without a complete header the installer never touched the prefix.
+        let Some(Ok(header)) = lines.next() else {
+            return Ok(None);
+        };
+        let Ok(header) = serde_json::from_str::<JournalHeader>(&header) else {
+            return Ok(None);
+        };
+
+        let mut packages = BTreeMap::new();
+        for line in lines {
+            match serde_json::from_str::<JournalLine>(&line?) {
+                Ok(line) => {
+                    packages.insert(line.name, line.state);
+                }
+                // Only the last line can be truncated.
+                Err(_) => break,
+            }
+        }
+
+        Ok(Some(Self {
+            previous: header.previous,
+            target: header.target,
+            packages,
+        }))
+    }
+
+    /// Returns the names of the packages that were partially linked or
+    /// unlinked when the transaction was interrupted.
+    pub fn incomplete_packages(&self) -> HashSet<PackageName> {
+        self.packages
+            .iter()
+            .filter(|(_, state)| state.is_incomplete())
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Returns the records to install to recover in the given direction.
+    pub fn records(&self, direction: RecoveryDirection) -> &[RepoDataRecord] {
+        match direction {
+            RecoveryDirection::RollForward => &self.target,
+            RecoveryDirection::RollBack => &self.previous,
+        }
+    }
+}
+
+/// Appends the progress of a running transaction to the journal of a prefix.
+/// Clones append to the same file.
+#[derive(Clone)]
+pub(crate) struct JournalWriter {
+    path: PathBuf,
+    file: Arc<Mutex<fs_err::File>>,
+}
+
+impl JournalWriter {
+    /// Creates a new journal in the prefix, replacing an existing one. The
+    /// header is flushed to disk before this function returns.
+    pub fn create(
+        prefix: &Path,
+        previous: Vec<RepoDataRecord>,
+        target: Vec<RepoDataRecord>,
+    ) -> Result<Self, std::io::Error> {
+        let path = InstallJournal::path(prefix);
+        let mut file = fs_err::File::create(&path)?;
+        serde_json::to_writer(&mut file, &JournalHeader { previous, target })?;
+        file.write_all(b"\n")?;
+        file.sync_data()?;
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+        })
+    }
+
+    /// Records the new state of a package. The line is flushed to disk before
+    /// this function returns.
+    pub fn record(&self, name: &PackageName, state: PackageState) -> Result<(), std::io::Error> {
+        let mut line = serde_json::to_vec(&JournalLine {
+            name: name.clone(),
+            state,
+        })?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&line)?;
+        file.sync_data()
+    }
+
+    /// Removes the journal after the transaction completed.
+    pub fn finish(self) -> Result<(), std::io::Error> {
+        drop(self.file);
+        remove_journal(&self.path)
+    }
+}
+
+/// Removes the journal at the given path if it exists.
+pub(crate) fn remove_journal(path: &Path) -> Result<(), std::io::Error> {
+    match fs_err::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use rattler_conda_types::PackageName;
+
+    use super::{InstallJournal, JournalWriter, PackageState, RecoveryDirection};
+    use crate::install::{Installer, TransactionOperation};
+
+    #[tokio::test]
+    async fn test_recover_interrupted_transaction() {
+        let prefix = tempfile::tempdir().unwrap();
+        // The path is canonicalized so that the url survives a round trip
+        // through the journal.
+        let record = crate::get_repodata_record(
+            crate::get_test_data_dir()
+                .join("packages/empty-0.1.0-h4616a5c_0.conda")
+                .canonicalize()
+                .unwrap(),
+        );
+        let name = record.package_record.name.clone();
+
+        // A successful installation leaves no journal behind.
+        Installer::new()
+            .install(prefix.path(), vec![record.clone()])
+            .await
+            .unwrap();
+        assert!(InstallJournal::from_prefix(prefix.path())
+            .unwrap()
+            .is_none());
+
+        // Simulate an installation that was killed while reinstalling the
+        // package, including a truncated last line.
+        let writer =
+            JournalWriter::create(prefix.path(), vec![record.clone()], vec![record.clone()])
+                .unwrap();
+        writer.record(&name, PackageState::Unlinking).unwrap();
+        drop(writer);
+        fs_err::OpenOptions::new()
+            .append(true)
+            .open(InstallJournal::path(prefix.path()))
+            .unwrap()
+            .write_all(b"{\"name\":\"emp")
+            .unwrap();
+
+        let journal = InstallJournal::from_prefix(prefix.path()).unwrap().unwrap();
+        assert_eq!(journal.packages.len(), 1);
+        assert_eq!(
+            journal.incomplete_packages(),
+            [PackageName::new_unchecked("empty")].into_iter().collect()
+        );
+        assert_eq!(journal.records(RecoveryDirection::RollBack), &[record]);
+
+        // Recovering reinstalls the package even though its record is still
+        // present and removes the journal.
+        let result = Installer::new()
+            .recover(prefix.path(), RecoveryDirection::RollForward)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(matches!(
+            result.transaction.operations.as_slice(),
+            [TransactionOperation::Change { .. }]
+        ));
+        assert!(InstallJournal::from_prefix(prefix.path())
+            .unwrap()
+            .is_none());
+        assert!(Installer::new()
+            .recover(prefix.path(), RecoveryDirection::RollForward)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_journal_excludes_ignored_packages() {
+        let prefix = tempfile::tempdir().unwrap();
+        let record = crate::get_repodata_record(
+            crate::get_test_data_dir()
+                .join("packages/empty-0.1.0-h4616a5c_0.conda")
+                .canonicalize()
+                .unwrap(),
+        );
+
+        // A package that cannot be fetched interrupts the transaction after
+        // the journal was written.
+        let mut missing = record.clone();
+        missing.package_record.name = PackageName::new_unchecked("missing");
+        missing.file_name = "missing-0.1.0-h4616a5c_0.conda".to_string();
+        missing.url = url::Url::from_file_path(prefix.path().join(&missing.file_name)).unwrap();
+
+        Installer::new()
+            .with_ignored_packages([record.package_record.name.clone()].into())
+            .install(prefix.path(), vec![record, missing.clone()])
+            .await
+            .unwrap_err();
+
+        // The ignored package is not part of the environment the transaction
+        // produces.
+        let journal = InstallJournal::from_prefix(prefix.path()).unwrap().unwrap();
+        assert!(journal.records(RecoveryDirection::RollBack).is_empty());
+        assert_eq!(journal.records(RecoveryDirection::RollForward), &[missing]);
+    }
+}
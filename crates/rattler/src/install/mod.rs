@@ -19,11 +19,15 @@ pub mod apple_codesign;
 mod clobber_registry;
 mod driver;
 mod entry_point;
+pub mod history;
+pub mod journal;
 pub mod link;
 pub mod link_script;
+mod prefix_lock;
 mod python;
 mod transaction;
 pub mod unlink;
+pub mod verify;
 
 mod installer;
 #[cfg(test)]
@@ -40,6 +44,7 @@ use std::{
 };
 
 pub use apple_codesign::AppleCodeSignBehavior;
+pub use clobber_registry::ClobberedPath;
 pub use driver::InstallDriver;
 use fs_err::tokio as tokio_fs;
 use futures::{stream::FuturesUnordered, FutureExt, StreamExt};
@@ -48,9 +53,13 @@ pub use installer::{
     DefaultProgressFormatter, IndicatifReporter, IndicatifReporterBuilder, Placement,
     ProgressFormatter,
 };
-pub use installer::{Installer, InstallerError, Reporter};
+pub use installer::{
+    DryRunResult, InstallPlan, Installer, InstallerError, PlannedOperation, PlannedOperationKind,
+    Reporter,
+};
 use itertools::Itertools;
 pub use link::{link_file, LinkFileError, LinkMethod};
+pub use prefix_lock::{PrefixLock, PrefixLockError, PREFIX_LOCK_FILE_NAME};
 pub use python::PythonInfo;
 use rattler_conda_types::{
     package::{IndexJson, LinkJson, NoArchLinks, PackageFile, PathsEntry, PathsJson},
@@ -65,7 +74,7 @@ use simple_spawn_blocking::Cancelled;
 use tokio::task::JoinError;
 use tracing::instrument;
 pub use transaction::{Transaction, TransactionError, TransactionOperation};
-pub use unlink::{empty_trash, unlink_package};
+pub use unlink::{empty_trash, unlink_package, unlink_package_locked};
 
 pub use crate::install::entry_point::{get_windows_launcher, python_entry_point_template};
 use crate::install::{
@@ -0,0 +1,169 @@
+//! An advisory lock that prevents multiple processes from modifying the same
+//! prefix at the same time.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use fs4::fs_std::FileExt;
+
+/// The name of the lock file in the `conda-meta` directory of a prefix.
+pub const PREFIX_LOCK_FILE_NAME: &str = "rattler.lock";
+
+/// How often the lock is polled while another process holds it.
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the wait callback is invoked while waiting for the lock.
+const WAIT_REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// An error that can occur when acquiring a [`PrefixLock`].
+#[derive(Debug, thiserror::Error)]
+pub enum PrefixLockError {
+    /// The lock file could not be created or locked.
+    #[error("failed to lock '{0}'")]
+    IoError(PathBuf, #[source] std::io::Error),
+
+    /// Another process held the lock for longer than the timeout.
+    #[error("timed out after {}s waiting for another process to release '{}'", .1.as_secs(), .0.display())]
+    Timeout(PathBuf, Duration),
+}
+
+/// An exclusive, advisory lock on a prefix.
+///
+/// The lock is held on `conda-meta/rattler.lock` and is released when this
+/// value is dropped. The [`crate::install::Installer`] holds the lock for the
+/// duration of a transaction, other code that modifies the prefix should hold
+/// it as well. [`crate::install::unlink_package_locked`] and
+/// [`crate::install::InstallDriver::post_process_locked`] acquire the lock
+/// themselves.
+///
+/// Like all advisory locks, this only protects against other processes that
+/// also use the lock. Locks are not reentrant: acquiring the lock of a prefix
+/// that is already locked by the current process waits as well.
+#[derive(Debug)]
+pub struct PrefixLock {
+    file: std::fs::File,
+    path: PathBuf,
+}
+
+impl Drop for PrefixLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+impl PrefixLock {
+    /// Returns the path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Tries to acquire the lock of the prefix without waiting. Returns `None`
+    /// if the lock is held by someone else.
+    pub fn try_acquire(prefix: &Path) -> Result<Option<Self>, PrefixLockError> {
+        let path = prefix.join("conda-meta").join(PREFIX_LOCK_FILE_NAME);
+        let file = open_lock_file(&path)?;
+        let acquired = FileExt::try_lock_exclusive(&file)
+            .map_err(|e| PrefixLockError::IoError(path.clone(), e))?;
+        Ok(acquired.then_some(Self { file, path }))
+    }
+
+    /// Acquires the lock of the prefix, waiting at most `timeout` for another
+    /// process to release it. Without a timeout this waits indefinitely.
+    pub async fn acquire(
+        prefix: &Path,
+        timeout: Option<Duration>,
+    ) -> Result<Self, PrefixLockError> {
+        Self::acquire_with_wait_callback(prefix, timeout, |_| {}).await
+    }
+
+    /// Same as [`Self::acquire`] but calls `on_wait` with the time waited so
+    /// far when the lock is contended and then roughly once a second until
+    /// the lock is acquired.
+    pub async fn acquire_with_wait_callback(
+        prefix: &Path,
+        timeout: Option<Duration>,
+        mut on_wait: impl FnMut(Duration),
+    ) -> Result<Self, PrefixLockError> {
+        let start = Instant::now();
+        let mut last_report: Option<Instant> = None;
+        loop {
+            if let Some(lock) = Self::try_acquire(prefix)? {
+                return Ok(lock);
+            }
+
+            let waited = start.elapsed();
+            if let Some(timeout) = timeout {
+                if waited >= timeout {
+                    return Err(PrefixLockError::Timeout(
+                        prefix.join("conda-meta").join(PREFIX_LOCK_FILE_NAME),
+                        timeout,
+                    ));
+                }
+            }
+            if last_report.is_none_or(|last| last.elapsed() >= WAIT_REPORT_INTERVAL) {
+                tracing::debug!("waiting for the lock on {}", prefix.display());
+                on_wait(waited);
+                last_report = Some(Instant::now());
+            }
+
+            let mut interval = POLL_INTERVAL;
+            if let Some(timeout) = timeout {
+                interval = interval.min(timeout - waited);
+            }
+            tokio::time::sleep(interval).await;
+        }
+    }
+}
+
+fn open_lock_file(path: &Path) -> Result<std::fs::File, PrefixLockError> {
+    if let Some(parent) = path.parent() {
+        fs_err::create_dir_all(parent)
+            .map_err(|e| PrefixLockError::IoError(path.to_path_buf(), e))?;
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .read(true)
+        .open(path)
+        .map_err(|e| PrefixLockError::IoError(path.to_path_buf(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{PrefixLock, PrefixLockError};
+
+    #[tokio::test]
+    async fn test_prefix_lock() {
+        let prefix = tempfile::tempdir().unwrap();
+
+        let lock = PrefixLock::try_acquire(prefix.path()).unwrap().unwrap();
+        assert!(lock.path().is_file());
+        assert!(PrefixLock::try_acquire(prefix.path()).unwrap().is_none());
+
+        // Waiting for the lock times out and reports the wait.
+        let mut waits = 0;
+        let result = PrefixLock::acquire_with_wait_callback(
+            prefix.path(),
+            Some(Duration::from_millis(300)),
+            |_| waits += 1,
+        )
+        .await;
+        assert!(matches!(result, Err(PrefixLockError::Timeout(..))));
+        assert_eq!(waits, 1);
+
+        // Once released, the lock can be acquired again.
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            drop(lock);
+        });
+        PrefixLock::acquire(prefix.path(), Some(Duration::from_secs(10)))
+            .await
+            .unwrap();
+        release.await.unwrap();
+    }
+}
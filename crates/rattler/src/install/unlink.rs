@@ -5,12 +5,15 @@ use std::{
     ffi::OsString,
     io::ErrorKind,
     path::{Path, PathBuf},
+    time::Duration,
 };
 
 use fs_err::tokio as tokio_fs;
 use rattler_conda_types::{prefix::Prefix, prefix_record::PrefixRecord};
 use uuid::Uuid;
 
+use super::{PrefixLock, PrefixLockError};
+
 /// Error that can occur while unlinking a package.
 #[derive(Debug, thiserror::Error)]
 pub enum UnlinkError {
@@ -37,6 +40,10 @@ pub enum UnlinkError {
     /// Failed to move a file to the trash
     #[error("failed to move file: {0} to {1}")]
     FailedToMoveFile(String, String, std::io::Error),
+
+    /// Failed to lock the prefix
+    #[error("failed to lock the prefix")]
+    FailedToLockPrefix(#[source] PrefixLockError),
 }
 
 pub(crate) fn recursively_remove_empty_directories(
@@ -178,6 +185,9 @@ async fn move_to_trash(target_prefix: &Prefix, path: &Path) -> Result<(), Unlink
 }
 
 /// Completely remove the specified package from the environment.
+///
+/// The caller is responsible for holding the [`PrefixLock`] of the prefix
+/// while packages are unlinked, see [`unlink_package_locked`].
 pub async fn unlink_package(
     target_prefix: &Prefix,
     prefix_record: &PrefixRecord,
@@ -213,18 +223,37 @@ pub async fn unlink_package(
     Ok(())
 }
 
+/// Same as [`unlink_package`] but holds the [`PrefixLock`] of the prefix while
+/// the package is unlinked, waiting at most `timeout` for another process to
+/// release it.
+pub async fn unlink_package_locked(
+    target_prefix: &Prefix,
+    prefix_record: &PrefixRecord,
+    timeout: Option<Duration>,
+) -> Result<(), UnlinkError> {
+    let _lock = PrefixLock::acquire(target_prefix.path(), timeout)
+        .await
+        .map_err(UnlinkError::FailedToLockPrefix)?;
+    unlink_package(target_prefix, prefix_record).await
+}
+
 #[cfg(test)]
 mod tests {
     use std::{
         fs::{self, File},
         io::Write,
         path::Path,
+        time::Duration,
     };
 
-    use rattler_conda_types::{prefix::Prefix, Platform, RepoDataRecord};
+    use rattler_conda_types::{prefix::Prefix, Platform, PrefixRecord, RepoDataRecord};
 
+    use super::UnlinkError;
     use crate::install::test_utils::download_and_get_prefix_record;
-    use crate::install::{empty_trash, unlink_package, InstallDriver, Transaction};
+    use crate::install::{
+        empty_trash, unlink_package, unlink_package_locked, InstallDriver, Installer, PrefixLock,
+        Transaction,
+    };
 
     #[tokio::test]
     async fn test_unlink_package() {
@@ -504,4 +533,44 @@ mod tests {
         empty_trash(environment_dir.path()).await.unwrap();
         assert!(!trash_path.exists());
     }
+
+    #[tokio::test]
+    async fn test_unlink_package_locked() {
+        let environment_dir = tempfile::TempDir::new().unwrap();
+        let prefix = Prefix::create(environment_dir.path()).unwrap();
+        Installer::new()
+            .install(
+                prefix.path(),
+                vec![crate::get_repodata_record(
+                    crate::get_test_data_dir().join("packages/empty-0.1.0-h4616a5c_0.conda"),
+                )],
+            )
+            .await
+            .unwrap();
+        let prefix_record = PrefixRecord::collect_from_prefix::<PrefixRecord>(prefix.path())
+            .unwrap()
+            .remove(0);
+
+        // The package is not unlinked while another process holds the lock.
+        let lock = PrefixLock::try_acquire(prefix.path()).unwrap().unwrap();
+        assert!(matches!(
+            unlink_package_locked(&prefix, &prefix_record, Some(Duration::from_millis(100))).await,
+            Err(UnlinkError::FailedToLockPrefix(_))
+        ));
+        assert!(prefix
+            .path()
+            .join("conda-meta")
+            .join(prefix_record.file_name())
+            .is_file());
+
+        drop(lock);
+        unlink_package_locked(&prefix, &prefix_record, None)
+            .await
+            .unwrap();
+        assert!(!prefix
+            .path()
+            .join("conda-meta")
+            .join(prefix_record.file_name())
+            .exists());
+    }
 }
@@ -0,0 +1,401 @@
+//! Verifies that the files installed in a prefix still match the information
+//! stored in the `conda-meta` records of the prefix, and repairs packages that
+//! drifted by re-linking them from the package cache.
+
+use std::{
+    collections::HashSet,
+    fmt::{Display, Formatter},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use rattler_cache::validation::ValidationMode;
+use rattler_conda_types::{
+    prefix_record::{PathType, PathsEntry},
+    MatchSpec, PackageName, ParseStrictness, PrefixRecord,
+};
+use rattler_digest::{Sha256, Sha256Hash};
+use rayon::prelude::*;
+
+use super::{
+    clobber_registry::CLOBBERS_DIR_NAME, installer::InstallationResult, Installer, InstallerError,
+    Prefix,
+};
+
+/// Describes how an installed file differs from its record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathIssue {
+    /// The file does not exist.
+    Missing,
+
+    /// A directory was expected but something else was found.
+    NotADirectory,
+
+    /// The file is a symbolic link that points to a file that does not exist.
+    DanglingSoftLink,
+
+    /// The size of the file differs from the recorded size.
+    SizeMismatch {
+        /// The recorded size
+        expected: u64,
+        /// The size of the file in the prefix
+        actual: u64,
+    },
+
+    /// The hash of the file differs from the recorded hash.
+    HashMismatch {
+        /// The recorded hash
+        expected: Sha256Hash,
+        /// The hash of the file in the prefix
+        actual: Sha256Hash,
+    },
+
+    /// The file could not be read to compute its hash.
+    Unreadable(String),
+}
+
+impl Display for PathIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PathIssue::Missing => write!(f, "missing"),
+            PathIssue::NotADirectory => write!(f, "not a directory"),
+            PathIssue::DanglingSoftLink => write!(f, "dangling softlink"),
+            PathIssue::SizeMismatch { expected, actual } => {
+                write!(f, "size mismatch (expected {expected}, found {actual})")
+            }
+            PathIssue::HashMismatch { expected, actual } => {
+                write!(f, "hash mismatch (expected {expected:x}, found {actual:x})")
+            }
+            PathIssue::Unreadable(err) => write!(f, "unreadable ({err})"),
+        }
+    }
+}
+
+/// A single file of a package that drifted from its record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathDrift {
+    /// The path of the file relative to the prefix.
+    pub relative_path: PathBuf,
+
+    /// How the file differs from its record.
+    pub issue: PathIssue,
+}
+
+/// The files of an installed package that drifted from its record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageDrift {
+    /// The name of the package.
+    pub name: PackageName,
+
+    /// The name of the record of the package in the `conda-meta` directory.
+    pub record_file_name: String,
+
+    /// The files that drifted.
+    pub paths: Vec<PathDrift>,
+}
+
+/// The result of [`verify_prefix`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefixVerification {
+    /// The packages that have at least one file that drifted, ordered by name.
+    pub packages: Vec<PackageDrift>,
+
+    /// Files in the `__clobbers__` directory of the prefix that are not
+    /// referenced by any installed package, relative to the prefix.
+    pub leftover_clobbers: Vec<PathBuf>,
+}
+
+impl PrefixVerification {
+    /// Returns true if no drift was detected.
+    pub fn is_healthy(&self) -> bool {
+        self.packages.is_empty() && self.leftover_clobbers.is_empty()
+    }
+}
+
+/// Verifies the files of all packages installed in `prefix` against their
+/// records in `conda-meta`.
+///
+/// With [`ValidationMode::Fast`] only the existence, type and size of files
+/// are checked. [`ValidationMode::Full`] also compares the sha256 hashes of
+/// the files.
+pub fn verify_prefix(
+    prefix: &Path,
+    mode: ValidationMode,
+) -> Result<PrefixVerification, std::io::Error> {
+    let records: Vec<PrefixRecord> = PrefixRecord::collect_from_prefix(prefix)?;
+
+    let mut packages = records
+        .par_iter()
+        .filter_map(|record| {
+            let paths = record
+                .paths_data
+                .paths
+                .iter()
+                .filter_map(|entry| {
+                    verify_path(prefix, entry, mode).map(|issue| PathDrift {
+                        relative_path: entry.relative_path.clone(),
+                        issue,
+                    })
+                })
+                .collect::<Vec<_>>();
+            (!paths.is_empty()).then(|| PackageDrift {
+                name: record.repodata_record.package_record.name.clone(),
+                record_file_name: record.file_name(),
+                paths,
+            })
+        })
+        .collect::<Vec<_>>();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let referenced = records
+        .iter()
+        .flat_map(|record| &record.paths_data.paths)
+        .map(|entry| entry.relative_path.as_path())
+        .collect::<HashSet<_>>();
+    let mut leftover_clobbers = Vec::new();
+    collect_files(prefix, Path::new(CLOBBERS_DIR_NAME), &mut leftover_clobbers)?;
+    leftover_clobbers.retain(|path| !referenced.contains(path.as_path()));
+    leftover_clobbers.sort();
+
+    Ok(PrefixVerification {
+        packages,
+        leftover_clobbers,
+    })
+}
+
+/// Repairs the drift found by [`verify_prefix`].
+///
+/// Packages that drifted are re-linked from the package cache by
+/// reinstalling them with the given `installer`, which determines the package
+/// cache, download client and reporter that are used. All other packages are
+/// left untouched. Leftover files in the `__clobbers__` directory are removed.
+/// The prefix is locked for the whole repair.
+///
+/// Note that files that were modified in place through a hard link also
+/// modified the file in the package cache. Validate or clear the affected
+/// cache entries before repairing such packages.
+pub async fn repair_prefix(
+    prefix: &Path,
+    verification: &PrefixVerification,
+    installer: Installer,
+) -> Result<InstallationResult, InstallerError> {
+    // Hold the lock for the whole repair so that the records that are
+    // reinstalled are the ones in the prefix.
+    let target_prefix = Prefix::create(prefix.to_path_buf())
+        .map_err(|err| InstallerError::FailedToCreatePrefix(prefix.to_path_buf(), err))?;
+    let _lock = installer.lock_prefix(&target_prefix).await?;
+    for path in &verification.leftover_clobbers {
+        match fs_err::remove_file(prefix.join(path)) {
+            Err(e) if e.kind() != ErrorKind::NotFound => {
+                return Err(InstallerError::IoError(
+                    format!("failed to remove {}", path.display()),
+                    e,
+                ));
+            }
+            _ => {}
+        }
+    }
+    remove_empty_directories(&prefix.join(CLOBBERS_DIR_NAME));
+
+    let installed: Vec<PrefixRecord> = PrefixRecord::collect_from_prefix(prefix)
+        .map_err(InstallerError::FailedToDetectInstalledPackages)?;
+
+    // Reinstalling a package rewrites its record, so pass along the specs that
+    // were requested to preserve them.
+    let requested_specs = installed
+        .iter()
+        .flat_map(|record| &record.requested_specs)
+        .filter_map(|spec| MatchSpec::from_str(spec, ParseStrictness::Lenient).ok())
+        .collect();
+    let broken = verification
+        .packages
+        .iter()
+        .map(|package| package.name.clone())
+        .collect();
+    let records = installed
+        .iter()
+        .map(|record| record.repodata_record.clone())
+        .collect::<Vec<_>>();
+
+    installer
+        .with_installed_packages(installed)
+        .with_reinstall_packages(broken)
+        .with_requested_specs(requested_specs)
+        .install_locked(target_prefix, records)
+        .await
+}
+
+/// Verifies a single file of a package. Returns `None` if the file matches
+/// its record.
+fn verify_path(prefix: &Path, entry: &PathsEntry, mode: ValidationMode) -> Option<PathIssue> {
+    let path = prefix.join(&entry.relative_path);
+    let Ok(link_metadata) = fs_err::symlink_metadata(&path) else {
+        return Some(PathIssue::Missing);
+    };
+
+    // Follow symbolic links, files might have been installed as softlinks into
+    // the package cache.
+    let Ok(metadata) = fs_err::metadata(&path) else {
+        return Some(PathIssue::DanglingSoftLink);
+    };
+
+    match entry.path_type {
+        PathType::Directory => {
+            return (!metadata.is_dir()).then_some(PathIssue::NotADirectory);
+        }
+        // The recorded size and hash of a softlink refer to the link itself.
+        PathType::SoftLink if link_metadata.is_symlink() => return None,
+        _ => {}
+    }
+
+    if let Some(expected) = entry.size_in_bytes {
+        if metadata.len() != expected {
+            return Some(PathIssue::SizeMismatch {
+                expected,
+                actual: metadata.len(),
+            });
+        }
+    }
+
+    if mode == ValidationMode::Full {
+        if let Some(expected) = entry.sha256_in_prefix.or(entry.sha256) {
+            let actual = match rattler_digest::compute_file_digest::<Sha256>(&path) {
+                Ok(actual) => actual,
+                Err(err) => return Some(PathIssue::Unreadable(err.to_string())),
+            };
+            if actual != expected {
+                return Some(PathIssue::HashMismatch { expected, actual });
+            }
+        }
+    }
+
+    None
+}
+
+/// Collects all files below `relative_dir` into `files`, relative to `root`.
+fn collect_files(
+    root: &Path,
+    relative_dir: &Path,
+    files: &mut Vec<PathBuf>,
+) -> Result<(), std::io::Error> {
+    let read_dir = match fs_err::read_dir(root.join(relative_dir)) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in read_dir {
+        let entry = entry?;
+        let relative_path = relative_dir.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            collect_files(root, &relative_path, files)?;
+        } else {
+            files.push(relative_path);
+        }
+    }
+    Ok(())
+}
+
+/// Removes all empty directories below and including `path`.
+fn remove_empty_directories(path: &Path) {
+    if let Ok(read_dir) = std::fs::read_dir(path) {
+        for entry in read_dir.filter_map(Result::ok) {
+            if entry.file_type().is_ok_and(|ty| ty.is_dir()) {
+                remove_empty_directories(&entry.path());
+            }
+        }
+    }
+    // This fails if the directory is not empty, which is fine.
+    let _ = std::fs::remove_dir(path);
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use rattler_cache::validation::ValidationMode;
+    use rattler_conda_types::prefix_record::{PathType, PathsEntry};
+
+    use super::{repair_prefix, verify_path, verify_prefix, PathIssue};
+    use crate::{install::Installer, package_cache::PackageCache};
+
+    #[tokio::test]
+    async fn test_verify_and_repair() {
+        let prefix = tempfile::tempdir().unwrap();
+        let cache_dir = tempfile::tempdir().unwrap();
+        let installer = || Installer::new().with_package_cache(PackageCache::new(cache_dir.path()));
+
+        let record = crate::get_repodata_record(
+            crate::get_test_data_dir().join("clobber/clobber-1-0.1.0-h4616a5c_0.tar.bz2"),
+        );
+        installer()
+            .install(prefix.path(), vec![record])
+            .await
+            .unwrap();
+        assert!(verify_prefix(prefix.path(), ValidationMode::Full)
+            .unwrap()
+            .is_healthy());
+
+        // Damage the installation. The file is replaced instead of modified in place,
+        // because it is hard linked to the file in the package cache.
+        let installed_file = PathBuf::from("clobber.txt");
+        fs_err::remove_file(prefix.path().join(&installed_file)).unwrap();
+        fs_err::write(prefix.path().join(&installed_file), "modified!").unwrap();
+        let stray_clobber = PathBuf::from("__clobbers__/stray/clobber.txt");
+        fs_err::create_dir_all(prefix.path().join("__clobbers__/stray")).unwrap();
+        fs_err::write(prefix.path().join(&stray_clobber), "stray").unwrap();
+
+        let verification = verify_prefix(prefix.path(), ValidationMode::Full).unwrap();
+        assert_eq!(verification.leftover_clobbers, vec![stray_clobber]);
+        assert_eq!(verification.packages.len(), 1);
+        let drift = &verification.packages[0];
+        assert_eq!(drift.name.as_normalized(), "clobber-1");
+        assert_eq!(drift.paths[0].relative_path, installed_file);
+        assert!(matches!(
+            drift.paths[0].issue,
+            PathIssue::SizeMismatch { .. } | PathIssue::HashMismatch { .. }
+        ));
+
+        fs_err::remove_file(prefix.path().join(&installed_file)).unwrap();
+        let verification = verify_prefix(prefix.path(), ValidationMode::Fast).unwrap();
+        assert_eq!(verification.packages[0].paths[0].issue, PathIssue::Missing);
+
+        // Repairing re-links the broken package.
+        let result = repair_prefix(prefix.path(), &verification, installer())
+            .await
+            .unwrap();
+        assert_eq!(result.transaction.operations.len(), 1);
+        assert!(!prefix.path().join("__clobbers__").exists());
+        assert!(verify_prefix(prefix.path(), ValidationMode::Full)
+            .unwrap()
+            .is_healthy());
+    }
+
+    #[test]
+    fn test_verify_unreadable_file() {
+        let prefix = tempfile::tempdir().unwrap();
+        // A directory in place of a file cannot be read to compute its hash.
+        fs_err::create_dir(prefix.path().join("file.txt")).unwrap();
+        let entry = PathsEntry {
+            relative_path: PathBuf::from("file.txt"),
+            original_path: None,
+            path_type: PathType::HardLink,
+            no_link: false,
+            sha256: Some(
+                rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>("contents"),
+            ),
+            sha256_in_prefix: None,
+            size_in_bytes: None,
+            file_mode: None,
+            prefix_placeholder: None,
+        };
+
+        assert_eq!(
+            verify_path(prefix.path(), &entry, ValidationMode::Fast),
+            None
+        );
+        assert!(matches!(
+            verify_path(prefix.path(), &entry, ValidationMode::Full),
+            Some(PathIssue::Unreadable(_))
+        ));
+    }
+}
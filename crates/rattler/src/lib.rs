@@ -17,7 +17,9 @@ use std::path::PathBuf;
 
 #[cfg(feature = "cli-tools")]
 pub mod cli;
+pub mod export;
 pub mod install;
+pub mod pack;
 pub use rattler_cache::{package_cache, validation};
 
 /// A helper function that returns a [`Channel`] instance that points to an
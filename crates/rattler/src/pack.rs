@@ -0,0 +1,595 @@
+//! Packs an installed prefix into a relocatable archive that can be unpacked
+//! at a different location, similar to `conda-pack`.
+//!
+//! Files that were installed with a prefix placeholder contain the absolute
+//! path of the prefix. [`pack`] records these files, together with the path of
+//! the prefix, in a [`PackManifest`] that is stored in the archive.
+//! [`unpack`] extracts the archive and calls [`relocate`] which replaces the
+//! old path with the new location and regenerates the activation scripts.
+//!
+//! Symbolic links that point into the prefix are stored relative to the link.
+//! Files outside of the prefix that symbolic links point to, e.g. in the
+//! package cache when the prefix was installed with softlinks, are stored as
+//! copies.
+
+use std::{
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use rattler_conda_types::{
+    compression_level::CompressionLevel,
+    package::FileMode,
+    prefix_record::{PathType, PrefixRecord},
+    Platform,
+};
+use rattler_package_streaming::ExtractError;
+use rattler_shell::{
+    activation::{ActivationError, ActivationVariables, Activator, PathModificationBehavior},
+    shell::{Bash, CmdExe, Shell},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::install::{
+    journal::InstallJournal, link::copy_and_replace_placeholders, PrefixLock, PrefixLockError,
+    PREFIX_LOCK_FILE_NAME,
+};
+
+/// The location of the [`PackManifest`] in a packed prefix. The manifest is
+/// stored as JSON but without extension, because all `.json` files in
+/// `conda-meta` are read as package records.
+pub const PACK_MANIFEST_PATH: &str = "conda-meta/rattler-pack";
+
+/// Describes how to relocate a packed prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PackManifest {
+    /// The path of the prefix the files in [`Self::prefix_files`] refer to.
+    pub prefix: String,
+
+    /// The platform of the prefix.
+    pub platform: Platform,
+
+    /// The files that contain the path of the prefix.
+    pub prefix_files: Vec<PrefixFile>,
+}
+
+/// A file that contains the path of the prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PrefixFile {
+    /// The path of the file relative to the prefix.
+    pub path: PathBuf,
+
+    /// How to replace the prefix in the file.
+    pub file_mode: FileMode,
+}
+
+/// An error that can occur when packing or unpacking a prefix.
+#[derive(Debug, thiserror::Error)]
+pub enum PackError {
+    /// A generic IO error occurred
+    #[error("{0}")]
+    IoError(String, #[source] std::io::Error),
+
+    /// The prefix is currently being modified by another process.
+    #[error("the prefix is locked by another process")]
+    PrefixLocked,
+
+    /// The lock of the prefix could not be acquired.
+    #[error("failed to lock the prefix")]
+    FailedToLockPrefix(#[source] PrefixLockError),
+
+    /// The last transaction in the prefix did not complete.
+    #[error("the last transaction in the prefix was interrupted")]
+    InterruptedTransaction,
+
+    /// The path of the prefix cannot be used for prefix replacement.
+    #[error("the path '{0}' is not valid UTF-8")]
+    InvalidPrefixPath(PathBuf),
+
+    /// The archive was not created with [`pack`].
+    #[error("the archive does not contain {PACK_MANIFEST_PATH}")]
+    MissingManifest,
+
+    /// The archive could not be extracted.
+    #[error("failed to extract the archive")]
+    ExtractError(#[source] ExtractError),
+
+    /// Binary files can only be relocated to a path that is not longer than
+    /// the original prefix.
+    #[error(
+        "the prefix contains binary files that cannot be relocated to a path longer than '{0}'"
+    )]
+    PrefixTooLong(String),
+
+    /// A symbolic link in the prefix points to a directory outside of it.
+    #[error("the symbolic link '{0}' points to a directory outside of the prefix")]
+    ExternalSymlink(PathBuf),
+
+    /// The activation scripts could not be generated.
+    #[error("failed to generate the activation scripts")]
+    ActivationError(#[source] ActivationError),
+}
+
+/// Writes the contents of the prefix as a `.tar.bz2` archive to
+/// `destination` and returns the manifest that is stored in it.
+///
+/// All files in the prefix are included, not only the files that belong to
+/// installed packages. The prefix must not be modified while it is packed,
+/// packing fails if another process holds its [`PrefixLock`].
+pub fn pack(
+    prefix: &Path,
+    platform: Platform,
+    destination: impl Write,
+) -> Result<PackManifest, PackError> {
+    let _lock = PrefixLock::try_acquire(prefix)
+        .map_err(PackError::FailedToLockPrefix)?
+        .ok_or(PackError::PrefixLocked)?;
+    if InstallJournal::path(prefix).exists() {
+        return Err(PackError::InterruptedTransaction);
+    }
+
+    let records: Vec<PrefixRecord> = PrefixRecord::collect_from_prefix(prefix)
+        .map_err(|e| PackError::IoError("failed to read the installed packages".to_string(), e))?;
+    let mut prefix_files: Vec<PrefixFile> = records
+        .iter()
+        .flat_map(|record| &record.paths_data.paths)
+        .filter_map(|entry| {
+            let file_mode = match entry.path_type {
+                PathType::UnixPythonEntryPoint | PathType::WindowsPythonEntryPointScript => {
+                    FileMode::Text
+                }
+                _ if entry.prefix_placeholder.is_some() => {
+                    entry.file_mode.unwrap_or(FileMode::Text)
+                }
+                _ => return None,
+            };
+            Some(PrefixFile {
+                path: entry.relative_path.clone(),
+                file_mode,
+            })
+        })
+        .collect();
+    prefix_files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let manifest = PackManifest {
+        prefix: prefix
+            .to_str()
+            .ok_or_else(|| PackError::InvalidPrefixPath(prefix.to_path_buf()))?
+            .to_string(),
+        platform,
+        prefix_files,
+    };
+
+    let io_err =
+        |e: std::io::Error| PackError::IoError("failed to write the archive".to_string(), e);
+    let mut archive = tar::Builder::new(bzip2::write::BzEncoder::new(
+        destination,
+        bzip2::Compression::new(CompressionLevel::Default.to_bzip2_level().map_err(io_err)?),
+    ));
+    archive.follow_symlinks(false);
+
+    // The manifest is the first entry, so that `unpack` can reject the archive
+    // before anything is extracted.
+    let manifest_json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| PackError::IoError("failed to write the manifest".to_string(), e.into()))?;
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest_json.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    archive
+        .append_data(&mut header, PACK_MANIFEST_PATH, manifest_json.as_slice())
+        .map_err(io_err)?;
+
+    let canonical_prefix = fs_err::canonicalize(prefix)
+        .map_err(|e| PackError::IoError("failed to read the prefix".to_string(), e))?;
+    for entry in walkdir::WalkDir::new(prefix)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| !is_excluded(prefix, entry.path()))
+    {
+        let entry = entry
+            .map_err(|e| PackError::IoError("failed to read the prefix".to_string(), e.into()))?;
+        let relative_path = entry
+            .path()
+            .strip_prefix(prefix)
+            .expect("walkdir returns paths in the prefix");
+        if entry.path_is_symlink() {
+            append_symlink(
+                &mut archive,
+                prefix,
+                &canonical_prefix,
+                entry.path(),
+                relative_path,
+            )?;
+            continue;
+        }
+        archive
+            .append_path_with_name(entry.path(), relative_path)
+            .map_err(|e| {
+                PackError::IoError(format!("failed to add {}", relative_path.display()), e)
+            })?;
+    }
+
+    archive
+        .into_inner()
+        .and_then(bzip2::write::BzEncoder::finish)
+        .map_err(io_err)?;
+
+    Ok(manifest)
+}
+
+/// Adds the symbolic link at `path` to the archive. Absolute links into the
+/// prefix are stored as relative links and links to files outside of the
+/// prefix as copies of the files, so that neither dangles once the archive
+/// is unpacked somewhere else.
+fn append_symlink<W: Write>(
+    archive: &mut tar::Builder<W>,
+    prefix: &Path,
+    canonical_prefix: &Path,
+    path: &Path,
+    relative_path: &Path,
+) -> Result<(), PackError> {
+    let io_err = |e: std::io::Error| {
+        PackError::IoError(format!("failed to add {}", relative_path.display()), e)
+    };
+    let target = fs_err::read_link(path).map_err(io_err)?;
+    let parent = path.parent().expect("links in the prefix have a parent");
+    if target.is_absolute() && target.starts_with(prefix) {
+        let relative_target =
+            pathdiff::diff_paths(&target, parent).expect("both paths are absolute");
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Symlink);
+        header.set_size(0);
+        header.set_mode(0o777);
+        return archive
+            .append_link(&mut header, relative_path, relative_target)
+            .map_err(io_err);
+    }
+
+    // Relative links within the prefix are stored as they are, even if they
+    // point to another link, which is handled separately.
+    let link_target = parent.join(&target);
+    let target_in_prefix = link_target
+        .parent()
+        .zip(link_target.file_name())
+        .and_then(|(dir, name)| Some(fs_err::canonicalize(dir).ok()?.join(name)))
+        .is_some_and(|link_target| link_target.starts_with(canonical_prefix));
+    match fs_err::canonicalize(path) {
+        Ok(resolved) if !target_in_prefix => {
+            if resolved.is_dir() {
+                return Err(PackError::ExternalSymlink(relative_path.to_path_buf()));
+            }
+            let mut file = fs_err::File::open(&resolved).map_err(io_err)?;
+            archive
+                .append_file(relative_path, file.file_mut())
+                .map_err(io_err)
+        }
+        // Dangling links are stored as they are.
+        _ => archive
+            .append_path_with_name(path, relative_path)
+            .map_err(io_err),
+    }
+}
+
+/// Extracts an archive created with [`pack`] into `target_prefix` and
+/// [`relocate`]s it.
+///
+/// The manifest is read and checked before anything is extracted, an archive
+/// that cannot be relocated to `target_prefix` leaves it untouched.
+pub fn unpack(archive: impl Read, target_prefix: &Path) -> Result<PackManifest, PackError> {
+    let extract_err = |e: std::io::Error| PackError::ExtractError(ExtractError::IoError(e));
+    let mut archive = rattler_package_streaming::read::stream_tar_bz2(archive);
+    let mut entries = archive.entries().map_err(extract_err)?;
+
+    let mut manifest_entry = entries
+        .next()
+        .ok_or(PackError::MissingManifest)?
+        .map_err(extract_err)?;
+    if manifest_entry.path().map_err(extract_err)? != Path::new(PACK_MANIFEST_PATH) {
+        return Err(PackError::MissingManifest);
+    }
+    let manifest: PackManifest = serde_json::from_reader(&mut manifest_entry)
+        .map_err(|e| PackError::IoError("failed to parse the manifest".to_string(), e.into()))?;
+    ensure_relocatable(target_prefix, &manifest)?;
+
+    fs_err::create_dir_all(target_prefix).map_err(extract_err)?;
+    for entry in entries {
+        entry
+            .and_then(|mut entry| entry.unpack_in(target_prefix))
+            .map_err(extract_err)?;
+    }
+
+    relocate(target_prefix, &manifest)
+}
+
+/// Returns `target_prefix` as a string if the files in the manifest can be
+/// relocated to it.
+fn ensure_relocatable<'a>(
+    target_prefix: &'a Path,
+    manifest: &PackManifest,
+) -> Result<&'a str, PackError> {
+    let new_prefix = target_prefix
+        .to_str()
+        .ok_or_else(|| PackError::InvalidPrefixPath(target_prefix.to_path_buf()))?;
+    let has_binary_files = manifest
+        .prefix_files
+        .iter()
+        .any(|file| file.file_mode == FileMode::Binary);
+    if has_binary_files
+        && !manifest.platform.is_windows()
+        && new_prefix.len() > manifest.prefix.len()
+    {
+        return Err(PackError::PrefixTooLong(manifest.prefix.clone()));
+    }
+    Ok(new_prefix)
+}
+
+/// Replaces the prefix recorded in the manifest with `target_prefix` in all
+/// files that contain it and regenerates the activation scripts
+/// (`bin/activate` or `Scripts\activate.bat`). Returns the manifest of the
+/// relocated prefix, which is also stored in the prefix.
+///
+/// On macOS, binaries that were modified need to be signed again before they
+/// can be executed.
+pub fn relocate(target_prefix: &Path, manifest: &PackManifest) -> Result<PackManifest, PackError> {
+    let new_prefix = ensure_relocatable(target_prefix, manifest)?;
+
+    if new_prefix != manifest.prefix {
+        for file in &manifest.prefix_files {
+            let path = target_prefix.join(&file.path);
+            let contents = match fs_err::read(&path) {
+                Ok(contents) => contents,
+                // Files that were removed from the prefix are not relocated.
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(PackError::IoError(
+                        format!("failed to read {}", file.path.display()),
+                        e,
+                    ))
+                }
+            };
+            let mut relocated = Vec::with_capacity(contents.len());
+            copy_and_replace_placeholders(
+                &contents,
+                &mut relocated,
+                &manifest.prefix,
+                new_prefix,
+                &manifest.platform,
+                file.file_mode,
+            )
+            .and_then(|()| {
+                if relocated == contents {
+                    Ok(())
+                } else {
+                    fs_err::write(&path, &relocated)
+                }
+            })
+            .map_err(|e| {
+                PackError::IoError(format!("failed to relocate {}", file.path.display()), e)
+            })?;
+        }
+    }
+
+    if manifest.platform.is_windows() {
+        write_activation_script(
+            target_prefix,
+            CmdExe,
+            manifest.platform,
+            "Scripts/activate.bat",
+        )?;
+    } else {
+        write_activation_script(target_prefix, Bash, manifest.platform, "bin/activate")?;
+    }
+
+    let manifest = PackManifest {
+        prefix: new_prefix.to_string(),
+        ..manifest.clone()
+    };
+    let manifest_json = serde_json::to_vec_pretty(&manifest)
+        .map_err(|e| PackError::IoError("failed to write the manifest".to_string(), e.into()))?;
+    fs_err::write(target_prefix.join(PACK_MANIFEST_PATH), manifest_json)
+        .map_err(|e| PackError::IoError("failed to write the manifest".to_string(), e))?;
+
+    Ok(manifest)
+}
+
+/// Writes a script that activates the prefix for the given shell.
+fn write_activation_script<T: Shell + Clone + 'static>(
+    prefix: &Path,
+    shell: T,
+    platform: Platform,
+    relative_path: &str,
+) -> Result<(), PackError> {
+    let activator =
+        Activator::from_path(prefix, shell, platform).map_err(PackError::ActivationError)?;
+    let script = activator
+        .activation(ActivationVariables {
+            path_modification_behavior: PathModificationBehavior::Prepend,
+            ..ActivationVariables::default()
+        })
+        .map_err(PackError::ActivationError)?
+        .script
+        .contents()
+        .map_err(|e| PackError::ActivationError(e.into()))?;
+
+    let path = prefix.join(relative_path);
+    let io_err =
+        |e: std::io::Error| PackError::IoError(format!("failed to write {relative_path}"), e);
+    if let Some(parent) = path.parent() {
+        fs_err::create_dir_all(parent).map_err(io_err)?;
+    }
+    fs_err::write(&path, script).map_err(io_err)
+}
+
+/// Returns true if the path should not be part of a packed prefix.
+fn is_excluded(prefix: &Path, path: &Path) -> bool {
+    let Ok(relative_path) = path.strip_prefix(prefix) else {
+        return false;
+    };
+    relative_path == Path::new(".trash")
+        || relative_path == Path::new("conda-meta").join(PREFIX_LOCK_FILE_NAME)
+        || relative_path == Path::new(PACK_MANIFEST_PATH)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+
+    use rattler_conda_types::{
+        package::FileMode,
+        prefix_record::{PathType, PathsEntry},
+        Platform, PrefixRecord,
+    };
+
+    use super::{pack, unpack, PackError, PACK_MANIFEST_PATH};
+
+    fn paths_entry(path: &str, file_mode: FileMode) -> PathsEntry {
+        PathsEntry {
+            relative_path: PathBuf::from(path),
+            original_path: None,
+            path_type: PathType::HardLink,
+            no_link: false,
+            sha256: None,
+            sha256_in_prefix: None,
+            size_in_bytes: None,
+            file_mode: Some(file_mode),
+            prefix_placeholder: Some("/opt/placeholder".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_pack_and_unpack() {
+        let source = tempfile::tempdir().unwrap();
+        let source_prefix = source.path().join("env");
+        let source_str = source_prefix.to_str().unwrap();
+
+        // Create a prefix with a text and a binary file that contain the
+        // prefix.
+        fs_err::create_dir_all(source_prefix.join("etc")).unwrap();
+        fs_err::create_dir_all(source_prefix.join("lib")).unwrap();
+        fs_err::create_dir_all(source_prefix.join("conda-meta")).unwrap();
+        fs_err::write(
+            source_prefix.join("etc/config.txt"),
+            format!("prefix={source_str}\n"),
+        )
+        .unwrap();
+        fs_err::write(
+            source_prefix.join("lib/libempty.so"),
+            [format!("{source_str}/lib").as_bytes(), b"\0tail"].concat(),
+        )
+        .unwrap();
+        let record = crate::get_repodata_record(
+            crate::get_test_data_dir().join("packages/empty-0.1.0-h4616a5c_0.conda"),
+        );
+        PrefixRecord::from_repodata_record(
+            record,
+            vec![
+                paths_entry("etc/config.txt", FileMode::Text),
+                paths_entry("lib/libempty.so", FileMode::Binary),
+            ],
+        )
+        .write_to_path(
+            source_prefix.join("conda-meta/empty-0.1.0-h4616a5c_0.json"),
+            true,
+        )
+        .unwrap();
+
+        let mut archive = Vec::new();
+        let manifest = pack(&source_prefix, Platform::Linux64, &mut archive).unwrap();
+        assert_eq!(manifest.prefix, source_str);
+        assert_eq!(manifest.prefix_files.len(), 2);
+        assert!(!source_prefix.join(PACK_MANIFEST_PATH).exists());
+
+        // Unpack at a location with a path of the same length.
+        let target = tempfile::tempdir().unwrap();
+        let target_prefix = target.path().join("vne");
+        let target_str = target_prefix.to_str().unwrap();
+        let manifest = unpack(archive.as_slice(), &target_prefix).unwrap();
+        assert_eq!(manifest.prefix, target_str);
+        assert_eq!(
+            fs_err::read_to_string(target_prefix.join("etc/config.txt")).unwrap(),
+            format!("prefix={target_str}\n")
+        );
+        assert!(fs_err::read(target_prefix.join("lib/libempty.so"))
+            .unwrap()
+            .starts_with(format!("{target_str}/lib\0").as_bytes()));
+        assert!(fs_err::read_to_string(target_prefix.join("bin/activate"))
+            .unwrap()
+            .contains(target_str));
+        assert_eq!(
+            PrefixRecord::collect_from_prefix::<PrefixRecord>(&target_prefix)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        // Binary files cannot be relocated to a longer path.
+        let longer_prefix = Path::new(target_str).join("nested");
+        assert!(matches!(
+            unpack(archive.as_slice(), &longer_prefix),
+            Err(PackError::PrefixTooLong(_))
+        ));
+        assert!(!longer_prefix.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_pack_softlinked_prefix() {
+        use std::os::unix::fs::symlink;
+
+        let source = tempfile::tempdir().unwrap();
+        let source_prefix = source.path().join("env");
+        let cache = source.path().join("cache");
+        fs_err::create_dir_all(source_prefix.join("conda-meta")).unwrap();
+        fs_err::create_dir_all(source_prefix.join("lib")).unwrap();
+        fs_err::create_dir_all(source_prefix.join("bin")).unwrap();
+        fs_err::create_dir_all(cache.join("share")).unwrap();
+        fs_err::write(cache.join("libfoo.so"), "foo").unwrap();
+
+        // A file linked from the package cache, an absolute link into the
+        // prefix and a relative link.
+        symlink(cache.join("libfoo.so"), source_prefix.join("lib/libfoo.so")).unwrap();
+        symlink(
+            source_prefix.join("lib/libfoo.so"),
+            source_prefix.join("bin/foo"),
+        )
+        .unwrap();
+        symlink("libfoo.so", source_prefix.join("lib/libfoo.so.1")).unwrap();
+
+        let mut archive = Vec::new();
+        pack(&source_prefix, Platform::Linux64, &mut archive).unwrap();
+
+        // The unpacked prefix does not depend on the source prefix or the cache.
+        let target = tempfile::tempdir().unwrap();
+        let target_prefix = target.path().join("env");
+        unpack(archive.as_slice(), &target_prefix).unwrap();
+        drop(source);
+
+        let lib = target_prefix.join("lib/libfoo.so");
+        assert!(!fs_err::symlink_metadata(&lib).unwrap().is_symlink());
+        assert_eq!(fs_err::read_to_string(&lib).unwrap(), "foo");
+        assert_eq!(
+            fs_err::read_link(target_prefix.join("bin/foo")).unwrap(),
+            Path::new("../lib/libfoo.so")
+        );
+        assert_eq!(
+            fs_err::read_to_string(target_prefix.join("bin/foo")).unwrap(),
+            "foo"
+        );
+        assert_eq!(
+            fs_err::read_link(target_prefix.join("lib/libfoo.so.1")).unwrap(),
+            Path::new("libfoo.so")
+        );
+
+        // Directories outside of the prefix are not copied.
+        let source = tempfile::tempdir().unwrap();
+        let source_prefix = source.path().join("env");
+        fs_err::create_dir_all(source_prefix.join("conda-meta")).unwrap();
+        symlink(source.path(), source_prefix.join("share")).unwrap();
+        assert!(matches!(
+            pack(&source_prefix, Platform::Linux64, &mut Vec::new()),
+            Err(PackError::ExternalSymlink(path)) if path == Path::new("share")
+        ));
+    }
+}
@@ -3,7 +3,7 @@ use std::{
     io::{Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use digest::generic_array::GenericArray;
@@ -154,6 +154,54 @@ impl CacheRwLock {
     }
 }
 
+impl CacheRwLock {
+    /// Tries to acquire a write lock without blocking. Returns `None` if the
+    /// lock is currently held by someone else.
+    pub async fn try_acquire_write(path: &Path) -> Result<Option<Self>, PackageCacheError> {
+        let lock_file_path = path.to_path_buf();
+        simple_spawn_blocking::tokio::run_blocking_task(move || {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .read(true)
+                .open(&lock_file_path)
+                .map_err(|e| {
+                    PackageCacheError::LockError(
+                        format!(
+                            "failed to open cache lock for writing: '{}",
+                            lock_file_path.display()
+                        ),
+                        e,
+                    )
+                })?;
+
+            let acquired = FileExt::try_lock_exclusive(&file).map_err(move |e| {
+                PackageCacheError::LockError(
+                    format!(
+                        "failed to acquire write lock on cache lock file: '{}'",
+                        lock_file_path.display()
+                    ),
+                    e,
+                )
+            })?;
+
+            Ok(acquired.then(|| CacheRwLock {
+                file: Arc::new(Mutex::new(file)),
+            }))
+        })
+        .await
+    }
+}
+
+impl CacheRwLock {
+    /// Updates the modification time of the lock file to the current time to
+    /// record that the cache entry was accessed.
+    pub fn touch(&self) -> Result<(), std::io::Error> {
+        self.file.lock().set_modified(SystemTime::now())
+    }
+}
+
 impl CacheRwLock {
     pub async fn write_revision_and_sha(
         &mut self,
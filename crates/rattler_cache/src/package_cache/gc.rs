@@ -0,0 +1,456 @@
+//! Inspection and garbage collection of the entries in a [`PackageCache`].
+
+use std::{
+    collections::HashSet,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use fs_err::tokio as tokio_fs;
+use rattler_conda_types::{
+    package::{IndexJson, PackageFile},
+    PackageRecord, PrefixRecord,
+};
+
+use super::{cache_lock::CacheRwLock, lock_file_path, origin_file_path, PackageCache};
+use crate::package_cache::PackageCacheError;
+
+/// Information about a single entry in the [`PackageCache`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageCacheEntry {
+    /// The directory that contains the extracted package.
+    pub path: PathBuf,
+
+    /// The name of the package, if it could be read from the package.
+    pub name: Option<String>,
+
+    /// The version of the package, if it could be read from the package.
+    pub version: Option<String>,
+
+    /// The build string of the package, if it could be read from the package.
+    pub build_string: Option<String>,
+
+    /// The total size of the files in the entry in bytes.
+    pub size: u64,
+
+    /// The last time the entry was used, if known.
+    pub last_access: Option<SystemTime>,
+
+    /// The url or path the package was fetched from. This is only recorded
+    /// for caches that use [`PackageCache::with_cached_origin`].
+    pub origin: Option<String>,
+}
+
+/// A set of packages that must be kept when garbage collecting the
+/// [`PackageCache`], for instance because they are installed in a prefix or
+/// referenced by a lock-file.
+#[derive(Debug, Clone, Default)]
+pub struct CacheRoots {
+    /// The `name-version-build` identifiers of the packages to keep.
+    identifiers: HashSet<String>,
+
+    /// Cache directories that are referenced directly.
+    paths: HashSet<PathBuf>,
+}
+
+impl CacheRoots {
+    /// Keeps the cache entries of the given package.
+    pub fn add_record(&mut self, record: &PackageRecord) {
+        self.identifiers.insert(format!(
+            "{}-{}-{}",
+            record.name.as_normalized(),
+            record.version,
+            record.build
+        ));
+    }
+
+    /// Keeps the cache entries of all the given packages. Use this to keep
+    /// the packages referenced by a lock-file.
+    pub fn add_records<'a>(&mut self, records: impl IntoIterator<Item = &'a PackageRecord>) {
+        for record in records {
+            self.add_record(record);
+        }
+    }
+
+    /// Keeps the cache entries of all packages installed in the given prefix.
+    pub fn add_prefix(&mut self, prefix: &Path) -> Result<(), std::io::Error> {
+        for record in PrefixRecord::collect_from_prefix::<PrefixRecord>(prefix)? {
+            if let Some(path) = &record.extracted_package_dir {
+                self.paths.insert(path.clone());
+            }
+            self.add_record(&record.repodata_record.package_record);
+        }
+        Ok(())
+    }
+
+    /// Returns true if the entry at the given path must be kept.
+    fn contains(&self, path: &Path) -> bool {
+        if self.paths.contains(path) {
+            return true;
+        }
+        let Some(dir_name) = path.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+        if self.identifiers.contains(dir_name) {
+            return true;
+        }
+
+        // Entries of caches that use `with_cached_origin` have the hash of the
+        // origin appended to the identifier.
+        dir_name.rsplit_once('-').is_some_and(|(identifier, hash)| {
+            hash.len() == 64
+                && hash.chars().all(|c| c.is_ascii_hexdigit())
+                && self.identifiers.contains(identifier)
+        })
+    }
+}
+
+/// Determines which entries are removed by
+/// [`PackageCache::garbage_collect`].
+///
+/// Entries are removed if they have not been used for longer than the maximum
+/// age, or, least recently used first, for as long as the cache is larger than
+/// the maximum size. Entries that are part of the roots or that are currently
+/// in use are never removed.
+#[derive(Debug, Clone, Default)]
+pub struct GarbageCollectOptions {
+    max_age: Option<Duration>,
+    max_size: Option<u64>,
+    roots: CacheRoots,
+    dry_run: bool,
+}
+
+impl GarbageCollectOptions {
+    /// Removes entries that have not been used for longer than `max_age`.
+    pub fn with_max_age(self, max_age: Duration) -> Self {
+        Self {
+            max_age: Some(max_age),
+            ..self
+        }
+    }
+
+    /// Removes the least recently used entries until the total size of the
+    /// cache is at most `max_size` bytes.
+    pub fn with_max_size(self, max_size: u64) -> Self {
+        Self {
+            max_size: Some(max_size),
+            ..self
+        }
+    }
+
+    /// Never removes the entries referenced by `roots`.
+    pub fn with_roots(self, roots: CacheRoots) -> Self {
+        Self { roots, ..self }
+    }
+
+    /// Only determines which entries would be removed without removing them.
+    pub fn with_dry_run(self, dry_run: bool) -> Self {
+        Self { dry_run, ..self }
+    }
+}
+
+/// The result of [`PackageCache::garbage_collect`].
+#[derive(Debug, Clone, Default)]
+pub struct GarbageCollectReport {
+    /// The entries that were removed.
+    pub removed: Vec<PackageCacheEntry>,
+
+    /// The entries that should have been removed but were in use.
+    pub skipped: Vec<PackageCacheEntry>,
+
+    /// The number of bytes that were freed.
+    pub freed_bytes: u64,
+
+    /// The total size of the entries that remain in the cache.
+    pub remaining_bytes: u64,
+}
+
+impl PackageCache {
+    /// Returns information about all the entries in the cache, ordered by
+    /// path.
+    pub async fn entries(&self) -> Result<Vec<PackageCacheEntry>, PackageCacheError> {
+        let path = self.inner.path.clone();
+        simple_spawn_blocking::tokio::run_blocking_task(move || read_entries(&path)).await
+    }
+
+    /// Removes entries from the cache as configured by `options`.
+    ///
+    /// An entry is only removed if no other process holds a lock on it. Lock
+    /// files are never removed, since other processes might be waiting on
+    /// them.
+    pub async fn garbage_collect(
+        &self,
+        options: &GarbageCollectOptions,
+    ) -> Result<GarbageCollectReport, PackageCacheError> {
+        let mut entries = self.entries().await?;
+        let now = SystemTime::now();
+
+        let mut report = GarbageCollectReport {
+            remaining_bytes: entries.iter().map(|entry| entry.size).sum(),
+            ..GarbageCollectReport::default()
+        };
+
+        // Consider the least recently used entries first. Entries for which the last
+        // access time is unknown are considered the oldest.
+        entries.sort_by_key(|entry| entry.last_access);
+
+        for entry in entries {
+            if options.roots.contains(&entry.path) {
+                continue;
+            }
+
+            let expired = options.max_age.is_some_and(|max_age| {
+                entry.last_access.is_none_or(|last_access| {
+                    now.duration_since(last_access).unwrap_or_default() > max_age
+                })
+            });
+            let over_budget = options
+                .max_size
+                .is_some_and(|max_size| report.remaining_bytes > max_size);
+            if !expired && !over_budget {
+                continue;
+            }
+
+            if remove_entry(&entry, options.dry_run).await? {
+                report.remaining_bytes -= entry.size;
+                report.freed_bytes += entry.size;
+                report.removed.push(entry);
+            } else {
+                report.skipped.push(entry);
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+/// Removes a single entry from the cache if it is not in use. Returns whether
+/// the entry was (or, for a dry run, would have been) removed.
+async fn remove_entry(entry: &PackageCacheEntry, dry_run: bool) -> Result<bool, PackageCacheError> {
+    let lock_path = lock_file_path(&entry.path);
+    let Some(mut lock) = CacheRwLock::try_acquire_write(&lock_path).await? else {
+        tracing::debug!("skipping '{}' because it is in use", entry.path.display());
+        return Ok(false);
+    };
+
+    // The entry might have been used between reading the entries and acquiring the
+    // lock.
+    if last_access(&entry.path) > entry.last_access {
+        return Ok(false);
+    }
+
+    if dry_run {
+        return Ok(true);
+    }
+
+    // Bump the revision so that other processes that have this revision cached
+    // revalidate the entry.
+    let revision = lock.read_revision()?;
+    lock.write_revision_and_sha(revision + 1, None).await?;
+
+    tokio_fs::remove_dir_all(&entry.path).await.map_err(|e| {
+        PackageCacheError::IoError(format!("failed to remove '{}'", entry.path.display()), e)
+    })?;
+    match tokio_fs::remove_file(origin_file_path(&entry.path)).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => {
+            tracing::warn!("{e}");
+        }
+        _ => {}
+    }
+
+    tracing::debug!("removed '{}' from the cache", entry.path.display());
+    Ok(true)
+}
+
+/// Reads all the entries from the cache directory.
+fn read_entries(cache_dir: &Path) -> Result<Vec<PackageCacheEntry>, PackageCacheError> {
+    let read_dir = match fs_err::read_dir(cache_dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(PackageCacheError::IoError(
+                "failed to read the cache directory".to_string(),
+                e,
+            ))
+        }
+    };
+
+    let mut entries = Vec::new();
+    for dir_entry in read_dir {
+        let dir_entry = dir_entry.map_err(|e| {
+            PackageCacheError::IoError("failed to read the cache directory".to_string(), e)
+        })?;
+        if !dir_entry.file_type().is_ok_and(|ty| ty.is_dir()) {
+            continue;
+        }
+
+        let path = dir_entry.path();
+        let index_json = IndexJson::from_package_directory(&path).ok();
+        let origin = fs_err::read_to_string(origin_file_path(&path)).ok();
+        entries.push(PackageCacheEntry {
+            name: index_json
+                .as_ref()
+                .map(|index| index.name.as_normalized().to_string()),
+            version: index_json.as_ref().map(|index| index.version.to_string()),
+            build_string: index_json.map(|index| index.build),
+            size: directory_size(&path),
+            last_access: last_access(&path),
+            origin,
+            path,
+        });
+    }
+
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(entries)
+}
+
+/// Returns the last time the entry at `path` was used. Every use of an entry
+/// updates the modification time of its lock file. If there is no lock file
+/// the modification time of the directory is used instead.
+fn last_access(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(lock_file_path(path))
+        .or_else(|_| std::fs::metadata(path))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Returns the total size of all files in a directory. Symbolic links are not
+/// followed.
+fn directory_size(path: &Path) -> u64 {
+    let Ok(read_dir) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    read_dir
+        .filter_map(Result::ok)
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => directory_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::{Path, PathBuf},
+        time::{Duration, SystemTime},
+    };
+
+    use rattler_conda_types::{PackageRecord, Version};
+    use tempfile::tempdir;
+
+    use super::{CacheRoots, GarbageCollectOptions};
+    use crate::package_cache::{lock_file_path, PackageCache};
+
+    fn get_test_data_dir() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test-data")
+    }
+
+    fn set_last_access(path: &Path, time: SystemTime) {
+        std::fs::File::options()
+            .write(true)
+            .open(lock_file_path(path))
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_entries_and_garbage_collect() {
+        let packages_dir = tempdir().unwrap();
+        let cache = PackageCache::new(packages_dir.path()).with_cached_origin();
+
+        let python_path = get_test_data_dir().join("clobber/clobber-python-0.1.0-cpython.conda");
+        let other_path = get_test_data_dir().join("clobber/clobber-1-0.1.0-h4616a5c_0.tar.bz2");
+        let python = cache
+            .get_or_fetch_from_path(&python_path, None)
+            .await
+            .unwrap()
+            .path()
+            .to_path_buf();
+        let other = cache
+            .get_or_fetch_from_path(&other_path, None)
+            .await
+            .unwrap()
+            .path()
+            .to_path_buf();
+
+        let entries = cache.entries().await.unwrap();
+        assert_eq!(entries.len(), 2);
+        let entry = entries.iter().find(|entry| entry.path == python).unwrap();
+        assert_eq!(entry.name.as_deref(), Some("clobber-python"));
+        assert_eq!(entry.version.as_deref(), Some("0.1.0"));
+        assert_eq!(entry.build_string.as_deref(), Some("cpython"));
+        assert_eq!(
+            entry.origin.as_deref(),
+            Some(python_path.to_string_lossy().as_ref())
+        );
+        assert!(entry.size > 0);
+
+        // Make the python package the least recently used one.
+        set_last_access(&python, SystemTime::now() - Duration::from_secs(3600));
+
+        // Keeping the python package prevents it from being removed.
+        let mut roots = CacheRoots::default();
+        roots.add_record(&PackageRecord::new(
+            "clobber-python".parse().unwrap(),
+            "0.1.0".parse::<Version>().unwrap(),
+            "cpython".to_string(),
+        ));
+        let report = cache
+            .garbage_collect(
+                &GarbageCollectOptions::default()
+                    .with_max_age(Duration::from_secs(60))
+                    .with_roots(roots),
+            )
+            .await
+            .unwrap();
+        assert!(report.removed.is_empty());
+        assert!(python.is_dir());
+
+        // A dry run does not remove anything.
+        let report = cache
+            .garbage_collect(
+                &GarbageCollectOptions::default()
+                    .with_max_age(Duration::from_secs(60))
+                    .with_dry_run(true),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.removed.len(), 1);
+        assert!(python.is_dir());
+
+        // Entries that are in use are not removed.
+        let lock = cache
+            .get_or_fetch_from_path(&python_path, None)
+            .await
+            .unwrap();
+        set_last_access(&python, SystemTime::now() - Duration::from_secs(3600));
+        let report = cache
+            .garbage_collect(&GarbageCollectOptions::default().with_max_size(0))
+            .await
+            .unwrap();
+        assert_eq!(report.skipped.len(), 1);
+        assert_eq!(report.removed.len(), 1);
+        assert_eq!(report.removed[0].path, other);
+        assert!(python.is_dir());
+        assert!(!other.exists());
+        drop(lock);
+
+        // Removed entries are fetched again.
+        let report = cache
+            .garbage_collect(&GarbageCollectOptions::default().with_max_size(0))
+            .await
+            .unwrap();
+        assert_eq!(report.removed.len(), 1);
+        assert_eq!(report.remaining_bytes, 0);
+        assert!(!python.exists());
+        let lock = cache
+            .get_or_fetch_from_path(&python_path, None)
+            .await
+            .unwrap();
+        assert!(lock.path().is_dir());
+    }
+}
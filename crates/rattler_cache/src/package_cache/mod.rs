@@ -32,8 +32,11 @@ use crate::validation::{validate_package_directory, ValidationMode};
 
 mod cache_key;
 mod cache_lock;
+mod gc;
 mod reporter;
 
+pub use gc::{CacheRoots, GarbageCollectOptions, GarbageCollectReport, PackageCacheEntry};
+
 /// A [`PackageCache`] manages a cache of extracted Conda packages on disk.
 ///
 /// The store does not provide an implementation to get the data into the store.
@@ -91,6 +94,10 @@ pub enum PackageCacheError {
     #[error("{0}")]
     LockError(String, #[source] std::io::Error),
 
+    /// An error occurred while inspecting or cleaning the cache
+    #[error("{0}")]
+    IoError(String, #[source] std::io::Error),
+
     /// The operation was cancelled
     #[error("operation was cancelled")]
     Cancelled,
@@ -123,6 +130,19 @@ impl PackageCache {
         }
     }
 
+    /// Returns the directory of the specified package if it is present in the
+    /// cache, without validating its contents or fetching it. The `url` is the
+    /// location the package would be fetched from, it is only used if the
+    /// cache was created with [`Self::with_cached_origin`].
+    pub fn cached_path(&self, pkg: impl Into<CacheKey>, url: &Url) -> Option<PathBuf> {
+        let mut cache_key = pkg.into();
+        if self.cache_origin {
+            cache_key = cache_key.with_url(url.clone());
+        }
+        let path = self.inner.path.join(cache_key.to_string());
+        path.is_dir().then_some(path)
+    }
+
     /// Returns the directory that contains the specified package.
     ///
     /// If the package was previously successfully fetched and stored in the
@@ -173,6 +193,15 @@ impl PackageCache {
         cache_entry.last_revision = Some(cache_lock.revision);
         cache_entry.last_sha256 = cache_lock.sha256;
 
+        // Record that the entry was used. This is used to determine which entries
+        // are least recently used when garbage collecting the cache.
+        if let Err(e) = cache_lock._lock.touch() {
+            tracing::debug!(
+                "failed to update the access time of '{}': {e}",
+                cache_lock.path.display()
+            );
+        }
+
         Ok(cache_lock)
     }
 
@@ -204,18 +233,22 @@ impl PackageCache {
     ) -> Result<CacheLock, PackageCacheError> {
         let path_buf = path.to_path_buf();
         let mut cache_key: CacheKey = ArchiveIdentifier::try_from_path(&path_buf).unwrap().into();
-        if self.cache_origin {
+        let origin = if self.cache_origin {
             cache_key = cache_key.with_path(path);
-        }
+            Some(path.to_string_lossy().into_owned())
+        } else {
+            None
+        };
 
         self.get_or_fetch(
             cache_key,
             move |destination| {
                 let path_buf = path_buf.clone();
+                let origin = origin.clone();
                 async move {
-                    rattler_package_streaming::tokio::fs::extract(&path_buf, &destination)
-                        .await
-                        .map(|_| ())
+                    rattler_package_streaming::tokio::fs::extract(&path_buf, &destination).await?;
+                    write_origin(&destination, origin.as_deref()).await?;
+                    Ok::<_, ExtractError>(())
                 }
             },
             reporter,
@@ -246,9 +279,12 @@ impl PackageCache {
         let request_start = SystemTime::now();
         // Convert into cache key
         let mut cache_key = pkg.into();
-        if self.cache_origin {
+        let origin = if self.cache_origin {
             cache_key = cache_key.with_url(url.clone());
-        }
+            Some(url.clone().redact().to_string())
+        } else {
+            None
+        };
         // Sha256 of the expected package
         let sha256 = cache_key.sha256();
         let md5 = cache_key.md5();
@@ -259,6 +295,7 @@ impl PackageCache {
             let client = client.clone();
             let retry_policy = retry_policy.clone();
             let download_reporter = download_reporter.clone();
+            let origin = origin.clone();
             async move {
                 let mut current_try = 0;
                 // Retry until the retry policy says to stop
@@ -307,6 +344,7 @@ impl PackageCache {
                                     });
                                 }
                             }
+                            write_origin(&destination, origin.as_deref()).await?;
                             return Ok(());
                         }
                         Err(err) => err,
@@ -362,14 +400,7 @@ where
 {
     // Acquire a read lock on the cache entry. This ensures that no other process is
     // currently writing to the cache.
-    let lock_file_path = {
-        // Append the `.lock` extension to the cache path to create the lock file path.
-        // `Path::with_extension` strips too much from the filename if it contains one
-        // or more dots.
-        let mut path_str = path.as_os_str().to_owned();
-        path_str.push(".lock");
-        PathBuf::from(path_str)
-    };
+    let lock_file_path = lock_file_path(&path);
 
     // Ensure the directory containing the lock-file exists.
     if let Some(root_dir) = lock_file_path.parent() {
@@ -391,9 +422,12 @@ where
         let cache_revision = read_lock.read_revision()?;
         let locked_sha256 = read_lock.read_sha256()?;
 
+        // An entry that was fetched without a known hash cannot be verified against
+        // the given hash, so it is fetched again.
         let hash_mismatch = match (given_sha, &locked_sha256) {
             (Some(given_hash), Some(locked_sha256)) => given_hash != locked_sha256,
-            _ => false,
+            (Some(_), None) => true,
+            (None, _) => false,
         };
 
         let cache_dir_exists = path.is_dir();
@@ -500,6 +534,36 @@ where
     }
 }
 
+/// Returns the path of a file that is stored next to the cache entry at `path`
+/// with the given extension.
+fn sibling_path(path: &Path, extension: &str) -> PathBuf {
+    // `Path::with_extension` strips too much from the filename if it contains one
+    // or more dots.
+    let mut path_str = path.as_os_str().to_owned();
+    path_str.push(".");
+    path_str.push(extension);
+    PathBuf::from(path_str)
+}
+
+/// Returns the path of the lock file of the cache entry at `path`.
+fn lock_file_path(path: &Path) -> PathBuf {
+    sibling_path(path, "lock")
+}
+
+/// Returns the path of the file that records where the cache entry at `path`
+/// was fetched from.
+fn origin_file_path(path: &Path) -> PathBuf {
+    sibling_path(path, "origin")
+}
+
+/// Records the origin of the cache entry at `destination`, if it is known.
+async fn write_origin(destination: &Path, origin: Option<&str>) -> Result<(), std::io::Error> {
+    match origin {
+        Some(origin) => tokio_fs::write(origin_file_path(destination), origin).await,
+        None => Ok(()),
+    }
+}
+
 struct PassthroughReporter {
     reporter: Arc<dyn CacheReporter>,
     index: Mutex<Option<usize>>,
@@ -538,7 +602,10 @@ mod test {
         future::IntoFuture,
         net::SocketAddr,
         path::{Path, PathBuf},
-        sync::{atomic::AtomicBool, Arc},
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
     };
 
     use assert_matches::assert_matches;
@@ -555,7 +622,9 @@ mod test {
     use bytes::Bytes;
     use futures::stream;
     use rattler_conda_types::package::{ArchiveIdentifier, PackageFile, PathsJson};
-    use rattler_digest::{compute_bytes_digest, parse_digest_from_hex, Sha256};
+    use rattler_digest::{
+        compute_bytes_digest, compute_file_digest, parse_digest_from_hex, Sha256,
+    };
     use rattler_networking::retry_policies::{DoNotRetryPolicy, ExponentialBackoffBuilder};
     use reqwest::Client;
     use reqwest_middleware::ClientBuilder;
@@ -832,6 +901,50 @@ mod test {
         assert_eq!(cache_c_lock.revision(), 2);
     }
 
+    #[tokio::test]
+    async fn test_unverified_entry_is_refetched_with_sha() {
+        let packages_dir = tempdir().unwrap();
+        let cache = PackageCache::new(packages_dir.path());
+        let package_path = get_test_data_dir().join("clobber/clobber-python-0.1.0-cpython.conda");
+        let key: CacheKey = ArchiveIdentifier::try_from_path(&package_path)
+            .unwrap()
+            .into();
+
+        let fetch_count = Arc::new(AtomicUsize::new(0));
+        let fetch = |key: CacheKey| {
+            let package_path = package_path.clone();
+            let fetch_count = fetch_count.clone();
+            cache.get_or_fetch(
+                key,
+                move |destination| {
+                    let package_path = package_path.clone();
+                    fetch_count.fetch_add(1, Ordering::Relaxed);
+                    async move {
+                        rattler_package_streaming::tokio::fs::extract(&package_path, &destination)
+                            .await
+                            .map(|_| ())
+                    }
+                },
+                None,
+            )
+        };
+
+        // Populate the cache without a hash.
+        let cache_lock = fetch(key.clone()).await.unwrap();
+        assert_eq!(cache_lock.sha256, None);
+        drop(cache_lock);
+
+        // Requesting the package with a hash fetches it again and records the
+        // hash, after which the entry is reused.
+        let sha256 = compute_file_digest::<Sha256>(&package_path).unwrap();
+        let key = key.with_sha256(sha256);
+        let cache_lock = fetch(key.clone()).await.unwrap();
+        assert_eq!(cache_lock.sha256, Some(sha256));
+        drop(cache_lock);
+        fetch(key).await.unwrap();
+        assert_eq!(fetch_count.load(Ordering::Relaxed), 2);
+    }
+
     fn get_file_name_from_path(path: &Path) -> &str {
         path.file_name().unwrap().to_str().unwrap()
     }
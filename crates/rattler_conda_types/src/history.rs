@@ -0,0 +1,472 @@
+//! Parsing and writing of the `conda-meta/history` file of an environment.
+//!
+//! Every transaction that is applied to an environment appends a revision to
+//! the history file. A revision starts with a header containing the time the
+//! transaction was applied, followed by optional comments and the packages
+//! that were added (`+`) or removed (`-`):
+//!
+//! ```text
+//! ==> 2024-01-01 12:00:00 <==
+//! # cmd: conda install python=3.12
+//! # update specs: ['python=3.12']
+//! -conda-forge/linux-64::python-3.11.9-hb806964_0_cpython
+//! +conda-forge/linux-64::python-3.12.4-h194c7f8_0_cpython
+//! ```
+//!
+//! By replaying the revisions the state of the environment at any revision can
+//! be reconstructed, see [`History::state_at`].
+
+use std::{
+    collections::BTreeSet,
+    fmt::{Display, Formatter},
+    io::{Read, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use chrono::NaiveDateTime;
+use fs_err::{File, OpenOptions};
+
+use crate::{Channel, RepoDataRecord};
+
+/// The format of the timestamps in the revision headers.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// The contents of a `conda-meta/history` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct History {
+    /// The revisions of the environment, oldest first. The index of a revision
+    /// is its revision number.
+    pub revisions: Vec<HistoryRevision>,
+}
+
+/// A single transaction recorded in a [`History`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryRevision {
+    /// The time at which the transaction was applied.
+    pub timestamp: NaiveDateTime,
+
+    /// The command that applied the transaction (`# cmd:`).
+    pub cmd: Option<String>,
+
+    /// The version of conda that applied the transaction (`# conda version:`).
+    pub conda_version: Option<String>,
+
+    /// The specs that were requested to be installed or updated
+    /// (`# update specs:`).
+    pub update_specs: Vec<String>,
+
+    /// The specs that were requested to be removed (`# remove specs:`).
+    pub remove_specs: Vec<String>,
+
+    /// Specs that were relaxed by the solver (`# neutered specs:`).
+    pub neutered_specs: Vec<String>,
+
+    /// Any other comments, without the leading `#`.
+    pub comments: Vec<String>,
+
+    /// The packages that were removed from the environment.
+    pub removed: BTreeSet<HistoryDist>,
+
+    /// The packages that were added to the environment.
+    pub added: BTreeSet<HistoryDist>,
+}
+
+/// A reference to a package in a [`History`], formatted as
+/// `channel::name-version-build` where the channel is optional.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HistoryDist {
+    /// The channel the package came from, optionally followed by the subdir
+    /// (e.g. `conda-forge/linux-64`).
+    pub channel: Option<String>,
+
+    /// The name of the package.
+    pub name: String,
+
+    /// The version of the package.
+    pub version: String,
+
+    /// The build string of the package.
+    pub build: String,
+}
+
+/// An error that can occur when parsing a [`History`].
+#[derive(Debug, thiserror::Error)]
+pub enum ParseHistoryError {
+    /// A line was found before the first revision header.
+    #[error("line {0}: expected a revision header")]
+    MissingHeader(usize),
+
+    /// The timestamp of a revision header could not be parsed.
+    #[error("line {0}: invalid timestamp '{1}'")]
+    InvalidTimestamp(usize, String, #[source] chrono::ParseError),
+
+    /// A package could not be parsed.
+    #[error("line {0}: {1}")]
+    InvalidDist(usize, #[source] ParseHistoryDistError),
+
+    /// An IO error occurred
+    #[error(transparent)]
+    IoError(#[from] std::io::Error),
+}
+
+/// An error that can occur when parsing a [`HistoryDist`].
+#[derive(Debug, thiserror::Error)]
+#[error("'{0}' is not of the form 'name-version-build'")]
+pub struct ParseHistoryDistError(String);
+
+impl History {
+    /// Parses a history file from a reader.
+    pub fn from_reader(mut reader: impl Read) -> Result<Self, ParseHistoryError> {
+        let mut str = String::new();
+        reader.read_to_string(&mut str)?;
+        Self::from_str(&str)
+    }
+
+    /// Parses a history file. A missing file is treated as an empty history.
+    pub fn from_path(path: &Path) -> Result<Self, ParseHistoryError> {
+        match File::open(path) {
+            Ok(file) => Self::from_reader(file),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Parses the history file of the environment at `prefix`.
+    pub fn from_prefix(prefix: &Path) -> Result<Self, ParseHistoryError> {
+        Self::from_path(&prefix.join("conda-meta").join("history"))
+    }
+
+    /// Returns the packages that were installed in the environment after
+    /// applying the given revision, or `None` if the revision does not exist.
+    pub fn state_at(&self, revision: usize) -> Option<BTreeSet<HistoryDist>> {
+        if revision >= self.revisions.len() {
+            return None;
+        }
+        let mut state = BTreeSet::new();
+        for revision in &self.revisions[..=revision] {
+            for dist in &revision.removed {
+                state.remove(dist);
+            }
+            state.extend(revision.added.iter().cloned());
+        }
+        Some(state)
+    }
+
+    /// Returns the packages that are installed according to the last
+    /// revision.
+    pub fn current_state(&self) -> BTreeSet<HistoryDist> {
+        self.revisions
+            .len()
+            .checked_sub(1)
+            .and_then(|revision| self.state_at(revision))
+            .unwrap_or_default()
+    }
+}
+
+impl FromStr for History {
+    type Err = ParseHistoryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut revisions: Vec<HistoryRevision> = Vec::new();
+        for (idx, line) in s.lines().enumerate() {
+            let line_number = idx + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(timestamp) = line
+                .strip_prefix("==>")
+                .and_then(|line| line.strip_suffix("<=="))
+            {
+                let timestamp = timestamp.trim();
+                let timestamp = NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+                    .map_err(|e| {
+                        ParseHistoryError::InvalidTimestamp(line_number, timestamp.to_string(), e)
+                    })?;
+                revisions.push(HistoryRevision::new(timestamp));
+                continue;
+            }
+
+            let Some(revision) = revisions.last_mut() else {
+                return Err(ParseHistoryError::MissingHeader(line_number));
+            };
+
+            if let Some(comment) = line.strip_prefix('#') {
+                revision.parse_comment(comment.trim());
+            } else if let Some(dist) = line.strip_prefix('-') {
+                revision.removed.insert(
+                    dist.parse()
+                        .map_err(|e| ParseHistoryError::InvalidDist(line_number, e))?,
+                );
+            } else {
+                // Very old history files list the packages of the first revision without a
+                // leading `+`.
+                let dist = line.strip_prefix('+').unwrap_or(line);
+                revision.added.insert(
+                    dist.parse()
+                        .map_err(|e| ParseHistoryError::InvalidDist(line_number, e))?,
+                );
+            }
+        }
+
+        Ok(Self { revisions })
+    }
+}
+
+impl Display for History {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for revision in &self.revisions {
+            write!(f, "{revision}")?;
+        }
+        Ok(())
+    }
+}
+
+impl HistoryRevision {
+    /// Constructs a new revision without any changes.
+    pub fn new(timestamp: NaiveDateTime) -> Self {
+        Self {
+            timestamp,
+            cmd: None,
+            conda_version: None,
+            update_specs: Vec::new(),
+            remove_specs: Vec::new(),
+            neutered_specs: Vec::new(),
+            comments: Vec::new(),
+            removed: BTreeSet::new(),
+            added: BTreeSet::new(),
+        }
+    }
+
+    /// Returns true if the revision does not add or remove any packages.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+
+    /// Appends this revision to the history file at `path`, creating the file
+    /// if it does not exist.
+    pub fn append_to_path(&self, path: &Path) -> Result<(), std::io::Error> {
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        file.write_all(self.to_string().as_bytes())
+    }
+
+    fn parse_comment(&mut self, comment: &str) {
+        if let Some(cmd) = comment.strip_prefix("cmd:") {
+            self.cmd = Some(cmd.trim().to_string());
+        } else if let Some(version) = comment.strip_prefix("conda version:") {
+            self.conda_version = Some(version.trim().to_string());
+        } else if let Some(specs) = comment.strip_prefix("update specs:") {
+            self.update_specs = parse_spec_list(specs);
+        } else if let Some(specs) = comment.strip_prefix("remove specs:") {
+            self.remove_specs = parse_spec_list(specs);
+        } else if let Some(specs) = comment.strip_prefix("neutered specs:") {
+            self.neutered_specs = parse_spec_list(specs);
+        } else {
+            self.comments.push(comment.to_string());
+        }
+    }
+}
+
+impl Display for HistoryRevision {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "==> {} <==", self.timestamp.format(TIMESTAMP_FORMAT))?;
+        if let Some(cmd) = &self.cmd {
+            writeln!(f, "# cmd: {cmd}")?;
+        }
+        if let Some(version) = &self.conda_version {
+            writeln!(f, "# conda version: {version}")?;
+        }
+        for comment in &self.comments {
+            writeln!(f, "# {comment}")?;
+        }
+        for dist in &self.removed {
+            writeln!(f, "-{dist}")?;
+        }
+        for dist in &self.added {
+            writeln!(f, "+{dist}")?;
+        }
+        for (key, specs) in [
+            ("update specs", &self.update_specs),
+            ("remove specs", &self.remove_specs),
+            ("neutered specs", &self.neutered_specs),
+        ] {
+            if !specs.is_empty() {
+                writeln!(f, "# {key}: {}", format_spec_list(specs))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl HistoryDist {
+    /// Returns the `name-version-build` string of the package.
+    pub fn dist_name(&self) -> String {
+        format!("{}-{}-{}", self.name, self.version, self.build)
+    }
+}
+
+impl FromStr for HistoryDist {
+    type Err = ParseHistoryDistError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (channel, dist) = match s.rsplit_once("::") {
+            Some((channel, dist)) => (Some(channel.to_string()), dist),
+            None => (None, s),
+        };
+        let mut parts = dist.rsplitn(3, '-');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(build), Some(version), Some(name))
+                if !build.is_empty() && !version.is_empty() && !name.is_empty() =>
+            {
+                Ok(Self {
+                    channel,
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    build: build.to_string(),
+                })
+            }
+            _ => Err(ParseHistoryDistError(s.to_string())),
+        }
+    }
+}
+
+impl Display for HistoryDist {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if let Some(channel) = &self.channel {
+            write!(f, "{channel}::")?;
+        }
+        write!(f, "{}-{}-{}", self.name, self.version, self.build)
+    }
+}
+
+impl From<&RepoDataRecord> for HistoryDist {
+    fn from(record: &RepoDataRecord) -> Self {
+        let channel = record.channel.as_deref().map(|channel| {
+            let name = url::Url::parse(channel).map_or_else(
+                |_| channel.to_string(),
+                |url| Channel::from_url(url).name().to_string(),
+            );
+            format!(
+                "{}/{}",
+                name.trim_end_matches('/'),
+                record.package_record.subdir
+            )
+        });
+        Self {
+            channel,
+            name: record.package_record.name.as_normalized().to_string(),
+            version: record.package_record.version.to_string(),
+            build: record.package_record.build.clone(),
+        }
+    }
+}
+
+/// Parses a python style list of strings (e.g. `['python', "numpy >=1,<2"]`).
+fn parse_spec_list(s: &str) -> Vec<String> {
+    let mut specs = Vec::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\'' || c == '"' {
+            specs.push(chars.by_ref().take_while(|&next| next != c).collect());
+        }
+    }
+    specs
+}
+
+/// Formats specs as a python style list of strings.
+fn format_spec_list(specs: &[String]) -> String {
+    let specs = specs
+        .iter()
+        .map(|spec| {
+            if spec.contains('\'') {
+                format!("\"{spec}\"")
+            } else {
+                format!("'{spec}'")
+            }
+        })
+        .collect::<Vec<_>>();
+    format!("[{}]", specs.join(", "))
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use super::{History, HistoryDist};
+
+    const HISTORY: &str = r#"==> 2023-05-02 10:15:31 <==
+# cmd: /opt/conda/bin/conda create -n test python=3.11
+# conda version: 23.3.1
++conda-forge/linux-64::python-3.11.3-h2755cc3_0_cpython
++conda-forge/noarch::tzdata-2023c-h71feb2d_0
+# update specs: ['python=3.11']
+==> 2023-05-03 09:00:00 <==
+# cmd: /opt/conda/bin/conda install -n test "numpy >=1,<2"
+# conda version: 23.3.1
++conda-forge/linux-64::numpy-1.24.3-py311h64a7726_0
+# update specs: ["numpy >=1,<2"]
+==> 2023-05-04 08:30:00 <==
+# cmd: /opt/conda/bin/conda install -n test python=3.12
+-conda-forge/linux-64::python-3.11.3-h2755cc3_0_cpython
++conda-forge/linux-64::python-3.12.0-hab00c5b_0_cpython
+# update specs: ['python=3.12']
+"#;
+
+    #[test]
+    fn test_parse() {
+        let history = History::from_str(HISTORY).unwrap();
+        assert_eq!(history.revisions.len(), 3);
+        let revision = &history.revisions[1];
+        assert_eq!(revision.update_specs, vec!["numpy >=1,<2".to_string()]);
+        assert_eq!(revision.conda_version.as_deref(), Some("23.3.1"));
+        let numpy = revision.added.first().unwrap();
+        assert_eq!(numpy.channel.as_deref(), Some("conda-forge/linux-64"));
+        assert_eq!(numpy.name, "numpy");
+        assert_eq!(numpy.version, "1.24.3");
+        assert_eq!(numpy.build, "py311h64a7726_0");
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let history = History::from_str(HISTORY).unwrap();
+        let reparsed = History::from_str(&history.to_string()).unwrap();
+        assert_eq!(history, reparsed);
+    }
+
+    #[test]
+    fn test_state_at() {
+        let history = History::from_str(HISTORY).unwrap();
+        let names = |revision| {
+            history
+                .state_at(revision)
+                .unwrap()
+                .iter()
+                .map(HistoryDist::dist_name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(0),
+            vec![
+                "python-3.11.3-h2755cc3_0_cpython",
+                "tzdata-2023c-h71feb2d_0"
+            ]
+        );
+        assert_eq!(
+            names(2),
+            vec![
+                "numpy-1.24.3-py311h64a7726_0",
+                "python-3.12.0-hab00c5b_0_cpython",
+                "tzdata-2023c-h71feb2d_0"
+            ]
+        );
+        assert!(history.state_at(3).is_none());
+        assert_eq!(history.current_state(), history.state_at(2).unwrap());
+    }
+
+    #[test]
+    fn test_missing_header() {
+        assert!(History::from_str("+python-3.11.3-h2755cc3_0").is_err());
+    }
+}
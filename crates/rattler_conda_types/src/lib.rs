@@ -22,6 +22,7 @@ pub mod version_spec;
 pub mod compression_level;
 mod environment_yaml;
 mod generic_virtual_package;
+pub mod history;
 pub mod package;
 mod package_name;
 pub mod prefix;
@@ -39,6 +40,7 @@ pub use explicit_environment_spec::{
     ParseExplicitEnvironmentSpecError, ParsePackageArchiveHashError,
 };
 pub use generic_virtual_package::GenericVirtualPackage;
+pub use history::History;
 pub use match_spec::{
     matcher::{StringMatcher, StringMatcherParseError},
     parse::ParseMatchSpecError,
@@ -53,8 +55,8 @@ pub use repo_data::{
     compute_package_url,
     patches::{PackageRecordPatch, PatchInstructions, RepoDataPatch},
     sharded::{Shard, ShardedRepodata, ShardedSubdirInfo},
-    ChannelInfo, ConvertSubdirError, PackageRecord, RecordFromPath, RepoData, SubdirRunExportsJson,
-    ValidatePackageRecordsError,
+    ChannelInfo, ConvertSubdirError, DependencyGraph, DependencyLink, DependencyPath,
+    PackageRecord, RecordFromPath, RepoData, SubdirRunExportsJson, ValidatePackageRecordsError,
 };
 pub use repo_data_record::{RepoDataRecord, SolverResult};
 pub use run_export::RunExportKind;
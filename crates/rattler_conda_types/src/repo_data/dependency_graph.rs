@@ -0,0 +1,505 @@
+use std::collections::VecDeque;
+
+use fxhash::FxHashMap;
+
+use super::topological_sort::package_name_from_match_spec;
+use crate::{MatchSpec, PackageName, PackageRecord, PrefixRecord};
+
+/// The dependency graph of a set of packages that form an environment, e.g.
+/// the result of a solve, the packages installed in a prefix or the packages
+/// of a lock-file environment.
+///
+/// The graph can be used to answer questions like "why is this package part
+/// of the environment?" ([`DependencyGraph::why`]), "which packages depend on
+/// this package?" ([`DependencyGraph::reverse_dependencies`]) and "which
+/// packages are no longer needed if this package is removed?"
+/// ([`DependencyGraph::orphaned_by_removal`]).
+///
+/// Edges are derived from the `depends` field of the records. Dependencies on
+/// packages that are not part of the environment (e.g. virtual packages) are
+/// ignored. Like [`PackageRecord::sort_topologically`] this only works for
+/// environments with unique package names.
+#[derive(Debug, Clone)]
+pub struct DependencyGraph<T> {
+    records: Vec<T>,
+    by_name: FxHashMap<PackageName, usize>,
+
+    /// For every record the dependency specs together with the index of the
+    /// record that satisfies them.
+    dependencies: Vec<Vec<(String, usize)>>,
+
+    /// For every record the indices of the records that depend on it.
+    dependents: Vec<Vec<usize>>,
+
+    /// The specs the environment was created from together with the index of
+    /// the record they select.
+    roots: Vec<(String, usize)>,
+}
+
+/// A single link in a [`DependencyPath`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DependencyLink<'a, T> {
+    /// The spec that caused `package` to be included. For the first link in a
+    /// path this is a requested spec, otherwise it is a dependency of the
+    /// package of the previous link.
+    pub spec: &'a str,
+
+    /// The package that satisfies `spec`.
+    pub package: &'a T,
+}
+
+/// A chain of dependencies from a requested spec to a package.
+pub type DependencyPath<'a, T> = Vec<DependencyLink<'a, T>>;
+
+impl<T: AsRef<PackageRecord>> DependencyGraph<T> {
+    /// Constructs the dependency graph of `records`.
+    ///
+    /// Because no requested specs are known, all packages that are not a
+    /// dependency of another package are considered to be requested. Use
+    /// [`DependencyGraph::with_requested_specs`] to provide the specs that the
+    /// environment was created from.
+    pub fn new(records: impl IntoIterator<Item = T>) -> Self {
+        let records: Vec<T> = records.into_iter().collect();
+        let by_name: FxHashMap<PackageName, usize> = records
+            .iter()
+            .enumerate()
+            .map(|(idx, record)| (record.as_ref().name.clone(), idx))
+            .collect();
+
+        let mut dependencies = Vec::with_capacity(records.len());
+        let mut dependents = vec![Vec::new(); records.len()];
+        for (idx, record) in records.iter().enumerate() {
+            let mut record_dependencies = Vec::new();
+            for spec in &record.as_ref().depends {
+                let name = package_name_from_match_spec(spec);
+                let Some(&dependency) = PackageName::try_from(name)
+                    .ok()
+                    .and_then(|name| by_name.get(&name))
+                else {
+                    continue;
+                };
+                record_dependencies.push((spec.clone(), dependency));
+                if !dependents[dependency].contains(&idx) {
+                    dependents[dependency].push(idx);
+                }
+            }
+            dependencies.push(record_dependencies);
+        }
+
+        let mut graph = Self {
+            records,
+            by_name,
+            dependencies,
+            dependents,
+            roots: Vec::new(),
+        };
+        graph.roots = graph
+            .top_level()
+            .into_iter()
+            .map(|idx| {
+                (
+                    graph.records[idx].as_ref().name.as_source().to_string(),
+                    idx,
+                )
+            })
+            .collect();
+        graph
+    }
+
+    /// Returns the packages that no other package depends on. For dependency
+    /// cycles that no other package depends on (e.g. `python` and `pip`) a
+    /// single package of the cycle is returned.
+    fn top_level(&self) -> Vec<usize> {
+        let none_removed = vec![false; self.records.len()];
+        let mut roots: Vec<usize> = (0..self.records.len())
+            .filter(|&idx| self.dependents[idx].is_empty())
+            .collect();
+        let mut reachable = self.closure(roots.iter().copied(), false, &none_removed);
+        while let Some(mut candidate) = reachable.iter().position(|reachable| !reachable) {
+            // Move up the graph until we find a package whose dependents are
+            // all (transitive) dependencies of the package itself.
+            loop {
+                let descendants = self.closure([candidate], false, &none_removed);
+                let ancestors = self.closure([candidate], true, &none_removed);
+                match (0..self.records.len()).find(|&idx| ancestors[idx] && !descendants[idx]) {
+                    Some(ancestor) => candidate = ancestor,
+                    None => break,
+                }
+            }
+            roots.push(candidate);
+            let descendants = self.closure([candidate], false, &none_removed);
+            for (reachable, descendant) in reachable.iter_mut().zip(descendants) {
+                *reachable |= descendant;
+            }
+        }
+        roots
+    }
+
+    /// Sets the specs the environment was created from. Specs without a name
+    /// or that select a package that is not part of the environment are
+    /// ignored.
+    #[must_use]
+    pub fn with_requested_specs(mut self, specs: impl IntoIterator<Item = MatchSpec>) -> Self {
+        self.roots = specs
+            .into_iter()
+            .filter_map(|spec| {
+                let idx = *self.by_name.get(spec.name.as_ref()?)?;
+                Some((spec.to_string(), idx))
+            })
+            .collect();
+        self
+    }
+
+    /// Returns all the records in the graph.
+    pub fn records(&self) -> &[T] {
+        &self.records
+    }
+
+    /// Returns the record with the given name.
+    pub fn get(&self, name: &PackageName) -> Option<&T> {
+        self.by_name.get(name).map(|&idx| &self.records[idx])
+    }
+
+    /// Returns the requested specs together with the record they select.
+    pub fn requested(&self) -> impl Iterator<Item = (&str, &T)> + '_ {
+        self.roots
+            .iter()
+            .map(|(spec, idx)| (spec.as_str(), &self.records[*idx]))
+    }
+
+    /// Returns the direct dependencies of a package together with the spec
+    /// that selected them. Returns `None` if the package is not part of the
+    /// graph.
+    pub fn dependencies(&self, name: &PackageName) -> Option<Vec<(&str, &T)>> {
+        let idx = *self.by_name.get(name)?;
+        Some(
+            self.dependencies[idx]
+                .iter()
+                .map(|(spec, dependency)| (spec.as_str(), &self.records[*dependency]))
+                .collect(),
+        )
+    }
+
+    /// Returns the packages that directly depend on a package together with
+    /// the spec through which they depend on it. Returns `None` if the
+    /// package is not part of the graph.
+    pub fn reverse_dependencies(&self, name: &PackageName) -> Option<Vec<(&str, &T)>> {
+        let idx = *self.by_name.get(name)?;
+        Some(
+            self.dependents[idx]
+                .iter()
+                .flat_map(|&dependent| {
+                    self.dependencies[dependent]
+                        .iter()
+                        .filter(move |(_, dependency)| *dependency == idx)
+                        .map(move |(spec, _)| (spec.as_str(), &self.records[dependent]))
+                })
+                .collect(),
+        )
+    }
+
+    /// Explains why a package is part of the environment.
+    ///
+    /// Returns, for every requested spec through which the package is
+    /// reachable, the shortest chain of specs that leads from the requested
+    /// spec to the package. Returns an empty list if the package is not part
+    /// of the graph or is not reachable from any requested spec.
+    pub fn why(&self, name: &PackageName) -> Vec<DependencyPath<'_, T>> {
+        let Some(&target) = self.by_name.get(name) else {
+            return Vec::new();
+        };
+
+        let mut paths = Vec::new();
+        for (root_spec, root) in &self.roots {
+            // Breadth-first search so that the shortest path is found. For
+            // every visited package we record the package and spec through
+            // which it was reached.
+            let mut parents: FxHashMap<usize, Option<(usize, &str)>> = FxHashMap::default();
+            parents.insert(*root, None);
+            let mut queue = VecDeque::from([*root]);
+            while let Some(idx) = queue.pop_front() {
+                if idx == target {
+                    break;
+                }
+                for (spec, dependency) in &self.dependencies[idx] {
+                    if !parents.contains_key(dependency) {
+                        parents.insert(*dependency, Some((idx, spec.as_str())));
+                        queue.push_back(*dependency);
+                    }
+                }
+            }
+
+            if !parents.contains_key(&target) {
+                continue;
+            }
+
+            let mut path = Vec::new();
+            let mut current = target;
+            while let Some((parent, spec)) = parents[&current] {
+                path.push(DependencyLink {
+                    spec,
+                    package: &self.records[current],
+                });
+                current = parent;
+            }
+            path.push(DependencyLink {
+                spec: root_spec.as_str(),
+                package: &self.records[*root],
+            });
+            path.reverse();
+            paths.push(path);
+        }
+
+        paths
+    }
+
+    /// Returns the packages that are no longer required by any of the
+    /// requested specs if the given packages are removed from the
+    /// environment. The removed packages themselves are not included.
+    pub fn orphaned_by_removal<'n>(
+        &self,
+        names: impl IntoIterator<Item = &'n PackageName>,
+    ) -> Vec<&T> {
+        let mut removed = vec![false; self.records.len()];
+        for name in names {
+            if let Some(&idx) = self.by_name.get(name) {
+                removed[idx] = true;
+            }
+        }
+
+        // Packages that were not required before the removal are not orphaned
+        // by it.
+        let required_before = self.reachable(&vec![false; self.records.len()]);
+        let required_after = self.reachable(&removed);
+        self.records
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| required_before[*idx] && !required_after[*idx] && !removed[*idx])
+            .map(|(_, record)| record)
+            .collect()
+    }
+
+    /// Returns for every record whether it is reachable from the requested
+    /// specs without passing through a removed record.
+    fn reachable(&self, removed: &[bool]) -> Vec<bool> {
+        self.closure(self.roots.iter().map(|(_, idx)| *idx), false, removed)
+    }
+
+    /// Returns for every record whether it can be reached from `start` by
+    /// following dependencies (or dependents if `upwards` is true) without
+    /// passing through a removed record.
+    fn closure(
+        &self,
+        start: impl IntoIterator<Item = usize>,
+        upwards: bool,
+        removed: &[bool],
+    ) -> Vec<bool> {
+        let mut visited = vec![false; self.records.len()];
+        let mut stack: Vec<usize> = start.into_iter().filter(|idx| !removed[*idx]).collect();
+        while let Some(idx) = stack.pop() {
+            if std::mem::replace(&mut visited[idx], true) {
+                continue;
+            }
+            let next: Vec<usize> = if upwards {
+                self.dependents[idx].clone()
+            } else {
+                self.dependencies[idx]
+                    .iter()
+                    .map(|(_, dependency)| *dependency)
+                    .collect()
+            };
+            stack.extend(
+                next.into_iter()
+                    .filter(|next| !removed[*next] && !visited[*next]),
+            );
+        }
+        visited
+    }
+}
+
+impl DependencyGraph<PrefixRecord> {
+    /// Constructs the dependency graph of the packages installed in a prefix.
+    /// The requested specs are taken from the `requested_specs` of the
+    /// records. If none of the records have requested specs, all packages
+    /// that are not a dependency of another package are considered to be
+    /// requested.
+    pub fn from_prefix_records(records: impl IntoIterator<Item = PrefixRecord>) -> Self {
+        let graph = Self::new(records);
+        let requested_specs: Vec<MatchSpec> = graph
+            .records
+            .iter()
+            .flat_map(|record| record.requested_specs.iter())
+            .filter_map(|spec| MatchSpec::from_str(spec, crate::ParseStrictness::Lenient).ok())
+            .collect();
+        if requested_specs.is_empty() {
+            graph
+        } else {
+            graph.with_requested_specs(requested_specs)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+    use crate::{ParseStrictness, Version};
+
+    fn record(name: &str, depends: &[&str]) -> PackageRecord {
+        let mut record = PackageRecord::new(
+            PackageName::new_unchecked(name),
+            Version::from_str("1.0").unwrap(),
+            String::from("0"),
+        );
+        record.depends = depends.iter().map(ToString::to_string).collect();
+        record
+    }
+
+    fn name(name: &str) -> PackageName {
+        PackageName::new_unchecked(name)
+    }
+
+    /// python -> openssl, requests -> urllib3 -> python, cryptography ->
+    /// openssl
+    fn environment() -> Vec<PackageRecord> {
+        vec![
+            record("python", &["openssl >=3", "__glibc >=2.17"]),
+            record("openssl", &[]),
+            record("requests", &["urllib3", "python >=3.8"]),
+            record("urllib3", &["python"]),
+            record("cryptography", &["openssl 3.*", "python"]),
+        ]
+    }
+
+    fn names<'a>(records: impl IntoIterator<Item = &'a PackageRecord>) -> Vec<&'a str> {
+        let mut names: Vec<_> = records
+            .into_iter()
+            .map(|record| record.name.as_normalized())
+            .collect();
+        names.sort_unstable();
+        names
+    }
+
+    #[test]
+    fn test_dependencies() {
+        let graph = DependencyGraph::new(environment());
+
+        let dependencies = graph.dependencies(&name("python")).unwrap();
+        assert_eq!(dependencies.len(), 1);
+        assert_eq!(dependencies[0].0, "openssl >=3");
+
+        let reverse = graph.reverse_dependencies(&name("openssl")).unwrap();
+        assert_eq!(
+            reverse
+                .iter()
+                .map(|(spec, record)| (*spec, record.name.as_normalized()))
+                .collect::<Vec<_>>(),
+            vec![("openssl >=3", "python"), ("openssl 3.*", "cryptography")]
+        );
+        assert!(graph.reverse_dependencies(&name("numpy")).is_none());
+
+        // Without requested specs the top-level packages are the roots.
+        assert_eq!(
+            names(graph.requested().map(|(_, record)| record)),
+            vec!["cryptography", "requests"]
+        );
+    }
+
+    #[test]
+    fn test_why() {
+        let graph = DependencyGraph::new(environment()).with_requested_specs([
+            MatchSpec::from_str("requests >=2", ParseStrictness::Lenient).unwrap(),
+            MatchSpec::from_str("cryptography", ParseStrictness::Lenient).unwrap(),
+        ]);
+
+        let paths = graph.why(&name("openssl"));
+        let paths: Vec<Vec<_>> = paths
+            .iter()
+            .map(|path| {
+                path.iter()
+                    .map(|link| (link.spec, link.package.name.as_normalized()))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                vec![
+                    ("requests >=2", "requests"),
+                    ("python >=3.8", "python"),
+                    ("openssl >=3", "openssl"),
+                ],
+                vec![("cryptography", "cryptography"), ("openssl 3.*", "openssl")],
+            ]
+        );
+
+        assert!(graph.why(&name("numpy")).is_empty());
+    }
+
+    #[test]
+    fn test_top_level_cycle() {
+        let graph = DependencyGraph::new(vec![
+            record("python", &["pip", "openssl"]),
+            record("pip", &["python"]),
+            record("openssl", &[]),
+        ]);
+        assert_eq!(graph.requested().count(), 1);
+        assert_eq!(graph.why(&name("openssl")).len(), 1);
+    }
+
+    #[test]
+    fn test_orphaned_by_removal() {
+        let graph = DependencyGraph::new(environment()).with_requested_specs([
+            MatchSpec::from_str("requests", ParseStrictness::Lenient).unwrap(),
+            MatchSpec::from_str("cryptography", ParseStrictness::Lenient).unwrap(),
+        ]);
+
+        assert!(graph
+            .orphaned_by_removal([&name("cryptography")])
+            .is_empty());
+        assert_eq!(
+            names(graph.orphaned_by_removal([&name("requests")])),
+            vec!["urllib3"]
+        );
+        assert_eq!(
+            names(graph.orphaned_by_removal([&name("requests"), &name("cryptography")])),
+            vec!["openssl", "python", "urllib3"]
+        );
+    }
+
+    #[test]
+    fn test_from_prefix_records() {
+        let prefix_records = environment().into_iter().map(|record| {
+            let requested = record.name.as_normalized() == "requests";
+            let mut prefix_record = PrefixRecord::from_repodata_record(
+                crate::RepoDataRecord {
+                    file_name: format!("{}-1.0-0.conda", record.name.as_normalized()),
+                    url: url::Url::parse("https://example.com/noarch/package.conda").unwrap(),
+                    channel: None,
+                    package_record: record,
+                },
+                Vec::new(),
+            );
+            if requested {
+                prefix_record.requested_specs = vec![String::from("requests")];
+            }
+            prefix_record
+        });
+
+        let graph = DependencyGraph::from_prefix_records(prefix_records);
+        assert_eq!(
+            graph.requested().map(|(spec, _)| spec).collect::<Vec<_>>(),
+            vec!["requests"]
+        );
+        assert_eq!(
+            names(
+                graph
+                    .orphaned_by_removal([&name("requests")])
+                    .into_iter()
+                    .map(|record| &record.repodata_record.package_record)
+            ),
+            vec!["openssl", "python", "urllib3"]
+        );
+    }
+}
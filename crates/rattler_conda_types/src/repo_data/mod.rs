@@ -1,10 +1,13 @@
 //! Defines [`RepoData`]. `RepoData` stores information of all packages present
 //! in a subdirectory of a channel. It provides indexing functionality.
 
+mod dependency_graph;
 pub mod patches;
 pub mod sharded;
 mod topological_sort;
 
+pub use dependency_graph::{DependencyGraph, DependencyLink, DependencyPath};
+
 use std::{
     collections::{BTreeMap, BTreeSet},
     fmt::{Display, Formatter},
@@ -441,6 +444,28 @@ pub struct SubdirRunExportsJson {
 }
 
 impl SubdirRunExportsJson {
+    /// Collects the run exports of all packages in the given [`RepoData`].
+    /// Packages without run exports are skipped.
+    pub fn from_repo_data(repo_data: &RepoData) -> Self {
+        fn collect(
+            records: &FxHashMap<String, PackageRecord>,
+        ) -> FxHashMap<String, PackageRunExports> {
+            records
+                .iter()
+                .filter_map(|(file_name, record)| {
+                    let run_exports = record.run_exports.clone()?;
+                    Some((file_name.clone(), PackageRunExports { run_exports }))
+                })
+                .collect()
+        }
+
+        Self {
+            info: repo_data.info.clone(),
+            packages: collect(&repo_data.packages),
+            conda_packages: collect(&repo_data.conda_packages),
+        }
+    }
+
     /// Get package [`RunExportsJson`] based on the package file name.
     pub fn get(&self, record: &RepoDataRecord) -> Option<&RunExportsJson> {
         let file_name = &record.file_name;
@@ -212,7 +212,7 @@ fn get_topological_order<T: AsRef<PackageRecord>>(
 }
 
 /// Helper function to obtain the package name from a match spec
-fn package_name_from_match_spec(d: &str) -> &str {
+pub(super) fn package_name_from_match_spec(d: &str) -> &str {
     // Unwrap is safe because split always returns at least one value
     d.split([' ', '=']).next().unwrap()
 }
@@ -6,6 +6,7 @@ use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use thiserror::Error;
 use url::Url;
 
+use crate::config::credential_helpers::CredentialHelpersMap;
 use crate::config::s3::S3OptionsMap;
 use crate::config::{
     build::BuildConfig, concurrency::ConcurrencyConfig, proxy::ProxyConfig,
@@ -15,6 +16,7 @@ use crate::config::{
 pub mod build;
 pub mod channel_config;
 pub mod concurrency;
+pub mod credential_helpers;
 pub mod proxy;
 pub mod repodata_config;
 pub mod run_post_link_scripts;
@@ -114,6 +116,11 @@ pub struct ConfigBase<T> {
     #[serde(skip_serializing_if = "S3OptionsMap::is_default")]
     pub s3_options: S3OptionsMap,
 
+    /// External executables that provide credentials, keyed by host pattern.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "CredentialHelpersMap::is_default")]
+    pub credential_helpers: CredentialHelpersMap,
+
     /// Run the post link scripts
     #[serde(default)]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -153,6 +160,7 @@ where
             concurrency: ConcurrencyConfig::default(),
             proxy_config: ProxyConfig::default(),
             s3_options: S3OptionsMap::default(),
+            credential_helpers: CredentialHelpersMap::default(),
             run_post_link_scripts: None,
             extensions: T::default(),
             loaded_from: Vec::new(),
@@ -246,6 +254,9 @@ where
     fn merge_config(self, other: &Self) -> Result<Self, MergeError> {
         Ok(Self {
             s3_options: self.s3_options.merge_config(&other.s3_options)?,
+            credential_helpers: self
+                .credential_helpers
+                .merge_config(&other.credential_helpers)?,
             // Use the other configuration's default channels if available
             default_channels: other
                 .default_channels
@@ -306,6 +317,7 @@ where
         keys.extend(get_keys(&self.proxy_config));
         keys.extend(get_keys(&self.extensions));
         keys.extend(get_keys(&self.s3_options));
+        keys.extend(get_keys(&self.credential_helpers));
 
         keys.push("default_channels".to_string());
         keys.push("authentication_override_file".to_string());
@@ -0,0 +1,88 @@
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use crate::config::Config;
+#[cfg(feature = "edit")]
+use crate::edit::ConfigEditError;
+
+/// The credential helpers to use, keyed by host pattern (e.g.
+/// `repo.example.com`, `*.corp.example` or `*`).
+#[derive(Default, Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CredentialHelpersMap(pub IndexMap<String, CredentialHelper>);
+
+/// An external executable that provides credentials.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct CredentialHelper {
+    /// The executable to invoke
+    pub command: String,
+
+    /// Arguments that are passed to the executable before the action
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+}
+
+impl Config for CredentialHelpersMap {
+    fn is_default(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn merge_config(self, other: &Self) -> Result<Self, super::MergeError> {
+        // Merge the two maps, overwriting existing host patterns
+        let mut merged = self.0.clone();
+        for (key, value) in &other.0 {
+            merged.insert(key.clone(), value.clone());
+        }
+        Ok(CredentialHelpersMap(merged))
+    }
+
+    #[cfg(feature = "edit")]
+    fn set(&mut self, key: &str, value: Option<String>) -> Result<(), ConfigEditError> {
+        if key == "credential-helpers" {
+            self.0 = value
+                .map(|value| {
+                    serde_json::de::from_str(&value).map_err(|e| ConfigEditError::JsonParseError {
+                        key: key.to_string(),
+                        source: e,
+                    })
+                })
+                .transpose()?
+                .unwrap_or_default();
+            return Ok(());
+        }
+        // Host patterns contain dots, so the rest of the key is the pattern.
+        let Some(pattern) = key.strip_prefix("credential-helpers.") else {
+            return Err(ConfigEditError::UnknownKey {
+                key: key.to_string(),
+                supported_keys: "".to_string(),
+            });
+        };
+        match value {
+            Some(value) => {
+                let helper: CredentialHelper = serde_json::de::from_str(&value).map_err(|e| {
+                    ConfigEditError::JsonParseError {
+                        key: key.to_string(),
+                        source: e,
+                    }
+                })?;
+                self.0.insert(pattern.to_string(), helper);
+            }
+            None => {
+                self.0.shift_remove(pattern);
+            }
+        }
+        Ok(())
+    }
+
+    fn get_extension_name(&self) -> String {
+        "credential-helpers".to_string()
+    }
+
+    fn validate(&self) -> Result<(), super::ValidationError> {
+        Ok(())
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.keys().map(ToString::to_string).collect()
+    }
+}
@@ -129,6 +129,10 @@ where
                 self.s3_options.set(key, value)?;
                 Ok(())
             }
+            key if key.starts_with("credential-helpers") => {
+                self.credential_helpers.set(key, value)?;
+                Ok(())
+            }
             key if key.starts_with("concurrency.") => {
                 self.concurrency.set(key, value)?;
                 Ok(())
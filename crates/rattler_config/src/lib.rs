@@ -83,6 +83,7 @@ mod tests {
         assert_eq!(config.tls_no_verify, Some(false));
         assert!(config.mirrors.is_empty());
         assert!(config.s3_options.0.is_empty());
+        assert!(config.credential_helpers.0.is_empty());
         assert_eq!(config.extensions, TestExtension::default());
     }
 
@@ -261,6 +262,34 @@ mod tests {
         assert!(!config.s3_options.0["mybucket"].force_path_style);
     }
 
+    #[test]
+    fn test_edit_credential_helpers() {
+        let mut config = TestConfig::default();
+
+        config
+            .set(
+                "credential-helpers.*.corp.example",
+                Some(r#"{"command": "corp-credentials", "args": ["--vault", "prod"]}"#.to_string()),
+            )
+            .unwrap();
+        let helper = &config.credential_helpers.0["*.corp.example"];
+        assert_eq!(helper.command, "corp-credentials");
+        assert_eq!(helper.args, ["--vault", "prod"]);
+
+        let toml = config.to_toml().unwrap();
+        assert!(
+            toml.contains("[credential-helpers.\"*.corp.example\"]"),
+            "{toml}"
+        );
+        let loaded: TestConfig = toml::from_str(&toml).unwrap();
+        assert_eq!(loaded.credential_helpers, config.credential_helpers);
+
+        config
+            .set("credential-helpers.*.corp.example", None)
+            .unwrap();
+        assert!(config.credential_helpers.0.is_empty());
+    }
+
     #[test]
     fn test_edit_run_post_link_scripts() {
         let mut config = TestConfig::default();
@@ -0,0 +1,214 @@
+//! Creation of the `channeldata.json` file that lives at the root of a channel.
+
+use std::collections::HashMap;
+
+use rattler_conda_types::{
+    package::{AboutJson, FileMode, PathsJson},
+    ChannelData, ChannelDataPackage, PackageRecord, Platform,
+};
+
+/// The version of the `channeldata.json` format written by the indexer.
+const CHANNELDATA_VERSION: u32 = 1;
+
+/// Information about a package archive that is not stored in its
+/// [`PackageRecord`] but is required to populate `channeldata.json`.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct PackageMetadata {
+    about: Option<AboutJson>,
+    has_activate_scripts: bool,
+    has_deactivate_scripts: bool,
+    has_post_link_scripts: bool,
+    has_pre_link_scripts: bool,
+    has_pre_unlink_scripts: bool,
+    binary_prefix: bool,
+    text_prefix: bool,
+}
+
+impl PackageMetadata {
+    /// Derives the metadata from the `info/about.json` and `info/paths.json`
+    /// files of a package archive.
+    pub(crate) fn new(about: Option<AboutJson>, paths: Option<&PathsJson>) -> Self {
+        let mut metadata = Self {
+            about,
+            ..Self::default()
+        };
+
+        for entry in paths.into_iter().flat_map(|paths| paths.paths.iter()) {
+            let path = entry.relative_path.to_string_lossy();
+            let file_name = path.rsplit('/').next().unwrap_or_default();
+            let in_scripts_dir = path.starts_with("bin/") || path.starts_with("Scripts/");
+
+            metadata.has_activate_scripts |= path.starts_with("etc/conda/activate.d/");
+            metadata.has_deactivate_scripts |= path.starts_with("etc/conda/deactivate.d/");
+            if in_scripts_dir && file_name.starts_with('.') {
+                let stem = file_name.trim_end_matches(".sh").trim_end_matches(".bat");
+                metadata.has_post_link_scripts |= stem.ends_with("-post-link");
+                metadata.has_pre_link_scripts |= stem.ends_with("-pre-link");
+                metadata.has_pre_unlink_scripts |= stem.ends_with("-pre-unlink");
+            }
+
+            match entry.prefix_placeholder.as_ref().map(|p| p.file_mode) {
+                Some(FileMode::Binary) => metadata.binary_prefix = true,
+                Some(FileMode::Text) => metadata.text_prefix = true,
+                None => {}
+            }
+        }
+
+        metadata
+    }
+}
+
+/// The packages of a single subdir after it has been indexed.
+pub(crate) struct IndexedSubdir {
+    /// The subdir that was indexed.
+    pub subdir: Platform,
+    /// All packages that are part of the subdir after indexing.
+    pub records: Vec<PackageRecord>,
+    /// Additional metadata of the packages whose archives were read during
+    /// this run.
+    pub metadata: Vec<(PackageRecord, PackageMetadata)>,
+}
+
+/// Updates the `channeldata.json` of a channel with the results of indexing
+/// one or more subdirs.
+///
+/// Information about subdirs that were not indexed in this run is retained
+/// from `previous`. Descriptive fields (summary, urls, flags) are only known
+/// for archives that were read during this run, so for packages that were
+/// already registered the previous values are kept.
+pub(crate) fn update_channel_data(
+    previous: Option<ChannelData>,
+    indexed: &[IndexedSubdir],
+) -> ChannelData {
+    let mut channel_data = previous.unwrap_or_else(|| ChannelData {
+        channeldata_version: CHANNELDATA_VERSION,
+        packages: HashMap::default(),
+        subdirs: Vec::new(),
+    });
+    channel_data.channeldata_version = CHANNELDATA_VERSION;
+
+    // Forget everything we knew about the subdirs that were indexed, they are
+    // recomputed below.
+    for package in channel_data.packages.values_mut() {
+        package
+            .subdirs
+            .retain(|subdir| !indexed.iter().any(|s| s.subdir.as_str() == subdir));
+        if package.subdirs.is_empty() {
+            package.version = None;
+            package.timestamp = None;
+            package.run_exports.clear();
+        }
+    }
+
+    for subdir in indexed {
+        for record in &subdir.records {
+            let package = channel_data
+                .packages
+                .entry(record.name.as_normalized().to_string())
+                .or_insert_with(empty_package);
+            if !package.subdirs.contains(&record.subdir) {
+                package.subdirs.push(record.subdir.clone());
+            }
+            if let Some(run_exports) = &record.run_exports {
+                package
+                    .run_exports
+                    .entry(record.version.version().clone())
+                    .or_insert_with(|| run_exports.clone());
+            }
+            let timestamp = record.timestamp.map(|ts| ts.timestamp() as u64);
+            package.timestamp = package.timestamp.max(timestamp);
+            if package
+                .version
+                .as_ref()
+                .is_none_or(|version| version < record.version.version())
+            {
+                package.version = Some(record.version.version().clone());
+                package.license = record.license.clone().or(package.license.take());
+            }
+        }
+
+        // Apply the descriptive metadata of the newest archive per package that
+        // was read in this run.
+        let mut newest: HashMap<&str, &(PackageRecord, PackageMetadata)> = HashMap::default();
+        for entry in &subdir.metadata {
+            let name = entry.0.name.as_normalized();
+            if newest.get(name).is_none_or(|(current, _)| {
+                (&current.version, current.build_number, current.timestamp)
+                    < (&entry.0.version, entry.0.build_number, entry.0.timestamp)
+            }) {
+                newest.insert(name, entry);
+            }
+        }
+        for (name, (record, metadata)) in newest {
+            let Some(package) = channel_data.packages.get_mut(name) else {
+                continue;
+            };
+            if package.version.as_ref() == Some(record.version.version()) {
+                apply_metadata(package, metadata);
+            }
+        }
+    }
+
+    channel_data
+        .packages
+        .retain(|_, package| !package.subdirs.is_empty());
+    for package in channel_data.packages.values_mut() {
+        package.subdirs.sort();
+    }
+    for subdir in indexed {
+        if !channel_data
+            .subdirs
+            .iter()
+            .any(|s| s == subdir.subdir.as_str())
+        {
+            channel_data.subdirs.push(subdir.subdir.to_string());
+        }
+    }
+    channel_data.subdirs.sort();
+
+    channel_data
+}
+
+fn apply_metadata(package: &mut ChannelDataPackage, metadata: &PackageMetadata) {
+    if let Some(about) = &metadata.about {
+        package.description = about.description.clone();
+        package.summary = about.summary.clone();
+        package.dev_url = about.dev_url.clone();
+        package.doc_url = about.doc_url.clone();
+        package.home = about.home.clone();
+        package.source_url = about.source_url.iter().cloned().collect();
+        if about.license.is_some() {
+            package.license = about.license.clone();
+        }
+    }
+    package.has_activate_scripts = metadata.has_activate_scripts;
+    package.has_deactivate_scripts = metadata.has_deactivate_scripts;
+    package.has_post_link_scripts = metadata.has_post_link_scripts;
+    package.has_pre_link_scripts = metadata.has_pre_link_scripts;
+    package.has_pre_unlink_scripts = metadata.has_pre_unlink_scripts;
+    package.binary_prefix = metadata.binary_prefix;
+    package.text_prefix = metadata.text_prefix;
+}
+
+fn empty_package() -> ChannelDataPackage {
+    ChannelDataPackage {
+        has_activate_scripts: false,
+        has_deactivate_scripts: false,
+        binary_prefix: false,
+        description: None,
+        dev_url: Vec::new(),
+        doc_url: Vec::new(),
+        home: Vec::new(),
+        source_url: Vec::new(),
+        license: None,
+        has_post_link_scripts: false,
+        has_pre_link_scripts: false,
+        has_pre_unlink_scripts: false,
+        run_exports: HashMap::default(),
+        subdirs: Vec::new(),
+        summary: None,
+        text_prefix: false,
+        timestamp: None,
+        version: None,
+    }
+}
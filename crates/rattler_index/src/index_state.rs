@@ -0,0 +1,126 @@
+//! Persisted state of previous index runs that allows incremental indexing.
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use opendal::{ErrorKind, Metadata, Operator};
+use rattler_conda_types::Platform;
+use serde::{Deserialize, Serialize};
+
+/// The name of the file in each subdir that stores the [`IndexState`].
+pub(crate) const INDEX_STATE: &str = ".index_state.json";
+
+/// The version of the [`IndexState`] format. State files with a different
+/// version are ignored.
+const INDEX_STATE_VERSION: u32 = 1;
+
+/// The state of a subdir after it was last indexed.
+///
+/// By comparing the archives currently in the subdir with the archives that
+/// were indexed previously, only archives that were added or changed since the
+/// last run have to be read.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct IndexState {
+    version: u32,
+
+    /// The archives that were indexed, keyed by filename.
+    pub files: BTreeMap<String, FileState>,
+
+    /// The sha256 hash of the repodata patch package that was applied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patch_sha256: Option<String>,
+
+    /// Whether `repodata.json.zst` and `run_exports.json.zst` were written.
+    pub write_zst: bool,
+
+    /// Whether sharded repodata was written.
+    pub write_shards: bool,
+}
+
+/// Information about a single archive that is used to detect whether it
+/// changed since it was indexed.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FileState {
+    /// The size of the file in bytes.
+    pub size: u64,
+
+    /// The last modification time of the file, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<DateTime<Utc>>,
+
+    /// The etag of the file, if the storage backend provides one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+}
+
+impl From<&Metadata> for FileState {
+    fn from(metadata: &Metadata) -> Self {
+        Self {
+            size: metadata.content_length(),
+            last_modified: metadata.last_modified(),
+            etag: metadata.etag().map(ToOwned::to_owned),
+        }
+    }
+}
+
+impl FileState {
+    /// Returns true if the metadata contains enough information to detect
+    /// changes to the file. Some backends (e.g. the filesystem) do not return
+    /// this information when listing a directory.
+    pub fn is_complete(metadata: &Metadata) -> bool {
+        metadata.last_modified().is_some() || metadata.etag().is_some()
+    }
+}
+
+impl IndexState {
+    /// Constructs a new state for the given options.
+    pub fn new(patch_sha256: Option<String>, write_zst: bool, write_shards: bool) -> Self {
+        Self {
+            version: INDEX_STATE_VERSION,
+            files: BTreeMap::new(),
+            patch_sha256,
+            write_zst,
+            write_shards,
+        }
+    }
+
+    /// Returns true if the outputs that were written with `self` are also
+    /// valid for `other`, ignoring the indexed files.
+    pub fn same_options(&self, other: &Self) -> bool {
+        self.patch_sha256 == other.patch_sha256
+            && self.write_zst == other.write_zst
+            && self.write_shards == other.write_shards
+    }
+
+    /// Reads the state of a subdir. Returns `None` if there is no state or if
+    /// it cannot be used.
+    pub async fn read(op: &Operator, subdir: Platform) -> anyhow::Result<Option<Self>> {
+        let path = format!("{subdir}/{INDEX_STATE}");
+        let bytes = match op.read(&path).await {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        match serde_json::from_slice::<Self>(&bytes.to_vec()) {
+            Ok(state) if state.version == INDEX_STATE_VERSION => Ok(Some(state)),
+            Ok(state) => {
+                tracing::info!("Ignoring {path} with unsupported version {}", state.version);
+                Ok(None)
+            }
+            Err(e) => {
+                tracing::warn!("Ignoring invalid {path}: {e}");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Writes the state of a subdir.
+    pub async fn write(&self, op: &Operator, subdir: Platform) -> anyhow::Result<()> {
+        let path = format!("{subdir}/{INDEX_STATE}");
+        tracing::info!("Writing index state to {path}");
+        op.write_with(&path, serde_json::to_vec(self)?)
+            .content_encoding("application/json")
+            .await?;
+        Ok(())
+    }
+}
@@ -2,6 +2,9 @@
 //! files
 #![deny(missing_docs)]
 
+mod channel_data;
+mod index_state;
+
 use std::{
     collections::{HashMap, HashSet},
     io::{BufRead, BufReader, Cursor, Read, Seek},
@@ -12,9 +15,11 @@ use std::{
 
 use anyhow::{Context, Result};
 use bytes::buf::Buf;
+use channel_data::{update_channel_data, IndexedSubdir, PackageMetadata};
 use fs_err::{self as fs};
 use futures::{stream::FuturesUnordered, StreamExt};
 use fxhash::FxHashMap;
+use index_state::{FileState, IndexState};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use opendal::{
     layers::RetryLayer,
@@ -22,9 +27,12 @@ use opendal::{
     Configurator, ErrorKind, Operator,
 };
 use rattler_conda_types::{
-    package::{ArchiveIdentifier, ArchiveType, IndexJson, PackageFile, RunExportsJson},
-    ChannelInfo, PackageRecord, PatchInstructions, Platform, RepoData, Shard, ShardedRepodata,
-    ShardedSubdirInfo,
+    package::{
+        AboutJson, ArchiveIdentifier, ArchiveType, IndexJson, PackageFile, PathsJson,
+        RunExportsJson,
+    },
+    ChannelData, ChannelInfo, PackageRecord, PatchInstructions, Platform, RepoData, Shard,
+    ShardedRepodata, ShardedSubdirInfo, SubdirRunExportsJson,
 };
 use rattler_digest::Sha256Hash;
 use rattler_package_streaming::{
@@ -40,6 +48,9 @@ use url::Url;
 const REPODATA_FROM_PACKAGES: &str = "repodata_from_packages.json";
 const REPODATA: &str = "repodata.json";
 const REPODATA_SHARDS: &str = "repodata_shards.msgpack.zst";
+const RUN_EXPORTS: &str = "run_exports.json";
+const CHANNELDATA: &str = "channeldata.json";
+const ATOMIC_WRITE_DIR: &str = ".rattler-index-tmp";
 const ZSTD_REPODATA_COMPRESSION_LEVEL: i32 = 19;
 const CACHE_CONTROL_IMMUTABLE: &str = "public, max-age=31536000, immutable";
 
@@ -137,17 +148,16 @@ pub fn package_record_from_tar_bz2(file: &Path) -> std::io::Result<PackageRecord
 /// This function will look for the `info/index.json` file in the conda package
 /// and extract the package record from it.
 pub fn package_record_from_tar_bz2_reader(reader: impl BufRead) -> std::io::Result<PackageRecord> {
+    package_from_tar_bz2_reader(reader).map(|(record, _)| record)
+}
+
+fn package_from_tar_bz2_reader(
+    reader: impl BufRead,
+) -> std::io::Result<(PackageRecord, PackageMetadata)> {
     let bytes = reader.bytes().collect::<Result<Vec<u8>, _>>()?;
     let reader = Cursor::new(&bytes);
     let mut archive = read::stream_tar_bz2(reader);
-    for entry in archive.entries()?.flatten() {
-        let mut entry = entry;
-        let path = entry.path()?;
-        if path.as_os_str().eq("info/index.json") {
-            return package_record_from_index_json(&bytes, &mut entry);
-        }
-    }
-    Err(std::io::Error::other("No index.json found"))
+    read_package_from_archive(&bytes, &mut archive)
 }
 
 /// Extract the package record from a `.conda` package file.
@@ -158,12 +168,16 @@ pub fn package_record_from_conda(file: &Path) -> std::io::Result<PackageRecord>
     package_record_from_conda_reader(BufReader::new(reader))
 }
 
-fn read_index_json_from_archive(
+/// Reads the package record and the additional metadata required for
+/// `channeldata.json` from the `info` files in the archive.
+fn read_package_from_archive(
     bytes: &Vec<u8>,
     archive: &mut tar::Archive<impl Read>,
-) -> std::io::Result<PackageRecord> {
+) -> std::io::Result<(PackageRecord, PackageMetadata)> {
     let mut index_json = None;
     let mut run_exports_json = None;
+    let mut about_json = None;
+    let mut paths_json = None;
     for entry in archive.entries()?.flatten() {
         let mut entry = entry;
         let path = entry.path()?;
@@ -171,12 +185,18 @@ fn read_index_json_from_archive(
             index_json = Some(package_record_from_index_json(bytes, &mut entry)?);
         } else if path.as_os_str().eq("info/run_exports.json") {
             run_exports_json = Some(RunExportsJson::from_reader(&mut entry)?);
+        } else if path.as_os_str().eq("info/about.json") {
+            // A malformed `about.json` should not prevent the package from being indexed.
+            about_json = AboutJson::from_reader(&mut entry).ok();
+        } else if path.as_os_str().eq("info/paths.json") {
+            paths_json = PathsJson::from_reader(&mut entry).ok();
         }
     }
 
     if let Some(mut index_json) = index_json {
         index_json.run_exports = run_exports_json;
-        return Ok(index_json);
+        let metadata = PackageMetadata::new(about_json, paths_json.as_ref());
+        return Ok((index_json, metadata));
     }
 
     Err(std::io::Error::other("No index.json found"))
@@ -186,10 +206,16 @@ fn read_index_json_from_archive(
 /// This function will look for the `info/index.json` file in the conda package
 /// and extract the package record from it.
 pub fn package_record_from_conda_reader(reader: impl BufRead) -> std::io::Result<PackageRecord> {
+    package_from_conda_reader(reader).map(|(record, _)| record)
+}
+
+fn package_from_conda_reader(
+    reader: impl BufRead,
+) -> std::io::Result<(PackageRecord, PackageMetadata)> {
     let bytes = reader.bytes().collect::<Result<Vec<u8>, _>>()?;
     let reader = Cursor::new(&bytes);
     let mut archive = seek::stream_conda_info(reader).expect("Could not open conda file");
-    read_index_json_from_archive(&bytes, &mut archive)
+    read_package_from_archive(&bytes, &mut archive)
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -200,11 +226,14 @@ async fn index_subdir(
     write_zst: bool,
     write_shards: bool,
     repodata_patch: Option<PatchInstructions>,
+    repodata_patch_sha256: Option<String>,
     progress: Option<MultiProgress>,
     semaphore: Arc<Semaphore>,
-) -> Result<()> {
+) -> Result<IndexedSubdir> {
     let mut registered_packages: FxHashMap<String, PackageRecord> = HashMap::default();
+    let mut previous_state = None;
     if !force {
+        previous_state = IndexState::read(&op, subdir).await?;
         let repodata_bytes = if repodata_patch.is_some() {
             op.read(&format!("{subdir}/{REPODATA_FROM_PACKAGES}")).await
         } else {
@@ -237,20 +266,22 @@ async fn index_subdir(
             subdir
         );
     }
-    let uploaded_packages: HashSet<String> = op
-        .list_with(&format!("{}/", subdir.as_str()))
-        .await?
-        .iter()
-        .filter_map(|entry| {
-            if entry.metadata().mode().is_file() {
-                let filename = entry.name().to_string();
-                // Check if the file is an archive package file.
-                ArchiveType::try_from(&filename).map(|_| filename)
-            } else {
-                None
-            }
-        })
-        .collect();
+    let mut state = IndexState::new(repodata_patch_sha256, write_zst, write_shards);
+    for entry in op.list_with(&format!("{}/", subdir.as_str())).await? {
+        let filename = entry.name();
+        // Check if the file is an archive package file.
+        if !entry.metadata().mode().is_file() || ArchiveType::try_from(filename).is_none() {
+            continue;
+        }
+        // Not all backends return the size and modification time when listing.
+        let file_state = if FileState::is_complete(entry.metadata()) {
+            FileState::from(entry.metadata())
+        } else {
+            FileState::from(&op.stat(entry.path()).await?)
+        };
+        state.files.insert(filename.to_string(), file_state);
+    }
+    let uploaded_packages: HashSet<String> = state.files.keys().cloned().collect();
 
     tracing::debug!(
         "Found {} already uploaded packages in subdir {}.",
@@ -272,8 +303,37 @@ async fn index_subdir(
         subdir
     );
 
-    for filename in packages_to_delete {
-        registered_packages.remove(&filename);
+    for filename in &packages_to_delete {
+        registered_packages.remove(filename);
+    }
+
+    // Archives that were replaced since the previous run have to be read again.
+    let packages_to_update = previous_state
+        .as_ref()
+        .map(|previous| {
+            state
+                .files
+                .iter()
+                .filter(|(filename, file_state)| {
+                    registered_packages.contains_key(*filename)
+                        && previous
+                            .files
+                            .get(*filename)
+                            .is_some_and(|previous| previous != *file_state)
+                })
+                .map(|(filename, _)| filename.clone())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    tracing::debug!(
+        "Updating {} changed packages in subdir {}.",
+        packages_to_update.len(),
+        subdir
+    );
+
+    for filename in &packages_to_update {
+        registered_packages.remove(filename);
     }
 
     let packages_to_add = uploaded_packages
@@ -287,6 +347,22 @@ async fn index_subdir(
         subdir
     );
 
+    // If nothing changed since the previous run the existing files are still up
+    // to date.
+    if packages_to_add.is_empty()
+        && packages_to_delete.is_empty()
+        && previous_state
+            .as_ref()
+            .is_some_and(|previous| previous.same_options(&state) && previous.files == state.files)
+    {
+        tracing::info!("Subdir {subdir} is up to date.");
+        return Ok(IndexedSubdir {
+            subdir,
+            records: registered_packages.into_values().collect(),
+            metadata: Vec::new(),
+        });
+    }
+
     let pb = if let Some(progress) = progress {
         progress.add(ProgressBar::new(packages_to_add.len() as u64))
     } else {
@@ -323,12 +399,12 @@ async fn index_subdir(
                     let reader = buffer.reader();
                     // We already know it's not None
                     let archive_type = ArchiveType::try_from(&filename).unwrap();
-                    let record = match archive_type {
-                        ArchiveType::TarBz2 => package_record_from_tar_bz2_reader(reader),
-                        ArchiveType::Conda => package_record_from_conda_reader(reader),
+                    let (record, metadata) = match archive_type {
+                        ArchiveType::TarBz2 => package_from_tar_bz2_reader(reader),
+                        ArchiveType::Conda => package_from_conda_reader(reader),
                     }?;
                     pb.inc(1);
-                    Ok::<(String, PackageRecord), std::io::Error>((filename.clone(), record))
+                    Ok::<_, std::io::Error>((filename.clone(), record, metadata))
                 }
             }
         };
@@ -372,9 +448,12 @@ async fn index_subdir(
         subdir
     );
 
-    for (filename, record) in results {
+    let mut metadata = Vec::with_capacity(results.len());
+    for (filename, record, package_metadata) in results {
+        metadata.push((record.clone(), package_metadata));
         registered_packages.insert(filename, record);
     }
+    let records = registered_packages.values().cloned().collect();
 
     let mut packages: FxHashMap<String, PackageRecord> = HashMap::default();
     let mut conda_packages: FxHashMap<String, PackageRecord> = HashMap::default();
@@ -390,7 +469,6 @@ async fn index_subdir(
         }
     }
 
-    // TODO: don't serialize run_exports and purls but in their own files
     let repodata = RepoData {
         info: Some(ChannelInfo {
             subdir: Some(subdir.to_string()),
@@ -408,9 +486,18 @@ async fn index_subdir(
         write_zst,
         write_shards,
         subdir,
-        op,
+        op.clone(),
     )
-    .await
+    .await?;
+
+    // The state is written last so that an interrupted run is redone.
+    state.write(&op, subdir).await?;
+
+    Ok(IndexedSubdir {
+        subdir,
+        records,
+        metadata,
+    })
 }
 
 fn serialize_msgpack_zst<T>(val: &T) -> Result<Vec<u8>>
@@ -423,6 +510,9 @@ where
 }
 
 /// Write a `repodata.json` for all packages in the given configurator's root.
+///
+/// Next to the `repodata.json` a `run_exports.json` is written that contains
+/// the run exports of all packages in the subdir (see [CEP 12](https://github.com/conda/ceps/blob/main/cep-0012.md)).
 pub async fn write_repodata(
     repodata: RepoData,
     repodata_patch: Option<PatchInstructions>,
@@ -465,6 +555,22 @@ pub async fn write_repodata(
         .content_encoding("application/json")
         .await?;
 
+    let run_exports_bytes = serde_json::to_vec(&SubdirRunExportsJson::from_repo_data(&repodata))?;
+    if write_zst {
+        let run_exports_zst_bytes =
+            zstd::stream::encode_all(&run_exports_bytes[..], ZSTD_REPODATA_COMPRESSION_LEVEL)?;
+        let run_exports_zst_path = format!("{subdir}/{RUN_EXPORTS}.zst");
+        tracing::info!("Writing zst run exports to {run_exports_zst_path}");
+        op.write(&run_exports_zst_path, run_exports_zst_bytes)
+            .await?;
+    }
+
+    let run_exports_path = format!("{subdir}/{RUN_EXPORTS}");
+    tracing::info!("Writing run exports to {run_exports_path}");
+    op.write_with(&run_exports_path, run_exports_bytes)
+        .content_encoding("application/json")
+        .await?;
+
     if write_shards {
         // See CEP 16 <https://github.com/conda/ceps/blob/main/cep-0016.md>
         tracing::info!("Creating sharded repodata");
@@ -591,9 +697,14 @@ pub async fn index_fs(
         multi_progress,
     }: IndexFsConfig,
 ) -> anyhow::Result<()> {
+    let channel = channel.canonicalize()?;
+    // Files are first written to a temporary directory and then moved into
+    // place so that readers never observe partially written files.
+    let atomic_write_dir = channel.join(ATOMIC_WRITE_DIR);
     let mut config = FsConfig::default();
-    config.root = Some(channel.canonicalize()?.to_string_lossy().to_string());
-    index(
+    config.root = Some(channel.to_string_lossy().to_string());
+    config.atomic_write_dir = Some(atomic_write_dir.to_string_lossy().to_string());
+    let result = index(
         target_platform,
         config,
         repodata_patch,
@@ -603,7 +714,10 @@ pub async fn index_fs(
         max_parallel,
         multi_progress,
     )
-    .await
+    .await;
+    // Only succeeds if the directory is empty, which is fine.
+    let _ = fs::remove_dir(&atomic_write_dir);
+    result
 }
 
 /// Configuration for `index_s3`
@@ -683,7 +797,12 @@ pub async fn index_s3(
 ///    1. Collect all uploaded packages in subdir
 ///    2. Collect all registered packages from `repodata.json` (if exists)
 ///    3. Determine which packages to add to and to delete from `repodata.json`
-///    4. Write `repodata.json` back
+///       and which packages changed since the previous run (based on the
+///       `.index_state.json` of the subdir)
+///    4. If anything changed, write `repodata.json` and `run_exports.json`
+///       back, followed by the new `.index_state.json`
+/// 3. Update the `channeldata.json` at the root of the channel with the
+///    packages of all indexed subdirs.
 #[allow(clippy::too_many_arguments)]
 pub async fn index<T: Configurator>(
     target_platform: Option<Platform>,
@@ -736,7 +855,7 @@ pub async fn index<T: Configurator>(
         subdirs.insert(Platform::NoArch);
     }
 
-    let repodata_patch = if let Some(path) = repodata_patch {
+    let (repodata_patch, repodata_patch_sha256) = if let Some(path) = repodata_patch {
         match ArchiveType::try_from(path.clone()) {
             Some(ArchiveType::Conda) => {}
             Some(ArchiveType::TarBz2) | None => {
@@ -748,11 +867,21 @@ pub async fn index<T: Configurator>(
         }
         let repodata_patch_path = format!("noarch/{path}");
         let repodata_patch_bytes = op.read(&repodata_patch_path).await?.to_bytes();
+        let repodata_patch_sha256 = format!(
+            "{:x}",
+            rattler_digest::compute_bytes_digest::<rattler_digest::Sha256>(&repodata_patch_bytes)
+        );
         let reader = Cursor::new(repodata_patch_bytes);
         let repodata_patch = repodata_patch_from_conda_package_stream(reader)?;
-        Some(repodata_patch)
+        (Some(repodata_patch), Some(repodata_patch_sha256))
     } else {
+        (None, None)
+    };
+
+    let previous_channel_data = if force {
         None
+    } else {
+        read_channel_data(&op).await?
     };
 
     let semaphore = Semaphore::new(max_parallel);
@@ -769,15 +898,17 @@ pub async fn index<T: Configurator>(
             repodata_patch
                 .as_ref()
                 .and_then(|p| p.subdirs.get(&subdir.to_string()).cloned()),
+            repodata_patch_sha256.clone(),
             multi_progress.clone(),
             semaphore.clone(),
         );
         tasks.push(tokio::spawn(task));
     }
 
+    let mut indexed_subdirs = Vec::with_capacity(subdirs.len());
     while let Some(join_result) = tasks.next().await {
         match join_result {
-            Ok(Ok(_)) => {}
+            Ok(Ok(indexed)) => indexed_subdirs.push(indexed),
             Ok(Err(e)) => {
                 tracing::error!("Failed to process subdir: {}", e);
                 tasks.clear();
@@ -790,5 +921,27 @@ pub async fn index<T: Configurator>(
             }
         }
     }
+
+    let channel_data = update_channel_data(previous_channel_data, &indexed_subdirs);
+    tracing::info!("Writing channel data to {CHANNELDATA}");
+    op.write_with(CHANNELDATA, serde_json::to_vec(&channel_data)?)
+        .content_encoding("application/json")
+        .await?;
+
     Ok(())
 }
+
+/// Reads the existing `channeldata.json` at the root of the channel, if any.
+async fn read_channel_data(op: &Operator) -> Result<Option<ChannelData>> {
+    match op.read(CHANNELDATA).await {
+        Ok(bytes) => match serde_json::from_slice(&bytes.to_vec()) {
+            Ok(channel_data) => Ok(Some(channel_data)),
+            Err(e) => {
+                tracing::warn!("Ignoring invalid {CHANNELDATA}: {e}");
+                Ok(None)
+            }
+        },
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
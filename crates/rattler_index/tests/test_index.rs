@@ -4,7 +4,7 @@ use std::{
     path::{Path, PathBuf},
 };
 
-use rattler_conda_types::Platform;
+use rattler_conda_types::{ChannelData, Platform, RepoData};
 use rattler_index::{index_fs, IndexFsConfig};
 use serde_json::Value;
 
@@ -100,6 +100,30 @@ async fn test_index() {
             .unwrap(),
         &expected_repodata_entry
     );
+
+    let run_exports_path = temp_dir.path().join(subdir_path).join("run_exports.json");
+    let run_exports_json: Value =
+        serde_json::from_reader(File::open(run_exports_path).unwrap()).unwrap();
+    assert!(run_exports_json
+        .get("packages.conda")
+        .unwrap()
+        .as_object()
+        .is_some());
+    assert!(temp_dir
+        .path()
+        .join(subdir_path)
+        .join("run_exports.json.zst")
+        .is_file());
+
+    let channel_data_path = temp_dir.path().join("channeldata.json");
+    let channel_data: ChannelData =
+        serde_json::from_reader(File::open(channel_data_path).unwrap()).unwrap();
+    assert_eq!(channel_data.subdirs, vec!["noarch", "win-64"]);
+    let conda = channel_data.packages.get("conda").unwrap();
+    assert_eq!(conda.subdirs, vec!["win-64"]);
+    assert_eq!(conda.version.as_ref().unwrap().to_string(), "22.11.1");
+    assert_eq!(conda.license.as_deref(), Some("BSD-3-Clause"));
+    assert!(conda.summary.is_some());
 }
 
 #[tokio::test]
@@ -124,8 +148,131 @@ async fn test_index_empty_directory_creates_noarch_repodata() {
 
     assert!(res.is_ok());
     assert!(noarch_path.is_dir());
-    assert_eq!(fs::read_dir(&noarch_path).unwrap().count(), 3);
+    assert_eq!(fs::read_dir(&noarch_path).unwrap().count(), 6);
     assert!(repodata_path.is_file());
     assert!(repodata_zst_path.is_file());
     assert!(repodata_msgpack_path.is_file());
+    assert!(noarch_path.join("run_exports.json").is_file());
+    assert!(noarch_path.join("run_exports.json.zst").is_file());
+    assert!(noarch_path.join(".index_state.json").is_file());
+
+    let channel_data_path = temp_dir.path().join("channeldata.json");
+    let channel_data: ChannelData =
+        serde_json::from_reader(File::open(channel_data_path).unwrap()).unwrap();
+    assert_eq!(channel_data.subdirs, vec!["noarch"]);
+    assert!(channel_data.packages.is_empty());
+}
+
+#[tokio::test]
+async fn test_index_updates_channeldata_incrementally() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let noarch_path = temp_dir.path().join("noarch");
+    let package_name = "empty-0.1.0-h4616a5c_0.conda";
+    fs::create_dir(&noarch_path).unwrap();
+    fs::copy(
+        test_data_dir().join("packages").join(package_name),
+        noarch_path.join(package_name),
+    )
+    .unwrap();
+
+    let config = || IndexFsConfig {
+        channel: temp_dir.path().into(),
+        target_platform: None,
+        repodata_patch: None,
+        write_zst: false,
+        write_shards: false,
+        force: false,
+        max_parallel: 1,
+        multi_progress: None,
+    };
+    let read_channel_data = || -> ChannelData {
+        serde_json::from_reader(File::open(temp_dir.path().join("channeldata.json")).unwrap())
+            .unwrap()
+    };
+
+    index_fs(config()).await.unwrap();
+
+    let channel_data = read_channel_data();
+    assert_eq!(channel_data.subdirs, vec!["noarch"]);
+    let empty = channel_data.packages.get("empty").unwrap();
+    assert_eq!(empty.subdirs, vec!["noarch"]);
+    assert_eq!(empty.version.as_ref().unwrap().to_string(), "0.1.0");
+    assert!(noarch_path.join("run_exports.json").is_file());
+    assert!(!noarch_path.join("run_exports.json.zst").exists());
+
+    // Re-indexing without changes keeps the package.
+    index_fs(config()).await.unwrap();
+    assert!(read_channel_data().packages.contains_key("empty"));
+
+    // Removing the archive removes the package from the channeldata.
+    fs::remove_file(noarch_path.join(package_name)).unwrap();
+    index_fs(config()).await.unwrap();
+    assert!(read_channel_data().packages.is_empty());
+}
+
+#[tokio::test]
+async fn test_index_skips_unchanged_subdirs() {
+    let temp_dir = tempfile::tempdir().unwrap();
+    let noarch_path = temp_dir.path().join("noarch");
+    let package_name = "empty-0.1.0-h4616a5c_0.conda";
+    fs::create_dir(&noarch_path).unwrap();
+    fs::copy(
+        test_data_dir().join("packages").join(package_name),
+        noarch_path.join(package_name),
+    )
+    .unwrap();
+
+    let config = || IndexFsConfig {
+        channel: temp_dir.path().into(),
+        target_platform: None,
+        repodata_patch: None,
+        write_zst: false,
+        write_shards: false,
+        force: false,
+        max_parallel: 1,
+        multi_progress: None,
+    };
+    let read_repodata = || -> RepoData {
+        serde_json::from_reader(File::open(noarch_path.join("repodata.json")).unwrap()).unwrap()
+    };
+    let repodata_modified = || {
+        fs::metadata(noarch_path.join("repodata.json"))
+            .unwrap()
+            .modified()
+            .unwrap()
+    };
+
+    index_fs(config()).await.unwrap();
+    assert_eq!(
+        read_repodata().conda_packages[package_name]
+            .name
+            .as_normalized(),
+        "empty"
+    );
+    let state: Value =
+        serde_json::from_reader(File::open(noarch_path.join(".index_state.json")).unwrap())
+            .unwrap();
+    assert!(state["files"][package_name]["size"].is_u64());
+    assert!(!temp_dir.path().join(".rattler-index-tmp").exists());
+
+    // Nothing changed, so the repodata is not rewritten.
+    let modified = repodata_modified();
+    index_fs(config()).await.unwrap();
+    assert_eq!(repodata_modified(), modified);
+
+    // Replacing an archive under the same name causes it to be read again.
+    fs::copy(
+        test_data_dir()
+            .join("link-scripts")
+            .join("link-scripts-0.1.0-h4616a5c_0.conda"),
+        noarch_path.join(package_name),
+    )
+    .unwrap();
+    index_fs(config()).await.unwrap();
+    assert_eq!(
+        read_repodata().conda_packages[package_name]
+            .name
+            .as_normalized(),
+        "link-scripts"
+    );
 }
@@ -0,0 +1,762 @@
+//! Computes the semantic difference between two [`LockFile`]s.
+//!
+//! Unlike a textual diff of the serialized lock-files, a [`LockFileDiff`]
+//! reports which packages were added, removed, upgraded or downgraded for each
+//! environment and platform. The diff can be rendered as a plain text table or
+//! as markdown, and it can be serialized (e.g. to JSON) for further processing.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use rattler_conda_types::Platform;
+use serde::Serialize;
+
+use crate::{
+    CondaPackageData, Environment, LockFile, LockedPackageRef, PypiPackageData, SolveOptions,
+};
+
+/// The semantic difference between two lock-files.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LockFileDiff {
+    /// The environments that differ between the two lock-files, indexed by
+    /// name. Environments without changes are not included.
+    pub environments: BTreeMap<String, EnvironmentDiff>,
+}
+
+/// Describes how an environment differs between two lock-files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct EnvironmentDiff {
+    /// Whether the environment was added, removed or modified.
+    pub status: EnvironmentStatus,
+
+    /// The channels of the environment if they changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<ValueChange<Vec<String>>>,
+
+    /// The solve options of the environment if they changed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<ValueChange<SolveOptions>>,
+
+    /// The changed packages for each platform. Platforms without changes are
+    /// not included.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub platforms: BTreeMap<Platform, Vec<PackageDiff>>,
+}
+
+/// Whether an environment was added, removed or modified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EnvironmentStatus {
+    /// The environment only exists in the new lock-file.
+    Added,
+
+    /// The environment only exists in the old lock-file.
+    Removed,
+
+    /// The environment exists in both lock-files but its content differs.
+    Modified,
+}
+
+/// A value that differs between two lock-files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ValueChange<T> {
+    /// The value in the old lock-file.
+    pub old: T,
+
+    /// The value in the new lock-file.
+    pub new: T,
+}
+
+/// A package that differs between two lock-files.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageDiff {
+    /// The normalized name of the package.
+    pub name: String,
+
+    /// The ecosystem the package belongs to.
+    pub kind: PackageKind,
+
+    /// How the package changed.
+    pub change: PackageChange,
+
+    /// The package in the old lock-file, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub old: Option<PackageVersion>,
+
+    /// The package in the new lock-file, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new: Option<PackageVersion>,
+}
+
+/// The ecosystem a package belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PackageKind {
+    /// A conda package.
+    Conda,
+
+    /// A pypi package.
+    Pypi,
+}
+
+/// How a package changed between two lock-files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PackageChange {
+    /// The package was added.
+    Added,
+
+    /// The package was removed.
+    Removed,
+
+    /// The package was updated to a newer version or build.
+    Upgraded,
+
+    /// The package was changed to an older version or build.
+    Downgraded,
+
+    /// The version did not change but the package did, e.g. it is now taken
+    /// from a different channel.
+    Changed,
+}
+
+/// Identifies a specific version of a package.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PackageVersion {
+    /// The version of the package.
+    pub version: String,
+
+    /// The build string of a conda package.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build: Option<String>,
+
+    /// The name of the channel a conda package was taken from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+}
+
+impl LockFileDiff {
+    /// Computes the difference between two lock-files.
+    pub fn from_lock_files(old: &LockFile, new: &LockFile) -> Self {
+        let names = old
+            .environments()
+            .chain(new.environments())
+            .map(|(name, _)| name)
+            .collect::<BTreeSet<_>>();
+
+        let environments = names
+            .into_iter()
+            .filter_map(|name| {
+                let diff = EnvironmentDiff::from_environments(
+                    old.environment(name),
+                    new.environment(name),
+                )?;
+                Some((name.to_string(), diff))
+            })
+            .collect();
+
+        Self { environments }
+    }
+
+    /// Returns true if the lock-files are semantically equal.
+    pub fn is_empty(&self) -> bool {
+        self.environments.is_empty()
+    }
+
+    /// Renders the difference as a plain text table.
+    pub fn to_table(&self) -> String {
+        let mut output = String::new();
+        for (name, environment) in &self.environments {
+            writeln!(
+                output,
+                "Environment: {name} ({})",
+                environment.status.as_str()
+            )
+            .unwrap();
+            for (field, old, new) in environment.metadata_changes() {
+                writeln!(output, "  {field}: {old} -> {new}").unwrap();
+            }
+            for (platform, packages) in &environment.platforms {
+                writeln!(output, "  {platform}:").unwrap();
+                let rows = packages
+                    .iter()
+                    .map(PackageDiff::columns)
+                    .collect::<Vec<_>>();
+                let mut widths = [0; 5];
+                for row in &rows {
+                    for (width, column) in widths.iter_mut().zip(row) {
+                        *width = (*width).max(column.chars().count());
+                    }
+                }
+                for row in rows {
+                    let line = row
+                        .iter()
+                        .zip(widths)
+                        .map(|(column, width)| format!("{column:width$}"))
+                        .collect::<Vec<_>>()
+                        .join("  ");
+                    writeln!(output, "    {}", line.trim_end()).unwrap();
+                }
+            }
+        }
+        output
+    }
+
+    /// Renders the difference as markdown, e.g. to be posted as a comment on a
+    /// pull request.
+    pub fn to_markdown(&self) -> String {
+        let mut output = String::new();
+        if self.is_empty() {
+            writeln!(output, "The lock-files are identical.").unwrap();
+            return output;
+        }
+        for (name, environment) in &self.environments {
+            writeln!(output, "### `{name}` ({})\n", environment.status.as_str()).unwrap();
+            let metadata_changes = environment.metadata_changes();
+            if !metadata_changes.is_empty() {
+                for (field, old, new) in metadata_changes {
+                    writeln!(output, "- {field}: `{old}` → `{new}`").unwrap();
+                }
+                writeln!(output).unwrap();
+            }
+            for (platform, packages) in &environment.platforms {
+                writeln!(output, "#### {platform}\n").unwrap();
+                writeln!(output, "| Package | Kind | Change | Old | New |").unwrap();
+                writeln!(output, "|---|---|---|---|---|").unwrap();
+                for package in packages {
+                    let [change, kind, name, old, new] = package.columns();
+                    let code = |value: String| {
+                        if value.is_empty() {
+                            value
+                        } else {
+                            format!("`{value}`")
+                        }
+                    };
+                    writeln!(
+                        output,
+                        "| {name} | {kind} | {change} | {} | {} |",
+                        code(old),
+                        code(new)
+                    )
+                    .unwrap();
+                }
+                writeln!(output).unwrap();
+            }
+        }
+        output
+    }
+}
+
+impl EnvironmentDiff {
+    /// Computes the difference between two versions of an environment. Returns
+    /// `None` if the environments are equal.
+    fn from_environments(
+        old: Option<Environment<'_>>,
+        new: Option<Environment<'_>>,
+    ) -> Option<Self> {
+        let status = match (&old, &new) {
+            (None, None) => return None,
+            (None, Some(_)) => EnvironmentStatus::Added,
+            (Some(_), None) => EnvironmentStatus::Removed,
+            (Some(_), Some(_)) => EnvironmentStatus::Modified,
+        };
+
+        let (channels, options) = match (&old, &new) {
+            (Some(old), Some(new)) => {
+                let old_channels = channel_urls(old);
+                let new_channels = channel_urls(new);
+                let channels = (old_channels != new_channels).then_some(ValueChange {
+                    old: old_channels,
+                    new: new_channels,
+                });
+                let options = (old.solve_options() != new.solve_options()).then(|| ValueChange {
+                    old: old.solve_options().clone(),
+                    new: new.solve_options().clone(),
+                });
+                (channels, options)
+            }
+            _ => (None, None),
+        };
+
+        let platforms = old
+            .iter()
+            .chain(new.iter())
+            .flat_map(Environment::platforms)
+            .collect::<BTreeSet<_>>();
+        let platforms = platforms
+            .into_iter()
+            .filter_map(|platform| {
+                let old_packages = old.as_ref().map(|env| env.packages(platform));
+                let new_packages = new.as_ref().map(|env| env.packages(platform));
+                let packages = diff_packages(
+                    old_packages.into_iter().flatten().flatten(),
+                    new_packages.into_iter().flatten().flatten(),
+                );
+                (!packages.is_empty()).then_some((platform, packages))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        if status == EnvironmentStatus::Modified
+            && channels.is_none()
+            && options.is_none()
+            && platforms.is_empty()
+        {
+            return None;
+        }
+
+        Some(Self {
+            status,
+            channels,
+            options,
+            platforms,
+        })
+    }
+
+    /// Returns the changed channels and solve options as `(field, old, new)`.
+    fn metadata_changes(&self) -> Vec<(&'static str, String, String)> {
+        let mut changes = Vec::new();
+        if let Some(channels) = &self.channels {
+            changes.push(("channels", channels.old.join(", "), channels.new.join(", ")));
+        }
+        if let Some(ValueChange { old, new }) = &self.options {
+            if old.strategy != new.strategy {
+                changes.push((
+                    "strategy",
+                    yaml_value(&old.strategy),
+                    yaml_value(&new.strategy),
+                ));
+            }
+            if old.channel_priority != new.channel_priority {
+                changes.push((
+                    "channel-priority",
+                    yaml_value(&old.channel_priority),
+                    yaml_value(&new.channel_priority),
+                ));
+            }
+            if old.exclude_newer != new.exclude_newer {
+                changes.push((
+                    "exclude-newer",
+                    format_cutoff(old.exclude_newer.as_ref()),
+                    format_cutoff(new.exclude_newer.as_ref()),
+                ));
+            }
+            if old.exclude_newer_overrides != new.exclude_newer_overrides {
+                changes.push((
+                    "exclude-newer-overrides",
+                    format_overrides(old),
+                    format_overrides(new),
+                ));
+            }
+        }
+        changes
+    }
+}
+
+impl EnvironmentStatus {
+    fn as_str(self) -> &'static str {
+        match self {
+            EnvironmentStatus::Added => "added",
+            EnvironmentStatus::Removed => "removed",
+            EnvironmentStatus::Modified => "modified",
+        }
+    }
+}
+
+impl PackageKind {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            PackageKind::Conda => "conda",
+            PackageKind::Pypi => "pypi",
+        }
+    }
+}
+
+impl PackageChange {
+    fn as_str(self) -> &'static str {
+        match self {
+            PackageChange::Added => "added",
+            PackageChange::Removed => "removed",
+            PackageChange::Upgraded => "upgraded",
+            PackageChange::Downgraded => "downgraded",
+            PackageChange::Changed => "changed",
+        }
+    }
+}
+
+impl PackageDiff {
+    /// Returns the columns of the package in the rendered tables.
+    fn columns(&self) -> [String; 5] {
+        let format = |version: &Option<PackageVersion>| {
+            version
+                .as_ref()
+                .map(ToString::to_string)
+                .unwrap_or_default()
+        };
+        [
+            self.change.as_str().to_string(),
+            self.kind.as_str().to_string(),
+            self.name.clone(),
+            format(&self.old),
+            format(&self.new),
+        ]
+    }
+}
+
+impl PackageVersion {
+    /// Returns the version of a locked package.
+    pub(crate) fn new(package: LockedPackageRef<'_>) -> Self {
+        match package {
+            LockedPackageRef::Conda(data) => PackageVersion {
+                version: data.record().version.to_string(),
+                build: Some(data.record().build.clone()).filter(|build| !build.is_empty()),
+                channel: data
+                    .as_binary()
+                    .and_then(|binary| binary.channel.clone())
+                    .map(|channel| {
+                        rattler_conda_types::Channel::from_url(channel)
+                            .name()
+                            .to_string()
+                    }),
+            },
+            LockedPackageRef::Pypi(data, _) => PackageVersion {
+                version: data.version.to_string(),
+                build: None,
+                channel: None,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for PackageVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.version)?;
+        if let Some(build) = &self.build {
+            write!(f, " {build}")?;
+        }
+        if let Some(channel) = &self.channel {
+            write!(f, " ({channel})")?;
+        }
+        Ok(())
+    }
+}
+
+/// A package in one of the lock-files together with the information required
+/// to determine how it changed.
+struct ComparablePackage<'lock> {
+    package: LockedPackageRef<'lock>,
+    version: PackageVersion,
+}
+
+impl<'lock> ComparablePackage<'lock> {
+    fn new(package: LockedPackageRef<'lock>) -> Self {
+        Self {
+            package,
+            version: PackageVersion::new(package),
+        }
+    }
+
+    fn key(&self) -> (PackageKind, String) {
+        package_key(self.package)
+    }
+
+    /// Determines how the package changed, or returns `None` if it did not.
+    fn compare(&self, new: &Self) -> Option<PackageChange> {
+        if self.package.location() == new.package.location() && self.version == new.version {
+            return None;
+        }
+        let ordering = match (self.package, new.package) {
+            (LockedPackageRef::Conda(old), LockedPackageRef::Conda(new)) => compare_conda(old, new),
+            (LockedPackageRef::Pypi(old, _), LockedPackageRef::Pypi(new, _)) => {
+                compare_pypi(old, new)
+            }
+            _ => Ordering::Equal,
+        };
+        Some(match ordering {
+            Ordering::Less => PackageChange::Upgraded,
+            Ordering::Greater => PackageChange::Downgraded,
+            Ordering::Equal => PackageChange::Changed,
+        })
+    }
+}
+
+/// Returns the ecosystem and normalized name that identify a package within a
+/// platform of an environment.
+pub(crate) fn package_key(package: LockedPackageRef<'_>) -> (PackageKind, String) {
+    match package {
+        LockedPackageRef::Conda(data) => (
+            PackageKind::Conda,
+            data.record().name.as_normalized().to_string(),
+        ),
+        LockedPackageRef::Pypi(data, _) => (PackageKind::Pypi, data.name.to_string()),
+    }
+}
+
+fn compare_conda(old: &CondaPackageData, new: &CondaPackageData) -> Ordering {
+    old.record()
+        .version
+        .cmp(&new.record().version)
+        .then_with(|| old.record().build_number.cmp(&new.record().build_number))
+}
+
+fn compare_pypi(old: &PypiPackageData, new: &PypiPackageData) -> Ordering {
+    old.version.cmp(&new.version)
+}
+
+/// Computes the difference between two sets of packages of the same platform.
+fn diff_packages<'lock>(
+    old: impl IntoIterator<Item = LockedPackageRef<'lock>>,
+    new: impl IntoIterator<Item = LockedPackageRef<'lock>>,
+) -> Vec<PackageDiff> {
+    let index = |packages: Vec<ComparablePackage<'lock>>| {
+        packages
+            .into_iter()
+            .map(|package| (package.key(), package))
+            .collect::<BTreeMap<_, _>>()
+    };
+    let mut old = index(old.into_iter().map(ComparablePackage::new).collect());
+    let new = index(new.into_iter().map(ComparablePackage::new).collect());
+
+    let mut changes = BTreeMap::new();
+    for (key, new_package) in new {
+        let change = match old.remove(&key) {
+            None => PackageChange::Added,
+            Some(old_package) => match old_package.compare(&new_package) {
+                None => continue,
+                Some(change) => {
+                    changes.insert(
+                        key.clone(),
+                        (change, Some(old_package.version), Some(new_package.version)),
+                    );
+                    continue;
+                }
+            },
+        };
+        changes.insert(key, (change, None, Some(new_package.version)));
+    }
+    for (key, old_package) in old {
+        changes.insert(
+            key,
+            (PackageChange::Removed, Some(old_package.version), None),
+        );
+    }
+
+    changes
+        .into_iter()
+        .map(|((kind, name), (change, old, new))| PackageDiff {
+            name,
+            kind,
+            change,
+            old,
+            new,
+        })
+        .collect()
+}
+
+fn channel_urls(environment: &Environment<'_>) -> Vec<String> {
+    environment
+        .channels()
+        .iter()
+        .map(|channel| channel.url.clone())
+        .collect()
+}
+
+fn yaml_value(value: &impl Serialize) -> String {
+    serde_yaml::to_string(value)
+        .map(|value| value.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn format_cutoff(cutoff: Option<&chrono::DateTime<chrono::Utc>>) -> String {
+    cutoff.map_or_else(|| "none".to_string(), chrono::DateTime::to_rfc3339)
+}
+
+fn format_overrides(options: &SolveOptions) -> String {
+    let overrides = &options.exclude_newer_overrides;
+    if overrides.is_empty() {
+        return "none".to_string();
+    }
+    overrides
+        .packages
+        .iter()
+        .map(|(name, cutoff)| (name.as_normalized(), cutoff))
+        .chain(
+            overrides
+                .channels
+                .iter()
+                .map(|(channel, cutoff)| (channel.as_str(), cutoff)),
+        )
+        .map(|(key, cutoff)| format!("{key}={}", format_cutoff(cutoff.as_ref())))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use rattler_conda_types::{PackageName, PackageRecord, Platform, Version};
+    use url::Url;
+
+    use super::*;
+    use crate::{
+        CondaBinaryData, LockFileBuilder, PypiPackageEnvironmentData, DEFAULT_ENVIRONMENT_NAME,
+    };
+
+    fn conda_package(name: &str, version: &str, build_number: u64) -> CondaPackageData {
+        let mut package_record = PackageRecord::new(
+            PackageName::new_unchecked(name),
+            Version::from_str(version).unwrap(),
+            format!("h123_{build_number}"),
+        );
+        package_record.build_number = build_number;
+        let file_name = format!("{name}-{version}-h123_{build_number}.conda");
+        CondaPackageData::Binary(CondaBinaryData {
+            package_record,
+            location: Url::parse(&format!(
+                "https://conda.anaconda.org/conda-forge/linux-64/{file_name}"
+            ))
+            .unwrap()
+            .into(),
+            file_name,
+            channel: Some(
+                Url::parse("https://conda.anaconda.org/conda-forge/")
+                    .unwrap()
+                    .into(),
+            ),
+        })
+    }
+
+    fn pypi_package(name: &str, version: &str) -> PypiPackageData {
+        PypiPackageData {
+            name: name.parse().unwrap(),
+            version: version.parse().unwrap(),
+            location: Url::parse(&format!(
+                "https://files.pythonhosted.org/packages/{name}-{version}-py3-none-any.whl"
+            ))
+            .unwrap()
+            .into(),
+            hash: None,
+            requires_dist: Vec::new(),
+            requires_python: None,
+            editable: false,
+        }
+    }
+
+    fn lock_file(packages: Vec<CondaPackageData>, pypi_packages: Vec<PypiPackageData>) -> LockFile {
+        let mut builder = LockFileBuilder::new();
+        builder.set_channels(
+            DEFAULT_ENVIRONMENT_NAME,
+            ["https://conda.anaconda.org/conda-forge/"],
+        );
+        for package in packages {
+            builder.add_conda_package(DEFAULT_ENVIRONMENT_NAME, Platform::Linux64, package);
+        }
+        for package in pypi_packages {
+            builder.add_pypi_package(
+                DEFAULT_ENVIRONMENT_NAME,
+                Platform::Linux64,
+                package,
+                PypiPackageEnvironmentData::default(),
+            );
+        }
+        builder.finish()
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = lock_file(
+            vec![
+                conda_package("python", "3.11.0", 0),
+                conda_package("numpy", "2.0.0", 0),
+                conda_package("openssl", "3.0.0", 1),
+                conda_package("pip", "24.0", 0),
+            ],
+            vec![pypi_package("rich", "13.0.0")],
+        );
+        let new = lock_file(
+            vec![
+                conda_package("python", "3.12.0", 0),
+                conda_package("numpy", "1.26.0", 0),
+                conda_package("openssl", "3.0.0", 2),
+                conda_package("requests", "2.32.0", 0),
+            ],
+            vec![pypi_package("rich", "13.7.1")],
+        );
+
+        let diff = LockFileDiff::from_lock_files(&old, &new);
+        let changes = diff.environments[DEFAULT_ENVIRONMENT_NAME].platforms[&Platform::Linux64]
+            .iter()
+            .map(|package| (package.name.as_str(), package.change))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                ("numpy", PackageChange::Downgraded),
+                ("openssl", PackageChange::Upgraded),
+                ("pip", PackageChange::Removed),
+                ("python", PackageChange::Upgraded),
+                ("requests", PackageChange::Added),
+                ("rich", PackageChange::Upgraded),
+            ]
+        );
+
+        insta::assert_snapshot!("table", diff.to_table());
+        insta::assert_snapshot!("markdown", diff.to_markdown());
+    }
+
+    #[test]
+    fn test_diff_channels_and_options() {
+        let old = lock_file(vec![conda_package("python", "3.12.0", 0)], Vec::new());
+        let mut builder = LockFileBuilder::new();
+        builder
+            .set_channels(
+                DEFAULT_ENVIRONMENT_NAME,
+                ["https://prefix.dev/conda-forge/"],
+            )
+            .set_options(
+                DEFAULT_ENVIRONMENT_NAME,
+                SolveOptions {
+                    exclude_newer: Some("2025-04-15T12:15:00Z".parse().unwrap()),
+                    ..SolveOptions::default()
+                },
+            )
+            .add_conda_package(
+                DEFAULT_ENVIRONMENT_NAME,
+                Platform::Linux64,
+                conda_package("python", "3.12.0", 0),
+            );
+        let new = builder.finish();
+
+        let diff = LockFileDiff::from_lock_files(&old, &new);
+        let environment = &diff.environments[DEFAULT_ENVIRONMENT_NAME];
+        assert!(environment.platforms.is_empty());
+        assert_eq!(
+            environment.metadata_changes(),
+            vec![
+                (
+                    "channels",
+                    "https://conda.anaconda.org/conda-forge/".to_string(),
+                    "https://prefix.dev/conda-forge/".to_string()
+                ),
+                (
+                    "exclude-newer",
+                    "none".to_string(),
+                    "2025-04-15T12:15:00+00:00".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        let lock = lock_file(
+            vec![conda_package("python", "3.12.0", 0)],
+            vec![pypi_package("rich", "13.7.1")],
+        );
+        assert!(LockFileDiff::from_lock_files(&lock, &lock.clone()).is_empty());
+    }
+}
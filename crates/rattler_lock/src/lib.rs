@@ -80,13 +80,15 @@ use std::{collections::HashMap, io::Read, path::Path, str::FromStr, sync::Arc};
 
 use fxhash::FxHashMap;
 use indexmap::IndexSet;
-use rattler_conda_types::{Platform, RepoDataRecord};
+use rattler_conda_types::{DependencyGraph, Platform, RepoDataRecord};
 
 mod builder;
 mod channel;
 mod conda;
+pub mod diff;
 mod file_format_version;
 mod hash;
+pub mod merge;
 pub mod options;
 mod parse;
 mod pypi;
@@ -101,8 +103,10 @@ pub use conda::{
     CondaBinaryData, CondaPackageData, CondaSourceData, ConversionError, GitShallowSpec, InputHash,
     PackageBuildSource,
 };
+pub use diff::LockFileDiff;
 pub use file_format_version::FileFormatVersion;
 pub use hash::PackageHashes;
+pub use merge::LockFileMerge;
 pub use options::SolveOptions;
 pub use parse::ParseCondaLockError;
 pub use pypi::{PypiPackageData, PypiPackageEnvironmentData, PypiSourceTreeHashable};
@@ -271,7 +275,7 @@ impl<'lock> Environment<'lock> {
     ///
     /// Note that the order of the channels is significant. The first channel is
     /// the highest priority channel.
-    pub fn channels(&self) -> &[Channel] {
+    pub fn channels(&self) -> &'lock [Channel] {
         &self.data().channels
     }
 
@@ -280,12 +284,12 @@ impl<'lock> Environment<'lock> {
     /// If there are no pypi packages in the lock-file this will return `None`.
     ///
     /// Starting with version `5` of the format this should not be optional.
-    pub fn pypi_indexes(&self) -> Option<&PypiIndexes> {
+    pub fn pypi_indexes(&self) -> Option<&'lock PypiIndexes> {
         self.data().indexes.as_ref()
     }
 
     /// Returns the solver options that were used to create this environment.
-    pub fn solve_options(&self) -> &SolveOptions {
+    pub fn solve_options(&self) -> &'lock SolveOptions {
         &self.data().options
     }
 
@@ -401,6 +405,21 @@ impl<'lock> Environment<'lock> {
             .map(|packages| packages.filter_map(LockedPackageRef::as_conda))
     }
 
+    /// Returns the [`DependencyGraph`] of the conda packages for a specific
+    /// platform. Returns `None` if the platform is not defined for this
+    /// environment.
+    ///
+    /// The lock-file does not record the specs the environment was solved
+    /// for, so all packages that are not a dependency of another package are
+    /// considered to be requested. Use
+    /// [`DependencyGraph::with_requested_specs`] to override this.
+    pub fn conda_dependency_graph(
+        &self,
+        platform: Platform,
+    ) -> Option<DependencyGraph<&'lock CondaPackageData>> {
+        self.conda_packages(platform).map(DependencyGraph::new)
+    }
+
     /// Takes all the conda packages, converts them to [`RepoDataRecord`] and
     /// returns them or returns an error if the conversion failed. Returns
     /// `None` if the specified platform is not defined for this
@@ -539,7 +558,7 @@ mod test {
         str::FromStr,
     };
 
-    use rattler_conda_types::{Platform, RepoDataRecord};
+    use rattler_conda_types::{PackageName, Platform, RepoDataRecord};
     use rstest::*;
 
     use super::{LockFile, DEFAULT_ENVIRONMENT_NAME};
@@ -665,6 +684,32 @@ mod test {
             .has_pypi_packages(Platform::OsxArm64));
     }
 
+    #[test]
+    fn test_conda_dependency_graph() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/conda-lock")
+            .join("v4/python-lock.yml");
+        let conda_lock = LockFile::from_path(&path).unwrap();
+        let environment = conda_lock.environment(DEFAULT_ENVIRONMENT_NAME).unwrap();
+
+        let graph = environment
+            .conda_dependency_graph(Platform::Linux64)
+            .unwrap();
+        let openssl = PackageName::new_unchecked("openssl");
+        let paths = graph.why(&openssl);
+        assert!(!paths.is_empty());
+        assert!(paths.iter().all(|path| path.last().is_some_and(|link| link
+            .package
+            .record()
+            .name
+            == openssl)));
+        assert!(!graph.reverse_dependencies(&openssl).unwrap().is_empty());
+
+        assert!(environment
+            .conda_dependency_graph(Platform::EmscriptenWasm32)
+            .is_none());
+    }
+
     #[test]
     fn test_is_empty() {
         let path = Path::new(env!("CARGO_MANIFEST_DIR"))
@@ -0,0 +1,470 @@
+//! Three-way merge of [`LockFile`]s.
+//!
+//! When two branches both update a lock-file, a textual merge of the YAML
+//! often produces a broken file. [`LockFileMerge`] instead merges the
+//! lock-files at the level of environments, their metadata and individual
+//! packages, and reports the changes that could not be combined as
+//! [`MergeConflict`]s.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use rattler_conda_types::Platform;
+use serde::Serialize;
+
+use crate::{
+    diff::{package_key, PackageKind, PackageVersion},
+    Channel, Environment, LockFile, LockFileBuilder, LockedPackageRef, PypiIndexes,
+    PypiPackageEnvironmentData, SolveOptions,
+};
+
+/// The result of a three-way merge of lock-files.
+#[derive(Debug, Clone)]
+pub struct LockFileMerge {
+    /// The merged lock-file. Parts that conflict are taken from `ours`.
+    pub lock_file: LockFile,
+
+    /// The changes that could not be merged automatically.
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// A change to an environment that conflicts between both sides of a merge.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MergeConflict {
+    /// The name of the environment.
+    pub environment: String,
+
+    /// The part of the environment that conflicts.
+    #[serde(flatten)]
+    pub kind: MergeConflictKind,
+}
+
+/// The part of an environment that conflicts between both sides of a merge.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "conflict", rename_all = "kebab-case")]
+pub enum MergeConflictKind {
+    /// The environment was removed on one side and modified on the other.
+    Environment,
+
+    /// The channels were changed on both sides.
+    Channels,
+
+    /// The pypi indexes were changed on both sides.
+    PypiIndexes,
+
+    /// The solve options were changed on both sides.
+    SolveOptions,
+
+    /// A package was changed on both sides.
+    Package {
+        /// The platform of the package.
+        platform: Platform,
+
+        /// The ecosystem the package belongs to.
+        kind: PackageKind,
+
+        /// The normalized name of the package.
+        name: String,
+
+        /// The package on our side, or `None` if it was removed.
+        ours: Option<PackageVersion>,
+
+        /// The package on their side, or `None` if it was removed.
+        theirs: Option<PackageVersion>,
+    },
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let environment = &self.environment;
+        match &self.kind {
+            MergeConflictKind::Environment => write!(
+                f,
+                "environment '{environment}' was removed on one side and modified on the other"
+            ),
+            MergeConflictKind::Channels => {
+                write!(
+                    f,
+                    "the channels of environment '{environment}' were changed on both sides"
+                )
+            }
+            MergeConflictKind::PypiIndexes => write!(
+                f,
+                "the pypi indexes of environment '{environment}' were changed on both sides"
+            ),
+            MergeConflictKind::SolveOptions => write!(
+                f,
+                "the solve options of environment '{environment}' were changed on both sides"
+            ),
+            MergeConflictKind::Package {
+                platform,
+                kind,
+                name,
+                ours,
+                theirs,
+            } => {
+                let format = |version: &Option<PackageVersion>| {
+                    version
+                        .as_ref()
+                        .map_or_else(|| "removed".to_string(), ToString::to_string)
+                };
+                write!(
+                    f,
+                    "{} package '{name}' of environment '{environment}' ({platform}) is {} on our side but {} on their side",
+                    kind.as_str(),
+                    format(ours),
+                    format(theirs)
+                )
+            }
+        }
+    }
+}
+
+impl LockFileMerge {
+    /// Merges the changes between `base` and `ours` with the changes between
+    /// `base` and `theirs`.
+    pub fn from_lock_files(base: &LockFile, ours: &LockFile, theirs: &LockFile) -> Self {
+        let names = base
+            .environments()
+            .chain(ours.environments())
+            .chain(theirs.environments())
+            .map(|(name, _)| name)
+            .collect::<BTreeSet<_>>();
+
+        let mut builder = LockFileBuilder::new();
+        let mut conflicts = Vec::new();
+        for name in names {
+            let [base_env, ours_env, theirs_env] = [base, ours, theirs]
+                .map(|lock_file| lock_file.environment(name).map(EnvironmentSnapshot::new));
+
+            let (ours_env, theirs_env) = match (ours_env, theirs_env) {
+                (None, None) => continue,
+                (Some(ours_env), Some(theirs_env)) => (ours_env, theirs_env),
+                (ours_env, theirs_env) => {
+                    // The environment only exists on one side, it was either added on that
+                    // side or removed on the other.
+                    let is_ours = ours_env.is_some();
+                    let remaining = ours_env.or(theirs_env).expect("one side is present");
+                    match &base_env {
+                        Some(base_env) if *base_env == remaining => {}
+                        Some(_) => {
+                            conflicts.push(MergeConflict {
+                                environment: name.to_string(),
+                                kind: MergeConflictKind::Environment,
+                            });
+                            if is_ours {
+                                remaining.add_to(&mut builder, name);
+                            }
+                        }
+                        None => remaining.add_to(&mut builder, name),
+                    }
+                    continue;
+                }
+            };
+
+            let mut conflict = |kind| {
+                conflicts.push(MergeConflict {
+                    environment: name.to_string(),
+                    kind,
+                });
+            };
+
+            let channels = merge_value(
+                base_env.as_ref().map(|env| env.channels),
+                Some(ours_env.channels),
+                Some(theirs_env.channels),
+            )
+            .unwrap_or_else(|ours| {
+                conflict(MergeConflictKind::Channels);
+                ours
+            })
+            .unwrap_or_default();
+
+            let indexes = merge_value(
+                base_env.as_ref().map(|env| env.indexes),
+                Some(ours_env.indexes),
+                Some(theirs_env.indexes),
+            )
+            .unwrap_or_else(|ours| {
+                conflict(MergeConflictKind::PypiIndexes);
+                ours
+            })
+            .flatten();
+
+            let options = merge_value(
+                base_env.as_ref().map(|env| env.options),
+                Some(ours_env.options),
+                Some(theirs_env.options),
+            )
+            .unwrap_or_else(|ours| {
+                conflict(MergeConflictKind::SolveOptions);
+                ours
+            });
+
+            let keys = base_env
+                .iter()
+                .chain([&ours_env, &theirs_env])
+                .flat_map(|env| env.packages.keys())
+                .collect::<BTreeSet<_>>();
+            let mut packages = BTreeMap::new();
+            for key in keys {
+                let [base_packages, ours_packages, theirs_packages] =
+                    [base_env.as_ref(), Some(&ours_env), Some(&theirs_env)]
+                        .map(|env| env.and_then(|env| env.packages.get(key)));
+                let merged = merge_value(base_packages, ours_packages, theirs_packages)
+                    .unwrap_or_else(|ours| {
+                        let (platform, kind, name) = key.clone();
+                        let version = |packages: Option<&Vec<PackageEntry<'_>>>| {
+                            packages
+                                .and_then(|packages| packages.first())
+                                .map(|package| PackageVersion::new(package.0))
+                        };
+                        conflict(MergeConflictKind::Package {
+                            platform,
+                            kind,
+                            name,
+                            ours: version(ours_packages),
+                            theirs: version(theirs_packages),
+                        });
+                        ours
+                    });
+                if let Some(merged) = merged {
+                    packages.insert(key.clone(), merged.clone());
+                }
+            }
+
+            EnvironmentSnapshot {
+                channels,
+                indexes,
+                options: options.unwrap_or(ours_env.options),
+                packages,
+            }
+            .add_to(&mut builder, name);
+        }
+
+        Self {
+            lock_file: builder.finish(),
+            conflicts,
+        }
+    }
+
+    /// Returns true if the lock-files were merged without conflicts.
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// Merges a value that was changed on two sides. Returns the merged value or,
+/// if both sides changed the value differently, our value as an error.
+fn merge_value<T: PartialEq>(
+    base: Option<T>,
+    ours: Option<T>,
+    theirs: Option<T>,
+) -> Result<Option<T>, Option<T>> {
+    if ours == theirs || theirs == base {
+        Ok(ours)
+    } else if ours == base {
+        Ok(theirs)
+    } else {
+        Err(ours)
+    }
+}
+
+/// A locked package that can be compared between lock-files.
+#[derive(Clone, Copy)]
+struct PackageEntry<'lock>(LockedPackageRef<'lock>);
+
+impl PartialEq for PackageEntry<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.0, other.0) {
+            (LockedPackageRef::Conda(a), LockedPackageRef::Conda(b)) => a == b,
+            (LockedPackageRef::Pypi(a, a_env), LockedPackageRef::Pypi(b, b_env)) => {
+                a == b && a_env.extras == b_env.extras
+            }
+            _ => false,
+        }
+    }
+}
+
+type PackageKey = (Platform, PackageKind, String);
+
+/// The content of an environment in one of the lock-files.
+#[derive(PartialEq)]
+struct EnvironmentSnapshot<'lock> {
+    channels: &'lock [Channel],
+    indexes: Option<&'lock PypiIndexes>,
+    options: &'lock SolveOptions,
+    packages: BTreeMap<PackageKey, Vec<PackageEntry<'lock>>>,
+}
+
+impl<'lock> EnvironmentSnapshot<'lock> {
+    fn new(environment: Environment<'lock>) -> Self {
+        let mut packages = BTreeMap::<_, Vec<_>>::new();
+        for (platform, platform_packages) in environment.packages_by_platform() {
+            for package in platform_packages {
+                let (kind, name) = package_key(package);
+                packages
+                    .entry((platform, kind, name))
+                    .or_default()
+                    .push(PackageEntry(package));
+            }
+        }
+        Self {
+            channels: environment.channels(),
+            indexes: environment.pypi_indexes(),
+            options: environment.solve_options(),
+            packages,
+        }
+    }
+
+    fn add_to(&self, builder: &mut LockFileBuilder, name: &str) {
+        builder
+            .set_channels(name, self.channels.iter().cloned())
+            .set_options(name, self.options.clone());
+        if let Some(indexes) = self.indexes {
+            builder.set_pypi_indexes(name, indexes.clone());
+        }
+        for ((platform, _, _), packages) in &self.packages {
+            for package in packages {
+                match package.0 {
+                    LockedPackageRef::Conda(data) => {
+                        builder.add_conda_package(name, *platform, data.clone());
+                    }
+                    LockedPackageRef::Pypi(data, environment_data) => {
+                        builder.add_pypi_package(
+                            name,
+                            *platform,
+                            data.clone(),
+                            PypiPackageEnvironmentData {
+                                extras: environment_data.extras.clone(),
+                            },
+                        );
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::str::FromStr;
+
+    use rattler_conda_types::{PackageName, PackageRecord, Platform, Version};
+    use url::Url;
+
+    use super::*;
+    use crate::{CondaBinaryData, CondaPackageData, DEFAULT_ENVIRONMENT_NAME};
+
+    fn lock_file(packages: &[(&str, &str)]) -> LockFile {
+        let mut builder = LockFileBuilder::new();
+        builder.set_channels(
+            DEFAULT_ENVIRONMENT_NAME,
+            ["https://conda.anaconda.org/conda-forge/"],
+        );
+        for (name, version) in packages {
+            let file_name = format!("{name}-{version}-h123_0.conda");
+            let package = CondaPackageData::Binary(CondaBinaryData {
+                package_record: PackageRecord::new(
+                    PackageName::new_unchecked(*name),
+                    Version::from_str(version).unwrap(),
+                    "h123_0".to_string(),
+                ),
+                location: Url::parse(&format!(
+                    "https://conda.anaconda.org/conda-forge/linux-64/{file_name}"
+                ))
+                .unwrap()
+                .into(),
+                file_name,
+                channel: None,
+            });
+            builder.add_conda_package(DEFAULT_ENVIRONMENT_NAME, Platform::Linux64, package);
+        }
+        builder.finish()
+    }
+
+    fn versions(lock_file: &LockFile) -> Vec<(String, String)> {
+        let mut versions = lock_file
+            .default_environment()
+            .unwrap()
+            .conda_packages(Platform::Linux64)
+            .unwrap()
+            .map(|package| {
+                (
+                    package.record().name.as_normalized().to_string(),
+                    package.record().version.to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+        versions.sort();
+        versions
+    }
+
+    #[test]
+    fn test_merge_clean() {
+        let base = lock_file(&[("python", "3.11.0"), ("numpy", "1.26.0"), ("pip", "24.0")]);
+        let ours = lock_file(&[("python", "3.12.0"), ("numpy", "1.26.0"), ("pip", "24.0")]);
+        let theirs = lock_file(&[
+            ("python", "3.11.0"),
+            ("numpy", "1.26.0"),
+            ("requests", "2.32.0"),
+        ]);
+
+        let merge = LockFileMerge::from_lock_files(&base, &ours, &theirs);
+        assert!(merge.is_clean());
+        assert_eq!(
+            versions(&merge.lock_file),
+            vec![
+                ("numpy".to_string(), "1.26.0".to_string()),
+                ("python".to_string(), "3.12.0".to_string()),
+                ("requests".to_string(), "2.32.0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_conflict() {
+        let base = lock_file(&[("python", "3.11.0"), ("numpy", "1.26.0")]);
+        let ours = lock_file(&[("python", "3.11.0"), ("numpy", "2.0.0")]);
+        let theirs = lock_file(&[("python", "3.12.0"), ("numpy", "2.1.0")]);
+
+        let merge = LockFileMerge::from_lock_files(&base, &ours, &theirs);
+        assert_eq!(merge.conflicts.len(), 1);
+        assert_eq!(
+            merge.conflicts[0].to_string(),
+            "conda package 'numpy' of environment 'default' (linux-64) is 2.0.0 h123_0 on our side but 2.1.0 h123_0 on their side"
+        );
+
+        // Conflicting packages are taken from our side.
+        assert_eq!(
+            versions(&merge.lock_file),
+            vec![
+                ("numpy".to_string(), "2.0.0".to_string()),
+                ("python".to_string(), "3.12.0".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_merge_removed_environment() {
+        let base = lock_file(&[("python", "3.11.0")]);
+        let ours = LockFile::default();
+        let theirs = lock_file(&[("python", "3.12.0")]);
+
+        let merge = LockFileMerge::from_lock_files(&base, &ours, &theirs);
+        assert_eq!(
+            merge.conflicts,
+            vec![MergeConflict {
+                environment: DEFAULT_ENVIRONMENT_NAME.to_string(),
+                kind: MergeConflictKind::Environment,
+            }]
+        );
+
+        // Removing an unmodified environment is not a conflict.
+        let merge = LockFileMerge::from_lock_files(&base, &ours, &base);
+        assert!(merge.is_clean());
+        assert!(merge.lock_file.default_environment().is_none());
+    }
+}
@@ -1,7 +1,7 @@
 //! Defines [`SolveOptions`] and reexports from `rattler_solve` that are used.
 
 // Reexport these fields.
-pub use rattler_solve::{ChannelPriority, SolveStrategy};
+pub use rattler_solve::{ChannelPriority, ExcludeNewerOverrides, SolveStrategy};
 
 /// Options that were used during the resolution of the packages stored in the
 /// lock-file. These options strongly influence the outcome of the solve and are
@@ -20,4 +20,9 @@ pub struct SolveOptions {
     /// Packages after this date have been excluded from the lock file.
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub exclude_newer: Option<chrono::DateTime<chrono::Utc>>,
+
+    /// Packages and channels for which a different cutoff than
+    /// `exclude_newer` was used.
+    #[serde(default, skip_serializing_if = "ExcludeNewerOverrides::is_empty")]
+    pub exclude_newer_overrides: ExcludeNewerOverrides,
 }
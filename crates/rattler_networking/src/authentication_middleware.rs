@@ -1,18 +1,23 @@
 //! `reqwest` middleware that authenticates requests with data from the `AuthenticationStorage`
 use crate::authentication_storage::AuthenticationStorageError;
+use crate::oauth::{self, OAuthError};
 use crate::{Authentication, AuthenticationStorage};
 use base64::prelude::BASE64_STANDARD;
 use base64::Engine;
-use reqwest::{Request, Response};
+use reqwest::{Request, Response, StatusCode};
 use reqwest_middleware::{Middleware, Next};
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::sync::OnceLock;
+use std::sync::{Arc, Mutex, OnceLock};
 use url::Url;
 
 /// `reqwest` middleware to authenticate requests
 #[derive(Clone)]
 pub struct AuthenticationMiddleware {
     auth_storage: AuthenticationStorage,
+    oauth_client: reqwest::Client,
+    /// Locks that serialize the refreshes of the OAuth credentials per key
+    refresh_locks: Arc<Mutex<HashMap<String, Arc<futures::lock::Mutex<()>>>>>,
 }
 
 #[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
@@ -30,12 +35,18 @@ impl Middleware for AuthenticationMiddleware {
         }
 
         let url = req.url().clone();
-        match self.auth_storage.get_by_url(url) {
+        match self.auth_storage.get_entry_by_url(url) {
             Err(_) => {
                 // Forward error to caller (invalid URL)
                 next.run(req, extensions).await
             }
-            Ok((url, auth)) => {
+            Ok((url, Some((key, auth @ Authentication::OAuth { .. })))) => {
+                let mut req = req;
+                *req.url_mut() = url;
+                self.handle_oauth(req, &key, auth, extensions, next).await
+            }
+            Ok((url, entry)) => {
+                let auth = entry.map(|(_, auth)| auth);
                 let url = Self::authenticate_url(url, &auth);
 
                 let mut req = req;
@@ -51,14 +62,122 @@ impl Middleware for AuthenticationMiddleware {
 impl AuthenticationMiddleware {
     /// Create a new authentication middleware with the given authentication storage
     pub fn from_auth_storage(auth_storage: AuthenticationStorage) -> Self {
-        Self { auth_storage }
+        Self {
+            auth_storage,
+            oauth_client: reqwest::Client::default(),
+            refresh_locks: Arc::default(),
+        }
     }
 
     /// Create a new authentication middleware with the default authentication storage
     pub fn from_env_and_defaults() -> Result<Self, AuthenticationStorageError> {
-        Ok(Self {
-            auth_storage: AuthenticationStorage::from_env_and_defaults()?,
-        })
+        Ok(Self::from_auth_storage(
+            AuthenticationStorage::from_env_and_defaults()?,
+        ))
+    }
+
+    /// Authenticate the request with an OAuth access token.
+    ///
+    /// An access token that has expired is refreshed before the request is
+    /// sent. If the server rejects the access token the request is retried
+    /// once with a refreshed token.
+    async fn handle_oauth(
+        &self,
+        req: Request,
+        key: &str,
+        mut auth: Authentication,
+        extensions: &mut http::Extensions,
+        next: Next<'_>,
+    ) -> reqwest_middleware::Result<Response> {
+        let can_refresh = matches!(
+            auth,
+            Authentication::OAuth {
+                refresh_token: Some(_),
+                ..
+            }
+        );
+
+        let mut refreshed = false;
+        if can_refresh && oauth::is_expired(&auth) {
+            match self.refresh(key, &auth).await {
+                Ok(new_auth) => {
+                    auth = new_auth;
+                    refreshed = true;
+                }
+                Err(err) => tracing::warn!("failed to refresh the access token for {key}: {err}"),
+            }
+        }
+
+        let retry = if can_refresh && !refreshed {
+            req.try_clone()
+        } else {
+            None
+        };
+        let req = Self::authenticate_request(req, &Some(auth.clone())).await?;
+        let response = next.clone().run(req, extensions).await?;
+        let Some(retry) = retry else {
+            return Ok(response);
+        };
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        match self.refresh(key, &auth).await {
+            Ok(auth) => {
+                let req = Self::authenticate_request(retry, &Some(auth)).await?;
+                next.run(req, extensions).await
+            }
+            Err(err) => {
+                tracing::warn!("failed to refresh the access token for {key}: {err}");
+                Ok(response)
+            }
+        }
+    }
+
+    /// Refresh the access token of OAuth credentials and store the new tokens
+    /// under the given key.
+    ///
+    /// Refreshes of the same key are serialized. If the stored credentials
+    /// were already replaced by a concurrent refresh those are returned
+    /// instead, providers that rotate refresh tokens reject a refresh token
+    /// that was used before.
+    async fn refresh(
+        &self,
+        key: &str,
+        auth: &Authentication,
+    ) -> Result<Authentication, OAuthError> {
+        let lock = self
+            .refresh_locks
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        let _guard = lock.lock().await;
+
+        let current = match self.auth_storage.get(key) {
+            Ok(Some(stored @ Authentication::OAuth { .. })) => stored,
+            _ => auth.clone(),
+        };
+        let replaced = match (&current, auth) {
+            (
+                Authentication::OAuth {
+                    access_token: current_token,
+                    ..
+                },
+                Authentication::OAuth { access_token, .. },
+            ) => current_token != access_token,
+            _ => false,
+        };
+        if replaced && !oauth::is_expired(&current) {
+            return Ok(current);
+        }
+
+        let auth = oauth::refresh(&self.oauth_client, &current).await?;
+        if let Err(err) = self.auth_storage.store(key, &auth) {
+            tracing::warn!("failed to store the refreshed credentials for {key}: {err}");
+        }
+        Ok(auth)
     }
 
     /// Authenticate the given URL with the given authentication information
@@ -90,7 +209,11 @@ impl AuthenticationMiddleware {
     ) -> reqwest_middleware::Result<reqwest::Request> {
         if let Some(credentials) = auth {
             match credentials {
-                Authentication::BearerToken(token) => {
+                Authentication::BearerToken(token)
+                | Authentication::OAuth {
+                    access_token: token,
+                    ..
+                } => {
                     let bearer_auth = format!("Bearer {token}");
 
                     let mut header_value = reqwest::header::HeaderValue::from_str(&bearer_auth)
@@ -143,7 +266,13 @@ pub fn default_auth_store_fallback_directory() -> &'static Path {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::authentication_storage::backends::file::FileStorage;
+    use crate::authentication_storage::{
+        backends::{
+            file::FileStorage,
+            memory::{MemoryStorage, MemoryStorageError},
+        },
+        AuthenticationStorageError, StorageBackend,
+    };
     use std::sync::Arc;
     use tempfile::tempdir;
 
@@ -428,6 +557,146 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_most_specific_credentials() -> anyhow::Result<()> {
+        let tdir = tempdir()?;
+        let backends: [Arc<dyn StorageBackend + Send + Sync>; 2] = [
+            Arc::new(FileStorage::from_path(tdir.path().join("auth.json"))?),
+            Arc::new(MemoryStorage::new()),
+        ];
+        for backend in backends {
+            let mut storage = AuthenticationStorage::empty();
+            storage.add_backend(backend);
+            for key in [
+                "prefix.dev",
+                "prefix.dev/channel-a",
+                "prefix.dev/channel-a/noarch",
+                // File names are not part of the lookup keys
+                "prefix.dev/channel-b/noarch/repodata.json",
+                "*.corp.example",
+                "*.corp.example/team",
+                "repo.corp.example",
+            ] {
+                storage.store(key, &Authentication::BearerToken(key.to_string()))?;
+            }
+
+            for (url, expected) in [
+                (
+                    "https://prefix.dev/channel-a/noarch/repodata.json",
+                    Some("prefix.dev/channel-a/noarch"),
+                ),
+                (
+                    "https://prefix.dev/channel-a/noarch/",
+                    Some("prefix.dev/channel-a/noarch"),
+                ),
+                (
+                    "https://prefix.dev/channel-a/noarch",
+                    Some("prefix.dev/channel-a"),
+                ),
+                (
+                    "https://prefix.dev/channel-a/linux-64/repodata.json",
+                    Some("prefix.dev/channel-a"),
+                ),
+                (
+                    "https://prefix.dev/channel-b/noarch/repodata.json",
+                    Some("prefix.dev"),
+                ),
+                (
+                    "https://prefix.dev/channel-ab/noarch/repodata.json",
+                    Some("prefix.dev"),
+                ),
+                (
+                    "https://repo.corp.example/team/noarch/repodata.json",
+                    Some("repo.corp.example"),
+                ),
+                (
+                    "https://conda.corp.example/team/noarch/repodata.json",
+                    Some("*.corp.example/team"),
+                ),
+                (
+                    "https://conda.corp.example/other/noarch/repodata.json",
+                    Some("*.corp.example"),
+                ),
+                ("https://example.com/channel-a/noarch/repodata.json", None),
+            ] {
+                let (_, entry) = storage.get_entry_by_url(url)?;
+                assert_eq!(
+                    entry.as_ref().map(|(key, _)| key.as_str()),
+                    expected,
+                    "{url}"
+                );
+                if let Some((key, credentials)) = entry {
+                    assert_eq!(credentials, Authentication::BearerToken(key));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A backend that fails until it is told to recover.
+    #[derive(Debug, Default)]
+    struct FlakyStorage {
+        memory: MemoryStorage,
+        failing: std::sync::atomic::AtomicBool,
+    }
+
+    impl StorageBackend for FlakyStorage {
+        fn store(
+            &self,
+            host: &str,
+            authentication: &Authentication,
+        ) -> Result<(), AuthenticationStorageError> {
+            self.memory.store(host, authentication)
+        }
+
+        fn get(&self, host: &str) -> Result<Option<Authentication>, AuthenticationStorageError> {
+            if self.failing.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err(MemoryStorageError::LockError.into());
+            }
+            self.memory.get(host)
+        }
+
+        fn delete(&self, host: &str) -> Result<(), AuthenticationStorageError> {
+            self.memory.delete(host)
+        }
+    }
+
+    #[test]
+    fn test_failed_lookups_are_not_cached() -> anyhow::Result<()> {
+        let backend = Arc::new(FlakyStorage::default());
+        backend
+            .failing
+            .store(true, std::sync::atomic::Ordering::SeqCst);
+        backend.store(
+            "prefix.dev",
+            &Authentication::CondaToken("token".to_string()),
+        )?;
+
+        let mut storage = AuthenticationStorage::empty();
+        storage.add_backend(backend.clone());
+        assert_eq!(storage.get("prefix.dev")?, None);
+
+        // Once the backend recovers the credentials are found.
+        backend
+            .failing
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(
+            storage.get("prefix.dev")?,
+            Some(Authentication::CondaToken("token".to_string()))
+        );
+
+        // Misses from healthy backends are still remembered.
+        assert_eq!(storage.get("example.com")?, None);
+        backend.store(
+            "example.com",
+            &Authentication::CondaToken("token".to_string()),
+        )?;
+        assert_eq!(storage.get("example.com")?, None);
+
+        Ok(())
+    }
+
     #[test]
     fn test_rattler_auth_file_env_var_handling() -> anyhow::Result<()> {
         let tdir = tempdir()?;
@@ -2,6 +2,7 @@
 use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 /// The different Authentication methods that are supported in the conda
 /// ecosystem
@@ -28,6 +29,20 @@ pub enum Authentication {
         /// The session token to use for S3 authentication
         session_token: Option<String>,
     },
+    /// OAuth 2.0 / OIDC tokens, the access token is sent as a bearer
+    /// token and is refreshed with the refresh token when it expires
+    OAuth {
+        /// The short-lived access token
+        access_token: String,
+        /// The token to obtain a new access token with
+        refresh_token: Option<String>,
+        /// The unix timestamp (in seconds) at which the access token expires
+        expires_at: Option<u64>,
+        /// The endpoint of the identity provider that refreshes the tokens
+        token_endpoint: Url,
+        /// The client ID the tokens were issued to
+        client_id: String,
+    },
 }
 
 /// An error that can occur when parsing an authentication string
@@ -56,6 +71,7 @@ impl Authentication {
             Authentication::BasicHTTP { .. } => "BasicHTTP",
             Authentication::CondaToken(_) => "CondaToken",
             Authentication::S3Credentials { .. } => "S3",
+            Authentication::OAuth { .. } => "OAuth",
         }
     }
 }
@@ -0,0 +1,446 @@
+//! Storage backend that delegates to external credential helpers.
+//!
+//! A helper is an executable that is invoked as `<command> [args...] <action>`
+//! where the action is `get`, `store` or `erase`. The helper receives a JSON
+//! object on stdin with the `host`, the `path` if the credentials are scoped
+//! to a path on the host and, for `store`, the `authentication` in the same
+//! format as the credentials file, e.g.
+//!
+//! ```json
+//! {"host": "repo.corp.example", "path": "channel", "authentication": {"BearerToken": "..."}}
+//! ```
+//!
+//! For `get` the helper prints the authentication as JSON to stdout, or
+//! nothing if it has no credentials for the host and path. A non-zero exit
+//! status is treated as an error. Wildcard keys like `*.corp.example` are
+//! never passed to a helper.
+//!
+//! With the `rattler_config` feature the helpers are read from the
+//! `credential-helpers` table of the config file that the
+//! `RATTLER_CONFIG_FILE` environment variable points to.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use serde::Serialize;
+
+use crate::{
+    authentication_storage::{AuthenticationStorageError, StorageBackend},
+    Authentication,
+};
+
+/// An external executable that provides credentials.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CredentialHelper {
+    /// The executable to invoke
+    pub command: String,
+    /// Arguments that are passed to the executable before the action
+    pub args: Vec<String>,
+}
+
+/// A struct that implements storage and access of authentication information
+/// by invoking external credential helpers.
+///
+/// Every helper is configured for a host pattern: an exact host, a wildcard
+/// like `*.corp.example` that matches the domain and all its subdomains, or
+/// `*` to match every host. If several patterns match a host the most
+/// specific one is used: an exact host, then the wildcard with the longest
+/// domain, then `*`.
+#[derive(Clone, Debug, Default)]
+pub struct CredentialHelperStorage {
+    helpers: Vec<(String, CredentialHelper)>,
+}
+
+/// An error that can occur when invoking a credential helper
+#[derive(thiserror::Error, Debug)]
+pub enum CredentialHelperStorageError {
+    /// No credential helper is configured for the host
+    #[error("no credential helper is configured for {0}")]
+    NoHelper(String),
+
+    /// The credential helper could not be executed
+    #[error("failed to execute credential helper `{0}`")]
+    IoError(String, #[source] std::io::Error),
+
+    /// The credential helper exited with an error
+    #[error("credential helper `{command}` failed with {status}: {stderr}")]
+    Failed {
+        /// The command of the helper
+        command: String,
+        /// The exit status of the helper
+        status: std::process::ExitStatus,
+        /// The error output of the helper
+        stderr: String,
+    },
+
+    /// The credential helper printed invalid credentials
+    #[error("credential helper `{0}` returned invalid credentials")]
+    JSONError(String, #[source] serde_json::Error),
+}
+
+#[derive(Serialize)]
+struct HelperRequest<'a> {
+    host: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authentication: Option<&'a Authentication>,
+}
+
+impl CredentialHelperStorage {
+    /// Create a new storage from host patterns and their helpers
+    pub fn from_helpers(helpers: impl IntoIterator<Item = (String, CredentialHelper)>) -> Self {
+        Self {
+            helpers: helpers.into_iter().collect(),
+        }
+    }
+
+    /// Returns true if no helpers are configured
+    pub fn is_empty(&self) -> bool {
+        self.helpers.is_empty()
+    }
+
+    /// Returns the helper with the most specific pattern that matches the
+    /// given host
+    fn helper(&self, host: &str) -> Option<&CredentialHelper> {
+        self.helpers
+            .iter()
+            .filter(|(pattern, _)| pattern_matches(pattern, host))
+            .max_by_key(|(pattern, _)| pattern_specificity(pattern))
+            .map(|(_, helper)| helper)
+    }
+
+    /// Invokes the helper for the storage key and returns the helper and its
+    /// output, or `None` if no helper handles the key.
+    fn run(
+        &self,
+        key: &str,
+        action: &str,
+        authentication: Option<&Authentication>,
+    ) -> Result<Option<(&CredentialHelper, Vec<u8>)>, CredentialHelperStorageError> {
+        let Some((host, path)) = split_key(key) else {
+            return Ok(None);
+        };
+        let Some(helper) = self.helper(host) else {
+            return Ok(None);
+        };
+        let io_error = |err| CredentialHelperStorageError::IoError(helper.command.clone(), err);
+
+        let mut child = Command::new(&helper.command)
+            .args(&helper.args)
+            .arg(action)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(io_error)?;
+        let request = serde_json::to_vec(&HelperRequest {
+            host,
+            path,
+            authentication,
+        })
+        .expect("serializing the request cannot fail");
+        // Helpers that do not need the request may exit without reading it.
+        match child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(&request)
+        {
+            Err(err) if err.kind() != std::io::ErrorKind::BrokenPipe => {
+                return Err(io_error(err));
+            }
+            _ => {}
+        }
+
+        let output = child.wait_with_output().map_err(io_error)?;
+        if !output.status.success() {
+            return Err(CredentialHelperStorageError::Failed {
+                command: helper.command.clone(),
+                status: output.status,
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            });
+        }
+        Ok(Some((helper, output.stdout)))
+    }
+}
+
+/// Splits a storage key into the host and the path the credentials are scoped
+/// to, e.g. `repo.corp.example/channel` or `s3://bucket/channel`. Returns
+/// `None` for wildcard keys like `*.corp.example`.
+fn split_key(key: &str) -> Option<(&str, Option<&str>)> {
+    let key = key.split_once("://").map_or(key, |(_, rest)| rest);
+    let (host, path) = match key.split_once('/') {
+        Some((host, path)) => (host, Some(path.trim_matches('/'))),
+        None => (key, None),
+    };
+    let path = path.filter(|path| !path.is_empty());
+    (!host.starts_with('*')).then_some((host, path))
+}
+
+/// Returns true if the host pattern matches the host. The host may itself be
+/// a wildcard like `*.corp.example`, which matches if the pattern covers all
+/// of its subdomains.
+fn pattern_matches(pattern: &str, host: &str) -> bool {
+    if pattern == "*" || pattern == host {
+        return true;
+    }
+    let Some(domain) = pattern.strip_prefix("*.") else {
+        return false;
+    };
+    let host = host.strip_prefix("*.").unwrap_or(host);
+    host == domain
+        || host
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.ends_with('.'))
+}
+
+/// Returns how specific a host pattern is, higher is more specific.
+fn pattern_specificity(pattern: &str) -> usize {
+    match pattern.strip_prefix("*") {
+        Some(domain) => domain.len(),
+        None => usize::MAX,
+    }
+}
+
+#[cfg(feature = "rattler_config")]
+impl From<rattler_config::config::credential_helpers::CredentialHelpersMap>
+    for CredentialHelperStorage
+{
+    fn from(helpers: rattler_config::config::credential_helpers::CredentialHelpersMap) -> Self {
+        Self::from_helpers(helpers.0.into_iter().map(|(pattern, helper)| {
+            (
+                pattern,
+                CredentialHelper {
+                    command: helper.command,
+                    args: helper.args,
+                },
+            )
+        }))
+    }
+}
+
+impl StorageBackend for CredentialHelperStorage {
+    fn store(
+        &self,
+        host: &str,
+        authentication: &Authentication,
+    ) -> Result<(), AuthenticationStorageError> {
+        match self.run(host, "store", Some(authentication))? {
+            Some(_) => Ok(()),
+            None => Err(CredentialHelperStorageError::NoHelper(host.to_string()).into()),
+        }
+    }
+
+    fn get(&self, host: &str) -> Result<Option<Authentication>, AuthenticationStorageError> {
+        let Some((helper, output)) = self.run(host, "get", None)? else {
+            return Ok(None);
+        };
+        if output.iter().all(u8::is_ascii_whitespace) {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&output).map_err(|err| {
+            CredentialHelperStorageError::JSONError(helper.command.clone(), err)
+        })?))
+    }
+
+    fn delete(&self, host: &str) -> Result<(), AuthenticationStorageError> {
+        match self.run(host, "erase", None)? {
+            Some(_) => Ok(()),
+            None => Err(CredentialHelperStorageError::NoHelper(host.to_string()).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_matches() {
+        assert!(pattern_matches("*", "repo.prefix.dev"));
+        assert!(pattern_matches("repo.prefix.dev", "repo.prefix.dev"));
+        assert!(!pattern_matches("repo.prefix.dev", "prefix.dev"));
+        assert!(pattern_matches("*.corp.example", "corp.example"));
+        assert!(pattern_matches("*.corp.example", "repo.corp.example"));
+        assert!(pattern_matches("*.corp.example", "*.corp.example"));
+        assert!(pattern_matches("*.corp.example", "*.repo.corp.example"));
+        assert!(!pattern_matches("*.corp.example", "notcorp.example"));
+        assert!(!pattern_matches("*.corp.example", "*.example"));
+    }
+
+    #[test]
+    fn test_most_specific_helper() {
+        let helper = |command: &str| CredentialHelper {
+            command: command.to_string(),
+            args: Vec::new(),
+        };
+        let storage = CredentialHelperStorage::from_helpers([
+            ("*".to_string(), helper("any")),
+            ("*.corp.example".to_string(), helper("corp")),
+            ("*.team.corp.example".to_string(), helper("team")),
+            ("repo.team.corp.example".to_string(), helper("repo")),
+        ]);
+        let command = |host: &str| storage.helper(host).map(|helper| helper.command.as_str());
+        assert_eq!(command("repo.team.corp.example"), Some("repo"));
+        assert_eq!(command("conda.team.corp.example"), Some("team"));
+        assert_eq!(command("team.corp.example"), Some("team"));
+        assert_eq!(command("conda.corp.example"), Some("corp"));
+        assert_eq!(command("prefix.dev"), Some("any"));
+    }
+
+    /// Writes a helper script that serves and stores credentials in the
+    /// directory that is passed as its first argument. The requests of `get`
+    /// are appended to `requests` in that directory.
+    #[cfg(unix)]
+    fn helper_script(dir: &std::path::Path) -> std::path::PathBuf {
+        use std::os::unix::fs::PermissionsExt;
+
+        let script = dir.join("helper.sh");
+        fs_err::write(
+            &script,
+            r#"#!/bin/sh
+dir="$1"
+case "$2" in
+    get) cat >> "$dir/requests"; echo >> "$dir/requests"
+         cat "$dir/credentials" 2>/dev/null || true ;;
+    store) cat > "$dir/stored" ;;
+    erase) rm -f "$dir/credentials" ;;
+    *) echo "unknown action $2" >&2; exit 1 ;;
+esac
+"#,
+        )
+        .unwrap();
+        fs_err::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        script
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_credential_helper() {
+        let tdir = tempfile::tempdir().unwrap();
+        let script = helper_script(tdir.path());
+
+        let storage = CredentialHelperStorage::from_helpers([(
+            "*.corp.example".to_string(),
+            CredentialHelper {
+                command: script.to_string_lossy().into_owned(),
+                args: vec![tdir.path().to_string_lossy().into_owned()],
+            },
+        )]);
+
+        // Hosts without a helper are not handled.
+        assert_eq!(storage.get("prefix.dev").unwrap(), None);
+        assert!(storage
+            .store(
+                "prefix.dev",
+                &Authentication::BearerToken("token".to_string())
+            )
+            .is_err());
+
+        assert_eq!(storage.get("repo.corp.example").unwrap(), None);
+        fs_err::write(
+            tdir.path().join("credentials"),
+            r#"{"BearerToken":"token"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            storage.get("repo.corp.example").unwrap(),
+            Some(Authentication::BearerToken("token".to_string()))
+        );
+
+        storage
+            .store(
+                "repo.corp.example",
+                &Authentication::CondaToken("secret".to_string()),
+            )
+            .unwrap();
+        assert_eq!(
+            fs_err::read_to_string(tdir.path().join("stored")).unwrap(),
+            r#"{"host":"repo.corp.example","authentication":{"CondaToken":"secret"}}"#
+        );
+
+        storage.delete("repo.corp.example").unwrap();
+        assert_eq!(storage.get("repo.corp.example").unwrap(), None);
+    }
+
+    #[cfg(all(unix, feature = "rattler_config"))]
+    #[test]
+    fn test_credential_helper_from_config() {
+        let tdir = tempfile::tempdir().unwrap();
+        let script = helper_script(tdir.path());
+        fs_err::write(
+            tdir.path().join("credentials"),
+            r#"{"BearerToken":"token"}"#,
+        )
+        .unwrap();
+        let config_file = tdir.path().join("config.toml");
+        fs_err::write(
+            &config_file,
+            format!(
+                "[credential-helpers.\"*.corp.example\"]\ncommand = {:?}\nargs = [{:?}]\n",
+                script.to_string_lossy(),
+                tdir.path().to_string_lossy()
+            ),
+        )
+        .unwrap();
+
+        temp_env::with_vars(
+            [
+                ("RATTLER_CONFIG_FILE", Some(config_file.as_os_str())),
+                ("RATTLER_AUTH_FILE", None),
+            ],
+            || {
+                let storage = crate::AuthenticationStorage::from_env_and_defaults().unwrap();
+                // The helper is asked for the most specific key first.
+                let (_, entry) = storage
+                    .get_entry_by_url("https://repo.corp.example/channel/noarch/repodata.json")
+                    .unwrap();
+                assert_eq!(
+                    entry,
+                    Some((
+                        "repo.corp.example/channel/noarch".to_string(),
+                        Authentication::BearerToken("token".to_string())
+                    ))
+                );
+            },
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_credential_helper_calls_per_lookup() {
+        let tdir = tempfile::tempdir().unwrap();
+        let script = helper_script(tdir.path());
+        let mut storage = crate::AuthenticationStorage::empty();
+        storage.add_backend(std::sync::Arc::new(CredentialHelperStorage::from_helpers(
+            [(
+                "*".to_string(),
+                CredentialHelper {
+                    command: script.to_string_lossy().into_owned(),
+                    args: vec![tdir.path().to_string_lossy().into_owned()],
+                },
+            )],
+        )));
+
+        // The helper is asked for every directory of the path but never for
+        // wildcard keys.
+        let (_, entry) = storage
+            .get_entry_by_url("https://repo.corp.example/channel/noarch/repodata.json")
+            .unwrap();
+        assert_eq!(entry, None);
+        assert_eq!(
+            fs_err::read_to_string(tdir.path().join("requests")).unwrap(),
+            concat!(
+                r#"{"host":"repo.corp.example","path":"channel/noarch"}"#,
+                "\n",
+                r#"{"host":"repo.corp.example","path":"channel"}"#,
+                "\n",
+                r#"{"host":"repo.corp.example"}"#,
+                "\n",
+            )
+        );
+    }
+}
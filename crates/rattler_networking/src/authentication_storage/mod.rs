@@ -24,6 +24,19 @@ pub enum AuthenticationStorageError {
     /// An error occurred when accessing the memory storage
     #[error("MemoryStorageError")]
     MemoryStorageError(#[from] crate::authentication_storage::backends::memory::MemoryStorageError),
+    /// An error occurred when invoking a credential helper
+    #[error("CredentialHelperStorageError")]
+    CredentialHelperStorageError(
+        #[from]
+        crate::authentication_storage::backends::credential_helper::CredentialHelperStorageError,
+    ),
+    /// The config file with the credential helpers could not be loaded
+    #[cfg(feature = "rattler_config")]
+    #[error("failed to load the config file {0}")]
+    ConfigError(
+        std::path::PathBuf,
+        #[source] rattler_config::config::LoadError,
+    ),
 }
 
 /// A trait that defines the interface for authentication storage backends
@@ -8,7 +8,10 @@ use std::{
 };
 use url::Url;
 
-use crate::authentication_storage::{backends::file::FileStorage, AuthenticationStorageError};
+use crate::authentication_storage::{
+    backends::{credential_helper::CredentialHelperStorage, file::FileStorage},
+    AuthenticationStorageError,
+};
 
 use super::{authentication::Authentication, StorageBackend};
 
@@ -44,10 +47,21 @@ impl AuthenticationStorage {
     /// Create a new authentication storage with the default backends
     /// Following order:
     /// - file storage from `RATTLER_AUTH_FILE` (if set)
+    /// - credential helpers from the config file at `RATTLER_CONFIG_FILE` (if
+    ///   set and the `rattler_config` feature is enabled)
     /// - keyring storage
     /// - file storage from the default location
     /// - netrc storage
     pub fn from_env_and_defaults() -> Result<Self, AuthenticationStorageError> {
+        Self::from_env_and_defaults_with_credential_helpers(credential_helpers_from_env()?)
+    }
+
+    /// Create a new authentication storage with the default backends and the
+    /// given credential helpers, which are consulted right after the file
+    /// storage from `RATTLER_AUTH_FILE` (if set).
+    pub fn from_env_and_defaults_with_credential_helpers(
+        credential_helpers: CredentialHelperStorage,
+    ) -> Result<Self, AuthenticationStorageError> {
         let mut storage = Self::empty();
 
         if let Ok(auth_file) = std::env::var("RATTLER_AUTH_FILE") {
@@ -58,6 +72,9 @@ impl AuthenticationStorage {
             );
             storage.add_backend(Arc::from(FileStorage::from_path(path.into())?));
         }
+        if !credential_helpers.is_empty() {
+            storage.add_backend(Arc::from(credential_helpers));
+        }
         #[cfg(feature = "keyring")]
         storage.add_backend(Arc::from(KeyringAuthenticationStorage::default()));
         #[cfg(feature = "dirs")]
@@ -118,6 +135,7 @@ impl AuthenticationStorage {
             }
         }
 
+        let mut any_failed = false;
         for backend in &self.backends {
             match backend.get(host) {
                 Ok(Some(auth)) => {
@@ -127,6 +145,7 @@ impl AuthenticationStorage {
                 }
                 Ok(None) => {}
                 Err(_e) => {
+                    any_failed = true;
                     #[cfg(feature = "keyring")]
                     if let AuthenticationStorageError::KeyringStorageError(
                         KeyringAuthenticationStorageError::StorageError(_),
@@ -140,12 +159,18 @@ impl AuthenticationStorage {
             }
         }
 
+        // Remember misses as well, looking up a URL tries many keys. A backend
+        // that failed might have the credentials, so try again next time.
+        if !any_failed {
+            let mut cache = self.cache.lock().unwrap();
+            cache.insert(host.to_string(), None);
+        }
         Ok(None)
     }
 
     /// Retrieve the authentication information for the given URL
-    /// (including the authentication information for the wildcard
-    /// host if no credentials are found for the given host)
+    /// (including the authentication information for path-scoped and
+    /// wildcard hosts, see [`Self::get_entry_by_url`])
     ///
     /// E.g. if credentials are stored for `*.prefix.dev` and the
     /// given URL is `https://repo.prefix.dev`, the credentials
@@ -154,19 +179,42 @@ impl AuthenticationStorage {
         &self,
         url: U,
     ) -> Result<(Url, Option<Authentication>), reqwest::Error> {
+        let (url, entry) = self.get_entry_by_url(url)?;
+        Ok((url, entry.map(|(_, credentials)| credentials)))
+    }
+
+    /// Retrieve the authentication information for the given URL together
+    /// with the key it is stored under, so that it can be updated with
+    /// [`Self::store`].
+    ///
+    /// Credentials can be stored for a host (`prefix.dev`), for all
+    /// subdomains of a domain (`*.prefix.dev`) and for a path prefix of
+    /// either (`prefix.dev/channel-a`, `*.prefix.dev/channel-a`). The most
+    /// specific match is returned: the exact host before wildcard domains,
+    /// longer domains before shorter ones, and for each of them the longest
+    /// path prefix before shorter ones and the host itself. Path prefixes are
+    /// directories, the file name at the end of the URL is never part of a
+    /// key.
+    pub fn get_entry_by_url<U: IntoUrl>(
+        &self,
+        url: U,
+    ) -> Result<(Url, Option<(String, Authentication)>), reqwest::Error> {
         let url = url.into_url()?;
         let Some(host) = url.host_str() else {
             return Ok((url, None));
         };
 
-        match self.get(host) {
-            Ok(None) => {}
-            Err(_) => return Ok((url, None)),
-            Ok(Some(credentials)) => return Ok((url, Some(credentials))),
-        };
-
         // S3 protocol URLs need to be treated separately since they follow a different schema
         if url.scheme() == "s3" {
+            match self.get(host) {
+                Ok(None) => {}
+                Err(_) => return Ok((url, None)),
+                Ok(Some(credentials)) => {
+                    let host = host.to_string();
+                    return Ok((url, Some((host, credentials))));
+                }
+            };
+
             let mut current_url = url.clone();
             loop {
                 match self.get(current_url.as_str()) {
@@ -185,37 +233,52 @@ impl AuthenticationStorage {
                             _ => return Ok((url, None)), // No more subpaths to check
                         }
                     }
-                    Ok(Some(credentials)) => return Ok((url, Some(credentials))),
+                    Ok(Some(credentials)) => {
+                        return Ok((url, Some((current_url.to_string(), credentials))))
+                    }
                     Err(_) => return Ok((url, None)),
                 }
             }
         }
 
-        // Check for credentials under e.g. `*.prefix.dev`
-        let Some(mut domain) = url.domain() else {
-            return Ok((url, None));
-        };
-
-        loop {
-            let wildcard_host = format!("*.{domain}");
-
-            let Ok(credentials) = self.get(&wildcard_host) else {
-                return Ok((url, None));
-            };
-
-            if let Some(credentials) = credentials {
-                return Ok((url, Some(credentials)));
+        // The host itself followed by e.g. `*.repo.prefix.dev`, `*.prefix.dev`, `*.dev`
+        let mut hosts = vec![host.to_string()];
+        if let Some(mut domain) = url.domain() {
+            loop {
+                hosts.push(format!("*.{domain}"));
+                match domain.split_once('.') {
+                    Some((_, rest)) => domain = rest,
+                    None => break,
+                }
             }
+        }
 
-            let possible_rest = domain.split_once('.').map(|(_, rest)| rest);
+        // Only directories scope credentials, the last segment of a path that
+        // does not end with a slash is a file name like `repodata.json`.
+        let mut segments = url
+            .path_segments()
+            .map(|segments| segments.filter(|s| !s.is_empty()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        if !url.path().ends_with('/') {
+            segments.pop();
+        }
 
-            match possible_rest {
-                Some(rest) => {
-                    domain = rest;
+        for host in hosts {
+            for len in (0..=segments.len()).rev() {
+                let key = if len == 0 {
+                    host.clone()
+                } else {
+                    format!("{host}/{}", segments[..len].join("/"))
+                };
+                match self.get(&key) {
+                    Ok(None) => {}
+                    Ok(Some(credentials)) => return Ok((url, Some((key, credentials)))),
+                    Err(_) => return Ok((url, None)),
                 }
-                _ => return Ok((url, None)), // No more subdomains to check
             }
         }
+
+        Ok((url, None))
     }
 
     /// Delete the authentication information for the given host
@@ -251,3 +314,20 @@ impl AuthenticationStorage {
         }
     }
 }
+
+/// Reads the credential helpers from the config file that
+/// `RATTLER_CONFIG_FILE` points to.
+#[cfg(feature = "rattler_config")]
+fn credential_helpers_from_env() -> Result<CredentialHelperStorage, AuthenticationStorageError> {
+    let Ok(config_file) = std::env::var("RATTLER_CONFIG_FILE") else {
+        return Ok(CredentialHelperStorage::default());
+    };
+    let config = rattler_config::config::ConfigBase::<()>::load_from_files([&config_file])
+        .map_err(|err| AuthenticationStorageError::ConfigError(config_file.into(), err))?;
+    Ok(config.credential_helpers.into())
+}
+
+#[cfg(not(feature = "rattler_config"))]
+fn credential_helpers_from_env() -> Result<CredentialHelperStorage, AuthenticationStorageError> {
+    Ok(CredentialHelperStorage::default())
+}
@@ -5,6 +5,7 @@ pub use authentication_middleware::AuthenticationMiddleware;
 pub use authentication_storage::{authentication::Authentication, storage::AuthenticationStorage};
 pub use mirror_middleware::MirrorMiddleware;
 pub use oci_middleware::OciMiddleware;
+pub use oci_push::OciPusher;
 
 #[cfg(feature = "gcs")]
 pub mod gcs_middleware;
@@ -20,5 +21,7 @@ pub mod authentication_middleware;
 pub mod authentication_storage;
 
 pub mod mirror_middleware;
+pub mod oauth;
 pub mod oci_middleware;
+pub mod oci_push;
 pub mod retry_policies;
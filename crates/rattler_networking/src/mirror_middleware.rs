@@ -1,15 +1,47 @@
 //! Middleware to handle mirrors
+//!
+//! Requests are sent to the healthiest mirror: mirrors with fewer consecutive
+//! failures are preferred, and among those the mirror that is expected to
+//! serve a file fastest. That estimate combines the time until the mirror
+//! responded with the throughput it had for larger files.
+//! A mirror that fails `max_failures` times in a row is skipped until a
+//! cooldown has passed, after which a single request is sent to it to check
+//! whether it recovered.
 use std::{
     collections::HashMap,
-    sync::atomic::{self, AtomicUsize},
+    sync::{Mutex, MutexGuard},
+    time::Duration,
 };
 
+use futures::future::{select, Either};
 use http::Extensions;
 use itertools::Itertools;
-use reqwest::{Request, Response};
+use reqwest::{Method, Request, Response};
 use reqwest_middleware::{Middleware, Next, Result};
 use url::Url;
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+use wasmtimer::std::Instant;
+
+/// The default time a mirror is skipped after it failed too often.
+const DEFAULT_COOLDOWN: Duration = Duration::from_secs(30);
+
+/// The weight of a new latency measurement in the moving average.
+const LATENCY_SMOOTHING: f64 = 0.3;
+
+/// The weight of a new throughput measurement in the moving average.
+const THROUGHPUT_SMOOTHING: f64 = 0.3;
+
+/// Responses smaller than this are dominated by latency and do not say much
+/// about the throughput of a mirror.
+const MIN_THROUGHPUT_SAMPLE: u64 = 64 * 1024;
+
+/// The size of the file a mirror is ranked for, this weighs the throughput
+/// of a mirror against its latency.
+const RANKING_SIZE: f64 = 1024.0 * 1024.0;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 /// Settings for the specific mirror (e.g. no zstd or bz2 support)
 pub struct Mirror {
@@ -21,19 +53,136 @@ pub struct Mirror {
     pub no_bz2: bool,
     /// Disable jlap support (for repodata.jlap files)
     pub no_jlap: bool,
-    /// Allowed number of failures before the mirror is considered dead
+    /// Allowed number of consecutive failures before the mirror is skipped
+    /// for a cooldown period
     pub max_failures: Option<usize>,
 }
 
-#[allow(dead_code)]
+impl Mirror {
+    /// Returns true if the mirror can serve the given file
+    fn supports(&self, path: &str) -> bool {
+        !(path.ends_with(".json.zst") && self.no_zstd
+            || path.ends_with(".json.bz2") && self.no_bz2
+            || path.ends_with(".jlap") && self.no_jlap)
+    }
+}
+
+/// Statistics of a mirror, e.g. for diagnostics
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MirrorStats {
+    /// The url of the mirror
+    pub url: Url,
+    /// The number of requests that were sent to the mirror
+    pub requests: usize,
+    /// The number of requests that failed
+    pub failures: usize,
+    /// The number of requests that failed since the last successful one
+    pub consecutive_failures: usize,
+    /// The moving average of the time until the mirror responded to
+    /// successful requests
+    pub latency: Option<Duration>,
+    /// The moving average of the bytes per second of successful responses
+    /// to larger files
+    pub throughput: Option<u64>,
+    /// Whether the mirror is currently skipped because it failed too often
+    pub circuit_open: bool,
+}
+
+#[derive(Default)]
+struct MirrorHealth {
+    requests: usize,
+    failures: usize,
+    consecutive_failures: usize,
+    latency: Option<Duration>,
+    /// Bytes per second
+    throughput: Option<f64>,
+    /// The mirror is skipped until this time
+    open_until: Option<Instant>,
+}
+
+impl MirrorHealth {
+    /// The expected number of seconds to download a file of
+    /// [`RANKING_SIZE`] bytes from the mirror.
+    fn expected_time(&self) -> f64 {
+        let latency = self.latency.unwrap_or_default().as_secs_f64();
+        let transfer = self
+            .throughput
+            .map_or(0.0, |throughput| RANKING_SIZE / throughput);
+        latency + transfer
+    }
+}
+
 struct MirrorState {
-    failures: AtomicUsize,
+    health: Mutex<MirrorHealth>,
     mirror: Mirror,
 }
 
 impl MirrorState {
-    pub fn add_failure(&self) {
-        self.failures.fetch_add(1, atomic::Ordering::Relaxed);
+    fn health(&self) -> MutexGuard<'_, MirrorHealth> {
+        self.health.lock().unwrap()
+    }
+
+    fn record_latency(&self, latency: Duration) {
+        let mut health = self.health();
+        health.latency = Some(match health.latency {
+            Some(average) => {
+                average.mul_f64(1.0 - LATENCY_SMOOTHING) + latency.mul_f64(LATENCY_SMOOTHING)
+            }
+            None => latency,
+        });
+    }
+
+    /// Records a successful request that took `latency` until the mirror
+    /// responded with a body of `size` bytes.
+    fn record_success(&self, latency: Duration, size: Option<u64>) {
+        self.record_latency(latency);
+        let mut health = self.health();
+        if let Some(size) = size.filter(|size| *size >= MIN_THROUGHPUT_SAMPLE) {
+            let throughput = size as f64 / latency.as_secs_f64().max(f64::EPSILON);
+            health.throughput = Some(match health.throughput {
+                Some(average) => {
+                    average * (1.0 - THROUGHPUT_SMOOTHING) + throughput * THROUGHPUT_SMOOTHING
+                }
+                None => throughput,
+            });
+        }
+        health.requests += 1;
+        health.consecutive_failures = 0;
+        health.open_until = None;
+    }
+
+    /// Records a request that was cancelled because another mirror responded
+    /// first, it took at least `latency`.
+    fn record_cancelled(&self, latency: Duration) {
+        self.record_latency(latency);
+        self.health().requests += 1;
+    }
+
+    fn record_failure(&self, cooldown: Duration) {
+        let mut health = self.health();
+        health.requests += 1;
+        health.failures += 1;
+        health.consecutive_failures += 1;
+        if self
+            .mirror
+            .max_failures
+            .is_some_and(|max| health.consecutive_failures >= max)
+        {
+            health.open_until = Some(Instant::now() + cooldown);
+        }
+    }
+
+    fn stats(&self) -> MirrorStats {
+        let health = self.health();
+        MirrorStats {
+            url: self.mirror.url.clone(),
+            requests: health.requests,
+            failures: health.failures,
+            consecutive_failures: health.consecutive_failures,
+            latency: health.latency,
+            throughput: health.throughput.map(|throughput| throughput as u64),
+            circuit_open: health.open_until.is_some(),
+        }
     }
 }
 
@@ -41,6 +190,8 @@ impl MirrorState {
 pub struct MirrorMiddleware {
     mirror_map: HashMap<Url, Vec<MirrorState>>,
     sorted_keys: Vec<(String, Url)>,
+    cooldown: Duration,
+    hedge_delay: Option<Duration>,
 }
 
 impl MirrorMiddleware {
@@ -52,7 +203,7 @@ impl MirrorMiddleware {
                 let mirrors = mirrors
                     .into_iter()
                     .map(|mirror| MirrorState {
-                        failures: AtomicUsize::new(0),
+                        health: Mutex::default(),
                         mirror,
                     })
                     .collect();
@@ -70,6 +221,25 @@ impl MirrorMiddleware {
         Self {
             mirror_map,
             sorted_keys,
+            cooldown: DEFAULT_COOLDOWN,
+            hedge_delay: None,
+        }
+    }
+
+    /// Sets how long a mirror that failed `max_failures` times in a row is
+    /// skipped before it is tried again (defaults to 30 seconds).
+    pub fn with_cooldown(self, cooldown: Duration) -> Self {
+        Self { cooldown, ..self }
+    }
+
+    /// Enables hedging of small repodata requests (sharded repodata, JLAP
+    /// files and `HEAD` requests): if the selected mirror did not respond
+    /// within the given delay, the request is also sent to the next best
+    /// mirror and the first response is used.
+    pub fn with_hedge_delay(self, hedge_delay: Duration) -> Self {
+        Self {
+            hedge_delay: Some(hedge_delay),
+            ..self
         }
     }
 
@@ -78,23 +248,142 @@ impl MirrorMiddleware {
     pub fn keys(&self) -> &[(String, Url)] {
         &self.sorted_keys
     }
-}
 
-fn select_mirror(mirrors: &[MirrorState]) -> Option<&MirrorState> {
-    let mut min_failures = usize::MAX;
-    let mut min_failures_index = usize::MAX;
+    /// Returns the statistics of the mirrors of every mirrored url
+    pub fn stats(&self) -> HashMap<Url, Vec<MirrorStats>> {
+        self.mirror_map
+            .iter()
+            .map(|(url, mirrors)| {
+                (
+                    url.clone(),
+                    mirrors.iter().map(MirrorState::stats).collect(),
+                )
+            })
+            .collect()
+    }
 
-    for (i, mirror) in mirrors.iter().enumerate() {
-        let failures = mirror.failures.load(atomic::Ordering::Relaxed);
-        if failures < min_failures && mirror.mirror.max_failures.is_none_or(|max| failures < max) {
-            min_failures = failures;
-            min_failures_index = i;
+    /// Returns the available mirrors for the given path, best first.
+    fn select_mirrors<'a>(&self, mirrors: &'a [MirrorState], path: &str) -> Vec<&'a MirrorState> {
+        let now = Instant::now();
+
+        // A mirror whose cooldown has passed gets a single request to check
+        // whether it recovered, it is skipped again until that request is done.
+        // Mirrors that cannot serve the path are not probed with it.
+        for mirror in mirrors.iter().filter(|mirror| mirror.mirror.supports(path)) {
+            let mut health = mirror.health();
+            if health.open_until.is_some_and(|until| until <= now) {
+                health.open_until = Some(now + self.cooldown);
+                return vec![mirror];
+            }
         }
+
+        // Prefer mirrors with fewer consecutive failures, then mirrors that are
+        // expected to serve a file faster. Mirrors without measurements are
+        // tried first so they become known, ties keep the configured order.
+        mirrors
+            .iter()
+            .filter_map(|mirror| {
+                let health = mirror.health();
+                let available = health.open_until.is_none()
+                    && mirror
+                        .mirror
+                        .max_failures
+                        .is_none_or(|max| health.consecutive_failures < max);
+                available.then(|| (health.consecutive_failures, health.expected_time(), mirror))
+            })
+            .sorted_by(|(a_failures, a_time, _), (b_failures, b_time, _)| {
+                a_failures.cmp(b_failures).then(a_time.total_cmp(b_time))
+            })
+            .map(|(_, _, mirror)| mirror)
+            .collect()
     }
-    if min_failures_index == usize::MAX {
-        return None;
+
+    /// Sends the request to the mirror and records the outcome.
+    async fn send(
+        &self,
+        mirror: &MirrorState,
+        req: Request,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let is_head = req.method() == Method::HEAD;
+        let start = Instant::now();
+        let res = next.run(req, extensions).await;
+
+        // record a failure if the request failed so we can avoid the mirror in the future
+        if is_success(&res) {
+            // The content length of a `HEAD` response says nothing about
+            // the throughput, no body was sent.
+            let size = res
+                .as_ref()
+                .ok()
+                .filter(|_| !is_head)
+                .and_then(Response::content_length);
+            mirror.record_success(start.elapsed(), size);
+        } else {
+            mirror.record_failure(self.cooldown);
+        }
+        res
+    }
+
+    /// Sends the request to the primary mirror and, if it did not respond
+    /// within the delay, also to the secondary mirror.
+    #[allow(clippy::too_many_arguments)]
+    async fn send_hedged(
+        &self,
+        primary: &MirrorState,
+        secondary: &MirrorState,
+        req: Request,
+        hedged_req: Request,
+        delay: Duration,
+        extensions: &mut Extensions,
+        next: Next<'_>,
+    ) -> Result<Response> {
+        let mut hedged_extensions = extensions.clone();
+        let primary_start = Instant::now();
+        let first = Box::pin(self.send(primary, req, extensions, next.clone()));
+        let first = match select(first, Box::pin(sleep(delay))).await {
+            Either::Left((res, _)) => return res,
+            Either::Right(((), first)) => first,
+        };
+
+        let secondary_start = Instant::now();
+        let second = Box::pin(self.send(secondary, hedged_req, &mut hedged_extensions, next));
+        let (res, other, loser, loser_start) = match select(first, second).await {
+            Either::Left((res, second)) => (res, second, secondary, secondary_start),
+            Either::Right((res, first)) => (res, first, primary, primary_start),
+        };
+        if !is_success(&res) {
+            return other.await;
+        }
+
+        // The other mirror was at least this slow.
+        drop(other);
+        loser.record_cancelled(loser_start.elapsed());
+        res
     }
-    Some(&mirrors[min_failures_index])
+}
+
+fn is_success(res: &Result<Response>) -> bool {
+    res.as_ref()
+        .is_ok_and(|res| !res.status().is_server_error())
+}
+
+/// Returns true for small repodata requests that are worth sending to a
+/// second mirror when the first one is slow. Full `repodata.json` files can
+/// be large and are never hedged.
+fn is_hedgeable(req: &Request) -> bool {
+    let path = req.url().path();
+    req.method() == Method::HEAD
+        || req.method() == Method::GET
+            && (path.ends_with(".msgpack.zst") || path.ends_with(".jlap"))
+}
+
+async fn sleep(duration: Duration) {
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(duration).await;
+    #[cfg(target_arch = "wasm32")]
+    wasmtimer::tokio::sleep(duration).await;
 }
 
 #[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
@@ -112,10 +401,9 @@ impl Middleware for MirrorMiddleware {
             if let Some(url_rest) = url_str.strip_prefix(key) {
                 let url_rest = url_rest.trim_start_matches('/');
                 // replace the key with the mirror
-                let mirrors = self.mirror_map.get(url).unwrap();
-                let selected_mirror = select_mirror(mirrors);
+                let mirrors = self.select_mirrors(self.mirror_map.get(url).unwrap(), url_rest);
 
-                let Some(selected_mirror) = selected_mirror else {
+                let Some(selected_mirror) = mirrors.first().copied() else {
                     return Ok(create_404_response(req.url(), "All mirrors are dead"));
                 };
 
@@ -142,17 +430,31 @@ impl Middleware for MirrorMiddleware {
                     ));
                 }
 
-                *req.url_mut() = selected_url;
-                let res = next.run(req, extensions).await;
+                let hedge = self.hedge_delay.filter(|_| is_hedgeable(&req)).zip(
+                    mirrors
+                        .iter()
+                        .skip(1)
+                        .find(|mirror| mirror.mirror.supports(url_rest)),
+                );
 
-                // record a failure if the request failed so we can avoid the mirror in the future
-                match res.as_ref() {
-                    Ok(res) if res.status().is_server_error() => selected_mirror.add_failure(),
-                    Err(_) => selected_mirror.add_failure(),
-                    _ => {}
+                *req.url_mut() = selected_url;
+                if let Some((delay, secondary)) = hedge {
+                    if let Some(mut hedged_req) = req.try_clone() {
+                        *hedged_req.url_mut() = secondary.mirror.url.join(url_rest).unwrap();
+                        return self
+                            .send_hedged(
+                                selected_mirror,
+                                secondary,
+                                req,
+                                hedged_req,
+                                delay,
+                                extensions,
+                                next,
+                            )
+                            .await;
+                    }
                 }
-
-                return res;
+                return self.send(selected_mirror, req, extensions, next).await;
             }
         }
 
@@ -180,7 +482,15 @@ pub(crate) fn create_404_response(_url: &Url, _body: &str) -> Response {
 
 #[cfg(test)]
 mod test {
-    use std::{future::IntoFuture, net::SocketAddr};
+    use std::{
+        future::IntoFuture,
+        net::SocketAddr,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
     use axum::{extract::State, http::StatusCode, routing::get, Router};
     use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
@@ -293,6 +603,232 @@ mod test {
         assert!(res.text().await.unwrap() == "Hi from counter: server 2");
     }
 
+    /// A server that answers every request with its name after a delay and
+    /// fails the first `failures` requests.
+    async fn slow_server(name: &'static str, delay: Duration, failures: usize) -> Url {
+        let remaining_failures = Arc::new(AtomicUsize::new(failures));
+        let router = Router::new().fallback(move || {
+            let remaining_failures = remaining_failures.clone();
+            async move {
+                tokio::time::sleep(delay).await;
+                let failed = remaining_failures
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                    .is_ok();
+                if failed {
+                    (StatusCode::INTERNAL_SERVER_ERROR, name)
+                } else {
+                    (StatusCode::OK, name)
+                }
+            }
+        });
+
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        format!("http://{}:{}", addr.ip(), addr.port())
+            .parse()
+            .unwrap()
+    }
+
+    fn mirror_client(
+        mirrors: Vec<Mirror>,
+        configure: impl FnOnce(MirrorMiddleware) -> MirrorMiddleware,
+    ) -> (
+        reqwest_middleware::ClientWithMiddleware,
+        Arc<MirrorMiddleware>,
+    ) {
+        let mirror_map = [("http://bla.com".parse().unwrap(), mirrors)].into();
+        let middleware = Arc::new(configure(MirrorMiddleware::from_map(mirror_map)));
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with_arc(middleware.clone())
+            .build();
+        (client, middleware)
+    }
+
+    async fn get_text(client: &reqwest_middleware::ClientWithMiddleware, path: &str) -> String {
+        let res = client
+            .get(format!("http://bla.com/{path}"))
+            .send()
+            .await
+            .unwrap();
+        res.text().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mirror_latency() {
+        let slow = slow_server("slow", Duration::from_millis(100), 0).await;
+        let fast = slow_server("fast", Duration::ZERO, 0).await;
+        let (client, middleware) =
+            mirror_client(vec![mirror_setting(slow), mirror_setting(fast)], |m| m);
+
+        // Both mirrors are tried once, after that the faster one is used.
+        assert_eq!(get_text(&client, "count").await, "slow");
+        assert_eq!(get_text(&client, "count").await, "fast");
+        assert_eq!(get_text(&client, "count").await, "fast");
+
+        let stats = &middleware.stats()[&"http://bla.com".parse().unwrap()];
+        assert_eq!(stats[0].requests, 1);
+        assert_eq!(stats[1].requests, 2);
+        assert!(stats[0].latency.unwrap() > stats[1].latency.unwrap());
+    }
+
+    /// A server that answers every request after a delay with its name,
+    /// padded to `size` bytes.
+    async fn bulky_server(name: &'static str, delay: Duration, size: usize) -> Url {
+        let body = format!("{name}{}", " ".repeat(size - name.len()));
+        let router = Router::new().fallback(move || {
+            let body = body.clone();
+            async move {
+                tokio::time::sleep(delay).await;
+                body
+            }
+        });
+
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        format!("http://{}:{}", addr.ip(), addr.port())
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mirror_throughput() {
+        // The narrow mirror responds sooner, but the wide mirror serves far
+        // more bytes per second.
+        let narrow = bulky_server("narrow", Duration::from_millis(20), 64 * 1024).await;
+        let wide = bulky_server("wide", Duration::from_millis(40), 4 * 1024 * 1024).await;
+        let (client, middleware) =
+            mirror_client(vec![mirror_setting(narrow), mirror_setting(wide)], |m| m);
+
+        // Both mirrors are tried once, after that the wider one is used.
+        assert_eq!(get_text(&client, "count").await.trim_end(), "narrow");
+        assert_eq!(get_text(&client, "count").await.trim_end(), "wide");
+        assert_eq!(get_text(&client, "count").await.trim_end(), "wide");
+
+        let stats = &middleware.stats()[&"http://bla.com".parse().unwrap()];
+        assert!(stats[0].latency.unwrap() < stats[1].latency.unwrap());
+        assert!(stats[0].throughput.unwrap() < stats[1].throughput.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_mirror_small_responses_skip_throughput() {
+        let fast = slow_server("fast", Duration::ZERO, 0).await;
+        let (client, middleware) = mirror_client(vec![mirror_setting(fast)], |m| m);
+
+        assert_eq!(get_text(&client, "count").await, "fast");
+        let stats = &middleware.stats()[&"http://bla.com".parse().unwrap()];
+        assert!(stats[0].latency.is_some());
+        assert_eq!(stats[0].throughput, None);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_circuit_breaker() {
+        let flaky = slow_server("flaky", Duration::ZERO, 1).await;
+        let fast = slow_server("fast", Duration::from_millis(20), 0).await;
+        let (client, middleware) = mirror_client(
+            vec![
+                Mirror {
+                    max_failures: Some(1),
+                    ..mirror_setting(flaky)
+                },
+                mirror_setting(fast),
+            ],
+            |m| m.with_cooldown(Duration::from_millis(100)),
+        );
+        let key = "http://bla.com".parse().unwrap();
+
+        assert_eq!(get_text(&client, "count").await, "flaky");
+        assert!(middleware.stats()[&key][0].circuit_open);
+        assert_eq!(get_text(&client, "count").await, "fast");
+        assert_eq!(get_text(&client, "count").await, "fast");
+
+        // After the cooldown the mirror is tried again and recovers.
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(get_text(&client, "count").await, "flaky");
+        let stats = &middleware.stats()[&key][0];
+        assert!(!stats.circuit_open);
+        assert_eq!(stats.failures, 1);
+        assert_eq!(stats.consecutive_failures, 0);
+        assert_eq!(get_text(&client, "count").await, "flaky");
+    }
+
+    #[tokio::test]
+    async fn test_mirror_probe_skips_unsupported_paths() {
+        let flaky = slow_server("flaky", Duration::ZERO, 1).await;
+        let fast = slow_server("fast", Duration::ZERO, 0).await;
+        let (client, middleware) = mirror_client(
+            vec![
+                Mirror {
+                    max_failures: Some(1),
+                    no_jlap: true,
+                    ..mirror_setting(flaky)
+                },
+                mirror_setting(fast),
+            ],
+            |m| m.with_cooldown(Duration::from_millis(100)),
+        );
+        let key = "http://bla.com".parse().unwrap();
+
+        assert_eq!(get_text(&client, "count").await, "flaky");
+        assert!(middleware.stats()[&key][0].circuit_open);
+
+        // A file the mirror does not serve is not used to probe it.
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(
+            get_text(&client, "conda-forge/linux-64/repodata.jlap").await,
+            "fast"
+        );
+        assert_eq!(get_text(&client, "count").await, "flaky");
+        assert!(!middleware.stats()[&key][0].circuit_open);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_all_dead() {
+        let broken = test_server("broken", true).await;
+        let (client, _) = mirror_client(
+            vec![Mirror {
+                max_failures: Some(1),
+                ..mirror_setting(broken)
+            }],
+            |m| m,
+        );
+
+        let res = client.get("http://bla.com/count").send().await.unwrap();
+        assert!(res.status().is_server_error());
+        let res = client.get("http://bla.com/count").send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert_eq!(res.text().await.unwrap(), "All mirrors are dead");
+    }
+
+    #[tokio::test]
+    async fn test_mirror_hedging() {
+        let slow = slow_server("slow", Duration::from_secs(2), 0).await;
+        let fast = slow_server("fast", Duration::ZERO, 0).await;
+        let (client, middleware) =
+            mirror_client(vec![mirror_setting(slow), mirror_setting(fast)], |m| {
+                m.with_hedge_delay(Duration::from_millis(50))
+            });
+
+        let start = std::time::Instant::now();
+        assert_eq!(
+            get_text(&client, "conda-forge/repodata_shards.msgpack.zst").await,
+            "fast"
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // The slow mirror is now known to be slower, it took at least as long
+        // as the delay before the request was hedged.
+        let stats = &middleware.stats()[&"http://bla.com".parse().unwrap()];
+        assert!(stats[0].latency.unwrap() > stats[1].latency.unwrap());
+        assert!(stats[0].latency.unwrap() >= Duration::from_millis(50));
+        assert_eq!(stats[0].requests, 1);
+        assert_eq!(stats[0].failures, 0);
+        assert_eq!(stats[1].requests, 1);
+    }
+
     #[test]
     fn test_mirror_sort() {
         let keys: Vec<Url> = vec![
@@ -0,0 +1,537 @@
+//! OAuth 2.0 / OIDC support for short-lived access tokens.
+//!
+//! Credentials are obtained with the device authorization grant (RFC 8628)
+//! and stored as [`Authentication::OAuth`]. The access token is refreshed with
+//! the refresh token by the
+//! [`AuthenticationMiddleware`](crate::AuthenticationMiddleware) when it
+//! expires or when the server rejects it.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::{Client, StatusCode};
+use serde::Deserialize;
+use url::Url;
+
+use crate::Authentication;
+
+/// Access tokens that expire within this many seconds are refreshed before
+/// they are used.
+const EXPIRY_MARGIN_SECONDS: u64 = 30;
+
+/// An error that can occur while obtaining or refreshing OAuth tokens
+#[derive(thiserror::Error, Debug)]
+pub enum OAuthError {
+    /// A request to the identity provider failed
+    #[error(transparent)]
+    Reqwest(#[from] reqwest::Error),
+
+    /// The identity provider does not support the device authorization grant
+    #[error("{0} does not support the device authorization grant")]
+    DeviceFlowNotSupported(Url),
+
+    /// The credentials do not contain a refresh token
+    #[error("the credentials cannot be refreshed because they do not contain a refresh token")]
+    MissingRefreshToken,
+
+    /// The user denied the authorization request
+    #[error("the authorization request was denied")]
+    AccessDenied,
+
+    /// The device code expired before the user completed the authorization
+    #[error("the device code expired before the authorization was completed")]
+    ExpiredToken,
+
+    /// The token endpoint returned an error
+    #[error("the token endpoint returned an error: {error}")]
+    TokenEndpoint {
+        /// The error code, e.g. `invalid_grant`
+        error: String,
+        /// The human readable description of the error
+        description: Option<String>,
+    },
+}
+
+/// The endpoints of an OIDC identity provider.
+#[derive(Debug, Clone, Deserialize)]
+pub struct OidcConfiguration {
+    /// The endpoint that issues and refreshes tokens
+    pub token_endpoint: Url,
+
+    /// The endpoint that starts the device authorization grant
+    pub device_authorization_endpoint: Option<Url>,
+}
+
+impl OidcConfiguration {
+    /// Reads the configuration from the
+    /// `.well-known/openid-configuration` document of the given issuer.
+    pub async fn discover(client: &Client, issuer: &Url) -> Result<Self, OAuthError> {
+        let mut url = issuer.clone();
+        url.set_path(&format!(
+            "{}/.well-known/openid-configuration",
+            issuer.path().trim_end_matches('/')
+        ));
+        Ok(client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+}
+
+/// A pending device authorization that the user has to complete in a
+/// browser.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceAuthorization {
+    /// The code that identifies this authorization request
+    pub device_code: String,
+
+    /// The code the user has to enter at the verification URI
+    pub user_code: String,
+
+    /// The URI the user has to visit
+    #[serde(alias = "verification_url")]
+    pub verification_uri: String,
+
+    /// The verification URI with the user code already filled in
+    pub verification_uri_complete: Option<String>,
+
+    /// The number of seconds after which the device code expires
+    pub expires_in: u64,
+
+    /// The number of seconds to wait between polling the token endpoint
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+/// The result of polling the token endpoint during the device flow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DevicePoll {
+    /// The user has not completed the authorization yet, poll again after
+    /// the given interval
+    Pending(Duration),
+
+    /// The user completed the authorization
+    Complete(Authentication),
+}
+
+impl DeviceAuthorization {
+    /// Starts the device authorization grant for the given client.
+    pub async fn request(
+        client: &Client,
+        configuration: &OidcConfiguration,
+        client_id: &str,
+        scopes: &[String],
+    ) -> Result<Self, OAuthError> {
+        let endpoint = configuration
+            .device_authorization_endpoint
+            .clone()
+            .ok_or_else(|| {
+                OAuthError::DeviceFlowNotSupported(configuration.token_endpoint.clone())
+            })?;
+        let scope = scopes.join(" ");
+        let response = client
+            .post(endpoint)
+            .form(&[("client_id", client_id), ("scope", scope.as_str())])
+            .send()
+            .await?;
+        Ok(token_endpoint_response(response).await?.json().await?)
+    }
+
+    /// Polls the token endpoint once.
+    ///
+    /// When the identity provider asks to slow down, the interval of this
+    /// authorization is increased by five seconds as required by RFC 8628.
+    pub async fn poll(
+        &mut self,
+        client: &Client,
+        configuration: &OidcConfiguration,
+        client_id: &str,
+    ) -> Result<DevicePoll, OAuthError> {
+        let response = client
+            .post(configuration.token_endpoint.clone())
+            .form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                ("device_code", self.device_code.as_str()),
+                ("client_id", client_id),
+            ])
+            .send()
+            .await?;
+        match token_endpoint_response(response).await {
+            Ok(response) => Ok(DevicePoll::Complete(
+                response.json::<TokenResponse>().await?.into_authentication(
+                    &configuration.token_endpoint,
+                    client_id,
+                    None,
+                ),
+            )),
+            Err(OAuthError::TokenEndpoint { error, .. }) if error == "authorization_pending" => {
+                Ok(DevicePoll::Pending(Duration::from_secs(self.interval)))
+            }
+            Err(OAuthError::TokenEndpoint { error, .. }) if error == "slow_down" => {
+                self.interval += 5;
+                Ok(DevicePoll::Pending(Duration::from_secs(self.interval)))
+            }
+            Err(OAuthError::TokenEndpoint { error, .. }) if error == "access_denied" => {
+                Err(OAuthError::AccessDenied)
+            }
+            Err(OAuthError::TokenEndpoint { error, .. }) if error == "expired_token" => {
+                Err(OAuthError::ExpiredToken)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// A successful response of the token endpoint.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+    expires_in: Option<u64>,
+}
+
+impl TokenResponse {
+    /// Converts the response to credentials. Identity providers may omit the
+    /// refresh token when refreshing, in which case the previous one is kept.
+    fn into_authentication(
+        self,
+        token_endpoint: &Url,
+        client_id: &str,
+        previous_refresh_token: Option<&str>,
+    ) -> Authentication {
+        Authentication::OAuth {
+            access_token: self.access_token,
+            refresh_token: self
+                .refresh_token
+                .or_else(|| previous_refresh_token.map(ToString::to_string)),
+            expires_at: self.expires_in.map(|expires_in| now() + expires_in),
+            token_endpoint: token_endpoint.clone(),
+            client_id: client_id.to_string(),
+        }
+    }
+}
+
+/// An error response of the token endpoint.
+#[derive(Debug, Deserialize)]
+struct TokenErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+/// Converts an OAuth error response (RFC 6749, section 5.2) to an
+/// [`OAuthError::TokenEndpoint`].
+async fn token_endpoint_response(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, OAuthError> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    if matches!(
+        response.status(),
+        StatusCode::BAD_REQUEST | StatusCode::UNAUTHORIZED
+    ) {
+        let status = response.status();
+        return match response.json::<TokenErrorResponse>().await {
+            Ok(error) => Err(OAuthError::TokenEndpoint {
+                error: error.error,
+                description: error.error_description,
+            }),
+            Err(_) => Err(OAuthError::TokenEndpoint {
+                error: status.to_string(),
+                description: None,
+            }),
+        };
+    }
+    Ok(response.error_for_status()?)
+}
+
+/// Returns true if the credentials are OAuth credentials whose access token
+/// has expired or is about to expire.
+pub fn is_expired(authentication: &Authentication) -> bool {
+    match authentication {
+        Authentication::OAuth {
+            expires_at: Some(expires_at),
+            ..
+        } => *expires_at <= now() + EXPIRY_MARGIN_SECONDS,
+        _ => false,
+    }
+}
+
+/// Exchanges the refresh token of OAuth credentials for a new access token.
+pub async fn refresh(
+    client: &Client,
+    authentication: &Authentication,
+) -> Result<Authentication, OAuthError> {
+    let Authentication::OAuth {
+        refresh_token: Some(refresh_token),
+        token_endpoint,
+        client_id,
+        ..
+    } = authentication
+    else {
+        return Err(OAuthError::MissingRefreshToken);
+    };
+
+    let response = client
+        .post(token_endpoint.clone())
+        .form(&[
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.as_str()),
+            ("client_id", client_id.as_str()),
+        ])
+        .send()
+        .await?;
+    Ok(token_endpoint_response(response)
+        .await?
+        .json::<TokenResponse>()
+        .await?
+        .into_authentication(token_endpoint, client_id, Some(refresh_token)))
+}
+
+/// Returns the current unix timestamp in seconds.
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        future::IntoFuture,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{header, HeaderMap, Method, StatusCode, Uri},
+        response::{IntoResponse, Response},
+        Router,
+    };
+    use url::Url;
+
+    use super::{DeviceAuthorization, DevicePoll, OidcConfiguration};
+    use crate::{
+        authentication_storage::backends::memory::MemoryStorage, Authentication,
+        AuthenticationMiddleware, AuthenticationStorage,
+    };
+
+    #[derive(Default)]
+    struct Provider {
+        url: String,
+        polls: usize,
+        unauthorized: usize,
+        refreshes: usize,
+        /// Whether refresh tokens can only be used once
+        rotate: bool,
+    }
+
+    fn json(body: String) -> Response {
+        ([(header::CONTENT_TYPE, "application/json")], body).into_response()
+    }
+
+    /// An identity provider that completes the device flow on the second
+    /// poll and a resource that only accepts the refreshed access token.
+    /// When `rotate` is set the refresh token is replaced on every refresh.
+    async fn provider(
+        State(provider): State<Arc<Mutex<Provider>>>,
+        method: Method,
+        uri: Uri,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> Response {
+        let mut provider = provider.lock().unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        match (method, uri.path()) {
+            (Method::GET, "/.well-known/openid-configuration") => json(format!(
+                r#"{{"token_endpoint":"{0}/token","device_authorization_endpoint":"{0}/device"}}"#,
+                provider.url
+            )),
+            (Method::POST, "/device") => json(format!(
+                r#"{{"device_code":"device","user_code":"ABCD-EFGH","verification_uri":"{}/activate","expires_in":600,"interval":0}}"#,
+                provider.url
+            )),
+            (Method::POST, "/token") if body.contains("device_code=device") => {
+                provider.polls += 1;
+                if provider.polls == 1 {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        json(r#"{"error":"authorization_pending"}"#.to_string()),
+                    )
+                        .into_response()
+                } else {
+                    json(
+                        r#"{"access_token":"access-1","refresh_token":"refresh-1","expires_in":3600}"#
+                            .to_string(),
+                    )
+                }
+            }
+            (Method::POST, "/token")
+                if body.contains("refresh_token=refresh-1")
+                    && !(provider.rotate && provider.refreshes > 0) =>
+            {
+                provider.refreshes += 1;
+                if provider.rotate {
+                    json(
+                        r#"{"access_token":"access-2","refresh_token":"refresh-2","expires_in":3600}"#
+                            .to_string(),
+                    )
+                } else {
+                    json(r#"{"access_token":"access-2","expires_in":3600}"#.to_string())
+                }
+            }
+            (Method::POST, "/token") => (
+                StatusCode::BAD_REQUEST,
+                json(r#"{"error":"invalid_grant"}"#.to_string()),
+            )
+                .into_response(),
+            (Method::GET, "/protected") => {
+                if headers.get(header::AUTHORIZATION).unwrap() == "Bearer access-2" {
+                    StatusCode::OK.into_response()
+                } else {
+                    provider.unauthorized += 1;
+                    StatusCode::UNAUTHORIZED.into_response()
+                }
+            }
+            _ => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    async fn test_provider() -> (Url, Arc<Mutex<Provider>>) {
+        let state = Arc::new(Mutex::new(Provider::default()));
+        let router = Router::new().fallback(provider).with_state(state.clone());
+
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        let url = format!("http://{}:{}", addr.ip(), addr.port());
+        state.lock().unwrap().url.clone_from(&url);
+        (url.parse().unwrap(), state)
+    }
+
+    fn storage_with(authentication: &Authentication) -> AuthenticationStorage {
+        let mut storage = AuthenticationStorage::empty();
+        storage.add_backend(Arc::new(MemoryStorage::new()));
+        storage.store("127.0.0.1", authentication).unwrap();
+        storage
+    }
+
+    fn expired_credentials(url: &Url, expires_at: Option<u64>) -> Authentication {
+        Authentication::OAuth {
+            access_token: "access-1".to_string(),
+            refresh_token: Some("refresh-1".to_string()),
+            expires_at,
+            token_endpoint: url.join("token").unwrap(),
+            client_id: "rattler".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_device_flow() {
+        let (url, _) = test_provider().await;
+        let client = reqwest::Client::new();
+
+        let configuration = OidcConfiguration::discover(&client, &url).await.unwrap();
+        let mut authorization =
+            DeviceAuthorization::request(&client, &configuration, "rattler", &[])
+                .await
+                .unwrap();
+        assert_eq!(authorization.user_code, "ABCD-EFGH");
+
+        let poll = authorization
+            .poll(&client, &configuration, "rattler")
+            .await
+            .unwrap();
+        assert!(matches!(poll, DevicePoll::Pending(_)));
+        let DevicePoll::Complete(Authentication::OAuth {
+            access_token,
+            refresh_token,
+            expires_at,
+            ..
+        }) = authorization
+            .poll(&client, &configuration, "rattler")
+            .await
+            .unwrap()
+        else {
+            panic!("expected the device flow to complete");
+        };
+        assert_eq!(access_token, "access-1");
+        assert_eq!(refresh_token.as_deref(), Some("refresh-1"));
+        assert!(expires_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_on_unauthorized() {
+        let (url, provider) = test_provider().await;
+        let storage = storage_with(&expired_credentials(&url, None));
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(AuthenticationMiddleware::from_auth_storage(storage.clone()))
+            .build();
+
+        let response = client.get(url.join("protected").unwrap()).send().await;
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+        assert_eq!(provider.lock().unwrap().unauthorized, 1);
+
+        // The refreshed tokens are stored and the refresh token is kept.
+        let Some(Authentication::OAuth {
+            access_token,
+            refresh_token,
+            ..
+        }) = storage.get("127.0.0.1").unwrap()
+        else {
+            panic!("expected OAuth credentials");
+        };
+        assert_eq!(access_token, "access-2");
+        assert_eq!(refresh_token.as_deref(), Some("refresh-1"));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_when_expired() {
+        let (url, provider) = test_provider().await;
+        let storage = storage_with(&expired_credentials(&url, Some(0)));
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(AuthenticationMiddleware::from_auth_storage(storage))
+            .build();
+
+        let response = client.get(url.join("protected").unwrap()).send().await;
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+        assert_eq!(provider.lock().unwrap().unauthorized, 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_refresh_with_rotating_tokens() {
+        let (url, provider) = test_provider().await;
+        provider.lock().unwrap().rotate = true;
+        let storage = storage_with(&expired_credentials(&url, Some(0)));
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(AuthenticationMiddleware::from_auth_storage(storage.clone()))
+            .build();
+
+        // Only one of the requests refreshes the token, the others use the
+        // refreshed token instead of the refresh token that was rotated.
+        let responses = futures::future::join_all(
+            (0..8).map(|_| client.get(url.join("protected").unwrap()).send()),
+        )
+        .await;
+        for response in responses {
+            assert_eq!(response.unwrap().status(), StatusCode::OK);
+        }
+        assert_eq!(provider.lock().unwrap().refreshes, 1);
+        assert_eq!(provider.lock().unwrap().unauthorized, 0);
+
+        let Some(Authentication::OAuth { refresh_token, .. }) = storage.get("127.0.0.1").unwrap()
+        else {
+            panic!("expected OAuth credentials");
+        };
+        assert_eq!(refresh_token.as_deref(), Some("refresh-2"));
+    }
+}
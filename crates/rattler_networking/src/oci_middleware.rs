@@ -2,6 +2,7 @@
 use std::{
     collections::HashMap,
     fmt::{Display, Formatter},
+    net::IpAddr,
 };
 
 use http::{
@@ -10,13 +11,13 @@ use http::{
 };
 use reqwest::{Request, Response};
 use reqwest_middleware::{Middleware, Next};
-use serde::Deserialize;
-use url::{ParseError, Url};
+use serde::{Deserialize, Serialize};
+use url::{Host, ParseError, Url};
 
 use crate::mirror_middleware::create_404_response;
 
 #[derive(thiserror::Error, Debug)]
-enum OciMiddlewareError {
+pub(crate) enum OciMiddlewareError {
     #[error("Reqwest error: {0}")]
     Reqwest(#[from] reqwest::Error),
 
@@ -32,6 +33,7 @@ enum OciMiddlewareError {
 pub struct OciMiddleware;
 
 /// The action to perform on the OCI registry
+#[derive(Debug, Clone, Copy)]
 pub enum OciAction {
     /// Pull an artifact
     Pull,
@@ -42,8 +44,8 @@ pub enum OciAction {
 }
 
 #[derive(Clone, Debug, Deserialize)]
-struct OCIToken {
-    token: String,
+pub(crate) struct OCIToken {
+    pub(crate) token: String,
 }
 
 impl Display for OciAction {
@@ -57,15 +59,20 @@ impl Display for OciAction {
 }
 
 // [oci://ghcr.io/channel-mirrors/conda-forge]/[osx-arm64/xtensor]
-async fn get_token(url: &OCIUrl, action: OciAction) -> Result<String, OciMiddlewareError> {
+// Registries without a token endpoint (e.g. a plain `registry:2`) do not require
+// authentication, in which case `None` is returned.
+async fn get_token(url: &OCIUrl, action: OciAction) -> Result<Option<String>, OciMiddlewareError> {
     let token_url = url.token_url(action)?;
 
     let response = reqwest::get(token_url.clone()).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
 
     match response.error_for_status() {
         Ok(response) => {
             let token = response.json::<OCIToken>().await?;
-            Ok(token.token)
+            Ok(Some(token.token))
         }
         Err(e) => {
             tracing::error!("OCI Mirror: failed to get token with URL: {}", token_url);
@@ -75,12 +82,37 @@ async fn get_token(url: &OCIUrl, action: OciAction) -> Result<String, OciMiddlew
 }
 
 #[derive(Debug)]
-struct OCIUrl {
-    url: Url,
-    host: String,
-    path: String,
-    tag: String,
-    media_type: String,
+pub(crate) struct OCIUrl {
+    pub(crate) url: Url,
+    pub(crate) scheme: &'static str,
+    pub(crate) host: String,
+    pub(crate) path: String,
+    pub(crate) tag: String,
+    pub(crate) media_type: String,
+}
+
+/// Returns the scheme used to talk to the registry of an `oci://` url. Like
+/// docker, registries on the local machine are accessed over plain http.
+fn registry_scheme(url: &Url) -> &'static str {
+    // `oci` is not a special scheme so ip addresses are parsed as domains.
+    let is_local = match url.host() {
+        Some(Host::Domain(domain)) => {
+            domain == "localhost"
+                || domain
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .parse::<IpAddr>()
+                    .is_ok_and(|ip| ip.is_loopback())
+        }
+        Some(Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    };
+    if is_local {
+        "http"
+    } else {
+        "https"
+    }
 }
 
 /// OCI registry tags are not allowed to contain `+`, `!`, or `=`, so we need to
@@ -103,22 +135,34 @@ fn version_build_tag(tag: &str) -> String {
 impl OCIUrl {
     pub fn manifest_url(&self) -> Result<Url, ParseError> {
         format!(
-            "https://{}/v2/{}/manifests/{}",
-            self.host, self.path, self.tag
+            "{}://{}/v2/{}/manifests/{}",
+            self.scheme, self.host, self.path, self.tag
         )
         .parse()
     }
 
     pub fn token_url(&self, action: OciAction) -> Result<Url, ParseError> {
         format!(
-            "https://{}/token?scope=repository:{}:{}",
-            self.host, self.path, action
+            "{}://{}/token?scope=repository:{}:{}",
+            self.scheme, self.host, self.path, action
         )
         .parse()
     }
 
     pub fn blob_url(&self, sha256: &str) -> Result<Url, ParseError> {
-        format!("https://{}/v2/{}/blobs/{}", self.host, self.path, sha256).parse()
+        format!(
+            "{}://{}/v2/{}/blobs/{}",
+            self.scheme, self.host, self.path, sha256
+        )
+        .parse()
+    }
+
+    pub fn blob_upload_url(&self) -> Result<Url, ParseError> {
+        format!(
+            "{}://{}/v2/{}/blobs/uploads/",
+            self.scheme, self.host, self.path
+        )
+        .parse()
     }
 
     pub fn new(url: &Url) -> Result<Self, ParseError> {
@@ -127,9 +171,13 @@ impl OCIUrl {
 
         let mut res = OCIUrl {
             url: url.clone(),
+            scheme: registry_scheme(url),
             tag: "latest".to_string(),
             media_type: "".to_string(),
-            host: url.host_str().unwrap_or("").to_string(),
+            host: match url.port() {
+                Some(port) => format!("{}:{port}", url.host_str().unwrap_or("")),
+                None => url.host_str().unwrap_or("").to_string(),
+            },
             path: url.path().trim_start_matches('/').to_string(),
         };
 
@@ -176,12 +224,14 @@ impl OCIUrl {
         let oci_url = OCIUrl::new(req.url())?;
         let token = get_token(&oci_url, OciAction::Pull).await?;
 
-        req.headers_mut().insert(
-            AUTHORIZATION,
-            format!("Bearer {token}")
-                .parse()
-                .expect("Could not parse token header"),
-        );
+        if let Some(token) = &token {
+            req.headers_mut().insert(
+                AUTHORIZATION,
+                format!("Bearer {token}")
+                    .parse()
+                    .expect("Could not parse token header"),
+            );
+        }
 
         // if we know the hash, we can pull the artifact directly
         // if we don't, we need to pull the manifest and then pull the artifact
@@ -195,12 +245,13 @@ impl OCIUrl {
             // get the tag from the URL retrieve the manifest
             let manifest_url = oci_url.manifest_url()?; // TODO: handle error
 
-            let manifest = reqwest::Client::new()
+            let mut request = reqwest::Client::new()
                 .get(manifest_url)
-                .header(AUTHORIZATION, format!("Bearer {token}"))
-                .header(ACCEPT, "application/vnd.oci.image.manifest.v1+json")
-                .send()
-                .await?;
+                .header(ACCEPT, "application/vnd.oci.image.manifest.v1+json");
+            if let Some(token) = &token {
+                request = request.header(AUTHORIZATION, format!("Bearer {token}"));
+            }
+            let manifest = request.send().await?;
 
             let manifest: Manifest = manifest.json().await?;
 
@@ -222,23 +273,27 @@ impl OCIUrl {
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Deserialize)]
-struct Layer {
-    digest: String,
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Layer {
+    pub(crate) digest: String,
     #[serde(rename = "mediaType")]
-    media_type: String,
-    size: u64,
-    annotations: Option<HashMap<String, String>>,
+    pub(crate) media_type: String,
+    pub(crate) size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) annotations: Option<HashMap<String, String>>,
 }
 
 #[allow(dead_code)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
-struct Manifest {
-    schema_version: u64,
-    layers: Vec<Layer>,
-    config: Layer,
-    annotations: Option<HashMap<String, String>>,
+pub(crate) struct Manifest {
+    pub(crate) schema_version: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) media_type: Option<String>,
+    pub(crate) layers: Vec<Layer>,
+    pub(crate) config: Layer,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) annotations: Option<HashMap<String, String>>,
 }
 
 #[cfg_attr(not(target_arch = "wasm32"), async_trait::async_trait)]
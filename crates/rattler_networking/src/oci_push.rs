@@ -0,0 +1,458 @@
+//! Pushes conda packages and repodata to an OCI registry.
+//!
+//! Artifacts are stored in the layout that is read by
+//! [`crate::OciMiddleware`]: a package `foo-1.0-h123_0.conda` of the channel
+//! `oci://ghcr.io/my-org/my-channel` and subdir `linux-64` is stored in the
+//! repository `my-org/my-channel/linux-64/foo` with the tag `1.0-h123_0`. The
+//! repodata of a subdir is stored in the repository
+//! `my-org/my-channel/linux-64/repodata.json` with the tag `latest` and
+//! contains a layer for every variant (e.g. `repodata.json` and
+//! `repodata.json.zst`).
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use http::header::{AUTHORIZATION, CONTENT_TYPE, LOCATION};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{
+    oci_middleware::{Layer, Manifest, OCIToken, OCIUrl, OciAction},
+    Authentication,
+};
+
+/// The media type of the manifests pushed to the registry.
+const MANIFEST_MEDIA_TYPE: &str = "application/vnd.oci.image.manifest.v1+json";
+
+/// The media type of the (empty) config of the manifests.
+const CONFIG_MEDIA_TYPE: &str = "application/vnd.oci.empty.v1+json";
+
+/// The annotation that contains the file name of a layer.
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// An error that can occur when pushing to an OCI registry.
+#[derive(Debug, thiserror::Error)]
+pub enum OciPushError {
+    /// A file could not be read.
+    #[error("failed to read '{}'", .0.display())]
+    IoError(PathBuf, #[source] std::io::Error),
+
+    /// The url of the channel or the artifact is invalid.
+    #[error("invalid url")]
+    ParseError(#[from] url::ParseError),
+
+    /// The file is neither a conda package nor repodata.
+    #[error("'{0}' is not a conda package or repodata file")]
+    UnsupportedFile(String),
+
+    /// A request to the registry failed.
+    #[error("failed to send request to the registry")]
+    Reqwest(#[from] reqwest::Error),
+
+    /// The registry responded with an unexpected status code.
+    #[error("the registry responded to {0} with {1}: {2}")]
+    UnexpectedStatus(Url, StatusCode, String),
+
+    /// The registry did not return where to upload a blob to.
+    #[error("the registry did not return an upload location for {0}")]
+    MissingUploadLocation(Url),
+}
+
+/// Pushes conda packages and repodata to an OCI registry.
+#[derive(Debug, Clone, Default)]
+pub struct OciPusher {
+    client: Client,
+    authentication: Option<Authentication>,
+}
+
+/// A blob that is pushed as a layer of a manifest.
+struct Blob {
+    file_name: String,
+    media_type: String,
+    bytes: Vec<u8>,
+}
+
+impl Blob {
+    fn digest(&self) -> String {
+        format!("sha256:{:x}", Sha256::digest(&self.bytes))
+    }
+}
+
+impl OciPusher {
+    /// Constructs a new instance that authenticates with the given
+    /// credentials. Basic credentials are exchanged for a token at the token
+    /// endpoint of the registry, a bearer token is used as is.
+    pub fn new(authentication: Option<Authentication>) -> Self {
+        Self {
+            client: Client::default(),
+            authentication,
+        }
+    }
+
+    /// Sets the client that is used to send requests.
+    #[must_use]
+    pub fn with_client(self, client: Client) -> Self {
+        Self { client, ..self }
+    }
+
+    /// Pushes a package archive to the given channel, e.g.
+    /// `oci://ghcr.io/my-org/my-channel`. Returns the url from which the
+    /// package can be pulled through the [`crate::OciMiddleware`].
+    pub async fn push_package(
+        &self,
+        channel: &Url,
+        subdir: &str,
+        package: &Path,
+    ) -> Result<Url, OciPushError> {
+        let file_name = file_name(package)?;
+        let url = artifact_url(channel, subdir, &file_name)?;
+        let oci_url = OCIUrl::new(&url)?;
+        if !oci_url
+            .media_type
+            .starts_with("application/vnd.conda.package")
+        {
+            return Err(OciPushError::UnsupportedFile(file_name));
+        }
+
+        let blob = read_blob(package, file_name, oci_url.media_type.clone())?;
+        self.push(&oci_url, vec![blob]).await?;
+        Ok(url)
+    }
+
+    /// Pushes the variants of the repodata of a subdir (e.g. `repodata.json`
+    /// and `repodata.json.zst`) to the given channel as a single manifest.
+    pub async fn push_repodata(
+        &self,
+        channel: &Url,
+        subdir: &str,
+        repodata: &[PathBuf],
+    ) -> Result<Url, OciPushError> {
+        let url = artifact_url(channel, subdir, "repodata.json")?;
+        let oci_url = OCIUrl::new(&url)?;
+
+        let mut blobs = Vec::with_capacity(repodata.len());
+        for path in repodata {
+            let file_name = file_name(path)?;
+            let media_type = OCIUrl::new(&artifact_url(channel, subdir, &file_name)?)?.media_type;
+            if !file_name.starts_with("repodata.json") || media_type.is_empty() {
+                return Err(OciPushError::UnsupportedFile(file_name));
+            }
+            blobs.push(read_blob(path, file_name, media_type)?);
+        }
+
+        self.push(&oci_url, blobs).await?;
+        Ok(url)
+    }
+
+    /// Pushes the blobs and a manifest that references them as layers.
+    async fn push(&self, oci_url: &OCIUrl, blobs: Vec<Blob>) -> Result<(), OciPushError> {
+        let authorization = self.authorization(oci_url).await?;
+
+        let config = Blob {
+            file_name: String::new(),
+            media_type: CONFIG_MEDIA_TYPE.to_string(),
+            bytes: b"{}".to_vec(),
+        };
+        self.push_blob(oci_url, authorization.as_deref(), &config)
+            .await?;
+        let mut layers = Vec::with_capacity(blobs.len());
+        for blob in &blobs {
+            self.push_blob(oci_url, authorization.as_deref(), blob)
+                .await?;
+            layers.push(Layer {
+                digest: blob.digest(),
+                media_type: blob.media_type.clone(),
+                size: blob.bytes.len() as u64,
+                annotations: Some(HashMap::from([(
+                    TITLE_ANNOTATION.to_string(),
+                    blob.file_name.clone(),
+                )])),
+            });
+        }
+
+        let manifest = Manifest {
+            schema_version: 2,
+            media_type: Some(MANIFEST_MEDIA_TYPE.to_string()),
+            layers,
+            config: Layer {
+                digest: config.digest(),
+                media_type: config.media_type,
+                size: config.bytes.len() as u64,
+                annotations: None,
+            },
+            annotations: None,
+        };
+        let manifest_url = oci_url.manifest_url()?;
+        let request = self
+            .request(Method::PUT, manifest_url.clone(), authorization.as_deref())
+            .header(CONTENT_TYPE, MANIFEST_MEDIA_TYPE)
+            .body(serde_json::to_vec(&manifest).expect("manifests can be serialized"));
+        expect_success(manifest_url, request.send().await?).await?;
+
+        tracing::info!(
+            "pushed {}:{} to {}",
+            oci_url.path,
+            oci_url.tag,
+            oci_url.host
+        );
+        Ok(())
+    }
+
+    /// Uploads a blob unless the registry already has it.
+    async fn push_blob(
+        &self,
+        oci_url: &OCIUrl,
+        authorization: Option<&str>,
+        blob: &Blob,
+    ) -> Result<(), OciPushError> {
+        let digest = blob.digest();
+        let blob_url = oci_url.blob_url(&digest)?;
+        let response = self
+            .request(Method::HEAD, blob_url, authorization)
+            .send()
+            .await?;
+        if response.status().is_success() {
+            tracing::debug!("blob {digest} already exists in {}", oci_url.path);
+            return Ok(());
+        }
+
+        // Start an upload session and upload the blob in a single request.
+        let upload_url = oci_url.blob_upload_url()?;
+        let response = expect_success(
+            upload_url.clone(),
+            self.request(Method::POST, upload_url.clone(), authorization)
+                .send()
+                .await?,
+        )
+        .await?;
+        let location = response
+            .headers()
+            .get(LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .ok_or_else(|| OciPushError::MissingUploadLocation(upload_url.clone()))?;
+        let mut location = upload_url.join(location)?;
+        location.query_pairs_mut().append_pair("digest", &digest);
+
+        let request = self
+            .request(Method::PUT, location.clone(), authorization)
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(blob.bytes.clone());
+        expect_success(location, request.send().await?).await?;
+        Ok(())
+    }
+
+    /// Returns the value of the authorization header to push to the
+    /// repository, if any.
+    async fn authorization(&self, oci_url: &OCIUrl) -> Result<Option<String>, OciPushError> {
+        let basic = match &self.authentication {
+            Some(
+                Authentication::BearerToken(token)
+                | Authentication::CondaToken(token)
+                | Authentication::OAuth {
+                    access_token: token,
+                    ..
+                },
+            ) => {
+                return Ok(Some(format!("Bearer {token}")));
+            }
+            Some(Authentication::BasicHTTP { username, password }) => Some(format!(
+                "Basic {}",
+                STANDARD.encode(format!("{username}:{password}"))
+            )),
+            _ => None,
+        };
+
+        // Exchange the credentials for a token that allows pushing to the
+        // repository. Registries without a token endpoint use the credentials
+        // directly.
+        let token_url = oci_url.token_url(OciAction::PushPull)?;
+        let response = self
+            .request(Method::GET, token_url.clone(), basic.as_deref())
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(basic);
+        }
+        let token = expect_success(token_url, response)
+            .await?
+            .json::<OCIToken>()
+            .await?;
+        Ok(Some(format!("Bearer {}", token.token)))
+    }
+
+    fn request(&self, method: Method, url: Url, authorization: Option<&str>) -> RequestBuilder {
+        let request = self.client.request(method, url);
+        match authorization {
+            Some(authorization) => request.header(AUTHORIZATION, authorization),
+            None => request,
+        }
+    }
+}
+
+/// Returns the `oci://` url of a file in a subdir of a channel.
+fn artifact_url(channel: &Url, subdir: &str, file_name: &str) -> Result<Url, url::ParseError> {
+    let mut channel = channel.clone();
+    if !channel.path().ends_with('/') {
+        channel.set_path(&format!("{}/", channel.path()));
+    }
+    channel.join(&format!("{subdir}/{file_name}"))
+}
+
+fn file_name(path: &Path) -> Result<String, OciPushError> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(ToString::to_string)
+        .ok_or_else(|| OciPushError::UnsupportedFile(path.display().to_string()))
+}
+
+fn read_blob(path: &Path, file_name: String, media_type: String) -> Result<Blob, OciPushError> {
+    let bytes = fs_err::read(path).map_err(|e| OciPushError::IoError(path.to_path_buf(), e))?;
+    Ok(Blob {
+        file_name,
+        media_type,
+        bytes,
+    })
+}
+
+async fn expect_success(url: Url, response: Response) -> Result<Response, OciPushError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(OciPushError::UnexpectedStatus(url, status, body))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        future::IntoFuture,
+        net::SocketAddr,
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+
+    use axum::{
+        body::Bytes,
+        extract::State,
+        http::{Method, StatusCode, Uri},
+        response::{IntoResponse, Response},
+        Router,
+    };
+    use url::Url;
+
+    use super::OciPusher;
+    use crate::OciMiddleware;
+
+    /// The blobs and manifests stored by the registry, keyed by path.
+    type Storage = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// A minimal registry without authentication.
+    async fn registry(
+        State(storage): State<Storage>,
+        method: Method,
+        uri: Uri,
+        body: Bytes,
+    ) -> Response {
+        let path = uri.path();
+        let mut storage = storage.lock().unwrap();
+        if let Some(repository) = path.strip_suffix("/blobs/uploads/") {
+            if method == Method::POST {
+                let location = format!("{repository}/blobs/uploads/session");
+                return (StatusCode::ACCEPTED, [("Location", location)]).into_response();
+            }
+        }
+        if let Some(repository) = path.strip_suffix("/blobs/uploads/session") {
+            let digest = uri
+                .query()
+                .and_then(|query| query.strip_prefix("digest="))
+                .unwrap()
+                .replace("%3A", ":");
+            storage.insert(format!("{repository}/blobs/{digest}"), body.to_vec());
+            return StatusCode::CREATED.into_response();
+        }
+        match method {
+            Method::PUT => {
+                storage.insert(path.to_string(), body.to_vec());
+                StatusCode::CREATED.into_response()
+            }
+            Method::GET | Method::HEAD => match storage.get(path) {
+                Some(content) => content.clone().into_response(),
+                None => StatusCode::NOT_FOUND.into_response(),
+            },
+            _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
+        }
+    }
+
+    async fn test_registry() -> (Url, Storage) {
+        let storage = Storage::default();
+        let router = Router::new().fallback(registry).with_state(storage.clone());
+
+        let addr = SocketAddr::new([127, 0, 0, 1].into(), 0);
+        let listener = tokio::net::TcpListener::bind(&addr).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(axum::serve(listener, router.into_make_service()).into_future());
+        let url = format!("oci://{}:{}/my-org/channel", addr.ip(), addr.port())
+            .parse()
+            .unwrap();
+        (url, storage)
+    }
+
+    #[tokio::test]
+    async fn test_push_and_pull() {
+        let (channel, storage) = test_registry().await;
+        let package = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../../test-data/packages/empty-0.1.0-h4616a5c_0.conda");
+        let repodata_dir = tempfile::tempdir().unwrap();
+        let repodata = repodata_dir.path().join("repodata.json");
+        fs_err::write(&repodata, r#"{"packages":{}}"#).unwrap();
+
+        let pusher = OciPusher::new(None);
+        let url = pusher
+            .push_package(&channel, "noarch", &package)
+            .await
+            .unwrap();
+        assert!(url
+            .as_str()
+            .ends_with("/noarch/empty-0.1.0-h4616a5c_0.conda"));
+        pusher
+            .push_repodata(&channel, "noarch", &[repodata.clone()])
+            .await
+            .unwrap();
+        assert!(storage
+            .lock()
+            .unwrap()
+            .contains_key("/v2/my-org/channel/noarch/empty/manifests/0.1.0-h4616a5c_0"));
+
+        // The middleware reads the artifacts from the same locations.
+        let client = reqwest_middleware::ClientBuilder::new(reqwest::Client::new())
+            .with(OciMiddleware)
+            .build();
+        let pulled = client.get(url).send().await.unwrap();
+        assert_eq!(pulled.status(), 200);
+        assert_eq!(
+            pulled.bytes().await.unwrap(),
+            fs_err::read(&package).unwrap()
+        );
+        let pulled = client
+            .get(channel.join("channel/noarch/repodata.json").unwrap())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            pulled.bytes().await.unwrap(),
+            fs_err::read(&repodata).unwrap()
+        );
+
+        // Only conda packages and repodata can be pushed.
+        assert!(pusher
+            .push_package(&channel, "noarch", &repodata)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,65 @@
+use http::StatusCode;
+use rattler_conda_types::{Channel, ChannelData};
+use reqwest_middleware::ClientWithMiddleware;
+use url::Url;
+
+use crate::gateway::GatewayError;
+
+const CHANNELDATA: &str = "channeldata.json";
+
+/// Fetches and parses the `channeldata.json` file at the root of a channel.
+///
+/// Returns `None` if the channel does not contain a `channeldata.json` file.
+pub(crate) async fn fetch_channel_data(
+    client: &ClientWithMiddleware,
+    channel: &Channel,
+) -> Result<Option<ChannelData>, GatewayError> {
+    let url = channel
+        .base_url
+        .url()
+        .join(CHANNELDATA)
+        .expect("is a valid url segment");
+
+    let bytes = if url.scheme() == "file" {
+        read_local(&url).await?
+    } else {
+        let response = client.get(url.clone()).send().await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let response = response.error_for_status()?;
+        Some(response.bytes().await?.to_vec())
+    };
+
+    bytes
+        .map(|bytes| {
+            serde_json::from_slice(&bytes)
+                .map_err(|err| GatewayError::IoError(format!("failed to parse {url}"), err.into()))
+        })
+        .transpose()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn read_local(url: &Url) -> Result<Option<Vec<u8>>, GatewayError> {
+    let Some(path) = file_url::url_to_path(url) else {
+        return Err(GatewayError::UnsupportedUrl(
+            "unsupported file based url".to_string(),
+        ));
+    };
+    simple_spawn_blocking::tokio::run_blocking_task(move || match fs_err::read(&path) {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(GatewayError::IoError(
+            format!("failed to read {}", path.display()),
+            err,
+        )),
+    })
+    .await
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn read_local(url: &Url) -> Result<Option<Vec<u8>>, GatewayError> {
+    Err(GatewayError::UnsupportedUrl(format!(
+        "reading {url} is not supported on this platform"
+    )))
+}
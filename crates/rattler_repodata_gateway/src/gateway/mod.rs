@@ -1,6 +1,7 @@
 mod barrier_cell;
 mod builder;
 mod channel_config;
+mod channel_data;
 #[cfg(not(target_arch = "wasm32"))]
 mod direct_url_query;
 mod error;
@@ -23,7 +24,9 @@ pub use error::GatewayError;
 pub use query::{NamesQuery, RepoDataQuery};
 #[cfg(not(target_arch = "wasm32"))]
 use rattler_cache::package_cache::PackageCache;
-use rattler_conda_types::{Channel, MatchSpec, Platform, RepoDataRecord};
+use rattler_conda_types::{
+    Channel, ChannelData, MatchSpec, Platform, RepoDataRecord, SubdirRunExportsJson,
+};
 pub use repo_data::RepoData;
 use reqwest_middleware::ClientWithMiddleware;
 use run_exports_extractor::{RunExportExtractor, SubdirRunExportsCache};
@@ -178,6 +181,47 @@ impl Gateway {
         Ok(())
     }
 
+    /// Returns the contents of the `run_exports.json` file of a subdirectory of
+    /// a channel (see [CEP 12](https://github.com/conda/ceps/blob/main/cep-0012.md)).
+    ///
+    /// This allows retrieving the run exports of all packages in a subdir
+    /// without fetching the full repodata. Returns `None` if the subdirectory
+    /// does not provide a `run_exports.json` file.
+    pub async fn subdir_run_exports(
+        &self,
+        channel: &Channel,
+        platform: Platform,
+        progress_reporter: Option<Arc<dyn RunExportsReporter>>,
+    ) -> Option<Arc<SubdirRunExportsJson>> {
+        let mut extractor = RunExportExtractor::default()
+            .with_opt_max_concurrent_requests(self.inner.concurrent_requests_semaphore.clone())
+            .with_client(self.inner.client.clone())
+            .with_global_run_exports_cache(self.inner.subdir_run_exports_cache.clone());
+        extractor
+            .fetch_subdir_run_exports(&channel.platform_url(platform), progress_reporter)
+            .await
+    }
+
+    /// Fetches the `channeldata.json` file from the root of the given channel.
+    ///
+    /// Returns `None` if the channel does not provide a `channeldata.json`
+    /// file.
+    pub async fn channel_data(
+        &self,
+        channel: &Channel,
+    ) -> Result<Option<ChannelData>, GatewayError> {
+        let _permit = futures::future::OptionFuture::from(
+            self.inner
+                .concurrent_requests_semaphore
+                .clone()
+                .map(tokio::sync::Semaphore::acquire_owned),
+        )
+        .await
+        .transpose()
+        .expect("semaphore error");
+        channel_data::fetch_channel_data(&self.inner.client, channel).await
+    }
+
     /// Clears any in-memory cache for the given channel.
     ///
     /// Any subsequent query will re-fetch any required data from the source.
@@ -785,4 +829,46 @@ mod test {
 
         assert!(run_exports_in_place(&repodata_records));
     }
+
+    #[tokio::test]
+    async fn test_local_channel_data_and_subdir_run_exports() {
+        let channel_dir = tempfile::tempdir().unwrap();
+        fs_err::create_dir(channel_dir.path().join("linux-64")).unwrap();
+        fs_err::write(
+            channel_dir.path().join("channeldata.json"),
+            r#"{"channeldata_version": 1, "packages": {}, "subdirs": ["linux-64"]}"#,
+        )
+        .unwrap();
+        fs_err::write(
+            channel_dir.path().join("linux-64/run_exports.json"),
+            r#"{"info": {"subdir": "linux-64"}, "packages": {"foo-1.0-0.tar.bz2": {"run_exports": {"weak": ["foo >=1.0"]}}}}"#,
+        )
+        .unwrap();
+        let channel = Channel::from_directory(channel_dir.path());
+
+        let gateway = Gateway::new();
+        let channel_data = gateway.channel_data(&channel).await.unwrap().unwrap();
+        assert_eq!(channel_data.subdirs, vec!["linux-64"]);
+
+        let run_exports = gateway
+            .subdir_run_exports(&channel, Platform::Linux64, None)
+            .await
+            .unwrap();
+        assert_eq!(
+            run_exports.info().unwrap().subdir.as_deref(),
+            Some("linux-64")
+        );
+        assert!(gateway
+            .subdir_run_exports(&channel, Platform::Win64, None)
+            .await
+            .is_none());
+
+        let empty_dir = tempfile::tempdir().unwrap();
+        let empty_channel = Channel::from_directory(empty_dir.path());
+        assert!(gateway
+            .channel_data(&empty_channel)
+            .await
+            .unwrap()
+            .is_none());
+    }
 }
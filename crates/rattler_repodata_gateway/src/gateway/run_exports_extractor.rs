@@ -69,6 +69,13 @@ pub enum RunExportExtractorError {
     Cancelled,
 }
 
+#[cfg(not(target_arch = "wasm32"))]
+impl From<simple_spawn_blocking::Cancelled> for RunExportExtractorError {
+    fn from(_: simple_spawn_blocking::Cancelled) -> Self {
+        RunExportExtractorError::Cancelled
+    }
+}
+
 impl RunExportExtractor {
     /// Sets the maximum number of concurrent requests that the extractor can
     /// make.
@@ -264,14 +271,70 @@ impl RunExportExtractor {
         }
     }
 
+    /// Read the `run_exports.json.zst` or `run_exports.json` file from a local
+    /// subdirectory.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_local_subdir_run_exports(
+        subdir_path: &Path,
+    ) -> Result<Option<SubdirRunExportsJson>, RunExportExtractorError> {
+        let zst_path = subdir_path.join("run_exports.json.zst");
+        let decoded = match fs_err::read(&zst_path) {
+            Ok(bytes) => Some(zstd::decode_all(bytes.as_slice()).map_err(|err| {
+                RunExportExtractorError::DecodeRunExports(zst_path.display().to_string(), err)
+            })?),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => None,
+            Err(err) => {
+                return Err(RunExportExtractorError::DecodeRunExports(
+                    zst_path.display().to_string(),
+                    err,
+                ))
+            }
+        };
+
+        let json_path = subdir_path.join("run_exports.json");
+        let (path, bytes) = match decoded {
+            Some(decoded) => (zst_path, decoded),
+            None => match fs_err::read(&json_path) {
+                Ok(bytes) => (json_path, bytes),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(err) => {
+                    return Err(RunExportExtractorError::DecodeRunExports(
+                        json_path.display().to_string(),
+                        err,
+                    ))
+                }
+            },
+        };
+
+        serde_json::from_slice(&bytes).map(Some).map_err(|err| {
+            RunExportExtractorError::DecodeRunExports(path.display().to_string(), err.into())
+        })
+    }
+
     /// Fetch the `run_exports.json` file from the subdirectory URL, either from
     /// the `run_exports.json.zst` file or the `run_exports.json` file.
-    async fn fetch_subdir_run_exports(
+    pub(crate) async fn fetch_subdir_run_exports(
         &mut self,
         subdir_url: &Url,
         reporter: Option<Arc<dyn RunExportsReporter>>,
     ) -> Option<Arc<SubdirRunExportsJson>> {
         let url = subdir_url.clone();
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(subdir_path) = file_url::url_to_path(subdir_url) {
+            return self
+                .subdir_run_exports_cache
+                .get_or_try_init(url, || async move {
+                    let run_exports = simple_spawn_blocking::tokio::run_blocking_task(move || {
+                        Self::read_local_subdir_run_exports(&subdir_path)
+                    })
+                    .await?;
+                    Ok::<_, RunExportExtractorError>(run_exports.map(Arc::new))
+                })
+                .await
+                .unwrap_or(None);
+        }
+
         let client = self.client.clone()?;
 
         self.subdir_run_exports_cache
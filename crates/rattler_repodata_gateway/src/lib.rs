@@ -70,6 +70,8 @@ pub use reporter::{DownloadReporter, JLAPReporter, Reporter};
 
 #[cfg(feature = "gateway")]
 mod gateway;
+#[cfg(all(not(target_arch = "wasm32"), feature = "mirror"))]
+pub mod mirror;
 
 #[cfg(feature = "gateway")]
 pub use gateway::{
@@ -0,0 +1,548 @@
+//! Create and update an offline copy of a subset of one or more channels.
+//!
+//! A [`ChannelMirror`] resolves the closure of a set of [`MatchSpec`]s with a
+//! [`Gateway`], downloads the corresponding package archives into a local
+//! directory and writes filtered repodata next to them. The resulting
+//! directory is a regular conda channel that can be used without network
+//! access.
+//!
+//! ```no_run
+//! # use rattler_conda_types::{Channel, ChannelConfig, MatchSpec, ParseStrictness, Platform};
+//! # use rattler_repodata_gateway::{mirror::ChannelMirror, Gateway};
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! let channel_config = ChannelConfig::default_with_root_dir(std::env::current_dir()?);
+//! let channel = Channel::from_str("conda-forge", &channel_config)?;
+//! let summary = ChannelMirror::new(Gateway::new(), "./conda-forge-mirror")
+//!     .with_prune(true)
+//!     .sync(
+//!         [channel],
+//!         [Platform::Linux64, Platform::NoArch],
+//!         [MatchSpec::from_str("python 3.12.*", ParseStrictness::Strict)?],
+//!     )
+//!     .await?;
+//! println!("downloaded {} packages", summary.downloaded.len());
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io,
+    path::{Path, PathBuf},
+};
+
+use futures::{StreamExt, TryStreamExt};
+use opendal::{services::Fs, Operator};
+use rattler_conda_types::{
+    package::ArchiveType, Channel, ChannelInfo, MatchSpec, Platform, RepoDataRecord,
+};
+use rattler_digest::{HashingWriter, Sha256, Sha256Hash};
+use reqwest_middleware::ClientWithMiddleware;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::{Gateway, GatewayError};
+
+/// The default number of archives that are downloaded concurrently.
+const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 50;
+
+/// An error that can occur while mirroring channels.
+#[derive(Debug, thiserror::Error)]
+#[allow(missing_docs)]
+pub enum MirrorError {
+    #[error(transparent)]
+    GatewayError(#[from] GatewayError),
+
+    #[error("{0}")]
+    IoError(String, #[source] io::Error),
+
+    #[error("failed to download {0}")]
+    DownloadError(Url, #[source] reqwest_middleware::Error),
+
+    #[error("unsupported url {0}")]
+    UnsupportedUrl(Url),
+
+    #[error(
+        "the sha256 hash of {file_name} does not match, expected {expected:x} but got {actual:x}"
+    )]
+    HashMismatch {
+        file_name: String,
+        expected: Sha256Hash,
+        actual: Sha256Hash,
+    },
+
+    #[error("the package {0} has an unknown subdir '{1}'")]
+    UnknownSubdir(String, String),
+
+    #[error("failed to write repodata for {0}")]
+    WriteRepodataError(Platform, #[source] anyhow::Error),
+
+    #[error("the operation was cancelled")]
+    Cancelled,
+}
+
+impl From<simple_spawn_blocking::Cancelled> for MirrorError {
+    fn from(_: simple_spawn_blocking::Cancelled) -> Self {
+        MirrorError::Cancelled
+    }
+}
+
+/// The result of a [`ChannelMirror::sync`].
+#[derive(Debug, Default, Clone)]
+pub struct MirrorSummary {
+    /// The paths, relative to the mirror root, of the archives that were
+    /// downloaded.
+    pub downloaded: Vec<String>,
+
+    /// The paths, relative to the mirror root, of the archives that were
+    /// already present and did not have to be downloaded again.
+    pub up_to_date: Vec<String>,
+
+    /// The paths, relative to the mirror root, of the archives that were
+    /// removed because they are no longer referenced.
+    pub removed: Vec<String>,
+}
+
+/// Mirrors the packages required by a set of specs into a local directory
+/// that is laid out as a conda channel.
+///
+/// Syncing is incremental: archives that are already present in the target
+/// directory with the expected hash are not downloaded again. If pruning is
+/// enabled, archives in the synced subdirs that are no longer required are
+/// removed.
+///
+/// Archives are downloaded directly instead of through a
+/// [`rattler_cache::package_cache::PackageCache`] because the package cache
+/// only stores extracted packages.
+#[derive(Clone)]
+pub struct ChannelMirror {
+    gateway: Gateway,
+    client: ClientWithMiddleware,
+    target: PathBuf,
+    prune: bool,
+    write_zst: bool,
+    write_shards: bool,
+    max_concurrent_downloads: usize,
+}
+
+impl ChannelMirror {
+    /// Constructs a new mirror that writes to the `target` directory and uses
+    /// the `gateway` to resolve records.
+    pub fn new(gateway: Gateway, target: impl Into<PathBuf>) -> Self {
+        Self {
+            gateway,
+            client: ClientWithMiddleware::from(reqwest::Client::new()),
+            target: target.into(),
+            prune: false,
+            write_zst: true,
+            write_shards: false,
+            max_concurrent_downloads: DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+        }
+    }
+
+    /// Sets the client that is used to download archives.
+    #[must_use]
+    pub fn with_client(self, client: ClientWithMiddleware) -> Self {
+        Self { client, ..self }
+    }
+
+    /// Sets whether archives that are no longer referenced are removed from
+    /// the synced subdirs.
+    #[must_use]
+    pub fn with_prune(self, prune: bool) -> Self {
+        Self { prune, ..self }
+    }
+
+    /// Sets whether `repodata.json.zst` is written. Defaults to `true`.
+    #[must_use]
+    pub fn with_zst(self, write_zst: bool) -> Self {
+        Self { write_zst, ..self }
+    }
+
+    /// Sets whether sharded repodata is written. Defaults to `false`.
+    #[must_use]
+    pub fn with_shards(self, write_shards: bool) -> Self {
+        Self {
+            write_shards,
+            ..self
+        }
+    }
+
+    /// Sets the maximum number of archives that are downloaded concurrently.
+    #[must_use]
+    pub fn with_max_concurrent_downloads(self, max_concurrent_downloads: usize) -> Self {
+        Self {
+            max_concurrent_downloads: max_concurrent_downloads.max(1),
+            ..self
+        }
+    }
+
+    /// Resolves all records that are (recursively) required by `specs` from
+    /// `channels` for the given `platforms` and mirrors them.
+    ///
+    /// Channels are ordered by priority: if multiple channels contain an
+    /// archive with the same filename, the one from the first channel is used.
+    /// Repodata is written for every platform in `platforms`, even if no
+    /// packages were found for it.
+    pub async fn sync<AsChannel, ChannelIter, PlatformIter, SpecIter, IntoMatchSpec>(
+        &self,
+        channels: ChannelIter,
+        platforms: PlatformIter,
+        specs: SpecIter,
+    ) -> Result<MirrorSummary, MirrorError>
+    where
+        AsChannel: Into<Channel>,
+        ChannelIter: IntoIterator<Item = AsChannel>,
+        PlatformIter: IntoIterator<Item = Platform>,
+        <PlatformIter as IntoIterator>::IntoIter: Clone,
+        SpecIter: IntoIterator<Item = IntoMatchSpec>,
+        IntoMatchSpec: Into<MatchSpec>,
+    {
+        let platforms = platforms.into_iter();
+        let repo_data = self
+            .gateway
+            .query(channels, platforms.clone(), specs)
+            .recursive(true)
+            .await?;
+
+        // Group the records by subdir, the first channel that provides a
+        // filename wins.
+        let mut subdirs: HashMap<Platform, BTreeMap<String, RepoDataRecord>> = platforms
+            .map(|platform| (platform, BTreeMap::new()))
+            .collect();
+        for record in repo_data.iter().flat_map(crate::RepoData::iter) {
+            let Ok(subdir) = record.package_record.subdir.parse::<Platform>() else {
+                return Err(MirrorError::UnknownSubdir(
+                    record.file_name.clone(),
+                    record.package_record.subdir.clone(),
+                ));
+            };
+            subdirs
+                .entry(subdir)
+                .or_default()
+                .entry(record.file_name.clone())
+                .or_insert_with(|| record.clone());
+        }
+
+        let mut summary = MirrorSummary::default();
+        for (subdir, records) in &subdirs {
+            let subdir_path = self.target.join(subdir.as_str());
+            fs_err::tokio::create_dir_all(&subdir_path)
+                .await
+                .map_err(|e| {
+                    MirrorError::IoError(format!("failed to create {}", subdir_path.display()), e)
+                })?;
+
+            let results = futures::stream::iter(records.values())
+                .map(|record| self.sync_archive(&subdir_path, record))
+                .buffer_unordered(self.max_concurrent_downloads)
+                .try_collect::<Vec<_>>()
+                .await?;
+            for (file_name, downloaded) in results {
+                let path = format!("{subdir}/{file_name}");
+                if downloaded {
+                    summary.downloaded.push(path);
+                } else {
+                    summary.up_to_date.push(path);
+                }
+            }
+        }
+
+        // Write the repodata last so that it only references archives that
+        // have been downloaded.
+        let op = Operator::new(Fs::default().root(&self.target.to_string_lossy()))
+            .map_err(|e| MirrorError::IoError("failed to open the mirror".to_string(), e.into()))?
+            .finish();
+        for (&subdir, records) in &subdirs {
+            let repodata = repodata_from_records(subdir, records.values().cloned());
+            rattler_index::write_repodata(
+                repodata,
+                None,
+                self.write_zst,
+                self.write_shards,
+                subdir,
+                op.clone(),
+            )
+            .await
+            .map_err(|e| MirrorError::WriteRepodataError(subdir, e))?;
+        }
+
+        // Only prune once the repodata no longer references the archives that
+        // are removed.
+        if self.prune {
+            for (subdir, records) in &subdirs {
+                let subdir_path = self.target.join(subdir.as_str());
+                for file_name in prune_subdir(&subdir_path, records).await? {
+                    summary.removed.push(format!("{subdir}/{file_name}"));
+                }
+            }
+        }
+
+        summary.downloaded.sort();
+        summary.up_to_date.sort();
+        summary.removed.sort();
+        Ok(summary)
+    }
+
+    /// Makes sure the archive of `record` is present in `subdir_path`.
+    /// Returns the filename and whether the archive had to be downloaded.
+    async fn sync_archive(
+        &self,
+        subdir_path: &Path,
+        record: &RepoDataRecord,
+    ) -> Result<(String, bool), MirrorError> {
+        let destination = subdir_path.join(&record.file_name);
+        let expected_sha256 = record.package_record.sha256;
+
+        if let Some(expected) = expected_sha256 {
+            if file_sha256(&destination).await? == Some(expected) {
+                return Ok((record.file_name.clone(), false));
+            }
+        } else if destination.is_file() {
+            // Without a hash we can only assume the existing file is correct.
+            return Ok((record.file_name.clone(), false));
+        }
+
+        tracing::debug!("downloading {}", record.url);
+        let temp_file = tempfile::NamedTempFile::new_in(subdir_path).map_err(|e| {
+            MirrorError::IoError(
+                format!(
+                    "failed to create a temporary file in {}",
+                    subdir_path.display()
+                ),
+                e,
+            )
+        })?;
+        let (file, temp_path) = temp_file.into_parts();
+        let mut writer = HashingWriter::<_, Sha256>::new(tokio::fs::File::from_std(file));
+        let write_err =
+            |e| MirrorError::IoError(format!("failed to write {}", destination.display()), e);
+
+        if record.url.scheme() == "file" {
+            let source = file_url::url_to_path(&record.url)
+                .ok_or_else(|| MirrorError::UnsupportedUrl(record.url.clone()))?;
+            let mut reader = fs_err::tokio::File::open(&source).await.map_err(|e| {
+                MirrorError::IoError(format!("failed to open {}", source.display()), e)
+            })?;
+            tokio::io::copy(&mut reader, &mut writer)
+                .await
+                .map_err(write_err)?;
+        } else {
+            let response = self
+                .client
+                .get(record.url.clone())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status().map_err(Into::into))
+                .map_err(|e| MirrorError::DownloadError(record.url.clone(), e))?;
+            let mut stream = response.bytes_stream();
+            while let Some(chunk) = stream.next().await {
+                let chunk =
+                    chunk.map_err(|e| MirrorError::DownloadError(record.url.clone(), e.into()))?;
+                writer.write_all(&chunk).await.map_err(write_err)?;
+            }
+        }
+        writer.flush().await.map_err(write_err)?;
+        let (_, actual) = writer.finalize();
+
+        if let Some(expected) = expected_sha256 {
+            if expected != actual {
+                return Err(MirrorError::HashMismatch {
+                    file_name: record.file_name.clone(),
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        temp_path.persist(&destination).map_err(|e| {
+            MirrorError::IoError(
+                format!("failed to persist {}", destination.display()),
+                e.error,
+            )
+        })?;
+        Ok((record.file_name.clone(), true))
+    }
+}
+
+/// Computes the sha256 hash of the file at `path` or returns `None` if the
+/// file does not exist.
+async fn file_sha256(path: &Path) -> Result<Option<Sha256Hash>, MirrorError> {
+    let path = path.to_path_buf();
+    simple_spawn_blocking::tokio::run_blocking_task(move || {
+        match rattler_digest::compute_file_digest::<Sha256>(&path) {
+            Ok(hash) => Ok(Some(hash)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(MirrorError::IoError(
+                format!("failed to hash {}", path.display()),
+                e,
+            )),
+        }
+    })
+    .await
+}
+
+/// Removes all archives in `subdir_path` that are not part of `records`.
+/// Returns the filenames of the removed archives.
+async fn prune_subdir(
+    subdir_path: &Path,
+    records: &BTreeMap<String, RepoDataRecord>,
+) -> Result<Vec<String>, MirrorError> {
+    let read_err = |e| MirrorError::IoError(format!("failed to read {}", subdir_path.display()), e);
+    let mut entries = fs_err::tokio::read_dir(subdir_path)
+        .await
+        .map_err(read_err)?;
+    let mut removed = Vec::new();
+    while let Some(entry) = entries.next_entry().await.map_err(read_err)? {
+        let Ok(file_name) = entry.file_name().into_string() else {
+            continue;
+        };
+        if ArchiveType::try_from(&file_name).is_none() || records.contains_key(&file_name) {
+            continue;
+        }
+        tracing::debug!("removing {}", entry.path().display());
+        fs_err::tokio::remove_file(entry.path())
+            .await
+            .map_err(|e| MirrorError::IoError("failed to remove archive".to_string(), e))?;
+        removed.push(file_name);
+    }
+    Ok(removed)
+}
+
+/// Constructs the repodata of a single subdir from the given records.
+fn repodata_from_records(
+    subdir: Platform,
+    records: impl IntoIterator<Item = RepoDataRecord>,
+) -> rattler_conda_types::RepoData {
+    let (conda_packages, packages): (Vec<_>, Vec<_>) = records
+        .into_iter()
+        .map(|record| (record.file_name, record.package_record))
+        .partition(|(file_name, _)| ArchiveType::try_from(file_name) == Some(ArchiveType::Conda));
+    rattler_conda_types::RepoData {
+        info: Some(ChannelInfo {
+            subdir: Some(subdir.to_string()),
+            base_url: None,
+        }),
+        packages: packages.into_iter().collect(),
+        conda_packages: conda_packages.into_iter().collect(),
+        removed: std::iter::empty().collect(),
+        version: Some(2),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rattler_conda_types::{Channel, MatchSpec, ParseStrictness, Platform};
+    use rattler_index::{index_fs, IndexFsConfig};
+
+    use super::ChannelMirror;
+    use crate::Gateway;
+
+    const EMPTY: &str = "empty-0.1.0-h4616a5c_0.conda";
+    const LINK_SCRIPTS: &str = "link-scripts-0.1.0-h4616a5c_0.conda";
+
+    /// Creates a local channel that contains two noarch packages.
+    async fn source_channel() -> tempfile::TempDir {
+        let test_data = Path::new(env!("CARGO_MANIFEST_DIR")).join("../../test-data");
+        let channel_dir = tempfile::tempdir().unwrap();
+        let noarch = channel_dir.path().join("noarch");
+        fs_err::create_dir(&noarch).unwrap();
+        fs_err::copy(test_data.join("packages").join(EMPTY), noarch.join(EMPTY)).unwrap();
+        fs_err::copy(
+            test_data.join("link-scripts").join(LINK_SCRIPTS),
+            noarch.join(LINK_SCRIPTS),
+        )
+        .unwrap();
+        index_fs(IndexFsConfig {
+            channel: channel_dir.path().to_path_buf(),
+            target_platform: None,
+            repodata_patch: None,
+            write_zst: false,
+            write_shards: false,
+            force: true,
+            max_parallel: 1,
+            multi_progress: None,
+        })
+        .await
+        .unwrap();
+        channel_dir
+    }
+
+    fn specs(specs: &[&str]) -> Vec<MatchSpec> {
+        specs
+            .iter()
+            .map(|spec| MatchSpec::from_str(spec, ParseStrictness::Strict).unwrap())
+            .collect()
+    }
+
+    fn mirrored_packages(mirror: &Path) -> Vec<String> {
+        let repodata: rattler_conda_types::RepoData =
+            serde_json::from_slice(&fs_err::read(mirror.join("noarch/repodata.json")).unwrap())
+                .unwrap();
+        let mut packages: Vec<_> = repodata.conda_packages.into_keys().collect();
+        packages.sort();
+        packages
+    }
+
+    #[tokio::test]
+    async fn test_sync_and_prune() {
+        let source = source_channel().await;
+        let channel = Channel::from_directory(source.path());
+        let target = tempfile::tempdir().unwrap();
+        let mirror = ChannelMirror::new(Gateway::new(), target.path()).with_prune(true);
+
+        let summary = mirror
+            .sync([channel.clone()], [Platform::NoArch], specs(&["empty"]))
+            .await
+            .unwrap();
+        assert_eq!(summary.downloaded, vec![format!("noarch/{EMPTY}")]);
+        assert!(summary.up_to_date.is_empty());
+        assert!(target.path().join("noarch").join(EMPTY).is_file());
+        assert!(target.path().join("noarch/repodata.json.zst").is_file());
+        assert_eq!(mirrored_packages(target.path()), vec![EMPTY]);
+
+        // Syncing again only downloads the new package.
+        let summary = mirror
+            .sync(
+                [channel.clone()],
+                [Platform::NoArch],
+                specs(&["empty", "link-scripts"]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(summary.downloaded, vec![format!("noarch/{LINK_SCRIPTS}")]);
+        assert_eq!(summary.up_to_date, vec![format!("noarch/{EMPTY}")]);
+        assert_eq!(mirrored_packages(target.path()), vec![EMPTY, LINK_SCRIPTS]);
+
+        // Packages that are no longer required are pruned.
+        let summary = mirror
+            .sync([channel], [Platform::NoArch], specs(&["link-scripts"]))
+            .await
+            .unwrap();
+        assert!(summary.downloaded.is_empty());
+        assert_eq!(summary.removed, vec![format!("noarch/{EMPTY}")]);
+        assert!(!target.path().join("noarch").join(EMPTY).exists());
+        assert_eq!(mirrored_packages(target.path()), vec![LINK_SCRIPTS]);
+    }
+
+    #[tokio::test]
+    async fn test_sync_replaces_corrupt_archives() {
+        let source = source_channel().await;
+        let channel = Channel::from_directory(source.path());
+        let target = tempfile::tempdir().unwrap();
+        fs_err::create_dir(target.path().join("noarch")).unwrap();
+        fs_err::write(target.path().join("noarch").join(EMPTY), "corrupt").unwrap();
+
+        let summary = ChannelMirror::new(Gateway::new(), target.path())
+            .sync([channel], [Platform::NoArch], specs(&["empty"]))
+            .await
+            .unwrap();
+        assert_eq!(summary.downloaded, vec![format!("noarch/{EMPTY}")]);
+        assert_ne!(
+            fs_err::read(target.path().join("noarch").join(EMPTY)).unwrap(),
+            b"corrupt"
+        );
+    }
+}
@@ -0,0 +1,74 @@
+//! Resolves [`crate::SolverTask::channel_restrictions`] and
+//! [`crate::SolverTask::channel_order`] into a form that the solver backends can
+//! query efficiently.
+
+use std::collections::HashMap;
+
+use rattler_conda_types::{Channel, PackageName, RepoDataRecord};
+
+/// A channel as it is referred to by [`RepoDataRecord::channel`].
+#[derive(Debug, Clone)]
+pub(crate) struct ChannelRef {
+    /// The canonical name of the channel which is compared against
+    /// [`RepoDataRecord::channel`].
+    pub canonical_name: String,
+
+    /// A human readable name used in error messages.
+    #[cfg(feature = "resolvo")]
+    pub display_name: String,
+}
+
+impl From<&Channel> for ChannelRef {
+    fn from(channel: &Channel) -> Self {
+        Self {
+            canonical_name: channel.canonical_name(),
+            #[cfg(feature = "resolvo")]
+            display_name: channel
+                .name
+                .clone()
+                .unwrap_or_else(|| channel.base_url.to_string()),
+        }
+    }
+}
+
+/// The channel restrictions and channel order of a [`crate::SolverTask`].
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ChannelPreferences {
+    restrictions: HashMap<PackageName, ChannelRef>,
+    order: Vec<String>,
+}
+
+impl ChannelPreferences {
+    /// Resolves the channel restrictions and channel order of a
+    /// [`crate::SolverTask`].
+    pub fn new(restrictions: &HashMap<PackageName, Channel>, order: &[Channel]) -> Self {
+        Self {
+            restrictions: restrictions
+                .iter()
+                .map(|(name, channel)| (name.clone(), ChannelRef::from(channel)))
+                .collect(),
+            order: order.iter().map(Channel::canonical_name).collect(),
+        }
+    }
+
+    /// Returns true if a channel order was specified.
+    pub fn has_channel_order(&self) -> bool {
+        !self.order.is_empty()
+    }
+
+    /// Returns the rank of a channel, lower ranks have a higher priority.
+    /// Channels that are not part of the channel order all share the lowest
+    /// priority.
+    pub fn rank(&self, channel: Option<&str>) -> usize {
+        channel
+            .and_then(|channel| self.order.iter().position(|c| c == channel))
+            .unwrap_or(self.order.len())
+    }
+
+    /// Returns the channel the package of the record is restricted to if the
+    /// record originates from a different channel.
+    pub fn violated_restriction(&self, record: &RepoDataRecord) -> Option<&ChannelRef> {
+        let channel = self.restrictions.get(&record.package_record.name)?;
+        (record.channel.as_deref() != Some(channel.canonical_name.as_str())).then_some(channel)
+    }
+}
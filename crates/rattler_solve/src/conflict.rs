@@ -0,0 +1,319 @@
+//! Structured, machine-readable descriptions of why a [`crate::SolverTask`]
+//! could not be solved.
+//!
+//! Both solver backends translate their internal explanation of an unsolvable
+//! problem into a [`ConflictGraph`]. The graph is available through
+//! [`crate::UnsolvableProblem::conflict_graph`] and can be serialized (with
+//! the `serde` feature) to present the problem in other tools.
+
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use rattler_conda_types::{
+    GenericVirtualPackage, MatchSpec, PackageName, ParseStrictness, RepoDataRecord,
+};
+
+/// A graph that explains why a set of requested specs cannot be solved.
+///
+/// The first node is always [`ConflictNode::Root`] which represents the
+/// requested specs. Edges point from a package (or the root) to the
+/// candidates, unresolved dependencies or exclusion reasons involved in the
+/// conflict.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConflictGraph {
+    /// The specs that were requested.
+    pub requested_specs: Vec<String>,
+
+    /// The nodes of the graph, edges refer to the index in this list.
+    pub nodes: Vec<ConflictNode>,
+
+    /// The edges between the nodes.
+    pub edges: Vec<ConflictEdge>,
+}
+
+/// A node in a [`ConflictGraph`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum ConflictNode {
+    /// The requested specs.
+    Root,
+
+    /// A candidate package that is involved in the conflict.
+    Candidate(ConflictCandidate),
+
+    /// Sink for all dependencies for which no candidates are available.
+    Unresolved,
+
+    /// A reason why candidates were excluded from the solve.
+    Excluded {
+        /// Why the candidates were excluded.
+        reason: ExclusionReason,
+    },
+}
+
+/// Identifies a package that is involved in a conflict.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConflictCandidate {
+    /// The name of the package. For extras this is `name[extra]`.
+    pub name: String,
+
+    /// The version of the package, if it has one.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub version: Option<String>,
+
+    /// The build string of the package, if it has one.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub build: Option<String>,
+
+    /// The channel the package originates from.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub channel: Option<String>,
+
+    /// True if this is a virtual package.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub is_virtual: bool,
+}
+
+/// An edge in a [`ConflictGraph`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConflictEdge {
+    /// The index of the source node.
+    pub from: usize,
+
+    /// The index of the target node.
+    pub to: usize,
+
+    /// The relation between the two nodes.
+    pub kind: ConflictEdgeKind,
+}
+
+/// Describes the relation between two nodes in a [`ConflictGraph`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum ConflictEdgeKind {
+    /// The source requires `spec` and the target is a candidate for it (or
+    /// [`ConflictNode::Unresolved`] if there are no candidates).
+    Requires {
+        /// The requirement.
+        spec: String,
+    },
+
+    /// The source constrains `spec` which the target does not satisfy.
+    Constrains {
+        /// The constraint.
+        spec: String,
+    },
+
+    /// The source cannot be installed together with the target.
+    Conflicts,
+
+    /// The source and the target are different variants of the same package
+    /// and only one of them can be installed.
+    ForbidMultipleInstances,
+
+    /// The target conflicts with a locked (or pinned) package.
+    Locked,
+
+    /// The source was excluded for the reason in the target node.
+    Excluded,
+}
+
+/// The reason why a candidate was excluded from a solve.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", rename_all = "snake_case"))]
+pub enum ExclusionReason {
+    /// The package was uploaded after the `exclude_newer` cutoff.
+    ExcludeNewer {
+        /// The cutoff date.
+        cutoff: DateTime<Utc>,
+    },
+
+    /// The package is also available from a channel with a higher priority.
+    StrictChannelPriority {
+        /// The channel that the excluded package originates from.
+        channel: Option<String>,
+    },
+
+    /// The spec requested the package from a different channel.
+    NotInRequestedChannel {
+        /// The channel that was requested.
+        channel: String,
+    },
+
+    /// Any other reason, described by a message.
+    Other {
+        /// A human readable description of the reason.
+        message: String,
+    },
+}
+
+impl fmt::Display for ExclusionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExclusionReason::ExcludeNewer { cutoff } => write!(
+                f,
+                "the package is uploaded after the cutoff date of {cutoff}"
+            ),
+            ExclusionReason::StrictChannelPriority {
+                channel: Some(channel),
+            } => write!(
+                f,
+                "due to strict channel priority not using this option from: '{channel}'"
+            ),
+            ExclusionReason::StrictChannelPriority { channel: None } => write!(
+                f,
+                "due to strict channel priority not using from an unknown channel"
+            ),
+            ExclusionReason::NotInRequestedChannel { channel } => {
+                write!(f, "candidate not in requested channel: '{channel}'")
+            }
+            ExclusionReason::Other { message } => write!(f, "{message}"),
+        }
+    }
+}
+
+impl From<&RepoDataRecord> for ConflictCandidate {
+    fn from(record: &RepoDataRecord) -> Self {
+        Self {
+            name: record.package_record.name.as_normalized().to_string(),
+            version: Some(record.package_record.version.to_string()),
+            build: Some(record.package_record.build.clone()),
+            channel: record.channel.clone(),
+            is_virtual: false,
+        }
+    }
+}
+
+impl From<&GenericVirtualPackage> for ConflictCandidate {
+    fn from(package: &GenericVirtualPackage) -> Self {
+        Self {
+            name: package.name.as_normalized().to_string(),
+            version: Some(package.version.to_string()),
+            build: Some(package.build_string.clone()),
+            channel: None,
+            is_virtual: true,
+        }
+    }
+}
+
+impl ConflictGraph {
+    /// The index of the [`ConflictNode::Root`] node.
+    pub const ROOT: usize = 0;
+
+    /// Constructs a graph that only contains the root node.
+    #[cfg(feature = "libsolv_c")]
+    pub(crate) fn new(requested_specs: Vec<String>) -> Self {
+        Self {
+            requested_specs,
+            nodes: vec![ConflictNode::Root],
+            edges: Vec::new(),
+        }
+    }
+
+    /// Adds a node and returns its index.
+    #[cfg(feature = "libsolv_c")]
+    pub(crate) fn add_node(&mut self, node: ConflictNode) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    /// Adds an edge between two nodes, unless an identical edge already
+    /// exists.
+    #[cfg(feature = "libsolv_c")]
+    pub(crate) fn add_edge(&mut self, from: usize, to: usize, kind: ConflictEdgeKind) {
+        let edge = ConflictEdge { from, to, kind };
+        if !self.edges.contains(&edge) {
+            self.edges.push(edge);
+        }
+    }
+
+    /// Returns the candidates that were excluded from the solve together with
+    /// the reason why.
+    pub fn excluded_candidates(&self) -> Vec<(&ConflictCandidate, &ExclusionReason)> {
+        self.edges
+            .iter()
+            .filter(|edge| edge.kind == ConflictEdgeKind::Excluded)
+            .filter_map(
+                |edge| match (&self.nodes[edge.from], &self.nodes[edge.to]) {
+                    (ConflictNode::Candidate(candidate), ConflictNode::Excluded { reason }) => {
+                        Some((candidate, reason))
+                    }
+                    _ => None,
+                },
+            )
+            .collect()
+    }
+
+    /// Returns the requirements for which no candidates are available at all.
+    pub fn unresolved_requirements(&self) -> Vec<&str> {
+        let mut specs: Vec<&str> = self
+            .edges
+            .iter()
+            .filter(|edge| self.nodes[edge.to] == ConflictNode::Unresolved)
+            .filter_map(|edge| match &edge.kind {
+                ConflictEdgeKind::Requires { spec } => Some(spec.as_str()),
+                _ => None,
+            })
+            .collect();
+        specs.sort_unstable();
+        specs.dedup();
+        specs
+    }
+
+    /// Returns the names of the virtual packages that are required but not
+    /// available.
+    pub fn missing_virtual_packages(&self) -> Vec<PackageName> {
+        let mut names: Vec<PackageName> = self
+            .unresolved_requirements()
+            .into_iter()
+            .filter_map(|spec| {
+                MatchSpec::from_str(spec, ParseStrictness::Lenient)
+                    .ok()?
+                    .name
+            })
+            .filter(|name| name.as_normalized().starts_with("__"))
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Returns the constraints that were violated as `(source, spec, target)`
+    /// tuples, where `source` constrains `spec` which `target` does not
+    /// satisfy. A `source` of `None` refers to a requested constraint.
+    pub fn constraint_violations(
+        &self,
+    ) -> Vec<(Option<&ConflictCandidate>, &str, &ConflictCandidate)> {
+        self.edges
+            .iter()
+            .filter_map(|edge| {
+                let ConflictEdgeKind::Constrains { spec } = &edge.kind else {
+                    return None;
+                };
+                let ConflictNode::Candidate(target) = &self.nodes[edge.to] else {
+                    return None;
+                };
+                let source = match &self.nodes[edge.from] {
+                    ConflictNode::Candidate(source) => Some(source),
+                    _ => None,
+                };
+                Some((source, spec.as_str(), target))
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,82 @@
+//! Defines [`ExcludeNewerOverrides`].
+
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use rattler_conda_types::{PackageName, RepoDataRecord};
+
+/// Exceptions to [`crate::SolverTask::exclude_newer`] for specific packages or
+/// channels.
+///
+/// Each override either replaces the cutoff with a different timestamp or,
+/// when the value is `None`, disables the cutoff altogether. Package overrides
+/// take precedence over channel overrides, which in turn take precedence over
+/// [`crate::SolverTask::exclude_newer`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExcludeNewerOverrides {
+    /// Cutoffs for individual packages.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub packages: BTreeMap<PackageName, Option<DateTime<Utc>>>,
+
+    /// Cutoffs for all packages from a channel. Channels are identified by
+    /// their canonical name (see
+    /// [`rattler_conda_types::Channel::canonical_name`]) which is also stored
+    /// in [`RepoDataRecord::channel`].
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "BTreeMap::is_empty")
+    )]
+    pub channels: BTreeMap<String, Option<DateTime<Utc>>>,
+}
+
+impl ExcludeNewerOverrides {
+    /// Returns true if there are no overrides.
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty() && self.channels.is_empty()
+    }
+
+    /// Returns true if any of the overrides excludes packages.
+    pub fn has_cutoff(&self) -> bool {
+        self.packages
+            .values()
+            .chain(self.channels.values())
+            .any(Option::is_some)
+    }
+
+    /// Returns the cutoff that applies to the given record, given that
+    /// `exclude_newer` applies to all records without an override.
+    pub fn cutoff(
+        &self,
+        exclude_newer: Option<DateTime<Utc>>,
+        record: &RepoDataRecord,
+    ) -> Option<DateTime<Utc>> {
+        if let Some(cutoff) = self.packages.get(&record.package_record.name) {
+            return *cutoff;
+        }
+        if let Some(cutoff) = record
+            .channel
+            .as_ref()
+            .and_then(|channel| self.channels.get(channel))
+        {
+            return *cutoff;
+        }
+        exclude_newer
+    }
+
+    /// Returns the cutoff because of which the record is excluded, or `None`
+    /// if the record is not excluded.
+    #[cfg(any(feature = "libsolv_c", feature = "resolvo"))]
+    pub(crate) fn excluded_by(
+        &self,
+        exclude_newer: Option<DateTime<Utc>>,
+        record: &RepoDataRecord,
+    ) -> Option<DateTime<Utc>> {
+        let cutoff = self.cutoff(exclude_newer, record)?;
+        let timestamp = record.package_record.timestamp.as_ref()?;
+        (timestamp > &cutoff).then_some(cutoff)
+    }
+}
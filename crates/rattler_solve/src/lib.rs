@@ -4,15 +4,26 @@
 
 #![deny(missing_docs)]
 
+#[cfg(any(feature = "libsolv_c", feature = "resolvo"))]
+mod channel_preferences;
+pub mod conflict;
+mod exclude_newer;
 #[cfg(feature = "libsolv_c")]
 pub mod libsolv_c;
+mod relaxation;
 #[cfg(feature = "resolvo")]
 pub mod resolvo;
 
-use std::fmt;
+use std::{collections::HashMap, fmt, ops::Deref};
 
 use chrono::{DateTime, Utc};
-use rattler_conda_types::{GenericVirtualPackage, MatchSpec, RepoDataRecord, SolverResult};
+use conflict::ConflictGraph;
+pub use exclude_newer::ExcludeNewerOverrides;
+use itertools::Itertools;
+use rattler_conda_types::{
+    Channel, GenericVirtualPackage, MatchSpec, PackageName, RepoDataRecord, SolverResult,
+};
+pub use relaxation::Relaxation;
 
 /// Represents a solver implementation, capable of solving [`SolverTask`]s
 pub trait SolverImpl {
@@ -35,7 +46,7 @@ pub trait SolverImpl {
 #[derive(thiserror::Error, Debug)]
 pub enum SolveError {
     /// There is no set of dependencies that satisfies the requirements
-    Unsolvable(Vec<String>),
+    Unsolvable(UnsolvableProblem),
 
     /// The solver backend returned operations that we dont know how to install.
     /// Each string is a somewhat user-friendly representation of which
@@ -61,7 +72,15 @@ impl fmt::Display for SolveError {
                     f,
                     "Cannot solve the request because of: {}",
                     operations.join(", ")
-                )
+                )?;
+                if !operations.relaxations().is_empty() {
+                    write!(
+                        f,
+                        "\nThe request can be solved if you: {}",
+                        operations.relaxations().iter().format(", or ")
+                    )?;
+                }
+                Ok(())
             }
             SolveError::UnsupportedOperations(operations) => {
                 write!(f, "Unsupported operations: {}", operations.join(", "))
@@ -79,6 +98,93 @@ impl fmt::Display for SolveError {
     }
 }
 
+/// Describes why there is no set of dependencies that satisfies the
+/// requirements of a [`SolverTask`].
+///
+/// Dereferences to a list of user-friendly messages that explain the problem.
+/// If the solver backend was able to provide one, a structured
+/// [`ConflictGraph`] is available through [`Self::conflict_graph`]. When
+/// [`SolverTask::suggest_relaxations`] is enabled, [`Self::relaxations`]
+/// contains the relaxations that make the task solvable.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct UnsolvableProblem {
+    messages: Vec<String>,
+    conflict_graph: Option<ConflictGraph>,
+    relaxations: Vec<Relaxation>,
+}
+
+impl UnsolvableProblem {
+    /// Constructs a new instance from a list of messages.
+    pub fn new(messages: Vec<String>) -> Self {
+        Self {
+            messages,
+            conflict_graph: None,
+            relaxations: Vec::new(),
+        }
+    }
+
+    /// Attaches a structured description of the conflict.
+    #[must_use]
+    pub fn with_conflict_graph(self, conflict_graph: ConflictGraph) -> Self {
+        Self {
+            conflict_graph: Some(conflict_graph),
+            ..self
+        }
+    }
+
+    /// Attaches the relaxations that make the task solvable.
+    #[must_use]
+    pub fn with_relaxations(self, relaxations: Vec<Relaxation>) -> Self {
+        Self {
+            relaxations,
+            ..self
+        }
+    }
+
+    /// Returns the user-friendly messages that explain the problem.
+    pub fn messages(&self) -> &[String] {
+        &self.messages
+    }
+
+    /// Returns the structured description of the conflict, if available.
+    pub fn conflict_graph(&self) -> Option<&ConflictGraph> {
+        self.conflict_graph.as_ref()
+    }
+
+    /// Returns the relaxations of the task that each make it solvable. This is
+    /// only populated if [`SolverTask::suggest_relaxations`] is enabled.
+    pub fn relaxations(&self) -> &[Relaxation] {
+        &self.relaxations
+    }
+
+    /// Consumes this instance and returns the messages.
+    pub fn into_messages(self) -> Vec<String> {
+        self.messages
+    }
+}
+
+impl From<Vec<String>> for UnsolvableProblem {
+    fn from(messages: Vec<String>) -> Self {
+        Self::new(messages)
+    }
+}
+
+impl Deref for UnsolvableProblem {
+    type Target = [String];
+
+    fn deref(&self) -> &Self::Target {
+        &self.messages
+    }
+}
+
+/// Only the messages are included in the debug representation to keep the
+/// output of [`SolveError`] readable.
+impl fmt::Debug for UnsolvableProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.messages, f)
+    }
+}
+
 /// Represents the channel priority option to use during solves.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
@@ -142,12 +248,37 @@ pub struct SolverTask<TAvailablePackagesIterator> {
     /// or [`ChannelPriority::Disabled`]
     pub channel_priority: ChannelPriority,
 
+    /// Restricts packages to a single channel. Records of a package that is
+    /// listed here are only considered if they originate from the given
+    /// channel, regardless of whether the package is requested directly or
+    /// required by another package.
+    pub channel_restrictions: HashMap<PackageName, Channel>,
+
+    /// Overrides the priority of channels. Channels are normally prioritized
+    /// in the order in which they appear in `available_packages`. Channels
+    /// in this list take precedence over all other channels, in the order in
+    /// which they are listed.
+    pub channel_order: Vec<Channel>,
+
     /// Exclude any package that has a timestamp newer than the specified
     /// timestamp.
     pub exclude_newer: Option<DateTime<Utc>>,
 
+    /// Per-package and per-channel exceptions to `exclude_newer`.
+    pub exclude_newer_overrides: ExcludeNewerOverrides,
+
     /// The solve strategy.
     pub strategy: SolveStrategy,
+
+    /// If the task turns out to be unsolvable, try to solve relaxed variants
+    /// of it and report the ones that are solvable through
+    /// [`UnsolvableProblem::relaxations`].
+    ///
+    /// Only single relaxations (see [`Relaxation`]) are tried, not
+    /// combinations of them. Each relaxation requires an additional solve, so
+    /// this is disabled by default. The [`Self::timeout`] covers all of these
+    /// solves together.
+    pub suggest_relaxations: bool,
 }
 
 impl<'r, I: IntoIterator<Item = &'r RepoDataRecord>> FromIterator<I>
@@ -163,8 +294,12 @@ impl<'r, I: IntoIterator<Item = &'r RepoDataRecord>> FromIterator<I>
             constraints: Vec::new(),
             timeout: None,
             channel_priority: ChannelPriority::default(),
+            channel_restrictions: HashMap::new(),
+            channel_order: Vec::new(),
             exclude_newer: None,
+            exclude_newer_overrides: ExcludeNewerOverrides::default(),
             strategy: SolveStrategy::default(),
+            suggest_relaxations: false,
         }
     }
 }
@@ -3,7 +3,6 @@
 
 use std::{cmp::Ordering, collections::HashMap};
 
-use chrono::{DateTime, Utc};
 use rattler_conda_types::{package::ArchiveType, GenericVirtualPackage, RepoDataRecord};
 
 use super::{
@@ -47,14 +46,15 @@ pub fn add_solv_file(pool: &Pool, repo: &Repo<'_>, solv_bytes: &LibcByteSlice) {
     unsafe { libc::fclose(file) };
 }
 
-/// Adds [`RepoDataRecord`] to `repo`
+/// Adds [`RepoDataRecord`] to `repo`, skipping the records for which
+/// `is_excluded` returns true
 ///
 /// Panics if the repo does not belong to the pool
 pub fn add_repodata_records<'a>(
     pool: &Pool,
     repo: &Repo<'_>,
     repo_data: impl IntoIterator<Item = &'a RepoDataRecord>,
-    exclude_newer: Option<&DateTime<Utc>>,
+    is_excluded: impl Fn(&RepoDataRecord) -> bool,
 ) -> Result<Vec<SolvableId>, SolveError> {
     // Sanity check
     repo.ensure_belongs_to_pool(pool);
@@ -85,10 +85,9 @@ pub fn add_repodata_records<'a>(
 
     let mut solvable_ids = Vec::new();
     for (repo_data_index, repo_data) in repo_data.into_iter().enumerate() {
-        // Skip packages that are newer than the specified timestamp
-        match (exclude_newer, repo_data.package_record.timestamp.as_ref()) {
-            (Some(exclude_newer), Some(timestamp)) if *timestamp > *exclude_newer => continue,
-            _ => {}
+        // Skip packages that should not be considered by the solver
+        if is_excluded(repo_data) {
+            continue;
         }
 
         // Create a solvable for the package
@@ -260,8 +259,13 @@ fn add_or_reuse_solvable<'a>(
     Ok(Some(repo.add_solvable()))
 }
 
-pub fn add_virtual_packages(pool: &Pool, repo: &Repo<'_>, packages: &[GenericVirtualPackage]) {
+pub fn add_virtual_packages(
+    pool: &Pool,
+    repo: &Repo<'_>,
+    packages: &[GenericVirtualPackage],
+) -> Vec<SolvableId> {
     let data = repo.add_repodata();
+    let mut solvable_ids = Vec::with_capacity(packages.len());
 
     let solvable_buildflavor_id = pool.find_interned_str(SOLVABLE_BUILDFLAVOR).unwrap();
 
@@ -285,7 +289,11 @@ pub fn add_virtual_packages(pool: &Pool, repo: &Repo<'_>, packages: &[GenericVir
             solvable_buildflavor_id,
             &c_string(&package.build_string),
         );
+
+        solvable_ids.push(solvable_id);
     }
+
+    solvable_ids
 }
 
 fn reset_solvable(pool: &Pool, repo: &Repo<'_>, data: &Repodata<'_>, solvable_id: SolvableId) {
@@ -324,7 +332,7 @@ pub fn cache_repodata(
     // Add repodata to a new pool + repo
     let pool = Pool::default();
     let repo = Repo::new(&pool, url, channel_priority.unwrap_or(0));
-    add_repodata_records(&pool, &repo, data, None)?;
+    add_repodata_records(&pool, &repo, data, |_| false)?;
 
     // Export repo to .solv in memory
     let mut stream_ptr = std::ptr::null_mut();
@@ -9,7 +9,7 @@ use std::{
 pub use input::cache_repodata;
 use input::{add_repodata_records, add_solv_file, add_virtual_packages};
 pub use libc_byte_slice::LibcByteSlice;
-use output::get_required_packages;
+use output::{get_conflict_graph, get_required_packages};
 use rattler_conda_types::{MatchSpec, NamelessMatchSpec, RepoDataRecord, SolverResult};
 use wrapper::{
     flags::SolverFlag,
@@ -18,7 +18,10 @@ use wrapper::{
     solve_goal::SolveGoal,
 };
 
-use crate::{ChannelPriority, IntoRepoData, SolveError, SolveStrategy, SolverRepoData, SolverTask};
+use crate::{
+    channel_preferences::ChannelPreferences, ChannelPriority, IntoRepoData, SolveError,
+    SolveStrategy, SolverRepoData, SolverTask, UnsolvableProblem,
+};
 
 mod input;
 mod libc_byte_slice;
@@ -95,6 +98,10 @@ impl super::SolverImpl for Solver {
         &mut self,
         task: SolverTask<TAvailablePackagesIterator>,
     ) -> Result<SolverResult, SolveError> {
+        if task.suggest_relaxations {
+            return crate::relaxation::solve_with_relaxations(self, task);
+        }
+
         if task.timeout.is_some() {
             return Err(SolveError::UnsupportedOperations(vec![
                 "timeout".to_string()
@@ -122,12 +129,27 @@ impl super::SolverImpl for Solver {
         });
         pool.set_debug_level(Verbosity::Low);
 
-        let repodatas: Vec<Self::RepoData<'_>> = task
+        let mut repodatas: Vec<Self::RepoData<'_>> = task
             .available_packages
             .into_iter()
             .map(IntoRepoData::into)
             .collect();
 
+        // Reorder the repodatas according to the channel order, the sort is stable
+        // so channels that are not part of the channel order keep their order.
+        let channel_preferences =
+            ChannelPreferences::new(&task.channel_restrictions, &task.channel_order);
+        if channel_preferences.has_channel_order() {
+            repodatas.sort_by_cached_key(|repodata| {
+                channel_preferences.rank(
+                    repodata
+                        .records
+                        .first()
+                        .and_then(|record| record.channel.as_deref()),
+                )
+            });
+        }
+
         // Determine the channel priority for each channel in the repodata in the order
         // in which the repodatas are passed, where the first channel will have
         // the highest priority value and each successive channel will descend
@@ -163,7 +185,7 @@ impl super::SolverImpl for Solver {
 
         // Add virtual packages
         let repo = Repo::new(&pool, "virtual_packages", highest_priority);
-        add_virtual_packages(&pool, &repo, &task.virtual_packages);
+        let virtual_solvables = add_virtual_packages(&pool, &repo, &task.virtual_packages);
 
         // Mark the virtual packages as installed.
         pool.set_installed(&repo);
@@ -189,15 +211,28 @@ impl super::SolverImpl for Solver {
                 priority,
             ));
 
-            if let Some(solv_file) = repodata.solv_file {
-                add_solv_file(&pool, &repo, solv_file);
-            } else {
-                add_repodata_records(
-                    &pool,
-                    &repo,
-                    repodata.records.iter().copied(),
-                    task.exclude_newer.as_ref(),
-                )?;
+            let is_excluded = |record: &RepoDataRecord| {
+                // Skip packages that are newer than the specified timestamp
+                let is_newer = task
+                    .exclude_newer_overrides
+                    .excluded_by(task.exclude_newer, record)
+                    .is_some();
+                is_newer || channel_preferences.violated_restriction(record).is_some()
+            };
+
+            // The cached .solv file contains all records, so it can only be used if
+            // none of them are excluded.
+            let has_excluded = repodata.records.iter().any(|record| is_excluded(record));
+            match repodata.solv_file {
+                Some(solv_file) if !has_excluded => add_solv_file(&pool, &repo, solv_file),
+                _ => {
+                    add_repodata_records(
+                        &pool,
+                        &repo,
+                        repodata.records.iter().copied(),
+                        is_excluded,
+                    )?;
+                }
             }
 
             // Keep our own info about repodata_records
@@ -207,7 +242,8 @@ impl super::SolverImpl for Solver {
 
         // Create a special pool for records that are already installed or locked.
         let repo = Repo::new(&pool, "locked", highest_priority);
-        let installed_solvables = add_repodata_records(&pool, &repo, &task.locked_packages, None)?;
+        let installed_solvables =
+            add_repodata_records(&pool, &repo, &task.locked_packages, |_| false)?;
 
         // Also add the installed records to the repodata
         repo_mapping.insert(repo.id(), repo_mapping.len());
@@ -215,7 +251,8 @@ impl super::SolverImpl for Solver {
 
         // Create a special pool for records that are pinned and cannot be changed.
         let repo = Repo::new(&pool, "pinned", highest_priority);
-        let pinned_solvables = add_repodata_records(&pool, &repo, &task.pinned_packages, None)?;
+        let pinned_solvables =
+            add_repodata_records(&pool, &repo, &task.pinned_packages, |_| false)?;
 
         // Also add the installed records to the repodata
         repo_mapping.insert(repo.id(), repo_mapping.len());
@@ -238,6 +275,7 @@ impl super::SolverImpl for Solver {
         }
 
         // Specify the matchspec requests
+        let requested_specs = task.specs.iter().map(ToString::to_string).collect();
         for spec in task.specs {
             let id = pool.intern_matchspec(&spec);
             goal.install(id, false);
@@ -251,10 +289,10 @@ impl super::SolverImpl for Solver {
         // Add virtual packages to the queue. We want to install these as part of the
         // solution as well. This ensures that if a package only has a constraint on a
         // virtual package, the virtual package is installed.
-        for virtual_package in task.virtual_packages {
+        for virtual_package in &task.virtual_packages {
             let id = pool.intern_matchspec(&MatchSpec::from_nameless(
                 NamelessMatchSpec::default(),
-                Some(virtual_package.name),
+                Some(virtual_package.name.clone()),
             ));
             goal.install(id, false);
         }
@@ -268,7 +306,28 @@ impl super::SolverImpl for Solver {
             task.channel_priority == ChannelPriority::Strict,
         );
 
-        let transaction = solver.solve(&mut goal).map_err(SolveError::Unsolvable)?;
+        let transaction = match solver.solve(&mut goal) {
+            Ok(transaction) => transaction,
+            Err((messages, problems)) => {
+                let virtual_packages = virtual_solvables
+                    .into_iter()
+                    .zip(&task.virtual_packages)
+                    .collect::<Vec<_>>();
+                let conflict_graph = get_conflict_graph(
+                    &pool,
+                    &repo_mapping,
+                    &problems,
+                    &all_repodata_records,
+                    &virtual_packages,
+                    task.exclude_newer,
+                    &task.exclude_newer_overrides,
+                    requested_specs,
+                );
+                return Err(SolveError::Unsolvable(
+                    UnsolvableProblem::new(messages).with_conflict_graph(conflict_graph),
+                ));
+            }
+        };
 
         let required_records = get_required_packages(
             &pool,
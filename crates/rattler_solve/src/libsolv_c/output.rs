@@ -5,10 +5,18 @@ use super::{
     wrapper::pool::{Pool, StringId},
     wrapper::repo::RepoId,
     wrapper::solvable::SolvableId,
+    wrapper::solve_problem::SolveProblem,
     wrapper::transaction::Transaction,
     wrapper::{ffi, solvable},
 };
-use rattler_conda_types::RepoDataRecord;
+use crate::{
+    conflict::{ConflictCandidate, ConflictEdgeKind, ConflictGraph, ConflictNode, ExclusionReason},
+    ExcludeNewerOverrides,
+};
+use chrono::{DateTime, Utc};
+use rattler_conda_types::{
+    GenericVirtualPackage, MatchSpec, Matches, ParseStrictness, RepoDataRecord,
+};
 use std::collections::HashMap;
 
 /// Returns which packages should be installed in the environment
@@ -75,3 +83,206 @@ fn get_solvable_indexes(
 
     Some((repo_index, solvable_index))
 }
+
+/// Converts the rules involved in the problems that libsolv encountered into a
+/// [`ConflictGraph`].
+///
+/// libsolv does not know about records that were filtered out because of
+/// `exclude_newer` or its overrides. If a dependency cannot be resolved, matching records that
+/// were excluded are added to the graph instead.
+#[allow(clippy::too_many_arguments)]
+pub fn get_conflict_graph(
+    pool: &Pool,
+    repo_mapping: &HashMap<RepoId, usize>,
+    problems: &[SolveProblem],
+    repodata_records: &[Vec<&RepoDataRecord>],
+    virtual_packages: &[(SolvableId, &GenericVirtualPackage)],
+    exclude_newer: Option<DateTime<Utc>>,
+    exclude_newer_overrides: &ExcludeNewerOverrides,
+    requested_specs: Vec<String>,
+) -> ConflictGraph {
+    let solvable_index_id = pool
+        .find_interned_str("solvable:repodata_record_index")
+        .unwrap();
+
+    let mut graph = ConflictGraph::new(requested_specs);
+    let mut candidate_nodes = HashMap::<ffi::Id, usize>::new();
+    let mut candidate_node = |graph: &mut ConflictGraph, id: SolvableId| {
+        *candidate_nodes.entry(id.into()).or_insert_with(|| {
+            let candidate = match get_solvable_indexes(pool, repo_mapping, solvable_index_id, id) {
+                Some((repo_index, solvable_index)) => {
+                    ConflictCandidate::from(repodata_records[repo_index][solvable_index])
+                }
+                None => virtual_packages
+                    .iter()
+                    .find(|(virtual_id, _)| ffi::Id::from(*virtual_id) == ffi::Id::from(id))
+                    .map_or_else(
+                        || ConflictCandidate {
+                            name: format!("solvable {}", ffi::Id::from(id)),
+                            version: None,
+                            build: None,
+                            channel: None,
+                            is_virtual: false,
+                        },
+                        |(_, package)| ConflictCandidate::from(*package),
+                    ),
+            };
+            graph.add_node(ConflictNode::Candidate(candidate))
+        })
+    };
+
+    let mut excluded_nodes = HashMap::<String, usize>::new();
+    let mut excluded_node = |graph: &mut ConflictGraph, reason: ExclusionReason| {
+        *excluded_nodes
+            .entry(reason.to_string())
+            .or_insert_with(|| graph.add_node(ConflictNode::Excluded { reason }))
+    };
+
+    // Requirements for which libsolv does not tell us the candidates, these are
+    // connected to the candidates of the same name once all nodes are known.
+    let mut requirements = Vec::new();
+
+    // Requirements for which libsolv could not find any candidate.
+    let mut unresolved = Vec::new();
+
+    for problem in problems {
+        match problem {
+            SolveProblem::Job { dep } | SolveProblem::Pkg { dep } => {
+                requirements.push((ConflictGraph::ROOT, dep));
+            }
+            SolveProblem::PkgRequires { source, dep } => {
+                let source = candidate_node(&mut graph, *source);
+                requirements.push((source, dep));
+            }
+            SolveProblem::JobNothingProvidesDep { dep }
+            | SolveProblem::JobUnknownPackage { dep } => {
+                unresolved.push((ConflictGraph::ROOT, dep));
+            }
+            SolveProblem::PkgNothingProvidesDep { source, dep } => {
+                let source = candidate_node(&mut graph, *source);
+                unresolved.push((source, dep));
+            }
+            SolveProblem::PkgConflicts { source, target } => {
+                let source = candidate_node(&mut graph, *source);
+                let target = candidate_node(&mut graph, *target);
+                graph.add_edge(source, target, ConflictEdgeKind::Conflicts);
+            }
+            SolveProblem::PkgSameName { source, target } => {
+                let source = candidate_node(&mut graph, *source);
+                let target = candidate_node(&mut graph, *target);
+                graph.add_edge(source, target, ConflictEdgeKind::ForbidMultipleInstances);
+            }
+            SolveProblem::PkgConstrains {
+                source,
+                target,
+                dep,
+            } => {
+                let source = candidate_node(&mut graph, *source);
+                let target = candidate_node(&mut graph, *target);
+                graph.add_edge(
+                    source,
+                    target,
+                    ConflictEdgeKind::Constrains { spec: dep.clone() },
+                );
+            }
+            SolveProblem::StrictRepoPriority { source } => {
+                let source = candidate_node(&mut graph, *source);
+                let channel = match &graph.nodes[source] {
+                    ConflictNode::Candidate(candidate) => candidate.channel.clone(),
+                    _ => None,
+                };
+                let reason = excluded_node(
+                    &mut graph,
+                    ExclusionReason::StrictChannelPriority { channel },
+                );
+                graph.add_edge(source, reason, ConflictEdgeKind::Excluded);
+            }
+            SolveProblem::PkgNotInstallable { source } => {
+                let source = candidate_node(&mut graph, *source);
+                let reason = excluded_node(
+                    &mut graph,
+                    ExclusionReason::Other {
+                        message: "the package is not installable".to_string(),
+                    },
+                );
+                graph.add_edge(source, reason, ConflictEdgeKind::Excluded);
+            }
+            SolveProblem::Update | SolveProblem::Other(_) => {}
+        }
+    }
+
+    let mut unresolved_node = None;
+    let mut excluded_record_nodes = HashMap::<&str, usize>::new();
+    for (source, dep) in unresolved {
+        let excluded_records: Vec<(&RepoDataRecord, DateTime<Utc>)> =
+            match MatchSpec::from_str(dep, ParseStrictness::Lenient) {
+                Ok(spec) => repodata_records
+                    .iter()
+                    .flatten()
+                    .filter(|record| spec.matches(&record.package_record))
+                    .filter_map(|record| {
+                        let cutoff = exclude_newer_overrides.excluded_by(exclude_newer, record)?;
+                        Some((*record, cutoff))
+                    })
+                    .collect(),
+                Err(_) => Vec::new(),
+            };
+
+        if excluded_records.is_empty() {
+            let unresolved_node =
+                *unresolved_node.get_or_insert_with(|| graph.add_node(ConflictNode::Unresolved));
+            graph.add_edge(
+                source,
+                unresolved_node,
+                ConflictEdgeKind::Requires { spec: dep.clone() },
+            );
+            continue;
+        }
+
+        for (record, cutoff) in excluded_records {
+            let node = *excluded_record_nodes
+                .entry(record.url.as_str())
+                .or_insert_with(|| {
+                    let reason =
+                        excluded_node(&mut graph, ExclusionReason::ExcludeNewer { cutoff });
+                    let node =
+                        graph.add_node(ConflictNode::Candidate(ConflictCandidate::from(record)));
+                    graph.add_edge(node, reason, ConflictEdgeKind::Excluded);
+                    node
+                });
+            graph.add_edge(
+                source,
+                node,
+                ConflictEdgeKind::Requires { spec: dep.clone() },
+            );
+        }
+    }
+
+    for (source, dep) in requirements {
+        let Some(name) = MatchSpec::from_str(dep, ParseStrictness::Lenient)
+            .ok()
+            .and_then(|spec| spec.name)
+        else {
+            continue;
+        };
+        let candidates = graph
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|(index, node)| {
+                *index != source
+                    && matches!(node, ConflictNode::Candidate(candidate) if candidate.name == name.as_normalized())
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+        for candidate in candidates {
+            graph.add_edge(
+                source,
+                candidate,
+                ConflictEdgeKind::Requires { spec: dep.clone() },
+            );
+        }
+    }
+
+    graph
+}
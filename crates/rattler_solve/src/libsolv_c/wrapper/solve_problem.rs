@@ -9,8 +9,10 @@ use ffi::{
     SolverRuleinfo_SOLVER_RULE_PKG_CONFLICTS as SOLVER_RULE_SOLVER_RULE_PKG_CONFLICTS,
     SolverRuleinfo_SOLVER_RULE_PKG_CONSTRAINS as SOLVER_RULE_PKG_CONSTRAINS,
     SolverRuleinfo_SOLVER_RULE_PKG_NOTHING_PROVIDES_DEP as SOLVER_RULE_SOLVER_RULE_PKG_NOTHING_PROVIDES_DEP,
+    SolverRuleinfo_SOLVER_RULE_PKG_NOT_INSTALLABLE as SOLVER_RULE_PKG_NOT_INSTALLABLE,
     SolverRuleinfo_SOLVER_RULE_PKG_REQUIRES as SOLVER_RULE_PKG_REQUIRES,
     SolverRuleinfo_SOLVER_RULE_PKG_SAME_NAME as SOLVER_RULE_SOLVER_RULE_PKG_SAME_NAME,
+    SolverRuleinfo_SOLVER_RULE_STRICT_REPO_PRIORITY as SOLVER_RULE_STRICT_REPO_PRIORITY,
     SolverRuleinfo_SOLVER_RULE_UPDATE as SOLVER_RULE_SOLVER_RULE_UPDATE,
 };
 
@@ -55,15 +57,23 @@ pub enum SolveProblem {
     /// Not all dependency of package will appear, only enough to explain the
     //. problem. It is not a problem in itself, only a part of the graph.
     PkgRequires { source: SolvableId, dep: String },
+    /// A package cannot be installed at all, for instance because it was built
+    /// for a different architecture.
+    PkgNotInstallable { source: SolvableId },
     /// Package conflict between two solvables of same package name (handled the same as
     /// [`SolveProblem::PkgConflicts`]).
     PkgSameName {
         source: SolvableId,
         target: SolvableId,
     },
+    /// A package is excluded because it is also available from a repo with a
+    /// higher priority.
+    StrictRepoPriority { source: SolvableId },
     /// Encountered in the problems list from libsolv but unknown.
     /// Explicitly ignored until we do something with it.
     Update,
+    /// A rule type that we do not handle (yet).
+    Other(ffi::SolverRuleinfo),
 }
 
 impl SolveProblem {
@@ -97,12 +107,18 @@ impl SolveProblem {
                 source: source.unwrap(),
                 dep: dep.unwrap(),
             },
+            SOLVER_RULE_PKG_NOT_INSTALLABLE => Self::PkgNotInstallable {
+                source: source.unwrap(),
+            },
             SOLVER_RULE_SOLVER_RULE_PKG_SAME_NAME => Self::PkgSameName {
                 source: source.unwrap(),
                 target: target.unwrap(),
             },
+            SOLVER_RULE_STRICT_REPO_PRIORITY => Self::StrictRepoPriority {
+                source: source.unwrap(),
+            },
             SOLVER_RULE_SOLVER_RULE_UPDATE => Self::Update,
-            _ => panic!("Unknown problem type: {problem_type}"),
+            _ => Self::Other(problem_type),
         }
     }
 }
@@ -63,6 +63,7 @@ impl Solver<'_> {
         output
     }
 
+    /// Returns the rules that are involved in the problems that the solver encountered.
     pub fn all_solver_problems(&self) -> Vec<SolveProblem> {
         let mut problems = Vec::new();
         let mut problem_rules = Queue::<ffi::Id>::default();
@@ -105,7 +106,7 @@ impl Solver<'_> {
                     let source = if source_id < 0 || source_id >= nsolvables {
                         None
                     } else {
-                        Some(SolvableId(target_id))
+                        Some(SolvableId(source_id))
                     };
 
                     let dep = if dep_id == 0 {
@@ -130,8 +131,12 @@ impl Solver<'_> {
     }
 
     /// Solves all the problems in the `queue` and returns a transaction from the found solution.
-    /// Returns an error if problems remain unsolved.
-    pub fn solve(&mut self, queue: &mut SolveGoal) -> Result<Transaction<'_>, Vec<String>> {
+    /// Returns an error if problems remain unsolved, containing a user-friendly description of
+    /// each problem together with the rules that are involved in the problems.
+    pub fn solve(
+        &mut self,
+        queue: &mut SolveGoal,
+    ) -> Result<Transaction<'_>, (Vec<String>, Vec<SolveProblem>)> {
         let result = unsafe {
             // Run the solve method
             ffi::solver_solve(self.raw_ptr(), queue.raw_ptr());
@@ -146,7 +151,7 @@ impl Solver<'_> {
             // Safe because we know the `transaction` ptr is valid
             Ok(unsafe { Transaction::new(self, transaction) })
         } else {
-            Err(self.solver_problems())
+            Err((self.solver_problems(), self.all_solver_problems()))
         }
     }
 }
@@ -0,0 +1,204 @@
+//! Finds relaxations of an unsolvable [`SolverTask`] that make it solvable.
+
+use std::fmt;
+#[cfg(any(feature = "libsolv_c", feature = "resolvo"))]
+use std::time::Instant;
+
+use rattler_conda_types::{MatchSpec, PackageName};
+
+#[cfg(any(feature = "libsolv_c", feature = "resolvo"))]
+use crate::{
+    ChannelPriority, ExcludeNewerOverrides, IntoRepoData, SolveError, SolverImpl, SolverTask,
+};
+
+/// A single modification of a [`SolverTask`] that makes an unsolvable task
+/// solvable.
+///
+/// See [`SolverTask::suggest_relaxations`].
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Relaxation {
+    /// Drop the version and build constraints of a requested spec.
+    RelaxSpec {
+        /// The spec as it was requested.
+        spec: MatchSpec,
+
+        /// The spec without version and build constraints.
+        relaxed: MatchSpec,
+    },
+
+    /// Do not exclude packages based on [`SolverTask::exclude_newer`] or
+    /// [`SolverTask::exclude_newer_overrides`].
+    IgnoreExcludeNewer,
+
+    /// Solve with [`ChannelPriority::Disabled`] instead of
+    /// [`ChannelPriority::Strict`].
+    DisableStrictChannelPriority,
+
+    /// Remove the package from [`SolverTask::pinned_packages`].
+    Unpin {
+        /// The name of the pinned package.
+        package: PackageName,
+    },
+}
+
+impl fmt::Display for Relaxation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Relaxation::RelaxSpec { spec, relaxed } => {
+                write!(f, "relax '{spec}' to '{relaxed}'")
+            }
+            Relaxation::IgnoreExcludeNewer => write!(f, "do not exclude newer packages"),
+            Relaxation::DisableStrictChannelPriority => {
+                write!(f, "disable strict channel priority")
+            }
+            Relaxation::Unpin { package } => {
+                write!(f, "unpin '{}'", package.as_normalized())
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "libsolv_c", feature = "resolvo"))]
+impl Relaxation {
+    /// Returns all relaxations that can be applied to the task.
+    fn candidates<T>(task: &SolverTask<T>) -> Vec<Self> {
+        let mut candidates = Vec::new();
+
+        for spec in &task.specs {
+            let Some(name) = &spec.name else {
+                continue;
+            };
+            let relaxed = MatchSpec {
+                name: Some(name.clone()),
+                channel: spec.channel.clone(),
+                subdir: spec.subdir.clone(),
+                namespace: spec.namespace.clone(),
+                extras: spec.extras.clone(),
+                ..MatchSpec::default()
+            };
+            if &relaxed != spec {
+                candidates.push(Relaxation::RelaxSpec {
+                    spec: spec.clone(),
+                    relaxed,
+                });
+            }
+        }
+
+        if task.exclude_newer.is_some() || task.exclude_newer_overrides.has_cutoff() {
+            candidates.push(Relaxation::IgnoreExcludeNewer);
+        }
+
+        if task.channel_priority == ChannelPriority::Strict {
+            candidates.push(Relaxation::DisableStrictChannelPriority);
+        }
+
+        for record in &task.pinned_packages {
+            let package = record.package_record.name.clone();
+            if !candidates.contains(&Relaxation::Unpin {
+                package: package.clone(),
+            }) {
+                candidates.push(Relaxation::Unpin { package });
+            }
+        }
+
+        candidates
+    }
+
+    /// Applies the relaxation to a task.
+    fn apply<T>(&self, task: &mut SolverTask<T>) {
+        match self {
+            Relaxation::RelaxSpec { spec, relaxed } => {
+                for s in task.specs.iter_mut().filter(|s| *s == spec) {
+                    *s = relaxed.clone();
+                }
+            }
+            Relaxation::IgnoreExcludeNewer => {
+                task.exclude_newer = None;
+                task.exclude_newer_overrides = ExcludeNewerOverrides::default();
+            }
+            Relaxation::DisableStrictChannelPriority => {
+                task.channel_priority = ChannelPriority::Disabled;
+            }
+            Relaxation::Unpin { package } => task
+                .pinned_packages
+                .retain(|record| &record.package_record.name != package),
+        }
+    }
+}
+
+/// Solves the task and, if it turns out to be unsolvable, solves relaxed
+/// variants of the task. Each relaxation that makes the task solvable is
+/// attached to the returned [`SolveError::Unsolvable`].
+///
+/// Relaxations are only tried one at a time, combinations of relaxations are
+/// not searched. The [`SolverTask::timeout`] applies to all solves together,
+/// relaxations that cannot be tried before it expires are not reported.
+///
+/// This is used by the solver backends to implement
+/// [`SolverTask::suggest_relaxations`].
+#[cfg(any(feature = "libsolv_c", feature = "resolvo"))]
+pub(crate) fn solve_with_relaxations<'a, S, R, I>(
+    solver: &mut S,
+    task: SolverTask<I>,
+) -> Result<rattler_conda_types::SolverResult, SolveError>
+where
+    S: SolverImpl,
+    S::RepoData<'a>: Clone,
+    R: IntoRepoData<'a, S::RepoData<'a>>,
+    I: IntoIterator<Item = R>,
+{
+    // Convert the available packages once so the task can be solved multiple
+    // times.
+    let task = SolverTask {
+        available_packages: task
+            .available_packages
+            .into_iter()
+            .map(IntoRepoData::into)
+            .collect::<Vec<S::RepoData<'a>>>(),
+        locked_packages: task.locked_packages,
+        pinned_packages: task.pinned_packages,
+        virtual_packages: task.virtual_packages,
+        specs: task.specs,
+        constraints: task.constraints,
+        timeout: task.timeout,
+        channel_priority: task.channel_priority,
+        channel_restrictions: task.channel_restrictions,
+        channel_order: task.channel_order,
+        exclude_newer: task.exclude_newer,
+        exclude_newer_overrides: task.exclude_newer_overrides,
+        strategy: task.strategy,
+        suggest_relaxations: false,
+    };
+
+    let deadline = task.timeout.map(|timeout| Instant::now() + timeout);
+    let problem = match solver.solve(task.clone()) {
+        Err(SolveError::Unsolvable(problem)) => problem,
+        result => return result,
+    };
+
+    let mut relaxations = Vec::new();
+    for relaxation in Relaxation::candidates(&task) {
+        let mut relaxed_task = task.clone();
+        relaxation.apply(&mut relaxed_task);
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                tracing::debug!("the timeout expired before all relaxations were tried");
+                break;
+            }
+            relaxed_task.timeout = Some(remaining);
+        }
+        match solver.solve(relaxed_task) {
+            Ok(_) => relaxations.push(relaxation),
+            Err(SolveError::Unsolvable(_)) => {}
+            Err(err) => {
+                tracing::debug!("failed to solve with relaxation '{relaxation}': {err}");
+            }
+        }
+    }
+
+    Err(SolveError::Unsolvable(
+        problem.with_relaxations(relaxations),
+    ))
+}
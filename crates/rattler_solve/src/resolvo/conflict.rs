@@ -0,0 +1,117 @@
+//! Converts the conflict graph of [`resolvo`] into a [`ConflictGraph`].
+
+use itertools::Itertools;
+use resolvo::{
+    conflict::{
+        ConflictCause, ConflictEdge as ResolvoConflictEdge, ConflictGraph as ResolvoConflictGraph,
+        ConflictNode as ResolvoConflictNode,
+    },
+    Interner, Requirement, VersionSetId,
+};
+
+use super::{CondaDependencyProvider, SolverPackageRecord};
+use crate::conflict::{
+    ConflictCandidate, ConflictEdge, ConflictEdgeKind, ConflictGraph, ConflictNode, ExclusionReason,
+};
+
+/// Converts the graph that resolvo constructed for an unsolvable problem. The
+/// nodes keep the same indices as in the original graph, resolvo always adds
+/// the root node first.
+pub(super) fn conflict_graph(
+    graph: &ResolvoConflictGraph,
+    provider: &CondaDependencyProvider<'_>,
+    requested_specs: Vec<String>,
+) -> ConflictGraph {
+    let nodes = graph
+        .graph
+        .raw_nodes()
+        .iter()
+        .map(|node| match node.weight {
+            ResolvoConflictNode::Solvable(id) => match id.solvable() {
+                None => ConflictNode::Root,
+                Some(id) => {
+                    ConflictNode::Candidate(match &provider.pool.resolve_solvable(id).record {
+                        SolverPackageRecord::Record(record) => ConflictCandidate::from(*record),
+                        SolverPackageRecord::VirtualPackage(package) => {
+                            ConflictCandidate::from(*package)
+                        }
+                        SolverPackageRecord::Extra { package, extra } => ConflictCandidate {
+                            name: format!("{}[{extra}]", package.as_normalized()),
+                            version: None,
+                            build: None,
+                            channel: None,
+                            is_virtual: false,
+                        },
+                    })
+                }
+            },
+            ResolvoConflictNode::UnresolvedDependency => ConflictNode::Unresolved,
+            ResolvoConflictNode::Excluded(reason) => ConflictNode::Excluded {
+                reason: provider
+                    .exclusion_reasons
+                    .get(&reason)
+                    .cloned()
+                    .unwrap_or_else(|| ExclusionReason::Other {
+                        message: provider.pool.resolve_string(reason).to_string(),
+                    }),
+            },
+        })
+        .collect();
+
+    let edges = graph
+        .graph
+        .raw_edges()
+        .iter()
+        .map(|edge| ConflictEdge {
+            from: edge.source().index(),
+            to: edge.target().index(),
+            kind: match edge.weight {
+                ResolvoConflictEdge::Requires(requirement) => ConflictEdgeKind::Requires {
+                    spec: display_requirement(provider, requirement),
+                },
+                ResolvoConflictEdge::Conflict(ConflictCause::Constrains(version_set)) => {
+                    ConflictEdgeKind::Constrains {
+                        spec: display_version_set(provider, version_set),
+                    }
+                }
+                ResolvoConflictEdge::Conflict(ConflictCause::ForbidMultipleInstances) => {
+                    ConflictEdgeKind::ForbidMultipleInstances
+                }
+                ResolvoConflictEdge::Conflict(ConflictCause::Locked(_)) => ConflictEdgeKind::Locked,
+                ResolvoConflictEdge::Conflict(ConflictCause::Excluded) => {
+                    ConflictEdgeKind::Excluded
+                }
+            },
+        })
+        .collect();
+
+    ConflictGraph {
+        requested_specs,
+        nodes,
+        edges,
+    }
+}
+
+fn display_requirement(provider: &CondaDependencyProvider<'_>, requirement: Requirement) -> String {
+    match requirement {
+        Requirement::Single(version_set) => display_version_set(provider, version_set),
+        Requirement::Union(union) => provider
+            .version_sets_in_union(union)
+            .map(|version_set| display_version_set(provider, version_set))
+            .join(" | "),
+    }
+}
+
+fn display_version_set(
+    provider: &CondaDependencyProvider<'_>,
+    version_set: VersionSetId,
+) -> String {
+    let name = provider.version_set_name(version_set);
+    format!(
+        "{} {}",
+        provider.display_name(name),
+        provider.display_version_set(version_set)
+    )
+    .trim_end()
+    .to_string()
+}
@@ -12,7 +12,7 @@ use chrono::{DateTime, Utc};
 use conda_sorting::SolvableSorter;
 use itertools::Itertools;
 use rattler_conda_types::{
-    package::ArchiveType, GenericVirtualPackage, MatchSpec, Matches, NamelessMatchSpec,
+    package::ArchiveType, Channel, GenericVirtualPackage, MatchSpec, Matches, NamelessMatchSpec,
     PackageName, ParseMatchSpecError, ParseStrictness, RepoDataRecord, SolverResult,
 };
 use resolvo::{
@@ -24,11 +24,13 @@ use resolvo::{
 };
 
 use crate::{
-    resolvo::conda_sorting::CompareStrategy, ChannelPriority, IntoRepoData, SolveError,
-    SolveStrategy, SolverRepoData, SolverTask,
+    channel_preferences::ChannelPreferences, conflict::ExclusionReason,
+    resolvo::conda_sorting::CompareStrategy, ChannelPriority, ExcludeNewerOverrides, IntoRepoData,
+    SolveError, SolveStrategy, SolverRepoData, SolverTask, UnsolvableProblem,
 };
 
 mod conda_sorting;
+mod conflict;
 
 /// Represents the information required to load available packages into libsolv
 /// for a single channel and platform combination
@@ -244,6 +246,51 @@ impl From<&PackageName> for NameType {
     }
 }
 
+/// Options of a [`CondaDependencyProvider`] that are not passed to
+/// [`CondaDependencyProvider::new`], see
+/// [`CondaDependencyProvider::new_with_options`].
+#[derive(Debug, Clone, Default)]
+pub struct CondaDependencyProviderOptions {
+    channel_restrictions: HashMap<PackageName, Channel>,
+    channel_order: Vec<Channel>,
+    exclude_newer_overrides: ExcludeNewerOverrides,
+}
+
+impl CondaDependencyProviderOptions {
+    /// Restricts packages to a single channel, see
+    /// [`SolverTask::channel_restrictions`].
+    pub fn with_channel_restrictions(
+        self,
+        channel_restrictions: HashMap<PackageName, Channel>,
+    ) -> Self {
+        Self {
+            channel_restrictions,
+            ..self
+        }
+    }
+
+    /// Sets the order in which channels are preferred, see
+    /// [`SolverTask::channel_order`].
+    pub fn with_channel_order(self, channel_order: Vec<Channel>) -> Self {
+        Self {
+            channel_order,
+            ..self
+        }
+    }
+
+    /// Overrides the `exclude_newer` cutoff per package or channel, see
+    /// [`SolverTask::exclude_newer_overrides`].
+    pub fn with_exclude_newer_overrides(
+        self,
+        exclude_newer_overrides: ExcludeNewerOverrides,
+    ) -> Self {
+        Self {
+            exclude_newer_overrides,
+            ..self
+        }
+    }
+}
+
 /// An implement of [`resolvo::DependencyProvider`] that implements the
 /// ecosystem behavior for conda. This allows resolvo to solve for conda
 /// packages.
@@ -266,6 +313,9 @@ pub struct CondaDependencyProvider<'a> {
     strategy: SolveStrategy,
 
     direct_dependencies: HashSet<NameId>,
+
+    /// The structured reasons behind the interned exclusion messages.
+    exclusion_reasons: HashMap<StringId, ExclusionReason>,
 }
 
 impl<'a> CondaDependencyProvider<'a> {
@@ -282,8 +332,48 @@ impl<'a> CondaDependencyProvider<'a> {
         exclude_newer: Option<DateTime<Utc>>,
         strategy: SolveStrategy,
     ) -> Result<Self, SolveError> {
+        Self::new_with_options(
+            repodata,
+            favored_records,
+            locked_records,
+            virtual_packages,
+            match_specs,
+            stop_time,
+            channel_priority,
+            exclude_newer,
+            strategy,
+            &CondaDependencyProviderOptions::default(),
+        )
+    }
+
+    /// Constructs a new provider with additional options.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_options(
+        repodata: impl IntoIterator<Item = RepoData<'a>>,
+        favored_records: &'a [RepoDataRecord],
+        locked_records: &'a [RepoDataRecord],
+        virtual_packages: &'a [GenericVirtualPackage],
+        match_specs: &[MatchSpec],
+        stop_time: Option<std::time::SystemTime>,
+        channel_priority: ChannelPriority,
+        exclude_newer: Option<DateTime<Utc>>,
+        strategy: SolveStrategy,
+        options: &CondaDependencyProviderOptions,
+    ) -> Result<Self, SolveError> {
+        let CondaDependencyProviderOptions {
+            channel_restrictions,
+            channel_order,
+            exclude_newer_overrides,
+        } = options;
+        let channel_preferences = ChannelPreferences::new(channel_restrictions, channel_order);
         let pool = Pool::default();
         let mut records: HashMap<NameId, Candidates> = HashMap::default();
+        let mut exclusion_reasons = HashMap::default();
+        let mut intern_reason = |reason: ExclusionReason| {
+            let id = pool.intern_string(reason.to_string());
+            exclusion_reasons.insert(id, reason);
+            id
+        };
 
         // Add virtual packages to the records
         for virtual_package in virtual_packages {
@@ -309,6 +399,20 @@ impl<'a> CondaDependencyProvider<'a> {
         // Hashmap that maps the package name to the channel it was first found in.
         let mut package_name_found_in_channel = HashMap::<String, &Option<String>>::new();
 
+        // Reorder the repodata according to the channel order, the sort is stable
+        // so channels that are not part of the channel order keep their order.
+        let mut repodata = repodata.into_iter().collect::<Vec<_>>();
+        if channel_preferences.has_channel_order() {
+            repodata.sort_by_cached_key(|repo_data| {
+                channel_preferences.rank(
+                    repo_data
+                        .records
+                        .first()
+                        .and_then(|record| record.channel.as_deref()),
+                )
+            });
+        }
+
         // Add additional records
         for repo_data in repodata {
             // Iterate over all records and dedup records that refer to the same package
@@ -328,9 +432,9 @@ impl<'a> CondaDependencyProvider<'a> {
 
             for record in repo_data.records {
                 // Determine if this record will be excluded.
-                let excluded = matches!((&exclude_newer, &record.package_record.timestamp),
-                    (Some(exclude_newer), Some(record_timestamp))
-                        if record_timestamp > exclude_newer);
+                let excluded = exclude_newer_overrides
+                    .excluded_by(exclude_newer, record)
+                    .is_some();
 
                 let (file_name, archive_type) = ArchiveType::split_str(&record.file_name)
                     .unwrap_or((&record.file_name, ArchiveType::TarBz2));
@@ -388,16 +492,25 @@ impl<'a> CondaDependencyProvider<'a> {
                 candidates.candidates.push(solvable_id);
 
                 // Filter out any records that are newer than a specific date.
-                match (&exclude_newer, &record.package_record.timestamp) {
-                    (Some(exclude_newer), Some(record_timestamp))
-                        if record_timestamp > exclude_newer =>
-                    {
-                        let reason = pool.intern_string(format!(
-                            "the package is uploaded after the cutoff date of {exclude_newer}"
-                        ));
-                        candidates.excluded.push((solvable_id, reason));
-                    }
-                    _ => {}
+                if let Some(cutoff) = exclude_newer_overrides.excluded_by(exclude_newer, record) {
+                    let reason = intern_reason(ExclusionReason::ExcludeNewer { cutoff });
+                    candidates.excluded.push((solvable_id, reason));
+                }
+
+                // Add to excluded when the package is restricted to another channel.
+                if let Some(channel) = channel_preferences.violated_restriction(record) {
+                    tracing::debug!(
+                        "Ignoring {} because it is restricted to '{}'.",
+                        &record.package_record.name.as_normalized(),
+                        channel.display_name
+                    );
+                    let reason = ExclusionReason::NotInRequestedChannel {
+                        channel: channel.display_name.clone(),
+                    };
+                    candidates
+                        .excluded
+                        .push((solvable_id, intern_reason(reason)));
+                    continue;
                 }
 
                 // Add to excluded when package is not in the specified channel.
@@ -419,16 +532,15 @@ impl<'a> CondaDependencyProvider<'a> {
                                     });
                                 // Add record to the excluded with reason of being in the non
                                 // requested channel.
-                                let message = format!(
-                                    "candidate not in requested channel: '{}'",
-                                    spec_channel
+                                let reason = ExclusionReason::NotInRequestedChannel {
+                                    channel: spec_channel
                                         .name
                                         .clone()
-                                        .unwrap_or(spec_channel.base_url.to_string())
-                                );
+                                        .unwrap_or(spec_channel.base_url.to_string()),
+                                };
                                 candidates
                                     .excluded
-                                    .push((solvable_id, pool.intern_string(message)));
+                                    .push((solvable_id, intern_reason(reason)));
                                 continue;
                             }
                         }
@@ -450,9 +562,9 @@ impl<'a> CondaDependencyProvider<'a> {
                             );
                             candidates.excluded.push((
                                 solvable_id,
-                                pool.intern_string(format!(
-                                    "due to strict channel priority not using this option from: '{channel}'",
-                                )),
+                                intern_reason(ExclusionReason::StrictChannelPriority {
+                                    channel: Some(channel.clone()),
+                                }),
                             ));
                         } else {
                             tracing::debug!(
@@ -461,7 +573,9 @@ impl<'a> CondaDependencyProvider<'a> {
                                 );
                             candidates.excluded.push((
                                 solvable_id,
-                                pool.intern_string("due to strict channel priority not using from an unknown channel".to_string()),
+                                intern_reason(ExclusionReason::StrictChannelPriority {
+                                    channel: None,
+                                }),
                             ));
                         }
                     }
@@ -505,6 +619,7 @@ impl<'a> CondaDependencyProvider<'a> {
             stop_time,
             strategy,
             direct_dependencies,
+            exclusion_reasons,
         })
     }
 
@@ -804,12 +919,21 @@ impl super::SolverImpl for Solver {
         &mut self,
         task: SolverTask<TAvailablePackagesIterator>,
     ) -> Result<SolverResult, SolveError> {
+        if task.suggest_relaxations {
+            return crate::relaxation::solve_with_relaxations(self, task);
+        }
+
         let stop_time = task
             .timeout
             .map(|timeout| std::time::SystemTime::now() + timeout);
+        let requested_specs = task.specs.iter().map(ToString::to_string).collect();
 
         // Construct a provider that can serve the data.
-        let provider = CondaDependencyProvider::new(
+        let options = CondaDependencyProviderOptions::default()
+            .with_channel_restrictions(task.channel_restrictions)
+            .with_channel_order(task.channel_order)
+            .with_exclude_newer_overrides(task.exclude_newer_overrides);
+        let provider = CondaDependencyProvider::new_with_options(
             task.available_packages.into_iter().map(|r| r.into()),
             &task.locked_packages,
             &task.pinned_packages,
@@ -819,6 +943,7 @@ impl super::SolverImpl for Solver {
             task.channel_priority,
             task.exclude_newer,
             task.strategy,
+            &options,
         )?;
 
         // Construct the requirements that the solver needs to satisfy.
@@ -859,9 +984,16 @@ impl super::SolverImpl for Solver {
         let mut solver = LibSolvRsSolver::new(provider);
         let solvables = solver.solve(problem).map_err(|unsolvable_or_cancelled| {
             match unsolvable_or_cancelled {
-                UnsolvableOrCancelled::Unsolvable(problem) => {
-                    SolveError::Unsolvable(vec![problem.display_user_friendly(&solver).to_string()])
-                }
+                UnsolvableOrCancelled::Unsolvable(problem) => SolveError::Unsolvable(
+                    UnsolvableProblem::new(vec![problem
+                        .display_user_friendly(&solver)
+                        .to_string()])
+                    .with_conflict_graph(conflict::conflict_graph(
+                        &problem.graph(&solver),
+                        solver.provider(),
+                        requested_specs,
+                    )),
+                ),
                 // We are not doing this as of yet
                 // put a generic message in here for now
                 UnsolvableOrCancelled::Cancelled(_) => SolveError::Cancelled,
@@ -1,4 +1,8 @@
-use std::{collections::BTreeMap, str::FromStr, time::Instant};
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    time::Instant,
+};
 
 use chrono::{DateTime, Utc};
 use once_cell::sync::Lazy;
@@ -7,7 +11,9 @@ use rattler_conda_types::{
     ParseStrictness, RepoData, RepoDataRecord, SolverResult, Version,
 };
 use rattler_repodata_gateway::sparse::{PackageFormatSelection, SparseRepoData};
-use rattler_solve::{ChannelPriority, SolveError, SolveStrategy, SolverImpl, SolverTask};
+use rattler_solve::{
+    ChannelPriority, ExcludeNewerOverrides, SolveError, SolveStrategy, SolverImpl, SolverTask,
+};
 use url::Url;
 
 fn channel_config() -> ChannelConfig {
@@ -73,6 +79,12 @@ fn read_repodata(path: &str) -> Vec<RepoDataRecord> {
     repo_data.into_repo_data_records(&Channel::from_str("conda-forge", &channel_config()).unwrap())
 }
 
+fn read_repodata_from_channel(path: &str, channel: &Channel) -> Vec<RepoDataRecord> {
+    let repo_data: RepoData =
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap();
+    repo_data.into_repo_data_records(channel)
+}
+
 fn read_sparse_repodata(path: &str) -> SparseRepoData {
     SparseRepoData::from_file(
         Channel::from_str("dummy", &channel_config()).unwrap(),
@@ -219,6 +231,7 @@ macro_rules! solver_backend_tests {
     ($T:path) => {
         use chrono::{DateTime, Utc};
         use itertools::Itertools;
+        use rattler_solve::Relaxation;
 
         #[test]
         fn test_solve_quetz() {
@@ -325,6 +338,152 @@ macro_rules! solver_backend_tests {
             assert!(matches!(result.err(), Some(SolveError::Unsolvable(_))));
         }
 
+        #[test]
+        fn test_conflict_graph_missing_virtual_package() {
+            let result = solve::<$T>(
+                &[dummy_channel_json_path()],
+                SimpleSolveTask {
+                    specs: &["bar"],
+                    ..SimpleSolveTask::default()
+                },
+            );
+
+            let Err(SolveError::Unsolvable(problem)) = result else {
+                panic!("expected the solve to fail");
+            };
+            let graph = problem.conflict_graph().expect("expected a conflict graph");
+            assert_eq!(graph.requested_specs, vec!["bar".to_string()]);
+            assert_eq!(
+                graph.missing_virtual_packages(),
+                vec![rattler_conda_types::PackageName::new_unchecked("__unix")]
+            );
+        }
+
+        #[test]
+        fn test_suggest_relaxations() {
+            let date = "2021-12-12T12:12:12Z".parse::<DateTime<Utc>>().unwrap();
+
+            let result = solve::<$T>(
+                &[dummy_channel_json_path()],
+                SimpleSolveTask {
+                    specs: &["foo>=4"],
+                    exclude_newer: Some(date),
+                    suggest_relaxations: true,
+                    ..SimpleSolveTask::default()
+                },
+            );
+
+            let Err(SolveError::Unsolvable(problem)) = result else {
+                panic!("expected the solve to fail");
+            };
+            let parse = |spec| {
+                rattler_conda_types::MatchSpec::from_str(
+                    spec,
+                    rattler_conda_types::ParseStrictness::Lenient,
+                )
+                .unwrap()
+            };
+            assert_eq!(
+                problem.relaxations(),
+                &[
+                    Relaxation::RelaxSpec {
+                        spec: parse("foo>=4"),
+                        relaxed: parse("foo"),
+                    },
+                    Relaxation::IgnoreExcludeNewer,
+                ]
+            );
+        }
+
+        #[test]
+        fn test_channel_restrictions() {
+            let [first, second] = ["first", "second"]
+                .map(|name| Channel::from_str(name, &channel_config()).unwrap());
+
+            assert_eq!(
+                solve_foo_from_two_channels::<$T>(&first, &second, HashMap::new(), Vec::new()),
+                Some(first.canonical_name())
+            );
+            assert_eq!(
+                solve_foo_from_two_channels::<$T>(
+                    &first,
+                    &second,
+                    HashMap::from([(
+                        rattler_conda_types::PackageName::new_unchecked("foo"),
+                        second.clone()
+                    )]),
+                    Vec::new()
+                ),
+                Some(second.canonical_name())
+            );
+        }
+
+        #[test]
+        fn test_channel_restrictions_transitive() {
+            let [first, second] = ["first", "second"]
+                .map(|name| Channel::from_str(name, &channel_config()).unwrap());
+
+            // `bors` is not requested directly but is a dependency of `foobar`.
+            assert_eq!(
+                solve_from_two_channels::<$T>(
+                    "foobar",
+                    "bors",
+                    &first,
+                    &second,
+                    HashMap::from([(
+                        rattler_conda_types::PackageName::new_unchecked("bors"),
+                        second.clone()
+                    )]),
+                    Vec::new()
+                ),
+                Some(second.canonical_name())
+            );
+        }
+
+        #[test]
+        fn test_channel_order() {
+            let [first, second] = ["first", "second"]
+                .map(|name| Channel::from_str(name, &channel_config()).unwrap());
+
+            assert_eq!(
+                solve_foo_from_two_channels::<$T>(
+                    &first,
+                    &second,
+                    HashMap::new(),
+                    vec![second.clone()]
+                ),
+                Some(second.canonical_name())
+            );
+        }
+
+        #[test]
+        fn test_conflict_graph_exclude_newer() {
+            let date = "2021-12-12T12:12:12Z".parse::<DateTime<Utc>>().unwrap();
+
+            let result = solve::<$T>(
+                &[dummy_channel_json_path()],
+                SimpleSolveTask {
+                    specs: &["foo>=4"],
+                    exclude_newer: Some(date),
+                    ..SimpleSolveTask::default()
+                },
+            );
+
+            let Err(SolveError::Unsolvable(problem)) = result else {
+                panic!("expected the solve to fail");
+            };
+            let graph = problem.conflict_graph().expect("expected a conflict graph");
+            let excluded = graph.excluded_candidates();
+            assert!(!excluded.is_empty());
+            for (candidate, reason) in excluded {
+                assert_eq!(candidate.name, "foo");
+                assert_eq!(
+                    reason,
+                    &rattler_solve::conflict::ExclusionReason::ExcludeNewer { cutoff: date }
+                );
+            }
+        }
+
         #[test]
         fn test_solve_dummy_repo_with_virtual_package() {
             let pkgs = solve::<$T>(
@@ -542,6 +701,73 @@ macro_rules! solver_backend_tests {
             assert_eq!(&info.file_name, "foo-3.0.2-py36h1af98f8_1.tar.bz2", "even though there is a conda version available we expect the tar.bz2 version because we exclude the .conda version based on the timestamp");
         }
 
+        #[test]
+        fn test_exclude_newer_package_override() {
+            let date = "2021-12-12T12:12:12Z".parse::<DateTime<Utc>>().unwrap();
+
+            let pkgs = solve::<$T>(
+                &[dummy_channel_json_path()],
+                SimpleSolveTask {
+                    specs: &["foo"],
+                    exclude_newer: Some(date),
+                    exclude_newer_overrides: rattler_solve::ExcludeNewerOverrides {
+                        packages: [(
+                            rattler_conda_types::PackageName::new_unchecked("foo"),
+                            None,
+                        )]
+                        .into_iter()
+                        .collect(),
+                        ..rattler_solve::ExcludeNewerOverrides::default()
+                    },
+                    ..SimpleSolveTask::default()
+                },
+            )
+            .unwrap();
+
+            assert_eq!(1, pkgs.records.len());
+            assert_eq!("4.0.2", &pkgs.records[0].package_record.version.to_string());
+        }
+
+        #[test]
+        fn test_exclude_newer_channel_override() {
+            let date = "2021-12-12T12:12:12Z".parse::<DateTime<Utc>>().unwrap();
+            let channel = Channel::from_str("conda-forge", &channel_config())
+                .unwrap()
+                .canonical_name();
+
+            // The channel cutoff applies even without a global cutoff.
+            let mut overrides = rattler_solve::ExcludeNewerOverrides {
+                channels: [(channel, Some(date))].into_iter().collect(),
+                ..rattler_solve::ExcludeNewerOverrides::default()
+            };
+            let pkgs = solve::<$T>(
+                &[dummy_channel_json_path()],
+                SimpleSolveTask {
+                    specs: &["foo"],
+                    exclude_newer_overrides: overrides.clone(),
+                    ..SimpleSolveTask::default()
+                },
+            )
+            .unwrap();
+            assert_eq!("3.0.2", &pkgs.records[0].package_record.version.to_string());
+
+            // Package overrides take precedence over channel overrides.
+            overrides.packages.insert(
+                rattler_conda_types::PackageName::new_unchecked("foo"),
+                None,
+            );
+            let pkgs = solve::<$T>(
+                &[dummy_channel_json_path()],
+                SimpleSolveTask {
+                    specs: &["foo"],
+                    exclude_newer_overrides: overrides,
+                    ..SimpleSolveTask::default()
+                },
+            )
+            .unwrap();
+            assert_eq!("4.0.2", &pkgs.records[0].package_record.version.to_string());
+        }
+
         #[test]
         fn test_duplicate_record() {
             use rattler_solve::SolverImpl;
@@ -624,8 +850,9 @@ mod libsolv_c {
     use rattler_solve::{ChannelPriority, SolveStrategy};
 
     use super::{
-        dummy_channel_json_path, installed_package, solve, solve_real_world, FromStr,
-        GenericVirtualPackage, SimpleSolveTask, SolveError, Version,
+        channel_config, dummy_channel_json_path, installed_package, solve,
+        solve_foo_from_two_channels, solve_from_two_channels, solve_real_world, Channel, FromStr,
+        GenericVirtualPackage, HashMap, SimpleSolveTask, SolveError, Version,
     };
 
     solver_backend_tests!(rattler_solve::libsolv_c::Solver);
@@ -670,8 +897,12 @@ mod libsolv_c {
                 pinned_packages: Vec::new(),
                 timeout: None,
                 channel_priority: ChannelPriority::default(),
+                channel_restrictions: HashMap::new(),
+                channel_order: Vec::new(),
                 exclude_newer: None,
+                exclude_newer_overrides: rattler_solve::ExcludeNewerOverrides::default(),
                 strategy: SolveStrategy::default(),
+                suggest_relaxations: false,
             })
             .unwrap()
             .records;
@@ -714,6 +945,120 @@ mod libsolv_c {
             info.package_record.md5.as_ref().unwrap()
         );
     }
+
+    /// Solves `spec` from two channels that are both loaded from cached .solv
+    /// files.
+    #[cfg(target_family = "unix")]
+    fn solve_from_cached_solv_files(
+        spec: &str,
+        first: &Channel,
+        second: &Channel,
+        channel_restrictions: HashMap<rattler_conda_types::PackageName, Channel>,
+        exclude_newer_overrides: rattler_solve::ExcludeNewerOverrides,
+    ) -> Vec<rattler_conda_types::RepoDataRecord> {
+        use rattler_conda_types::{MatchSpec, ParseStrictness, Platform};
+        use rattler_solve::{SolverImpl, SolverTask};
+
+        use super::read_repodata_from_channel;
+
+        let records = [first, second]
+            .map(|channel| read_repodata_from_channel(&dummy_channel_json_path(), channel));
+        let solv_files = [first, second]
+            .iter()
+            .zip(&records)
+            .map(|(channel, records)| {
+                rattler_solve::libsolv_c::cache_repodata(
+                    channel.platform_url(Platform::Linux64).to_string(),
+                    records,
+                    None,
+                )
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        let available_packages = records
+            .iter()
+            .zip(&solv_files)
+            .map(|(records, solv_file)| rattler_solve::libsolv_c::RepoData {
+                records: records.iter().collect(),
+                solv_file: Some(solv_file),
+            })
+            .collect::<Vec<_>>();
+
+        rattler_solve::libsolv_c::Solver
+            .solve(SolverTask {
+                locked_packages: Vec::new(),
+                virtual_packages: Vec::new(),
+                available_packages,
+                specs: vec![MatchSpec::from_str(spec, ParseStrictness::Lenient).unwrap()],
+                constraints: Vec::new(),
+                pinned_packages: Vec::new(),
+                timeout: None,
+                channel_priority: ChannelPriority::default(),
+                channel_restrictions,
+                channel_order: Vec::new(),
+                exclude_newer: None,
+                exclude_newer_overrides,
+                strategy: SolveStrategy::default(),
+                suggest_relaxations: false,
+            })
+            .unwrap()
+            .records
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_channel_restrictions_with_cached_solv_file() {
+        let [first, second] =
+            ["first", "second"].map(|name| Channel::from_str(name, &channel_config()).unwrap());
+        let bors_channel = |channel_restrictions| {
+            solve_from_cached_solv_files(
+                "foobar",
+                &first,
+                &second,
+                channel_restrictions,
+                rattler_solve::ExcludeNewerOverrides::default(),
+            )
+            .into_iter()
+            .find(|record| record.package_record.name.as_normalized() == "bors")
+            .unwrap()
+            .channel
+        };
+
+        assert_eq!(bors_channel(HashMap::new()), Some(first.canonical_name()));
+        assert_eq!(
+            bors_channel(HashMap::from([(
+                rattler_conda_types::PackageName::new_unchecked("bors"),
+                second.clone()
+            )])),
+            Some(second.canonical_name())
+        );
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_exclude_newer_overrides_with_cached_solv_file() {
+        let [first, second] =
+            ["first", "second"].map(|name| Channel::from_str(name, &channel_config()).unwrap());
+        let date = "2021-12-12T12:12:12Z".parse().unwrap();
+
+        let records = solve_from_cached_solv_files(
+            "foo",
+            &first,
+            &second,
+            HashMap::new(),
+            rattler_solve::ExcludeNewerOverrides {
+                packages: [(
+                    rattler_conda_types::PackageName::new_unchecked("foo"),
+                    Some(date),
+                )]
+                .into_iter()
+                .collect(),
+                ..rattler_solve::ExcludeNewerOverrides::default()
+            },
+        );
+        assert_eq!(1, records.len());
+        assert_eq!("foo-3.0.2-py36h1af98f8_1.tar.bz2", records[0].file_name);
+    }
 }
 
 #[cfg(feature = "resolvo")]
@@ -727,8 +1072,9 @@ mod resolvo {
     #[cfg(feature = "experimental_extras")]
     use super::dummy_channel_with_optional_dependencies_json_path;
     use super::{
-        dummy_channel_json_path, installed_package, solve, solve_real_world, FromStr,
-        GenericVirtualPackage, SimpleSolveTask, SolveError, Version,
+        channel_config, dummy_channel_json_path, installed_package, solve,
+        solve_foo_from_two_channels, solve_from_two_channels, solve_real_world, Channel, FromStr,
+        GenericVirtualPackage, HashMap, SimpleSolveTask, SolveError, Version,
     };
 
     solver_backend_tests!(rattler_solve::resolvo::Solver);
@@ -1097,7 +1443,56 @@ struct SimpleSolveTask<'a> {
     pinned_packages: Vec<RepoDataRecord>,
     virtual_packages: Vec<GenericVirtualPackage>,
     exclude_newer: Option<DateTime<Utc>>,
+    exclude_newer_overrides: ExcludeNewerOverrides,
     strategy: SolveStrategy,
+    suggest_relaxations: bool,
+}
+
+/// Solves `foo` from two channels that contain the same packages and returns
+/// the channel that `foo` was taken from.
+fn solve_foo_from_two_channels<T: SolverImpl + Default>(
+    first: &Channel,
+    second: &Channel,
+    channel_restrictions: HashMap<rattler_conda_types::PackageName, Channel>,
+    channel_order: Vec<Channel>,
+) -> Option<String> {
+    solve_from_two_channels::<T>(
+        "foo",
+        "foo",
+        first,
+        second,
+        channel_restrictions,
+        channel_order,
+    )
+}
+
+/// Solves `spec` from two channels that contain the same packages and returns
+/// the channel that `package` was taken from.
+fn solve_from_two_channels<T: SolverImpl + Default>(
+    spec: &str,
+    package: &str,
+    first: &Channel,
+    second: &Channel,
+    channel_restrictions: HashMap<rattler_conda_types::PackageName, Channel>,
+    channel_order: Vec<Channel>,
+) -> Option<String> {
+    let first_records = read_repodata_from_channel(&dummy_channel_json_path(), first);
+    let second_records = read_repodata_from_channel(&dummy_channel_json_path(), second);
+
+    let task = SolverTask {
+        specs: vec![MatchSpec::from_str(spec, ParseStrictness::Lenient).unwrap()],
+        channel_restrictions,
+        channel_order,
+        ..SolverTask::from_iter([&first_records, &second_records])
+    };
+
+    let result = T::default().solve(task).unwrap();
+    result
+        .records
+        .into_iter()
+        .find(|record| record.package_record.name.as_normalized() == package)
+        .unwrap()
+        .channel
 }
 
 fn solve<T: SolverImpl + Default>(
@@ -1128,7 +1523,9 @@ fn solve<T: SolverImpl + Default>(
         constraints,
         pinned_packages: task.pinned_packages,
         exclude_newer: task.exclude_newer,
+        exclude_newer_overrides: task.exclude_newer_overrides,
         strategy: task.strategy,
+        suggest_relaxations: task.suggest_relaxations,
         ..SolverTask::from_iter(&repo_data)
     };
 
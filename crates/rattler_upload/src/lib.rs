@@ -11,7 +11,9 @@ use crate::utils::tool_configuration;
 /// Upload package to different channels
 pub async fn upload_from_args(args: UploadOpts) -> miette::Result<()> {
     // Validate package files are provided
-    if args.package_files.is_empty() {
+    let uploads_repodata =
+        matches!(&args.server_type, ServerType::Oci(opts) if !opts.repodata.is_empty());
+    if args.package_files.is_empty() && !uploads_repodata {
         return Err(miette::miette!("No package files were provided."));
     }
 
@@ -60,6 +62,15 @@ pub async fn upload_from_args(args: UploadOpts) -> miette::Result<()> {
             )
             .await
         }
+        ServerType::Oci(oci_opts) => {
+            upload::upload_package_to_oci(
+                &store,
+                oci_opts.channel,
+                &args.package_files,
+                &oci_opts.repodata,
+            )
+            .await
+        }
         ServerType::CondaForge(conda_forge_opts) => {
             let conda_forge_data = CondaForgeData::from(conda_forge_opts);
             upload::conda_forge::upload_packages_to_conda_forge(
@@ -23,6 +23,7 @@ use crate::upload::package::{sha256_sum, ExtractedPackage};
 
 mod anaconda;
 pub mod conda_forge;
+mod oci;
 pub mod opt;
 mod package;
 mod prefix;
@@ -32,6 +33,7 @@ mod trusted_publishing;
 #[cfg(feature = "s3")]
 pub use s3::upload_package_to_s3;
 
+pub use oci::upload_package_to_oci;
 pub use prefix::upload_package_to_prefix;
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -0,0 +1,65 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use miette::IntoDiagnostic;
+use rattler_networking::{AuthenticationStorage, OciPusher};
+use url::Url;
+
+use crate::upload::package::ExtractedPackage;
+
+/// Pushes packages and repodata to a channel in an OCI registry, e.g.
+/// `oci://ghcr.io/my-org/my-channel`.
+///
+/// The artifacts are stored in the layout that is read by
+/// [`rattler_networking::OciMiddleware`]. The subdir of a repodata file is
+/// taken from the name of its parent directory.
+pub async fn upload_package_to_oci(
+    auth_storage: &AuthenticationStorage,
+    channel: Url,
+    package_files: &Vec<PathBuf>,
+    repodata_files: &[PathBuf],
+) -> miette::Result<()> {
+    let (_, authentication) = auth_storage.get_by_url(channel.clone()).into_diagnostic()?;
+    let pusher = OciPusher::new(authentication);
+
+    for package_file in package_files {
+        let package = ExtractedPackage::from_package_file(package_file)?;
+        let subdir = package
+            .subdir()
+            .ok_or_else(|| miette::miette!("Failed to get subdir"))?;
+        let url = pusher
+            .push_package(&channel, subdir, package_file)
+            .await
+            .into_diagnostic()?;
+        tracing::info!("Pushed package to {url}");
+    }
+
+    let mut repodata_by_subdir = BTreeMap::<String, Vec<PathBuf>>::new();
+    for repodata_file in repodata_files {
+        let subdir = repodata_file
+            .canonicalize()
+            .into_diagnostic()?
+            .parent()
+            .and_then(|parent| parent.file_name())
+            .and_then(|name| name.to_str())
+            .map(ToString::to_string)
+            .ok_or_else(|| {
+                miette::miette!(
+                    "Failed to determine the subdir of {}",
+                    repodata_file.display()
+                )
+            })?;
+        repodata_by_subdir
+            .entry(subdir)
+            .or_default()
+            .push(repodata_file.clone());
+    }
+    for (subdir, repodata_files) in repodata_by_subdir {
+        let url = pusher
+            .push_repodata(&channel, &subdir, &repodata_files)
+            .await
+            .into_diagnostic()?;
+        tracing::info!("Pushed repodata to {url}");
+    }
+
+    Ok(())
+}
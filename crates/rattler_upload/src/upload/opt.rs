@@ -191,6 +191,7 @@ pub enum ServerType {
     Anaconda(AnacondaOpts),
     #[cfg(feature = "s3")]
     S3(S3Opts),
+    Oci(OciOpts),
     #[clap(hide = true)]
     CondaForge(CondaForgeOpts),
 }
@@ -439,6 +440,34 @@ pub struct S3Opts {
     pub force: bool,
 }
 
+fn parse_oci_url(value: &str) -> Result<Url, String> {
+    let url: Url =
+        Url::parse(value).map_err(|err| format!("`{value}` isn't a valid URL: {err}"))?;
+    if url.scheme() == "oci" && url.host_str().is_some() {
+        Ok(url)
+    } else {
+        Err(format!(
+            "Only OCI URLs of format oci://registry/... can be used, not `{value}`"
+        ))
+    }
+}
+
+/// Options for uploading to a channel in an OCI registry.
+/// Authentication is used from the keychain / auth-file.
+#[derive(Clone, Debug, PartialEq, Parser)]
+pub struct OciOpts {
+    /// The channel URL in the registry to upload the package to, e.g.,
+    /// `oci://ghcr.io/my-org/my-channel`
+    #[arg(short, long, env = "OCI_CHANNEL", value_parser = parse_oci_url)]
+    pub channel: Url,
+
+    /// Repodata files to upload alongside the packages, e.g.
+    /// `linux-64/repodata.json`. The subdir is taken from the name of the
+    /// parent directory.
+    #[arg(long)]
+    pub repodata: Vec<PathBuf>,
+}
+
 #[derive(Debug)]
 #[allow(missing_docs)]
 pub struct AnacondaData {
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+
 use chrono::DateTime;
 use pyo3::{
     exceptions::PyValueError, pybacked::PyBackedStr, pyfunction, types::PyAnyMethods, Bound,
@@ -5,7 +7,9 @@ use pyo3::{
 };
 use pyo3_async_runtimes::tokio::future_into_py;
 use rattler_repodata_gateway::sparse::SparseRepoData;
-use rattler_solve::{resolvo::Solver, RepoDataIter, SolveStrategy, SolverImpl, SolverTask};
+use rattler_solve::{
+    resolvo::Solver, ExcludeNewerOverrides, RepoDataIter, SolveStrategy, SolverImpl, SolverTask,
+};
 use tokio::task::JoinError;
 
 use crate::{
@@ -89,8 +93,12 @@ pub fn py_solve(
                 constraints: constraints.into_iter().map(Into::into).collect(),
                 timeout: timeout.map(std::time::Duration::from_micros),
                 channel_priority: channel_priority.into(),
+                channel_restrictions: HashMap::new(),
+                channel_order: Vec::new(),
                 exclude_newer,
+                exclude_newer_overrides: ExcludeNewerOverrides::default(),
                 strategy: strategy.map_or_else(Default::default, |v| v.0),
+                suggest_relaxations: false,
             };
 
             Ok::<_, PyErr>(
@@ -188,8 +196,12 @@ pub fn py_solve_with_sparse_repodata<'py>(
                 constraints: constraints.into_iter().map(Into::into).collect(),
                 timeout: timeout.map(std::time::Duration::from_micros),
                 channel_priority: channel_priority.into(),
+                channel_restrictions: HashMap::new(),
+                channel_order: Vec::new(),
                 exclude_newer,
+                exclude_newer_overrides: ExcludeNewerOverrides::default(),
                 strategy: strategy.map_or_else(Default::default, |v| v.0),
+                suggest_relaxations: false,
             };
 
             Ok::<_, PyErr>(